@@ -6,43 +6,180 @@ use candle::Device;
 use candle_nn as nn;
 use candle_nn::{Module, VarBuilder, Optimizer};
 use rand::Rng;
+use std::collections::VecDeque;
+use std::path::Path;
+
+use crate::StateKey;
 
 const ACTIONS: usize = 3;
+/// Default replay buffer size, in transitions.
+pub const REPLAY_CAPACITY: usize = 20000;
+/// Default number of stored transitions before `train_step` starts updating.
+pub const LEARN_START: usize = 1000;
+/// Default number of transitions collected with a uniformly random policy, before
+/// any `train_step`, regardless of epsilon.
+pub const WARMUP_STEPS: u64 = 1000;
+/// Default `max_grad_norm`: the global gradient norm each step is clipped to.
+pub const MAX_GRAD_NORM: f32 = 10.0;
+/// Initial AdamW learning rate.
+pub const LEARNING_RATE: f64 = 1e-3;
+
+/// TD loss used by `train_step`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LossKind {
+    /// Mean squared error; large TD errors (death penalties) dominate the gradient.
+    #[allow(dead_code)] // no CLI switch yet; Huber is the default
+    Mse,
+    /// Smooth L1: quadratic within `delta` of the target, linear beyond it.
+    Huber { delta: f32 },
+}
+
+impl Default for LossKind {
+    fn default() -> Self {
+        LossKind::Huber { delta: 1.0 }
+    }
+}
+
+/// Mean loss over a [B] vector of TD errors `q_a - target`.
+fn td_loss(err: &Tensor, kind: LossKind) -> candle::Result<Tensor> {
+    match kind {
+        LossKind::Mse => err.sqr()?.mean(0),
+        LossKind::Huber { delta } => {
+            // 0.5 * min(|e|, d)^2 + d * (|e| - min(|e|, d)) equals the piecewise form
+            let abs = err.abs()?;
+            let quad = abs.clamp(0f32, delta)?;
+            let lin = (&abs - &quad)?;
+            (quad.sqr()?.affine(0.5, 0.0)? + lin.affine(delta as f64, 0.0)?)?.mean(0)
+        }
+    }
+}
+
+/// Scale the gradients of `vars` so their global L2 norm is at most `max_norm`;
+/// returns the norm before clipping.
+fn clip_grad_norm(
+    grads: &mut candle::backprop::GradStore,
+    vars: &[candle::Var],
+    max_norm: f32,
+) -> candle::Result<f32> {
+    let mut sq = 0f32;
+    for v in vars {
+        if let Some(g) = grads.get(v.as_tensor()) {
+            sq += g.sqr()?.sum_all()?.to_scalar::<f32>()?;
+        }
+    }
+    let norm = sq.sqrt();
+    if norm > max_norm {
+        let scale = (max_norm / (norm + 1e-6)) as f64;
+        for v in vars {
+            if let Some(g) = grads.remove(v.as_tensor()) {
+                grads.insert(v.as_tensor(), g.affine(scale, 0.0)?);
+            }
+        }
+    }
+    Ok(norm)
+}
+
+/// Running reward mean and standard deviation (Welford's algorithm). Apple, death and
+/// shaping rewards differ by orders of magnitude; standardizing puts them on one scale.
+#[derive(Clone, Debug, Default)]
+pub struct RewardNormalizer {
+    count: u64,
+    mean: f64,
+    m2: f64, // sum of squared deviations from the running mean
+}
+
+impl RewardNormalizer {
+    /// Add one raw reward to the running statistics.
+    pub fn update(&mut self, r: f32) {
+        self.count += 1;
+        let d = r as f64 - self.mean;
+        self.mean += d / self.count as f64;
+        self.m2 += d * (r as f64 - self.mean);
+    }
+    /// Sample standard deviation so far; 1 until there are two samples.
+    pub fn std(&self) -> f32 {
+        if self.count < 2 {
+            return 1.0;
+        }
+        (self.m2 / (self.count - 1) as f64).sqrt() as f32
+    }
+    /// `r` standardized with the current statistics, clipped to +-10 deviations.
+    pub fn normalize(&self, r: f32) -> f32 {
+        ((r as f64 - self.mean) / (self.std() as f64).max(1e-6)).clamp(-10.0, 10.0) as f32
+    }
+}
+
+/// A transition waiting for its n-step return: (s, a, r, ns).
+type PendingStep = (Vec<f32>, u8, f32, Vec<f32>);
 
 pub struct Replay {
-    s: Vec<u32>,
+    s: Vec<f32>, // `dim` floats per entry (a single state index for embedding nets)
     a: Vec<u8>,
     r: Vec<f32>,
-    ns: Vec<u32>,
+    ns: Vec<f32>,
     done: Vec<u8>,
+    dim: usize,
     cap: usize,
     idx: usize,
     full: bool,
 }
 
 impl Replay {
-    pub fn new(cap: usize) -> Self {
-        Self { s: Vec::with_capacity(cap), a: Vec::with_capacity(cap), r: Vec::with_capacity(cap), ns: Vec::with_capacity(cap), done: Vec::with_capacity(cap), cap, idx: 0, full: false }
+    pub fn new(cap: usize, dim: usize) -> Self {
+        Self {
+            s: Vec::with_capacity(cap * dim),
+            a: Vec::with_capacity(cap),
+            r: Vec::with_capacity(cap),
+            ns: Vec::with_capacity(cap * dim),
+            done: Vec::with_capacity(cap),
+            dim,
+            cap,
+            idx: 0,
+            full: false,
+        }
     }
-    pub fn push(&mut self, s: u32, a: u8, r: f32, ns: u32, done: bool) {
+    pub fn push(&mut self, s: &[f32], a: u8, r: f32, ns: &[f32], done: bool) {
+        let d = self.dim;
         if self.full {
-            self.s[self.idx] = s;
+            let o = self.idx * d;
+            self.s[o..o + d].copy_from_slice(s);
             self.a[self.idx] = a;
             self.r[self.idx] = r;
-            self.ns[self.idx] = ns;
+            self.ns[o..o + d].copy_from_slice(ns);
             self.done[self.idx] = if done {1} else {0};
         } else {
-            self.s.push(s); self.a.push(a); self.r.push(r); self.ns.push(ns); self.done.push(if done {1}else{0});
-            if self.s.len() == self.cap { self.full = true; }
+            self.s.extend_from_slice(s);
+            self.a.push(a);
+            self.r.push(r);
+            self.ns.extend_from_slice(ns);
+            self.done.push(if done { 1 } else { 0 });
+            if self.a.len() == self.cap {
+                self.full = true;
+            }
         }
         self.idx = (self.idx + 1) % self.cap;
     }
-    pub fn len(&self) -> usize { if self.full { self.cap } else { self.s.len() } }
+    pub fn len(&self) -> usize {
+        if self.full { self.cap } else { self.a.len() }
+    }
+    /// Share of the buffer in use, 0..=1.
+    pub fn fill_ratio(&self) -> f32 {
+        self.len() as f32 / self.cap as f32
+    }
+}
+
+/// First layer of the Q-network: a learned embedding of the (hashed) state index,
+/// or a linear projection of a float feature vector.
+#[derive(Debug)]
+enum Input {
+    #[allow(dead_code)] // state-index path; the app drives the feature one
+    Embedding(nn::Embedding),
+    Features(nn::Linear),
 }
 
 #[derive(Debug)]
 pub struct DqnNet {
-    emb: nn::Embedding,
+    input: Input,
     mlp1: nn::Linear,
     mlp2: nn::Linear,
     out: nn::Linear,
@@ -50,17 +187,45 @@ pub struct DqnNet {
 }
 
 impl DqnNet {
+    #[allow(dead_code)] // state-index path
     pub fn new(vb: VarBuilder, device: &Device, state_vocab: usize, hidden: usize) -> candle::Result<Self> {
         // IMPORTANT: Scope variable names to avoid collisions across layers.
         let emb = nn::embedding(state_vocab, hidden, vb.clone().pp("emb"))?;
+        Self::with_input(Input::Embedding(emb), vb, device, hidden)
+    }
+    /// Network over `[batch, feature_dim]` float features instead of state indices.
+    pub fn new_features(
+        vb: VarBuilder,
+        device: &Device,
+        feature_dim: usize,
+        hidden: usize,
+    ) -> candle::Result<Self> {
+        let feat = nn::linear(feature_dim, hidden, vb.clone().pp("feat"))?;
+        Self::with_input(Input::Features(feat), vb, device, hidden)
+    }
+    fn with_input(
+        input: Input,
+        vb: VarBuilder,
+        device: &Device,
+        hidden: usize,
+    ) -> candle::Result<Self> {
         let mlp1 = nn::linear(hidden, hidden, vb.clone().pp("mlp1"))?;
         let mlp2 = nn::linear(hidden, hidden, vb.clone().pp("mlp2"))?;
         let out = nn::linear(hidden, ACTIONS, vb.pp("out"))?;
-        Ok(Self { emb, mlp1, mlp2, out, device: device.clone() })
+        Ok(Self {
+            input,
+            mlp1,
+            mlp2,
+            out,
+            device: device.clone(),
+        })
     }
-    pub fn q_values(&self, s_idx: &Tensor) -> candle::Result<Tensor> {
-        // s_idx: [batch] (u32 mapped to index space)
-        let x = self.emb.forward(s_idx)?;          // [batch, hidden]
+    pub fn q_values(&self, s: &Tensor) -> candle::Result<Tensor> {
+        // s: [batch] u32 state indices, or [batch, features] f32
+        let x = match &self.input {
+            Input::Embedding(emb) => emb.forward(s)?, // [batch, hidden]
+            Input::Features(feat) => feat.forward(s)?, // [batch, hidden]
+        };
         let x = x.relu()?;
         let x = self.mlp1.forward(&x)?.relu()?;
         let x = self.mlp2.forward(&x)?.relu()?;
@@ -73,74 +238,298 @@ pub struct DqnAgent {
     pub opt: nn::AdamW,
     pub replay: Replay,
     pub gamma: f32,
+    #[allow(dead_code)] // state-index path
     pub input_vocab: usize,
+    pub feature_dim: Option<usize>, // Some(F) when the net takes feature vectors
     pub epsilon: f32,
     pub min_epsilon: f32,
     pub decay: f32,
+    pub n_step: usize,        // 1 = plain one-step TD targets
+    pub action_repeat: usize, // game ticks per transition; each decision discounts by gamma^k
+    pub learn_start: usize,   // no updates until the replay holds this many transitions
+    pub warmup_steps: u64,    // act uniformly at random and skip updates for this many transitions
+    pub env_steps: u64,       // transitions recorded so far
+    pub loss: LossKind,
+    pub max_grad_norm: Option<f32>, // clip the global gradient norm before each step (None = no clipping)
+    pub reward_norm: Option<RewardNormalizer>, // standardize rewards before they enter the replay (off by default)
+    pub train_steps: u64,                      // gradient updates run so far
+    pub last_loss: Option<f32>,
+    pub avg_loss: f32,  // exponential moving average of the loss (0.99)
+    varmap: nn::VarMap, // all network weights, for export
+    pending: Vec<VecDeque<PendingStep>>, // per-trajectory (s, a, r, ns) not yet in replay
 }
 
 impl DqnAgent {
-    pub fn new(input_vocab: usize, hidden: usize, device: &Device) -> candle::Result<Self> {
-        let mut varmap = nn::VarMap::new();
+    /// Agent over hashed state indices (`state % input_vocab`) through an embedding.
+    /// Keys collide unless `input_vocab` covers the key space: 2^20 for the compact
+    /// tabular key, 2^22 for the extended one (the feature path avoids this entirely).
+    #[allow(dead_code)] // state-index path
+    pub fn new(
+        input_vocab: usize,
+        hidden: usize,
+        replay_capacity: usize,
+        device: &Device,
+    ) -> candle::Result<Self> {
+        let varmap = nn::VarMap::new();
         let vb = VarBuilder::from_varmap(&varmap, candle::DType::F32, device);
         let net = DqnNet::new(vb, device, input_vocab, hidden)?;
+        Self::with_net(net, &varmap, input_vocab, None, replay_capacity)
+    }
+
+    /// Agent over `feature_dim`-long float feature vectors (no hashing collisions).
+    pub fn new_features(
+        feature_dim: usize,
+        hidden: usize,
+        replay_capacity: usize,
+        device: &Device,
+    ) -> candle::Result<Self> {
+        let varmap = nn::VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, candle::DType::F32, device);
+        let net = DqnNet::new_features(vb, device, feature_dim, hidden)?;
+        Self::with_net(net, &varmap, 0, Some(feature_dim), replay_capacity)
+    }
+
+    fn with_net(
+        net: DqnNet,
+        varmap: &nn::VarMap,
+        input_vocab: usize,
+        feature_dim: Option<usize>,
+        replay_capacity: usize,
+    ) -> candle::Result<Self> {
         // Optimizer over all variables in the model
-        let opt = nn::AdamW::new_lr(varmap.all_vars(), 1e-3)?;
-        Ok(Self { net, opt, replay: Replay::new(20000), gamma: 0.99, input_vocab, epsilon: 0.25, min_epsilon: 0.05, decay: 0.999 })
+        let opt = nn::AdamW::new_lr(varmap.all_vars(), LEARNING_RATE)?;
+        let replay = Replay::new(replay_capacity.max(1), feature_dim.unwrap_or(1));
+        Ok(Self {
+            net,
+            opt,
+            replay,
+            gamma: 0.99,
+            input_vocab,
+            feature_dim,
+            epsilon: 0.25,
+            min_epsilon: 0.05,
+            decay: 0.999,
+            n_step: 1,
+            action_repeat: 1,
+            learn_start: LEARN_START,
+            warmup_steps: WARMUP_STEPS,
+            env_steps: 0,
+            loss: LossKind::default(),
+            max_grad_norm: Some(MAX_GRAD_NORM),
+            reward_norm: None,
+            train_steps: 0,
+            last_loss: None,
+            avg_loss: 0.0,
+            varmap: varmap.clone(),
+            pending: Vec::new(),
+        })
+    }
+
+    /// Current AdamW learning rate.
+    pub fn learning_rate(&self) -> f64 {
+        self.opt.learning_rate()
     }
 
-    pub fn select_action(&self, state: u32) -> candle::Result<usize> {
-        // Epsilon-greedy for exploration
-        if rand::thread_rng().r#gen::<f32>() < self.epsilon {
+    /// Change the AdamW learning rate; takes effect from the next `train_step`.
+    pub fn set_learning_rate(&mut self, lr: f64) {
+        self.opt.set_learning_rate(lr);
+    }
+
+    /// Write the network weights as safetensors. Tensor names follow the layers:
+    /// `emb.weight` [vocab, hidden] or `feat.weight` [hidden, F] + `feat.bias`, then
+    /// `mlp1`, `mlp2` and `out` (`.weight` [out, in], `.bias` [out]); the forward pass is
+    /// input -> relu -> mlp1 -> relu -> mlp2 -> relu -> out (3 Q-values, take the argmax).
+    pub fn export_weights(&self, path: &Path) -> candle::Result<()> {
+        self.varmap.save(path)
+    }
+
+    /// True while the replay is still being filled by the random warm-up policy.
+    pub fn in_warmup(&self) -> bool {
+        self.env_steps < self.warmup_steps
+    }
+
+    #[allow(dead_code)] // state-index path
+    pub fn select_action(&self, state: StateKey) -> candle::Result<usize> {
+        let s = Tensor::new(&[state.reduced(self.input_vocab as u32)], &self.net.device)?; // [1]
+        self.epsilon_greedy(&s)
+    }
+
+    /// Epsilon-greedy action for a feature vector (agents built with `new_features`).
+    pub fn select_action_features(&self, features: &[f32]) -> candle::Result<usize> {
+        let s = Tensor::from_slice(features, (1, features.len()), &self.net.device)?; // [1, F]
+        self.epsilon_greedy(&s)
+    }
+
+    fn epsilon_greedy(&self, s: &Tensor) -> candle::Result<usize> {
+        // Epsilon-greedy for exploration; purely random during warm-up
+        if self.in_warmup() || rand::thread_rng().r#gen::<f32>() < self.epsilon {
             return Ok(rand::thread_rng().gen_range(0..ACTIONS));
         }
-        let s = Tensor::new(&[state % self.input_vocab as u32], &self.net.device)?; // [1]
-        let q = self.net.q_values(&s)?; // [1, 3]
+        let q = self.net.q_values(s)?; // [1, 3]
         let idxs = q.argmax(1)?; // indices along dim=1, shape [1]
-        let v = idxs.to_vec1::<i64>()?;
+        let v = idxs.to_vec1::<u32>()?;
         Ok(v[0] as usize)
     }
 
-    pub fn push_transition(&mut self, s: u32, a: usize, r: f32, ns: u32, done: bool) {
-        self.replay.push(s, a as u8, r, ns, done);
+    /// Record a transition of trajectory `traj` (one per game); with `n_step > 1` the
+    /// replay receives `sum gamma^k r_k` over the next n rewards and the state n steps ahead.
+    #[allow(dead_code)] // state-index path
+    pub fn push_transition(
+        &mut self,
+        traj: usize,
+        s: StateKey,
+        a: usize,
+        r: f32,
+        ns: StateKey,
+        done: bool,
+    ) {
+        let vocab = self.input_vocab as u32;
+        let (s, ns) = (s.reduced(vocab) as f32, ns.reduced(vocab) as f32);
+        self.push_state(traj, &[s], a, r, &[ns], done);
+    }
+
+    /// `push_transition` for agents built with `new_features`.
+    pub fn push_transition_features(
+        &mut self,
+        traj: usize,
+        s: &[f32],
+        a: usize,
+        r: f32,
+        ns: &[f32],
+        done: bool,
+    ) {
+        self.push_state(traj, s, a, r, ns, done);
+    }
+
+    fn push_state(&mut self, traj: usize, s: &[f32], a: usize, r: f32, ns: &[f32], done: bool) {
+        self.env_steps += 1;
+        // Per-step rewards are standardized before any n-step summing
+        let r = match self.reward_norm.as_mut() {
+            Some(norm) => {
+                norm.update(r);
+                norm.normalize(r)
+            }
+            None => r,
+        };
+        let n = self.n_step.max(1);
+        if n == 1 {
+            self.replay.push(s, a as u8, r, ns, done);
+            return;
+        }
+        if self.pending.len() <= traj {
+            self.pending.resize_with(traj + 1, VecDeque::new);
+        }
+        self.pending[traj].push_back((s.to_vec(), a as u8, r, ns.to_vec()));
+        if self.pending[traj].len() >= n {
+            let (s0, a0, ret, last_ns) = n_step_entry(&self.pending[traj], self.decision_gamma());
+            self.replay.push(s0, a0, ret, last_ns, done);
+            self.pending[traj].pop_front();
+        }
+        if done {
+            // Episode over: the shorter tails are terminal, so no bootstrap is needed
+            while !self.pending[traj].is_empty() {
+                let (s0, a0, ret, last_ns) =
+                    n_step_entry(&self.pending[traj], self.decision_gamma());
+                self.replay.push(s0, a0, ret, last_ns, true);
+                self.pending[traj].pop_front();
+            }
+        }
+    }
+
+    /// Drop partially accumulated n-step windows (e.g. when games are reset at epoch end).
+    pub fn clear_pending(&mut self) {
+        for q in self.pending.iter_mut() {
+            q.clear();
+        }
     }
 
-    pub fn train_step(&mut self, batch: usize) -> candle::Result<()> {
+    /// One gradient step on `batch` replay transitions; returns its loss, or `None` when
+    /// skipped (during warm-up, and until the replay holds `learn_start` and `batch` transitions).
+    pub fn train_step(&mut self, batch: usize) -> candle::Result<Option<f32>> {
         let n = self.replay.len();
-        if n < batch { return Ok(()); }
+        if self.in_warmup() || n < batch || n < self.learn_start {
+            return Ok(None);
+        }
         // Sample first `batch` items (simple; can be improved with RNG)
-        let s: Vec<u32> = self.replay.s.iter().cloned().take(batch).collect();
+        let d = self.replay.dim;
+        let s = &self.replay.s[..batch * d];
         let a: Vec<i64> = self.replay.a.iter().map(|&x| x as i64).take(batch).collect();
         let r: Vec<f32> = self.replay.r.iter().cloned().take(batch).collect();
-        let ns: Vec<u32> = self.replay.ns.iter().cloned().take(batch).collect();
+        let ns = &self.replay.ns[..batch * d];
         let done: Vec<f32> = self.replay.done.iter().map(|&d| d as f32).take(batch).collect();
 
         let dev = &self.net.device;
-        let s_t = Tensor::new(&s[..], dev)?;               // [B]
-        let a_t = Tensor::new(&a[..], dev)?;               // [B]
-        let r_t = Tensor::new(&r[..], dev)?;               // [B]
-        let ns_t = Tensor::new(&ns[..], dev)?;             // [B]
-        let done_t = Tensor::new(&done[..], dev)?;         // [B]
-        let q = self.net.q_values(&s_t)?;                  // [B, 3]
-        let q_a = q.gather(&a_t.unsqueeze(1)?, 1)?         // [B,1]
-            .squeeze(1)?;                                  // [B]
-    let nq = self.net.q_values(&ns_t)?;                // [B,3]
-    let max_nq = nq.max(1)?.squeeze(1)?;               // [B]
-    // Build tensors for scalar/broadcast ops
-    let bsz = s.len();
-    let ones = Tensor::ones(&[bsz], candle::DType::F32, dev)?; // [B]
-    let not_done = (&ones - &done_t)?;                        // [B]
-    let gamma_t = Tensor::new(self.gamma, dev)?;              // scalar
-    let gamma_nq = (&max_nq * &gamma_t)?;                     // [B]
-    let target = (&r_t + (&not_done * &gamma_nq)?)?;          // [B]
-        let loss = (q_a - target)?.sqr()?.mean(0)?;        // MSE
-
-        self.opt.backward_step(&loss)?;
+        let s_t = self.states_tensor(s, batch)?; // [B] or [B, F]
+        let a_t = Tensor::new(&a[..], dev)?; // [B]
+        let r_t = Tensor::new(&r[..], dev)?; // [B]
+        let ns_t = self.states_tensor(ns, batch)?; // [B] or [B, F]
+        let done_t = Tensor::new(&done[..], dev)?; // [B]
+        let q = self.net.q_values(&s_t)?; // [B, 3]
+        let q_a = q
+            .gather(&a_t.unsqueeze(1)?, 1)? // [B,1]
+            .squeeze(1)?; // [B]
+        let target = self.td_target(&r_t, &done_t, &ns_t)?; // [B]
+        let loss = td_loss(&(q_a - target)?, self.loss)?; // Huber by default
+
+        match self.max_grad_norm {
+            Some(max_norm) => {
+                // Death penalties give huge TD errors; cap the step they can cause
+                let mut grads = loss.backward()?;
+                clip_grad_norm(&mut grads, &self.varmap.all_vars(), max_norm)?;
+                self.opt.step(&grads)?;
+            }
+            None => self.opt.backward_step(&loss)?,
+        }
+        let loss = loss.to_scalar::<f32>()?;
+        self.avg_loss = if self.train_steps == 0 {
+            loss
+        } else {
+            0.99 * self.avg_loss + 0.01 * loss
+        };
+        self.train_steps += 1;
+        self.last_loss = Some(loss);
         // Decay exploration a bit each step
         let new_eps = self.epsilon * self.decay;
         self.epsilon = new_eps.max(self.min_epsilon);
-        Ok(())
+        Ok(Some(loss))
+    }
+
+    /// Discount per transition: `gamma^k` for `k = action_repeat` ticks.
+    fn decision_gamma(&self) -> f32 {
+        self.gamma.powi(self.action_repeat.max(1) as i32)
     }
+
+    /// TD targets `r + (1 - done) * gamma^(n k) * max_a Q(ns, a)` for a batch of transitions.
+    fn td_target(&self, r_t: &Tensor, done_t: &Tensor, ns_t: &Tensor) -> candle::Result<Tensor> {
+        let nq = self.net.q_values(ns_t)?; // [B,3]
+        let max_nq = nq.max(1)?; // [B] (max drops the dim)
+        let ones = Tensor::ones(done_t.dims(), candle::DType::F32, done_t.device())?; // [B]
+        let not_done = (&ones - done_t)?; // [B]
+        let gamma_n = self.decision_gamma().powi(self.n_step.max(1) as i32) as f64; // gamma^(n k)
+        let gamma_nq = max_nq.affine(gamma_n, 0.0)?; // [B]
+        r_t + (&not_done * &gamma_nq)? // [B]
+    }
+
+    /// Network input for `batch` replay states: u32 indices, or a [B, F] float matrix.
+    fn states_tensor(&self, flat: &[f32], batch: usize) -> candle::Result<Tensor> {
+        match self.feature_dim {
+            Some(dim) => Tensor::from_slice(flat, (batch, dim), &self.net.device),
+            None => {
+                let idx: Vec<u32> = flat.iter().map(|&x| x as u32).collect();
+                Tensor::new(&idx[..], &self.net.device)
+            }
+        }
+    }
+}
+
+/// Discounted return of a pending window: (first state, first action, sum gamma^k r_k, last next-state).
+fn n_step_entry(window: &VecDeque<PendingStep>, gamma: f32) -> (&[f32], u8, f32, &[f32]) {
+    let (s0, a0, _, _) = &window[0];
+    let ret = window
+        .iter()
+        .rev()
+        .fold(0.0, |acc, (_, _, r, _)| r + gamma * acc);
+    (s0, *a0, ret, &window[window.len() - 1].3)
 }
 
 pub fn preferred_device() -> Device {
@@ -149,3 +538,319 @@ pub fn preferred_device() -> Device {
     if let Ok(dev) = Device::new_cuda(0) { return dev; }
     Device::Cpu
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_three_step_return_matches_hand_sum() {
+        let mut agent = DqnAgent::new(16, 8, REPLAY_CAPACITY, &Device::Cpu).unwrap();
+        agent.n_step = 3;
+        agent.gamma = 0.9;
+        // Two interleaved games; only game 0 follows the scripted rewards
+        agent.push_transition(0, StateKey(1), 0, 1.0, StateKey(2), false);
+        agent.push_transition(1, StateKey(9), 2, 100.0, StateKey(10), false);
+        agent.push_transition(0, StateKey(2), 1, 2.0, StateKey(3), false);
+        assert_eq!(agent.replay.len(), 0);
+        agent.push_transition(0, StateKey(3), 2, 3.0, StateKey(4), false);
+        assert_eq!(agent.replay.len(), 1);
+        let expected = 1.0 + 0.9 * 2.0 + 0.81 * 3.0;
+        assert!((agent.replay.r[0] - expected).abs() < 1e-5);
+        assert_eq!(
+            (
+                agent.replay.s[0],
+                agent.replay.a[0],
+                agent.replay.ns[0],
+                agent.replay.done[0]
+            ),
+            (1.0, 0, 4.0, 0)
+        );
+
+        // Terminal step flushes the remaining shorter windows as done
+        agent.push_transition(0, StateKey(4), 1, -10.0, StateKey(5), true);
+        assert_eq!(agent.replay.len(), 4);
+        assert!((agent.replay.r[1] - (2.0 + 0.9 * 3.0 + 0.81 * -10.0)).abs() < 1e-5);
+        assert!((agent.replay.r[2] - (3.0 + 0.9 * -10.0)).abs() < 1e-5);
+        assert!((agent.replay.r[3] - -10.0).abs() < 1e-5);
+        assert!(agent.replay.done[1..].iter().all(|&d| d == 1));
+        assert!(agent.pending[0].is_empty());
+        assert_eq!(agent.pending[1].len(), 1);
+    }
+
+    #[test]
+    fn test_td_target_matches_hand_computation() {
+        let mut agent = DqnAgent::new_features(2, 8, 64, &Device::Cpu).unwrap();
+        (agent.gamma, agent.n_step) = (0.9, 2);
+        let ns = Tensor::new(&[[1.0f32, 0.0], [0.0, 1.0]], &Device::Cpu).unwrap();
+        let nq = agent.net.q_values(&ns).unwrap().to_vec2::<f32>().unwrap();
+        let max_nq: Vec<f32> = nq
+            .iter()
+            .map(|q| q.iter().copied().fold(f32::MIN, f32::max))
+            .collect();
+        let r = Tensor::new(&[1.0f32, -2.0], &Device::Cpu).unwrap();
+        let done = Tensor::new(&[0.0f32, 1.0], &Device::Cpu).unwrap();
+        let target = agent
+            .td_target(&r, &done, &ns)
+            .unwrap()
+            .to_vec1::<f32>()
+            .unwrap();
+        assert!((target[0] - (1.0 + 0.81 * max_nq[0])).abs() < 1e-5);
+        assert_eq!(target[1], -2.0); // terminal: no bootstrap
+    }
+
+    #[test]
+    fn test_action_repeat_discounts_per_tick() {
+        let mut agent = DqnAgent::new_features(2, 8, 64, &Device::Cpu).unwrap();
+        (agent.gamma, agent.n_step, agent.action_repeat) = (0.9, 2, 3);
+        let k = 0.9f32.powi(3); // one decision spans three ticks
+        agent.push_transition_features(0, &[1.0, 0.0], 0, 1.0, &[0.0, 1.0], false);
+        agent.push_transition_features(0, &[0.0, 1.0], 1, 2.0, &[1.0, 1.0], false);
+        assert_eq!(agent.replay.len(), 1);
+        assert!((agent.replay.r[0] - (1.0 + k * 2.0)).abs() < 1e-5);
+
+        let ns = Tensor::new(&[[1.0f32, 1.0]], &Device::Cpu).unwrap();
+        let nq = agent.net.q_values(&ns).unwrap().to_vec2::<f32>().unwrap();
+        let max_nq = nq[0].iter().copied().fold(f32::MIN, f32::max);
+        let r = Tensor::new(&[agent.replay.r[0]], &Device::Cpu).unwrap();
+        let done = Tensor::new(&[0.0f32], &Device::Cpu).unwrap();
+        let target = agent
+            .td_target(&r, &done, &ns)
+            .unwrap()
+            .to_vec1::<f32>()
+            .unwrap();
+        assert!((target[0] - (agent.replay.r[0] + k * k * max_nq)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_reward_normalizer_standardizes_stream() {
+        use rand::SeedableRng;
+        // Snake-like rewards: rare apples and deaths among tiny shaping terms
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(7);
+        let mut sample = || match rng.gen_range(0..100) {
+            0..=4 => 10.0,
+            5..=6 => -30.0,
+            _ => rng.gen_range(-0.05f32..0.05),
+        };
+        let mut norm = RewardNormalizer::default();
+        let mut out = Vec::new();
+        for i in 0..40_000 {
+            let r = sample();
+            norm.update(r);
+            if i >= 20_000 {
+                out.push(norm.normalize(r));
+            }
+        }
+        let n = out.len() as f32;
+        let mean = out.iter().sum::<f32>() / n;
+        let var = out.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n;
+        assert!(mean.abs() < 0.05, "mean {}", mean);
+        assert!((var - 1.0).abs() < 0.1, "variance {}", var);
+
+        // Rewards reach the replay standardized, but only when enabled
+        let mut agent = DqnAgent::new(16, 8, REPLAY_CAPACITY, &Device::Cpu).unwrap();
+        agent.push_transition(0, StateKey(1), 0, 10.0, StateKey(2), false);
+        assert_eq!(agent.replay.r[0], 10.0);
+        agent.reward_norm = Some(norm.clone());
+        agent.push_transition(0, StateKey(1), 0, 10.0, StateKey(2), false);
+        assert!(agent.replay.r[1] > 1.0 && agent.replay.r[1] < 10.0);
+    }
+
+    #[test]
+    fn test_huber_loss_damps_outliers() {
+        let err = Tensor::new(&[0.5f32, -1.0, 10.0], &Device::Cpu).unwrap();
+        let mse = td_loss(&err, LossKind::Mse)
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        let huber = td_loss(&err, LossKind::default())
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        // (0.25 + 1 + 100) / 3 vs (0.125 + 0.5 + 9.5) / 3
+        assert!((mse - 33.75).abs() < 1e-4);
+        assert!((huber - 3.375).abs() < 1e-4);
+        let wide = td_loss(&err, LossKind::Huber { delta: 20.0 })
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert!((wide - mse / 2.0).abs() < 1e-4); // all errors inside delta: half the MSE
+    }
+
+    #[test]
+    fn test_export_weights_names_and_shapes() {
+        let agent = DqnAgent::new_features(5, 8, 16, &Device::Cpu).unwrap();
+        let path = std::env::temp_dir().join("snake_dqn_export_test.safetensors");
+        agent.export_weights(&path).unwrap();
+        let tensors = candle::safetensors::load(&path, &Device::Cpu).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(tensors["feat.weight"].dims(), &[8, 5]);
+        assert_eq!(tensors["mlp1.weight"].dims(), &[8, 8]);
+        assert_eq!(tensors["out.weight"].dims(), &[3, 8]);
+        assert_eq!(tensors["out.bias"].dims(), &[3]);
+        assert_eq!(tensors.len(), 8);
+    }
+
+    #[test]
+    fn test_grad_clipping_bounds_update() {
+        // Plain SGD (lr 1) so the parameter moves by exactly the applied gradient
+        let update = |clip: Option<f32>| {
+            let w = candle::Var::zeros(4, candle::DType::F32, &Device::Cpu).unwrap();
+            let loss = w.as_tensor().affine(1e6, 0.0).unwrap().sum_all().unwrap();
+            let mut grads = loss.backward().unwrap();
+            if let Some(max_norm) = clip {
+                let before =
+                    clip_grad_norm(&mut grads, std::slice::from_ref(&w), max_norm).unwrap();
+                assert!((before - 2e6).abs() < 1.0);
+            }
+            let mut sgd = nn::SGD::new(vec![w.clone()], 1.0).unwrap();
+            sgd.step(&grads).unwrap();
+            w.as_tensor()
+                .sqr()
+                .unwrap()
+                .sum_all()
+                .unwrap()
+                .to_scalar::<f32>()
+                .unwrap()
+                .sqrt()
+        };
+        assert!(update(Some(MAX_GRAD_NORM)) <= MAX_GRAD_NORM + 1e-3);
+        assert!(update(None) > 1e6);
+
+        let mut agent = DqnAgent::new_features(2, 8, 64, &Device::Cpu).unwrap();
+        (
+            agent.learn_start,
+            agent.warmup_steps,
+            agent.loss,
+            agent.max_grad_norm,
+        ) = (4, 0, LossKind::Mse, Some(MAX_GRAD_NORM));
+        for i in 0..4 {
+            agent.push_transition_features(0, &[1.0, 0.0], i % 3, -1e6, &[0.0, 1.0], true);
+        }
+        assert!(agent.train_step(4).unwrap().is_some_and(f32::is_finite));
+    }
+
+    #[test]
+    fn test_set_learning_rate_scales_the_update() {
+        // AdamW's first step moves each weight by about lr, so the shift tracks the rate
+        let shift = |lr: Option<f64>| {
+            let mut agent = DqnAgent::new_features(2, 8, 64, &Device::Cpu).unwrap();
+            (agent.learn_start, agent.warmup_steps) = (4, 0);
+            if let Some(lr) = lr {
+                agent.set_learning_rate(lr);
+            }
+            let before = agent
+                .net
+                .out
+                .weight()
+                .flatten_all()
+                .unwrap()
+                .to_vec1::<f32>()
+                .unwrap();
+            for i in 0..4 {
+                agent.push_transition_features(0, &[1.0, 0.0], i % 3, 1.0, &[0.0, 1.0], true);
+            }
+            agent.train_step(4).unwrap();
+            let after = agent
+                .net
+                .out
+                .weight()
+                .flatten_all()
+                .unwrap()
+                .to_vec1::<f32>()
+                .unwrap();
+            before
+                .iter()
+                .zip(&after)
+                .map(|(b, a)| (a - b).abs())
+                .fold(0f32, f32::max)
+        };
+        let mut agent = DqnAgent::new_features(2, 8, 64, &Device::Cpu).unwrap();
+        assert_eq!(agent.learning_rate(), LEARNING_RATE);
+        agent.set_learning_rate(1e-5);
+        assert_eq!(agent.learning_rate(), 1e-5);
+        assert!(shift(Some(1e-5)) < shift(None) / 10.0);
+    }
+
+    #[test]
+    fn test_train_step_waits_for_learn_start() {
+        let mut agent = DqnAgent::new_features(2, 8, 64, &Device::Cpu).unwrap();
+        (agent.learn_start, agent.warmup_steps) = (10, 0);
+        let probe = Tensor::new(&[[1.0f32, 0.0]], &Device::Cpu).unwrap();
+        let q0 = agent
+            .net
+            .q_values(&probe)
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap();
+        for i in 0..9 {
+            agent.push_transition_features(0, &[1.0, 0.0], i % 3, 1.0, &[0.0, 1.0], false);
+        }
+        assert_eq!(agent.train_step(4).unwrap(), None);
+        assert_eq!(
+            agent
+                .net
+                .q_values(&probe)
+                .unwrap()
+                .to_vec2::<f32>()
+                .unwrap(),
+            q0
+        );
+
+        agent.push_transition_features(0, &[1.0, 0.0], 0, 1.0, &[0.0, 1.0], true);
+        let loss = agent
+            .train_step(4)
+            .unwrap()
+            .expect("enough transitions to train");
+        assert!(loss.is_finite());
+        assert_eq!(
+            (agent.train_steps, agent.last_loss, agent.avg_loss),
+            (1, Some(loss), loss)
+        );
+        assert_ne!(
+            agent
+                .net
+                .q_values(&probe)
+                .unwrap()
+                .to_vec2::<f32>()
+                .unwrap(),
+            q0
+        );
+    }
+
+    #[test]
+    fn test_warmup_acts_randomly_and_skips_training() {
+        let mut agent = DqnAgent::new_features(2, 8, 64, &Device::Cpu).unwrap();
+        (
+            agent.epsilon,
+            agent.min_epsilon,
+            agent.learn_start,
+            agent.warmup_steps,
+        ) = (0.0, 0.0, 4, 20);
+        let s = [1.0f32, 0.0];
+        let counts = |agent: &DqnAgent| {
+            let mut counts = [0usize; ACTIONS];
+            for _ in 0..600 {
+                counts[agent.select_action_features(&s).unwrap()] += 1;
+            }
+            counts
+        };
+        // Greedy epsilon, yet every action shows up about a third of the time
+        assert!(
+            counts(&agent).iter().all(|&c| c > 120),
+            "{:?}",
+            counts(&agent)
+        );
+        for i in 0..19 {
+            agent.push_transition_features(0, &s, i % 3, 1.0, &[0.0, 1.0], false);
+            assert_eq!(agent.train_step(4).unwrap(), None);
+        }
+        assert!(agent.in_warmup());
+        assert_eq!(agent.train_steps, 0);
+
+        agent.push_transition_features(0, &s, 0, 1.0, &[0.0, 1.0], true);
+        assert!(!agent.in_warmup());
+        assert!(agent.train_step(4).unwrap().is_some());
+        assert_eq!(counts(&agent).iter().filter(|&&c| c > 0).count(), 1); // greedy again
+    }
+}
@@ -43,14 +43,21 @@ impl GpuRenderer {
     pub async fn new(window: &Window, width: u32, height: u32) -> anyhow::Result<Self> {
         let instance = wgpu::Instance::default();
         let surface = unsafe { instance.create_surface(window) }?;
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
+        let adapter = crate::request_adapter_with_fallback(|power, force_fallback| {
+            instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: power,
                 compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
+                force_fallback_adapter: force_fallback,
             })
-            .await
-            .ok_or_else(|| anyhow::anyhow!("No GPU adapter"))?;
+        })
+        .await
+        .ok_or_else(|| {
+            anyhow::anyhow!("No GPU adapter (tried high-performance, low-power and fallback)")
+        })?;
+        log_info!(
+            "[gpu-render] adapter: {}",
+            crate::describe_adapter(&adapter.get_info())
+        );
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
@@ -227,6 +234,23 @@ impl GpuRenderer {
     }
 
     pub fn render(&mut self, instances: &[Instance]) -> anyhow::Result<()> {
+        self.render_inner(instances, false).map(|_| ())
+    }
+
+    /// Render a frame and read it back as tightly packed RGBA8 `(width, height, pixels)`.
+    pub fn render_and_capture(
+        &mut self,
+        instances: &[Instance],
+    ) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+        self.render_inner(instances, true)?
+            .ok_or_else(|| anyhow::anyhow!("surface not ready, frame skipped"))
+    }
+
+    fn render_inner(
+        &mut self,
+        instances: &[Instance],
+        capture: bool,
+    ) -> anyhow::Result<Option<(u32, u32, Vec<u8>)>> {
         // Ensure capacity
         if instances.len() > self.instance_capacity {
             // Recreate buffer with larger capacity
@@ -243,41 +267,147 @@ impl GpuRenderer {
             self.queue.write_buffer(&self.instance_buf, 0, bytemuck::cast_slice(instances));
         }
 
-        let frame = self
-            .surface
-            .get_current_texture()
-            .map_err(|e| anyhow::anyhow!("surface acquire failed: {e}"))?;
+        let frame = match self.surface.get_current_texture() {
+            Ok(frame) => frame,
+            // The surface went stale (resize, display change, device reset): reconfigure and
+            // skip this frame; a timeout just skips it.
+            Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
+                self.surface.configure(&self.device, &self.config);
+                return Ok(None);
+            }
+            Err(wgpu::SurfaceError::Timeout) => return Ok(None),
+            Err(e) => return Err(anyhow::anyhow!("surface acquire failed: {e}")),
+        };
         let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("encoder") });
 
-        {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("grid+cells"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color { r: 0.04, g: 0.04, b: 0.06, a: 1.0 }), store: true },
-                })],
-                depth_stencil_attachment: None,
+        self.encode_pass(&mut encoder, &view, instances.len());
+
+        // Surface textures are not guaranteed to be copyable, so for a capture the same
+        // passes are drawn into an offscreen texture and copied into a mappable buffer
+        // (rows padded to 256 bytes).
+        let (w, h) = (self.config.width, self.config.height);
+        let padded_row = (w * 4).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback = if capture {
+            let size = wgpu::Extent3d {
+                width: w,
+                height: h,
+                depth_or_array_layers: 1,
+            };
+            let target = self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("capture-tex"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.config.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
             });
-            // Grid full-screen
-            rpass.set_pipeline(&self.grid_pipeline);
-            rpass.set_bind_group(0, &self.uniform_bg, &[]);
-            rpass.draw(0..3, 0..1); // full-screen triangle
+            let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+            self.encode_pass(&mut encoder, &target_view, instances.len());
+            let buf = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("capture-buf"),
+                size: (padded_row * h) as u64,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    texture: &target,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &buf,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(padded_row),
+                        rows_per_image: Some(h),
+                    },
+                },
+                size,
+            );
+            Some(buf)
+        } else {
+            None
+        };
+        self.queue.submit(Some(encoder.finish()));
 
-            // Cells
-            if !instances.is_empty() {
-                rpass.set_pipeline(&self.cell_pipeline);
-                rpass.set_bind_group(0, &self.uniform_bg, &[]);
-                rpass.set_vertex_buffer(0, self.quad_vb.slice(..));
-                rpass.set_vertex_buffer(1, self.instance_buf.slice(..(instances.len() * std::mem::size_of::<Instance>()) as u64));
-                rpass.draw(0..4, 0..(instances.len() as u32));
+        let captured = match readback {
+            Some(buf) => {
+                let slice = buf.slice(..);
+                slice.map_async(wgpu::MapMode::Read, |_| ());
+                self.device.poll(wgpu::Maintain::Wait);
+                let data = slice.get_mapped_range();
+                let bgra = matches!(
+                    self.config.format,
+                    wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+                );
+                let mut rgba = Vec::with_capacity((w * h * 4) as usize);
+                for row in data.chunks(padded_row as usize) {
+                    for px in row[..(w * 4) as usize].chunks_exact(4) {
+                        if bgra {
+                            rgba.extend_from_slice(&[px[2], px[1], px[0], px[3]]);
+                        } else {
+                            rgba.extend_from_slice(px);
+                        }
+                    }
+                }
+                drop(data);
+                buf.unmap();
+                Some((w, h, rgba))
             }
-        }
-        self.queue.submit(Some(encoder.finish()));
+            None => None,
+        };
         frame.present();
-        Ok(())
+        Ok(captured)
+    }
+
+    /// Record the grid + cell passes into `view`.
+    fn encode_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        instance_count: usize,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("grid+cells"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.04,
+                        g: 0.04,
+                        b: 0.06,
+                        a: 1.0,
+                    }),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        // Grid full-screen
+        rpass.set_pipeline(&self.grid_pipeline);
+        rpass.set_bind_group(0, &self.uniform_bg, &[]);
+        rpass.draw(0..3, 0..1); // full-screen triangle
+
+        // Cells
+        if instance_count > 0 {
+            rpass.set_pipeline(&self.cell_pipeline);
+            rpass.set_bind_group(0, &self.uniform_bg, &[]);
+            rpass.set_vertex_buffer(0, self.quad_vb.slice(..));
+            rpass.set_vertex_buffer(
+                1,
+                self.instance_buf
+                    .slice(..(instance_count * std::mem::size_of::<Instance>()) as u64),
+            );
+            rpass.draw(0..4, 0..(instance_count as u32));
+        }
     }
 }
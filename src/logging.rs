@@ -0,0 +1,70 @@
+//! Leveled console messages for `--log-level`. `log_debug!` and `log_info!` print to
+//! stdout and `log_warn!` to stderr, each only when its level is enabled.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Message severity, least severe first; a level shows itself and everything above.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Show messages at `level` and above from now on.
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether a message at `level` is shown.
+pub fn enabled(level: LogLevel) -> bool {
+    level as u8 >= LEVEL.load(Ordering::Relaxed)
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::LogLevel::Debug) {
+            println!($($arg)*);
+        }
+    };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::LogLevel::Info) {
+            println!($($arg)*);
+        }
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::LogLevel::Warn) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warn_level_suppresses_debug() {
+        set_level(LogLevel::Warn);
+        let mut formatted = false;
+        log_debug!("{}", {
+            formatted = true;
+            "hidden"
+        });
+        assert!(!formatted);
+        assert!(!enabled(LogLevel::Debug));
+        assert!(!enabled(LogLevel::Info));
+        assert!(enabled(LogLevel::Warn));
+        set_level(LogLevel::Debug);
+        assert!(enabled(LogLevel::Debug));
+        set_level(LogLevel::Info);
+    }
+}
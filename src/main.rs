@@ -8,23 +8,52 @@
 //! Key controls:
 //! - Arrows/WASD: move
 //! - P: pause/resume
+//! - . (period): advance the paused manual game by one tick
 //! - R: restart
 //! - E: toggle evolutionary training
 //! - S: save best agent
 //! - +/-: adjust speed (manual vs. evolution modes differ)
 //! - H: show/hide control panel
+//! - C: toggle color-blind-safe agent palette
+//! - T: cycle the training target score (10/25/50/100/full board)
+//! - [ / ]: lower/raise the discount factor gamma by 0.01
+//! - M: toggle mixed wall modes (half wrap, half solid) for training
+//! - Z: rebuild the population from the champion (during evolution)
+//! - Y: freeze the champion: it keeps a slot unchanged while the others evolve
+//! - Q: raise every agent's epsilon by 0.1 (up to 0.9) for a burst of exploration
+//! - PageUp/PageDown: raise/lower the episode step limit by 500 (from the next epoch)
+//! - Home: toggle the automatic training speed schedule (`SpeedSchedule`)
+//! - I: show/hide an arrow from the head toward the apple (manual play)
+//! - K: let the hand-coded greedy baseline play the manual game
+//! - F: in the best-agent view, run silently until the agent eats, then show it
+//! - X: in the best-agent view, slow training to a watchable 10 steps/s
+//! - Insert: in the best-agent view, let the arrow keys steer the shown agent (it learns from it)
+//! - V: highlight the agent's 8 vision cells in the best-agent view (B)
+//! - L: toggle smooth (interpolated) snake movement (manual play)
+//! - O: show the grid coordinates and contents of the cell under the mouse (manual play)
+//! - /: print the manual game's state key with its fields decoded
+//! - Tab: next page of boards in the arena (`--arena`)
+//! - F12: save a PNG screenshot of the current frame
 //! - Esc: quit
+//! - Mouse wheel / right-drag: zoom and pan the board, with a minimap inset (manual play)
+//!
+//! Any of these can be rebound in `keybindings.toml` (see `KeyBindings`).
 //!
 //! Learning summary:
-//! - State: compact 20-bit encoding (vision of 8 cells around head + apple direction + distance bucket)
+//! - State: compact 20-bit encoding (vision of 8 cells around head + apple direction + distance bucket);
+//!   `--state extended` adds a 2-bit snake length bucket (22 bits)
 //! - Actions: turn left, go straight, turn right
 //! - Rewards: +apple, -death, small step cost, shaping for distance improvement
 //! - Evolution: elitism, mutation, and staged restarts on stagnation
 
+#[macro_use]
+mod logging;
+
 #[cfg(all(feature = "gpu-nn-experimental", feature = "gpu-nn"))]
 mod gpu_nn;
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
+use logging::LogLevel;
 #[cfg(not(feature = "gpu-render"))]
 use pixels::{Pixels, SurfaceTexture};
 use rand::Rng;
@@ -32,10 +61,12 @@ use rand::SeedableRng;
 use rand::rngs::SmallRng;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashSet, VecDeque};
+use std::collections::VecDeque;
 use std::fs;
-use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 use wgpu::{Backends, Instance, PowerPreference};
 use winit::dpi::LogicalSize;
@@ -51,14 +82,59 @@ mod gpu_render;
 mod dqn;
 #[cfg(all(feature = "dqn-gpu", feature = "dqn-gpu-cuda"))]
 use candle_core::Device as _; // bring Device type to allow Device::new_cuda (name not used)
+/// Default window and render buffer size in pixels (`--window` overrides it).
 const WIDTH: u32 = 800;
 const HEIGHT: u32 = 600;
 const GRID_SIZE: u32 = 20;
 const GRID_WIDTH: u32 = WIDTH / GRID_SIZE;
 const GRID_HEIGHT: u32 = HEIGHT / GRID_SIZE;
 
+/// An RGBA8 frame and its size in pixels; the CPU drawing helpers clip to it.
+#[cfg(not(feature = "gpu-render"))]
+struct Canvas<'a> {
+    pixels: &'a mut [u8],
+    width: u32,
+    height: u32,
+}
+
+#[cfg(not(feature = "gpu-render"))]
+impl<'a> Canvas<'a> {
+    fn new(pixels: &'a mut [u8], width: u32, height: u32) -> Self {
+        debug_assert_eq!(pixels.len(), (width * height * 4) as usize);
+        Self {
+            pixels,
+            width,
+            height,
+        }
+    }
+
+    /// Width and height in pixels.
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+#[cfg(not(feature = "gpu-render"))]
+impl std::ops::Deref for Canvas<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.pixels
+    }
+}
+
+#[cfg(not(feature = "gpu-render"))]
+impl std::ops::DerefMut for Canvas<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.pixels
+    }
+}
+
+/// Number of recently vacated cells kept in the interactive game's fading trail.
+const TRAIL_LEN: usize = 12;
+
 /// Integer grid position (cell coordinates).
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 struct Pos {
     x: i32,
     y: i32,
@@ -71,6 +147,34 @@ impl Pos {
     }
 }
 
+/// Board dimensions in cells.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct GridConfig {
+    width: u32,
+    height: u32,
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        Self {
+            width: GRID_WIDTH,
+            height: GRID_HEIGHT,
+        }
+    }
+}
+
+impl GridConfig {
+    /// Total number of cells on the board.
+    fn cells(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Whether a position lies on the board.
+    fn contains(&self, p: Pos) -> bool {
+        p.x >= 0 && p.y >= 0 && p.x < self.width as i32 && p.y < self.height as i32
+    }
+}
+
 /// Snake movement direction.
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum Dir {
@@ -88,39 +192,135 @@ enum DeathCause {
     Wall,
 }
 
+/// Where `place_apple` puts new apples.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+enum AppleSpawn {
+    /// Any empty cell (the original behavior).
+    #[default]
+    Uniform,
+    /// An empty cell within `radius` steps of the head (curriculum); uniform if none.
+    NearHead { radius: i32 },
+}
+
+/// A fixed, seeded list of apple positions handed out in order by `place_apple`.
+/// Games sharing a sequence (and moves) see the same apples whatever else draws
+/// from their RNG, e.g. random tie-breaks.
+#[derive(Clone, Debug, PartialEq)]
+struct AppleSequence {
+    cells: Vec<Pos>,
+    next: usize, // index of the next apple to hand out
+}
+
+impl AppleSequence {
+    /// `len` random cells of `grid` drawn from `seed`, none on the starting snake.
+    #[cfg_attr(feature = "gpu-render", allow(dead_code))]
+    fn generate(seed: u64, len: usize, grid: GridConfig) -> Self {
+        let start = start_body(grid);
+        let mut cells = Vec::with_capacity(len);
+        if grid.cells() > start.len() {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            while cells.len() < len {
+                let x = rng.gen_range(0..grid.width as i32);
+                let y = rng.gen_range(0..grid.height as i32);
+                let p = Pos::new(x, y);
+                if !start.contains(&p) {
+                    cells.push(p);
+                }
+            }
+        }
+        Self { cells, next: 0 }
+    }
+
+    /// Next listed cell that isn't `blocked` (blocked ones are skipped for good);
+    /// `None` once the list is used up.
+    fn next_free(&mut self, blocked: impl Fn(Pos) -> bool) -> Option<Pos> {
+        while let Some(&p) = self.cells.get(self.next) {
+            self.next += 1;
+            if !blocked(p) {
+                return Some(p);
+            }
+        }
+        None
+    }
+}
+
+/// Ring of `p` on the board: 0 for the border cells, 1 just inside them, and so on.
+fn ring_index(grid: GridConfig, p: Pos) -> u32 {
+    let (w, h) = (grid.width as i32, grid.height as i32);
+    p.x.min(p.y).min(w - 1 - p.x).min(h - 1 - p.y).max(0) as u32
+}
+
+/// Narrowest open area (cells across) shrinking leaves; it stops closing rings there.
+const SHRINK_MIN_SIDE: u32 = 4;
+
+/// The three-cell snake every game starts with: head at the center, facing right.
+fn start_body(grid: GridConfig) -> [Pos; 3] {
+    let (x, y) = ((grid.width / 2) as i32, (grid.height / 2) as i32);
+    [Pos::new(x, y), Pos::new(x - 1, y), Pos::new(x - 2, y)]
+}
+
 /// Game state: snake body, apple, direction, score and flags.
 struct Game {
     snake: VecDeque<Pos>,
-    snake_set: HashSet<Pos>,
+    snake_set: AHashSet<Pos>, // body cells; `state_key` probes it 8 times per call
     dir: Dir,
     apple: Pos,
     alive: bool,
     score: usize,
     paused: bool,
     last_death: DeathCause,
-    wrap_world: bool, // if false, walls are solid and cause death
+    won: bool,                             // snake filled the whole board
+    grid: GridConfig,                      // board size in cells
+    wrap_world: bool,                      // if false, walls are solid and cause death
+    rng: SmallRng,                         // apple placement
+    apple_spawn: AppleSpawn,               // where new apples go
+    apple_sequence: Option<AppleSequence>, // fixed apple order; overrides `apple_spawn`
+    trail: VecDeque<(Pos, u8)>, // recently vacated cells with their age in ticks, newest first
+    trail_len: usize,           // 0 disables the trail (training games)
+    prev_snake: Option<VecDeque<Pos>>, // body before the last tick, for interpolation (manual game only)
+    steps: usize,                      // moves made so far
+    growth_per_apple: usize,           // cells gained per apple (1 = classic snake)
+    pending_growth: usize,             // tail pops still to skip for earlier apples
+    shrink_interval: Option<u32>,      // close the outermost open ring every this many moves
+    closed_rings: u32,                 // outer rings turned into wall so far
+    strict_tail: bool,                 // moving into the tail's cell kills, though it moves away
+    actions_taken: [u64; 3], // training decisions per relative action, see `step_repeated`
 }
 
 impl Game {
-    /// Create a new game with a short snake centered on the grid and a random apple.
-    fn new() -> Self {
-        Self::new_with_wrap(true)
+    /// Create the interactive game (with the ghost trail): a short snake centered on
+    /// the board and a random apple.
+    fn new_manual(grid: GridConfig, wrap_world: bool) -> Self {
+        let mut game = Self::new_in(grid, wrap_world, SmallRng::from_entropy().r#gen());
+        game.trail_len = TRAIL_LEN;
+        game.prev_snake = Some(game.snake.clone());
+        game
+    }
+
+    /// Fresh manual game on the same board, wall mode, growth and shrinking.
+    fn restarted(&self) -> Self {
+        Self::new_manual(self.grid, self.wrap_world)
+            .with_growth(self.growth_per_apple)
+            .with_shrink(self.shrink_interval)
+            .with_strict_tail(self.strict_tail)
     }
 
+    #[cfg(test)]
     fn new_with_wrap(wrap_world: bool) -> Self {
-        let start_x = (GRID_WIDTH / 2) as i32;
-        let start_y = (GRID_HEIGHT / 2) as i32;
-        let mut snake = VecDeque::new();
-        let mut snake_set = HashSet::new();
-        let p0 = Pos::new(start_x, start_y);
-        let p1 = Pos::new(start_x - 1, start_y);
-        let p2 = Pos::new(start_x - 2, start_y);
-        snake.push_back(p0);
-        snake_set.insert(p0);
-        snake.push_back(p1);
-        snake_set.insert(p1);
-        snake.push_back(p2);
-        snake_set.insert(p2);
+        Self::new_seeded(wrap_world, SmallRng::from_entropy().r#gen())
+    }
+
+    /// Create a new game whose apple placement is driven by a seeded RNG.
+    #[cfg(test)]
+    fn new_seeded(wrap_world: bool, seed: u64) -> Self {
+        Self::new_in(GridConfig::default(), wrap_world, seed)
+    }
+
+    /// Create a seeded game on a board of the given size.
+    fn new_in(grid: GridConfig, wrap_world: bool, seed: u64) -> Self {
+        let body = start_body(grid);
+        let snake: VecDeque<Pos> = body.into_iter().collect();
+        let snake_set: AHashSet<Pos> = body.into_iter().collect();
 
         let mut game = Self {
             snake,
@@ -131,26 +331,215 @@ impl Game {
             paused: false,
             snake_set,
             last_death: DeathCause::None,
+            won: false,
+            grid,
             wrap_world,
+            rng: SmallRng::seed_from_u64(seed),
+            apple_spawn: AppleSpawn::Uniform,
+            apple_sequence: None,
+            trail: VecDeque::new(),
+            trail_len: 0,
+            prev_snake: None,
+            steps: 0,
+            growth_per_apple: 1,
+            pending_growth: 0,
+            shrink_interval: None,
+            closed_rings: 0,
+            strict_tail: false,
+            actions_taken: [0; 3],
         };
         game.place_apple();
         game
     }
 
+    /// Game on the default board with a given body (head first), heading and apple.
+    /// Fails unless the body is non-empty, on the board, contiguous (across the edges
+    /// when `wrap` is set) and free of overlaps, and the apple is on an empty cell.
+    #[cfg(test)]
+    fn from_layout(snake: &[Pos], dir: Dir, apple: Pos, wrap: bool) -> Result<Self, String> {
+        let grid = GridConfig::default();
+        if snake.is_empty() {
+            return Err("the snake needs at least one cell".to_string());
+        }
+        let mut snake_set = AHashSet::with_capacity(snake.len());
+        for &p in snake {
+            if !grid.contains(p) {
+                return Err(format!("({}, {}) is off the board", p.x, p.y));
+            }
+            if !snake_set.insert(p) {
+                return Err(format!("the snake overlaps itself at ({}, {})", p.x, p.y));
+            }
+        }
+        for pair in snake.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let gap = if wrap {
+                toroidal_distance(a, b, grid)
+            } else {
+                (a.x - b.x).abs() + (a.y - b.y).abs()
+            };
+            if gap != 1 {
+                return Err(format!(
+                    "({}, {}) and ({}, {}) are not adjacent",
+                    a.x, a.y, b.x, b.y
+                ));
+            }
+        }
+        if !grid.contains(apple) || snake_set.contains(&apple) {
+            return Err(format!(
+                "the apple at ({}, {}) is not on an empty cell",
+                apple.x, apple.y
+            ));
+        }
+        let mut game = Self::new_in(grid, wrap, 0);
+        game.snake = snake.iter().copied().collect();
+        game.snake_set = snake_set;
+        game.dir = dir;
+        game.apple = apple;
+        Ok(game)
+    }
+
     /// Place an apple on a random empty cell (not colliding with the snake).
+    /// If the snake covers the whole board there is no such cell: the game is won.
     fn place_apple(&mut self) {
-        let mut rng = rand::thread_rng();
+        if self.snake_set.len() >= self.open_cells() {
+            self.won = true;
+            return;
+        }
+        if let Some(p) = self.apple_sequence.as_mut().and_then(|seq| {
+            seq.next_free(|p| {
+                self.snake_set.contains(&p) || ring_index(self.grid, p) < self.closed_rings
+            })
+        }) {
+            self.apple = p;
+            return;
+        }
+        if let AppleSpawn::NearHead { radius } = self.apple_spawn {
+            let near = self.empty_cells_near_head(radius);
+            if !near.is_empty() {
+                self.apple = near[self.rng.gen_range(0..near.len())];
+                return;
+            }
+        }
         loop {
-            let x = rng.gen_range(0..GRID_WIDTH as i32);
-            let y = rng.gen_range(0..GRID_HEIGHT as i32);
+            let x = self.rng.gen_range(0..self.grid.width as i32);
+            let y = self.rng.gen_range(0..self.grid.height as i32);
             let p = Pos::new(x, y);
-            if !self.snake_set.contains(&p) {
+            if !self.snake_set.contains(&p) && !self.is_obstacle(p) {
                 self.apple = p;
                 break;
             }
         }
     }
 
+    /// Switch the spawn mode and re-place the current apple under it (`Uniform` keeps
+    /// the apple, so seeded games are unchanged).
+    fn with_apple_spawn(mut self, spawn: AppleSpawn) -> Self {
+        self.apple_spawn = spawn;
+        if spawn != AppleSpawn::Uniform {
+            self.place_apple();
+        }
+        self
+    }
+
+    /// Take apples from `seq` in order (re-placing the current one), falling back to
+    /// `apple_spawn` once it runs out.
+    #[cfg_attr(feature = "gpu-render", allow(dead_code))]
+    fn with_apple_sequence(mut self, seq: AppleSequence) -> Self {
+        self.apple_sequence = Some(seq);
+        self.place_apple();
+        self
+    }
+
+    /// Shrink the board every `interval` moves (`None` = never): see `shrink`.
+    fn with_shrink(mut self, interval: Option<u32>) -> Self {
+        self.shrink_interval = interval.filter(|&n| n > 0);
+        self
+    }
+
+    /// Treat the tail's cell as deadly even when the tail leaves it this tick (the
+    /// behavior before tail-following was allowed).
+    fn with_strict_tail(mut self, strict: bool) -> Self {
+        self.strict_tail = strict;
+        self
+    }
+
+    /// Whether the head moving into `p` this tick hits the body. The tail vacates its
+    /// cell on the same tick unless the snake grows (an apple at `p` or growth still
+    /// pending), so following it is legal unless `strict_tail` is set. A two-cell
+    /// snake's tail is its neck, and turning back into that always dies.
+    fn hits_body(&self, p: Pos) -> bool {
+        if !self.snake_set.contains(&p) {
+            return false;
+        }
+        let tail_moves = p != self.apple && self.pending_growth == 0 && self.snake.len() > 2;
+        self.strict_tail || !tail_moves || self.snake.back() != Some(&p)
+    }
+
+    /// Whether `p` lies in a ring closed by shrinking (a solid wall).
+    #[inline]
+    fn is_obstacle(&self, p: Pos) -> bool {
+        self.closed_rings > 0
+            && self.grid.contains(p)
+            && ring_index(self.grid, p) < self.closed_rings
+    }
+
+    /// Cells not yet closed by shrinking.
+    fn open_cells(&self) -> usize {
+        let (w, h) = (self.grid.width, self.grid.height);
+        let r = self.closed_rings * 2;
+        (w.saturating_sub(r) * h.saturating_sub(r)) as usize
+    }
+
+    /// Close the outermost open ring: its cells become wall, a snake touching it dies
+    /// and an apple on it moves inside. Stops once the open area is `SHRINK_MIN_SIDE`
+    /// cells across.
+    fn shrink(&mut self) {
+        let side = self.grid.width.min(self.grid.height);
+        if side < (self.closed_rings + 1) * 2 + SHRINK_MIN_SIDE {
+            return;
+        }
+        self.closed_rings += 1;
+        if self.snake.iter().any(|&p| self.is_obstacle(p)) {
+            self.last_death = DeathCause::Wall;
+            self.alive = false;
+        } else if self.is_obstacle(self.apple) {
+            self.place_apple();
+        }
+    }
+
+    /// Grow by `n` cells per apple (at least 1): the first on the tick the apple is
+    /// eaten, the rest over the following ticks.
+    fn with_growth(mut self, n: usize) -> Self {
+        self.growth_per_apple = n.max(1);
+        self
+    }
+
+    /// Empty cells at most `radius` steps from the head (wrapping if the world wraps).
+    fn empty_cells_near_head(&self, radius: i32) -> Vec<Pos> {
+        let head = *self.snake.front().unwrap();
+        let (w, h) = (self.grid.width as i32, self.grid.height as i32);
+        let mut cells = Vec::new();
+        for dy in -radius..=radius {
+            let span = radius - dy.abs();
+            for dx in -span..=span {
+                let (x, y) = (head.x + dx, head.y + dy);
+                let p = if self.wrap_world {
+                    Pos::new(x.rem_euclid(w), y.rem_euclid(h))
+                } else {
+                    Pos::new(x, y)
+                };
+                if self.grid.contains(p)
+                    && !self.is_obstacle(p)
+                    && !self.snake_set.contains(&p)
+                    && !cells.contains(&p)
+                {
+                    cells.push(p);
+                }
+            }
+        }
+        cells
+    }
+
     /// Advance the game by one tick: move the snake, handle apple/self/wall collisions.
     fn update(&mut self) {
         if !self.alive || self.paused {
@@ -159,40 +548,19 @@ impl Game {
 
         // reset death cause at the start of a tick
         self.last_death = DeathCause::None;
-
-        let head = self.snake.front().unwrap();
-        // Move head; either wrap around or collide with walls
-        let mut new_x = head.x;
-        let mut new_y = head.y;
-        match self.dir {
-            Dir::Up => new_y -= 1,
-            Dir::Down => new_y += 1,
-            Dir::Left => new_x -= 1,
-            Dir::Right => new_x += 1,
-        }
-        if self.wrap_world {
-            if new_x < 0 {
-                new_x = GRID_WIDTH as i32 - 1;
-            } else if new_x >= GRID_WIDTH as i32 {
-                new_x = 0;
-            }
-            if new_y < 0 {
-                new_y = GRID_HEIGHT as i32 - 1;
-            } else if new_y >= GRID_HEIGHT as i32 {
-                new_y = 0;
-            }
-        } else {
-            // Solid walls
-            if new_x < 0 || new_x >= GRID_WIDTH as i32 || new_y < 0 || new_y >= GRID_HEIGHT as i32 {
-                self.last_death = DeathCause::Wall;
-                self.alive = false;
-                return;
-            }
+        if let Some(prev) = self.prev_snake.as_mut() {
+            prev.clone_from(&self.snake);
         }
-        let new_head = Pos::new(new_x, new_y);
 
-        // Check collision with self (tail collision disallowed like before)
-        if self.snake_set.contains(&new_head) {
+        // Move head; either wrap around or collide with solid walls
+        let Some(new_head) = self.next_head(self.dir) else {
+            self.last_death = DeathCause::Wall;
+            self.alive = false;
+            return;
+        };
+
+        // Check collision with self; the tail's cell is free if the tail moves on
+        if self.hits_body(new_head) {
             self.last_death = DeathCause::SelfCollision;
             self.alive = false;
             return;
@@ -200,14 +568,223 @@ impl Game {
 
         self.snake.push_front(new_head);
         self.snake_set.insert(new_head);
+        self.steps += 1;
 
         // Check if apple eaten
+        let mut vacated = None;
         if new_head == self.apple {
             self.score += 1;
+            self.pending_growth += self.growth_per_apple - 1;
             self.place_apple();
+            if self.won {
+                // Board is full: end the game without a death cause
+                self.alive = false;
+            }
+        } else if self.pending_growth > 0 {
+            // Still digesting an earlier apple: keep the tail where it is
+            self.pending_growth -= 1;
+            if self.snake_set.len() >= self.open_cells() {
+                self.won = true;
+                self.alive = false;
+            }
         } else if let Some(tail) = self.snake.pop_back() {
-            self.snake_set.remove(&tail);
+            if tail != new_head {
+                self.snake_set.remove(&tail);
+            }
+            vacated = Some(tail);
+        }
+        self.age_trail(vacated);
+        if self.alive
+            && self
+                .shrink_interval
+                .is_some_and(|n| self.steps.is_multiple_of(n as usize))
+        {
+            self.shrink();
+        }
+    }
+
+    /// Cell the head moves into going `dir`, wrapped around the edges if the world wraps;
+    /// `None` when it would hit a solid wall (including rings closed by shrinking).
+    fn next_head(&self, dir: Dir) -> Option<Pos> {
+        let head = self.snake.front().unwrap();
+        let (mut new_x, mut new_y) = (head.x, head.y);
+        match dir {
+            Dir::Up => new_y -= 1,
+            Dir::Down => new_y += 1,
+            Dir::Left => new_x -= 1,
+            Dir::Right => new_x += 1,
+        }
+        let next = if self.wrap_world {
+            let (grid_w, grid_h) = (self.grid.width as i32, self.grid.height as i32);
+            Some(Pos::new(new_x.rem_euclid(grid_w), new_y.rem_euclid(grid_h)))
+        } else {
+            Some(Pos::new(new_x, new_y)).filter(|&p| self.grid.contains(p))
+        };
+        next.filter(|&p| !self.is_obstacle(p))
+    }
+
+    /// Age the ghost trail by one tick, record the freshly vacated cell and drop expired ones.
+    fn age_trail(&mut self, vacated: Option<Pos>) {
+        if self.trail_len == 0 {
+            return;
+        }
+        for (_, age) in self.trail.iter_mut() {
+            *age = age.saturating_add(1);
+        }
+        if let Some(p) = vacated {
+            self.trail.push_front((p, 0));
+        }
+        while self
+            .trail
+            .back()
+            .is_some_and(|&(_, age)| age as usize >= self.trail_len)
+        {
+            self.trail.pop_back();
+        }
+    }
+
+    /// Advance exactly one tick even while paused (debug single-stepping); the pause
+    /// state is left as it was.
+    fn step_once(&mut self) {
+        let paused = self.paused;
+        self.paused = false;
+        self.update();
+        self.paused = paused;
+    }
+
+    /// Shortest distance from `from` to the apple: across the edges when the world wraps,
+    /// plain Manhattan with solid walls.
+    fn apple_distance(&self, from: Pos) -> i32 {
+        if self.wrap_world {
+            toroidal_distance(from, self.apple, self.grid)
+        } else {
+            (self.apple.x - from.x).abs() + (self.apple.y - from.y).abs()
+        }
+    }
+
+    /// Apply a relative action (0=left, 1=straight, 2=right), advance one tick and
+    /// report what happened for reward computation.
+    fn apply_action(&mut self, a: usize) -> StepOutcome {
+        self.change_dir(dir_after_action(self.dir, a));
+        let before_score = self.score;
+        let was_alive = self.alive;
+        let head0 = *self.snake.front().unwrap();
+        let d0 = self.apple_distance(head0);
+        self.update();
+        let head1 = *self.snake.front().unwrap();
+        StepOutcome {
+            ate: self.score > before_score,
+            died: was_alive && !self.alive && !self.won,
+            death: self.last_death,
+            dist_before: d0,
+            dist_after: self.apple_distance(head1),
+            length: self.snake.len(),
+            step: self.steps,
+            open_space: None,
+            wall_adjacency: None,
+        }
+    }
+
+    /// `apply_action` plus the extra observations `rewards` asks for (e.g. open space).
+    fn apply_action_for(&mut self, a: usize, rewards: &RewardConfig) -> StepOutcome {
+        let mut outcome = self.apply_action(a);
+        if rewards.open_space && self.alive {
+            let free = self.grid.cells().saturating_sub(self.snake.len()).max(1);
+            outcome.open_space = Some(self.reachable_cells_from_head() as f32 / free as f32);
+        }
+        if rewards.wall_hugging && self.alive {
+            outcome.wall_adjacency = Some(self.wall_adjacency());
+        }
+        outcome
+    }
+
+    /// Apply an action and score it with `compute_reward`; every training path (tabular,
+    /// DQN, GPU NN) steps through here so they all see the same rewards.
+    fn step_with_reward(&mut self, a: usize, rewards: &RewardConfig) -> (StepOutcome, f32) {
+        let outcome = self.apply_action_for(a, rewards);
+        let reward = compute_reward(rewards, &outcome);
+        (outcome, reward)
+    }
+
+    /// Take action `a`, then keep going straight for `repeat - 1` more ticks (stopping
+    /// early on death or a win), summing the rewards. "Repeat" means one turn followed by
+    /// straight moves: repeating a relative turn would just spin the snake in place.
+    /// Returns the last tick's outcome with the total reward.
+    fn step_repeated(
+        &mut self,
+        a: usize,
+        repeat: usize,
+        rewards: &RewardConfig,
+    ) -> (StepOutcome, f32) {
+        if let Some(count) = self.actions_taken.get_mut(a) {
+            *count += 1;
+        }
+        let (mut outcome, mut total) = self.step_with_reward(a, rewards);
+        for _ in 1..repeat {
+            if !self.alive || self.won {
+                break;
+            }
+            let (next, reward) = self.step_with_reward(1, rewards);
+            outcome = next;
+            total += reward;
+        }
+        (outcome, total)
+    }
+
+    /// Play a fixed script of relative actions (0 = left, 1 = straight, 2 = right), one
+    /// tick each, stopping early on death or a win. Returns `(score, death cause, ticks
+    /// played)`, so tests can drive whole games without touching the snake's internals.
+    #[cfg(test)]
+    fn apply_action_sequence(&mut self, actions: &[usize]) -> (usize, DeathCause, usize) {
+        let mut steps = 0;
+        for &a in actions {
+            if !self.alive || self.won {
+                break;
+            }
+            self.apply_action(a);
+            steps += 1;
+        }
+        (self.score, self.last_death, steps)
+    }
+
+    /// Lethal walls among the head's four neighbors: board edges (solid walls only)
+    /// and rings closed by shrinking. The body doesn't count.
+    fn wall_adjacency(&self) -> u8 {
+        [Dir::Up, Dir::Down, Dir::Left, Dir::Right]
+            .into_iter()
+            .filter(|&d| self.next_head(d).is_none())
+            .count() as u8
+    }
+
+    /// Number of empty cells reachable from the head (BFS through non-body cells,
+    /// across edges when the world wraps).
+    fn reachable_cells_from_head(&self) -> usize {
+        let Some(&head) = self.snake.front() else {
+            return 0;
+        };
+        let (w, h) = (self.grid.width as i32, self.grid.height as i32);
+        let idx = |p: Pos| (p.y * w + p.x) as usize;
+        let mut seen = vec![false; self.grid.cells()];
+        seen[idx(head)] = true;
+        let mut queue = VecDeque::from([head]);
+        let mut count = 0;
+        while let Some(p) = queue.pop_front() {
+            for (dx, dy) in [(0, -1), (0, 1), (-1, 0), (1, 0)] {
+                let mut n = Pos::new(p.x + dx, p.y + dy);
+                if self.wrap_world {
+                    n = Pos::new(n.x.rem_euclid(w), n.y.rem_euclid(h));
+                } else if !self.grid.contains(n) {
+                    continue;
+                }
+                if seen[idx(n)] || self.snake_set.contains(&n) || self.is_obstacle(n) {
+                    continue;
+                }
+                seen[idx(n)] = true;
+                count += 1;
+                queue.push_back(n);
+            }
         }
+        count
     }
 
     /// Change movement direction, disallowing 180-degree turns.
@@ -224,62 +801,105 @@ impl Game {
         }
     }
 
-    /// Draw the current game state to the frame buffer (RGBA8).
+    /// Draw the part of the game inside `view` to the frame buffer (RGBA8).
+    #[cfg(not(feature = "gpu-render"))]
+    fn draw(&self, frame: &mut Canvas, view: Viewport) {
+        self.draw_at(frame, None, view);
+    }
+
+    /// Draw the game; with `Some(t)` the snake is placed a fraction `t` of the way
+    /// from its previous cells (before the last tick) to the current ones.
     #[cfg(not(feature = "gpu-render"))]
-    fn draw(&self, frame: &mut [u8]) {
+    fn draw_at(&self, frame: &mut Canvas, t: Option<f32>, view: Viewport) {
+        let cell = view.cell(self.grid);
+        let visible = view.visible(self.grid);
         // Clear screen with dark background
         clear_rgba(frame, 30, 30, 40, 255);
 
-        // Draw grid
-        for y in 0..GRID_HEIGHT {
-            for x in 0..GRID_WIDTH {
-                if (x + y) % 2 == 0 {
-                    self.draw_rect(frame, x, y, 35, 35, 50);
+        // Draw grid (parity follows board cells so the pattern pans with the view)
+        let (ox, oy) = (view.offset.x as u32, view.offset.y as u32);
+        for y in 0..visible.height {
+            for x in 0..visible.width {
+                if self.is_obstacle(Pos::new((x + ox) as i32, (y + oy) as i32)) {
+                    // Closed by shrinking: solid brick-colored wall
+                    self.draw_rect(frame, cell, x, y, 110, 60, 45);
+                } else if (x + ox + y + oy) % 2 == 0 {
+                    self.draw_rect(frame, cell, x, y, 35, 35, 50);
                 }
             }
         }
 
         // Draw apple (red)
-        fill_cell_rgb(frame, self.apple.x as u32, self.apple.y as u32, 220, 50, 50);
+        if let Some(apple) = view.local(self.apple, self.grid) {
+            fill_cell_rgb(frame, cell, apple.x as u32, apple.y as u32, 220, 50, 50);
+        }
+
+        // Ghost trail: older cells fade out
+        for &(pos, age) in &self.trail {
+            let Some(pos) = view.local(pos, self.grid) else {
+                continue;
+            };
+            let alpha = self.trail_alpha(age);
+            fill_cell_rgba(frame, cell, pos.x as u32, pos.y as u32, 50, 140, 50, alpha);
+        }
 
-        // Draw snake
-        for (i, &pos) in self.snake.iter().enumerate() {
+        // Draw snake (tail first so the head stays on top when interpolated segments overlap)
+        let prev = self.prev_snake.as_ref().filter(|_| self.alive);
+        for (i, &board_pos) in self.snake.iter().enumerate().rev() {
+            let Some(pos) = view.local(board_pos, self.grid) else {
+                continue;
+            };
+            // Pixel offset of the interpolated segment from its current cell
+            let (ox, oy) = match (t, prev) {
+                (Some(t), Some(prev)) => {
+                    let from = prev.get(i).copied().unwrap_or(board_pos);
+                    let (fx, fy) = lerp_cell(from, board_pos, t);
+                    (
+                        ((fx - board_pos.x as f32) * cell.w as f32).round() as i32,
+                        ((fy - board_pos.y as f32) * cell.h as f32).round() as i32,
+                    )
+                }
+                _ => (0, 0),
+            };
+            let x = (pos.x * cell.w as i32 + ox).max(0) as u32;
+            let y = (pos.y * cell.h as i32 + oy).max(0) as u32;
             if i == 0 {
                 // Head (bright green)
-                fill_cell_rgb(frame, pos.x as u32, pos.y as u32, 100, 255, 100);
+                fill_rect_rgba(frame, x, y, cell.w, cell.h, 100, 255, 100, 255);
                 // Draw eyes based on direction
-                self.draw_eyes(frame, &pos);
+                self.draw_eyes(frame, cell, &pos, (ox, oy));
             } else {
                 // Body (gradient green)
                 let brightness = 200 - (i * 10).min(100) as u8;
-                fill_cell_rgb(frame, pos.x as u32, pos.y as u32, 50, brightness, 50);
+                fill_rect_rgba(frame, x, y, cell.w, cell.h, 50, brightness, 50, 255);
             }
         }
 
         // Draw score
+        let (width, height) = frame.size();
         if !self.alive {
             // Game over overlay
             draw_text(
                 frame,
-                "GAME OVER",
-                WIDTH / 2 - 80,
-                HEIGHT / 2 - 20,
+                if self.won { "YOU WIN" } else { "GAME OVER" },
+                width / 2 - 80,
+                height / 2 - 20,
                 2,
                 (255, 100, 100, 255),
             );
             draw_text(
                 frame,
                 &format!("SCORE: {}", self.score),
-                WIDTH / 2 - 70,
-                HEIGHT / 2 + 20,
+                width / 2 - 70,
+                height / 2 + 20,
                 2,
                 (255, 255, 255, 255),
             );
             draw_text(
                 frame,
                 "PRESS R TO RESTART",
-                WIDTH / 2 - 130,
-                HEIGHT / 2 + 60,
+                width / 2 - 130,
+                height / 2 + 60,
                 2,
                 (200, 200, 200, 255),
             );
@@ -287,8 +907,8 @@ impl Game {
             draw_text(
                 frame,
                 "PAUSED",
-                WIDTH / 2 - 50,
-                HEIGHT / 2,
+                width / 2 - 50,
+                height / 2,
                 2,
                 (255, 255, 100, 255),
             );
@@ -298,15 +918,26 @@ impl Game {
     }
 
     /// Fill a single cell-sized rectangle at the given grid position with an RGB color.
+    #[allow(clippy::too_many_arguments)]
     #[cfg(not(feature = "gpu-render"))]
-    fn draw_rect(&self, frame: &mut [u8], grid_x: u32, grid_y: u32, r: u8, g: u8, b: u8) {
-        let x = grid_x * GRID_SIZE;
-        let y = grid_y * GRID_SIZE;
-
-        for py in y..y + GRID_SIZE {
-            for px in x..x + GRID_SIZE {
-                if px < WIDTH && py < HEIGHT {
-                    let idx = ((py * WIDTH + px) * 4) as usize;
+    fn draw_rect(
+        &self,
+        frame: &mut Canvas,
+        cell: CellSize,
+        grid_x: u32,
+        grid_y: u32,
+        r: u8,
+        g: u8,
+        b: u8,
+    ) {
+        let x = grid_x * cell.w;
+        let y = grid_y * cell.h;
+        let (width, height) = frame.size();
+
+        for py in y..y + cell.h {
+            for px in x..x + cell.w {
+                if px < width && py < height {
+                    let idx = ((py * width + px) * 4) as usize;
                     if idx + 3 < frame.len() {
                         frame[idx] = r;
                         frame[idx + 1] = g;
@@ -318,2167 +949,8922 @@ impl Game {
         }
     }
 
-    /// Draw simple black "eyes" on the snake head based on current direction.
+    /// Draw simple black "eyes" on the snake head based on current direction,
+    /// shifted by `offset` pixels when the head is drawn between cells.
     #[cfg(not(feature = "gpu-render"))]
-    fn draw_eyes(&self, frame: &mut [u8], pos: &Pos) {
-        let base_x = pos.x as u32 * GRID_SIZE;
-        let base_y = pos.y as u32 * GRID_SIZE;
-
-        let (eye1_x, eye1_y, eye2_x, eye2_y) = match self.dir {
-            Dir::Right => (base_x + 12, base_y + 5, base_x + 12, base_y + 12),
-            Dir::Left => (base_x + 5, base_y + 5, base_x + 5, base_y + 12),
-            Dir::Up => (base_x + 5, base_y + 5, base_x + 12, base_y + 5),
-            Dir::Down => (base_x + 5, base_y + 12, base_x + 12, base_y + 12),
-        };
-
-        blend_pixel(frame, eye1_x, eye1_y, 0, 0, 0, 255);
-        blend_pixel(frame, eye2_x, eye2_y, 0, 0, 0, 255);
+    fn draw_eyes(&self, frame: &mut Canvas, cell: CellSize, pos: &Pos, offset: (i32, i32)) {
+        for (x, y) in eye_positions(*pos, self.dir, cell) {
+            let (x, y) = (x as i32 + offset.0, y as i32 + offset.1);
+            if x >= 0 && y >= 0 {
+                blend_pixel(frame, x as u32, y as u32, 0, 0, 0, 255);
+            }
+        }
     }
-}
-
-// ============================
-// Simple Q-learning Agent (used inside Evolution only)
-// ============================
 
-/// Simple Q-learning agent with epsilon-greedy policy.
-#[derive(Clone, Serialize, Deserialize)]
-struct QAgent {
-    q: AHashMap<u32, [f32; 3]>,
-    epsilon: f32,
-    min_epsilon: f32,
-    decay: f32,
-    alpha: f32,
-    gamma: f32,
-    steps: u64,
-    episodes: u64,
-    #[serde(skip)]
-    color: (u8, u8, u8), // RGB цвет агента (не сохраняется)
-}
+    /// What occupies `p`, for the hover readout.
+    #[cfg(not(feature = "gpu-render"))]
+    fn cell_contents(&self, p: Pos) -> &'static str {
+        if self.snake.front() == Some(&p) {
+            "head"
+        } else if self.snake_set.contains(&p) {
+            "snake"
+        } else if self.apple == p && !self.won {
+            "apple"
+        } else if self.is_obstacle(p) {
+            "wall"
+        } else {
+            "empty"
+        }
+    }
 
-impl QAgent {
-    /// Construct a new agent with balanced hyperparameters for the 20-bit state.
-    fn new() -> Self {
-        // Сбалансированные параметры для 20-битного vision
-        // Дефолтный цвет - яркий зелёный (будет перезаписан при создании популяции)
-        Self {
-            q: AHashMap::new(),
-            epsilon: 0.25,
-            min_epsilon: 0.05,
-            decay: 0.9992,
-            alpha: 0.3,
-            gamma: 0.95,
-            steps: 0,
-            episodes: 0,
-            color: (100, 220, 100),
+    /// Draw a short yellow arrow from the head toward the apple (if the head is in `view`).
+    #[cfg(not(feature = "gpu-render"))]
+    fn draw_hint(&self, frame: &mut Canvas, view: Viewport) {
+        let Some(&head) = self.snake.front() else {
+            return;
+        };
+        let Some((x, y)) = view.to_screen(head, self.grid) else {
+            return;
+        };
+        let cell = view.cell(self.grid);
+        let (cx, cy) = ((x + cell.w / 2) as i32, (y + cell.h / 2) as i32);
+        let ((dx, dy), len) = match hint_direction(head, self.apple) {
+            Dir::Up => ((0, -1), cell.h),
+            Dir::Down => ((0, 1), cell.h),
+            Dir::Left => ((-1, 0), cell.w),
+            Dir::Right => ((1, 0), cell.w),
+        };
+        // Shaft from the head's edge, 3 px wide, then a small arrowhead
+        let (start, end) = (len as i32 / 2 + 2, len as i32 * 3 / 2);
+        for t in start..=end {
+            // Arrowhead widens toward the tip's base, shaft stays thin
+            let half = if t > end - 5 { end - t } else { 1 };
+            for w in -half..=half {
+                let (x, y) = (cx + dx * t - dy * w, cy + dy * t + dx * w);
+                if x >= 0 && y >= 0 {
+                    blend_pixel(frame, x as u32, y as u32, 255, 220, 60, 220);
+                }
+            }
         }
     }
 
-    /// Construct an agent and set its display color.
-    fn new_with_color(r: u8, g: u8, b: u8) -> Self {
-        let mut agent = Self::new();
-        agent.color = (r, g, b);
-        agent
+    /// What the board shows, for comparing two frames with `dirty_cells`.
+    #[cfg_attr(feature = "gpu-render", allow(dead_code))]
+    fn board_cells(&self) -> BoardCells {
+        BoardCells {
+            snake: self.snake.iter().copied().collect(),
+            apple: self.apple,
+            trail: self.trail.iter().map(|&(p, _)| p).collect(),
+            grid: self.grid,
+            closed_rings: self.closed_rings,
+        }
     }
 
-    /// Get or initialize the Q-values array for a state key.
-    fn get_qs(&mut self, s: u32) -> &mut [f32; 3] {
-        self.q.entry(s).or_insert([0.0, 0.0, 0.0])
+    /// Opacity of a ghost trail cell `age` ticks old: fades to 0 at `trail_len`.
+    #[cfg(not(feature = "gpu-render"))]
+    fn trail_alpha(&self, age: u8) -> u8 {
+        let left = self.trail_len.saturating_sub(age as usize);
+        (120 * left / self.trail_len.max(1)) as u8
     }
 
-    /// Select an action index {0:left, 1:straight, 2:right} using epsilon-greedy policy.
-    fn select_action<R: Rng + ?Sized>(&mut self, s: u32, rng: &mut R) -> usize {
-        if rng.r#gen::<f32>() < self.epsilon {
-            rng.gen_range(0..3)
+    /// Repaint board cell `p` the way `draw` paints it (without interpolation).
+    #[cfg(not(feature = "gpu-render"))]
+    fn redraw_cell(&self, frame: &mut Canvas, view: Viewport, p: Pos) {
+        let Some(local) = view.local(p, self.grid) else {
+            return;
+        };
+        let cell = view.cell(self.grid);
+        let (x, y) = (local.x as u32, local.y as u32);
+        if self.is_obstacle(p) {
+            self.draw_rect(frame, cell, x, y, 110, 60, 45);
+        } else if (p.x + p.y) % 2 == 0 {
+            self.draw_rect(frame, cell, x, y, 35, 35, 50);
         } else {
-            let qs = *self.get_qs(s);
-            if qs[0] >= qs[1] && qs[0] >= qs[2] {
-                0
-            } else if qs[1] >= qs[2] {
-                1
-            } else {
-                2
+            self.draw_rect(frame, cell, x, y, 30, 30, 40);
+        }
+        if p == self.apple {
+            fill_cell_rgb(frame, cell, x, y, 220, 50, 50);
+        }
+        for &(_, age) in self.trail.iter().filter(|&&(t, _)| t == p) {
+            let alpha = self.trail_alpha(age);
+            fill_cell_rgba(frame, cell, x, y, 50, 140, 50, alpha);
+        }
+        if !self.snake_set.contains(&p) {
+            return;
+        }
+        match self.snake.iter().position(|&s| s == p) {
+            Some(0) => {
+                fill_cell_rgb(frame, cell, x, y, 100, 255, 100);
+                self.draw_eyes(frame, cell, &local, (0, 0));
+            }
+            Some(i) => {
+                let brightness = 200 - (i * 10).min(100) as u8;
+                fill_cell_rgb(frame, cell, x, y, 50, brightness, 50);
+            }
+            None => {}
+        }
+    }
+}
+
+/// Snapshot of the board taken by `Game::board_cells`.
+#[derive(Clone, Debug, PartialEq)]
+struct BoardCells {
+    snake: Vec<Pos>, // head first
+    apple: Pos,
+    trail: Vec<Pos>, // ghost trail cells, newest first
+    grid: GridConfig,
+    closed_rings: u32,
+}
+
+/// Cells whose contents changed between two snapshots: snake cells vacated or newly
+/// entered, plus the old and new apple when it moved. A plain move gives the vacated
+/// tail and the new head; the head turning into body is not a change of contents.
+#[cfg_attr(feature = "gpu-render", allow(dead_code))]
+fn dirty_cells(prev: &BoardCells, next: &BoardCells) -> Vec<Pos> {
+    let before: AHashSet<Pos> = prev.snake.iter().copied().collect();
+    let after: AHashSet<Pos> = next.snake.iter().copied().collect();
+    let mut dirty: Vec<Pos> = prev
+        .snake
+        .iter()
+        .filter(|p| !after.contains(p))
+        .chain(next.snake.iter().filter(|p| !before.contains(p)))
+        .copied()
+        .collect();
+    if prev.apple != next.apple {
+        for p in [prev.apple, next.apple] {
+            if !dirty.contains(&p) {
+                dirty.push(p);
             }
         }
     }
+    dirty
+}
 
-    /// Q-learning update for (state, action, reward, next_state, done).
-    fn learn(&mut self, s: u32, a: usize, r: f32, ns: u32, done: bool) {
-        let next_max = if done {
-            0.0
-        } else {
-            let nqs = self.q.get(&ns).copied().unwrap_or([0.0; 3]);
-            nqs[0].max(nqs[1]).max(nqs[2])
+/// Tint the 8 cells the agent sees around the head: red for danger, yellow for the
+/// apple, faint white for empty (wall cells outside the board are not drawn).
+#[cfg(not(feature = "gpu-render"))]
+fn draw_vision(frame: &mut Canvas, game: &Game) {
+    if !game.alive {
+        return;
+    }
+    let cell = CellSize::for_grid(game.grid, frame.size());
+    for (pos, class) in vision_cells(game).into_iter().zip(local_vision(game)) {
+        if !game.grid.contains(pos) {
+            continue;
+        }
+        let (r, g, b, a) = match class {
+            1 => (255, 40, 40, 110),
+            2 => (255, 230, 40, 140),
+            _ => (255, 255, 255, 35),
         };
-        let alpha = self.alpha;
-        let gamma = self.gamma;
-        let qsa = self.get_qs(s);
-        let td_target = r + gamma * next_max;
-        qsa[a] = qsa[a] + alpha * (td_target - qsa[a]);
+        fill_cell_rgba(frame, cell, pos.x as u32, pos.y as u32, r, g, b, a);
     }
+}
 
-    // Reset exploration parameters for more aggressive learning
-    /// Temporarily increase exploration and learning rate (used on restarts).
-    fn boost_exploration(&mut self) {
-        self.epsilon = 0.35; // умеренное увеличение
-        self.alpha = 0.45; // умеренное ускорение обучения
+/// Draw the manual game with the snake `t` (0..=1) of the way through the current tick.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_interpolated(frame: &mut Canvas, game: &Game, t: f32, view: Viewport) {
+    game.draw_at(frame, Some(t), view);
+}
+
+/// Body segments behind the head whose shade changes as the snake moves (see `draw_at`).
+#[cfg(not(feature = "gpu-render"))]
+const SHADED_SEGMENTS: usize = 11;
+
+/// Manual-game drawing that, once a full frame is on screen, repaints only the cells
+/// `dirty_cells` reports, the ghost trail (it fades every tick), the shaded segments
+/// near the head, and whatever lies under the overlays drawn on top (panel, FPS).
+/// Anything else falls back to a full `draw`.
+#[cfg(not(feature = "gpu-render"))]
+#[derive(Default)]
+struct DirtyRedraw {
+    last: Option<(BoardCells, Viewport)>, // what the frame shows, None when unknown
+    overlays: Vec<(u32, u32, u32, u32)>,  // rects painted over the previous frame
+}
+
+#[cfg(not(feature = "gpu-render"))]
+impl DirtyRedraw {
+    /// Forget the previous frame, e.g. after the training view drew over it.
+    fn invalidate(&mut self) {
+        self.last = None;
+    }
+
+    /// Draw `game` into `frame`. `incremental` is false when the caller draws extras
+    /// over the board (hint, tooltip, minimap); `overlays` are the frame rects
+    /// `(x, y, w, h)` the caller paints on top afterwards. Returns whether only the
+    /// changed cells were repainted.
+    fn draw(
+        &mut self,
+        frame: &mut Canvas,
+        game: &Game,
+        view: Viewport,
+        incremental: bool,
+        overlays: Vec<(u32, u32, u32, u32)>,
+    ) -> bool {
+        let board = game.board_cells();
+        // The game over and pause texts sit over the board, so those frames are redrawn whole
+        let incremental = incremental && game.alive && !game.paused;
+        let drawn = match self.last.take() {
+            Some((prev, prev_view))
+                if incremental
+                    && prev_view == view
+                    && prev.grid == board.grid
+                    && prev.closed_rings == board.closed_rings =>
+            {
+                let mut cells = dirty_cells(&prev, &board);
+                cells.extend(prev.trail.iter().chain(&board.trail));
+                cells.extend(game.snake.iter().take(SHADED_SEGMENTS));
+                for &(x, y, w, h) in self.overlays.iter().chain(&overlays) {
+                    // Pixels past the board's last cell only ever get the clear color
+                    fill_rect_rgba(frame, x, y, w, h, 30, 30, 40, 255);
+                    cells.extend(view.cells_in_rect(game.grid, (x, y, w, h)));
+                }
+                for p in cells {
+                    game.redraw_cell(frame, view, p);
+                }
+                true
+            }
+            _ => {
+                game.draw(frame, view);
+                false
+            }
+        };
+        self.last = incremental.then_some((board, view));
+        self.overlays = overlays;
+        drawn
     }
 }
 
-// ============================
-// Evolutionary trainer (population of agents)
-// ============================
+/// Whole-board inset in the bottom-right corner while zoomed in: snake and apple as
+/// dots, with the visible region outlined.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_minimap(frame: &mut Canvas, game: &Game, view: Viewport) {
+    if view.scale <= 1 {
+        return;
+    }
+    let (width, height) = frame.size();
+    let grid = game.grid;
+    let dot = (160 / grid.width.max(1))
+        .min(120 / grid.height.max(1))
+        .max(1);
+    let (w, h) = (grid.width * dot, grid.height * dot);
+    let (x0, y0) = (width.saturating_sub(w + 8), height.saturating_sub(h + 8));
+    fill_rect_rgba(frame, x0, y0, w, h, 0, 0, 0, 170);
+    let at = |p: Pos| (x0 + p.x as u32 * dot, y0 + p.y as u32 * dot);
+    if !game.won {
+        let (x, y) = at(game.apple);
+        fill_rect_rgba(frame, x, y, dot, dot, 220, 50, 50, 255);
+    }
+    for (i, &p) in game.snake.iter().enumerate() {
+        let (x, y) = at(p);
+        let g = if i == 0 { 255 } else { 170 };
+        fill_rect_rgba(frame, x, y, dot, dot, 60, g, 60, 255);
+    }
+    let visible = view.visible(grid);
+    let (vx, vy) = at(view.offset);
+    stroke_rect_rgba(
+        frame,
+        vx,
+        vy,
+        visible.width * dot,
+        visible.height * dot,
+        255,
+        255,
+        255,
+        200,
+    );
+    stroke_rect_rgba(frame, x0, y0, w, h, 255, 255, 255, 60);
+}
 
-/// Evolutionary trainer managing a population of QAgents and parallel games.
-struct EvoTrainer {
-    training: bool,
-    solved: bool,
-    pop: Vec<QAgent>,
-    pop_size: usize,
-    current: usize,
-    epoch: usize,
-    epoch_best: Vec<usize>,
-    scores: Vec<usize>,
-    step_limit: u32,
-    steps_taken: u32,
-    target_score: usize,
-    best_score: usize,
-    games: Vec<Game>,                  // parallel games for each individual
-    champion: Option<QAgent>,          // best agent ever found
-    champion_score: usize,             // best score ever achieved
-    champion_epoch: usize,             // epoch when champion was found
-    epochs_without_improvement: usize, // counter for stagnation
-    restart_count: usize,              // number of restarts performed
-    wrap_world: bool,                  // whether to wrap or collide with walls
+/// Fractional cell position of a segment moving from `from` to `to` at time `t` (0..=1).
+/// Jumps of more than one cell (a wrap-around teleport) snap to `to` instead.
+#[cfg(not(feature = "gpu-render"))]
+fn lerp_cell(from: Pos, to: Pos, t: f32) -> (f32, f32) {
+    let (dx, dy) = (to.x - from.x, to.y - from.y);
+    if dx.abs() + dy.abs() > 1 {
+        return (to.x as f32, to.y as f32);
+    }
+    let t = t.clamp(0.0, 1.0);
+    (from.x as f32 + dx as f32 * t, from.y as f32 + dy as f32 * t)
 }
 
-impl EvoTrainer {
-    /// Create a trainer with `pop_size` agents and parallel games.
-    fn new(pop_size: usize) -> Self {
-        let mut pop = Vec::with_capacity(pop_size);
-        let mut games = Vec::with_capacity(pop_size);
+/// Cardinal direction from `head` toward `apple` along the dominant axis
+/// (horizontal on ties).
+#[cfg(not(feature = "gpu-render"))]
+fn hint_direction(head: Pos, apple: Pos) -> Dir {
+    let (dx, dy) = (apple.x - head.x, apple.y - head.y);
+    if dx.abs() >= dy.abs() {
+        if dx < 0 { Dir::Left } else { Dir::Right }
+    } else if dy < 0 {
+        Dir::Up
+    } else {
+        Dir::Down
+    }
+}
 
-        // Генерируем уникальные цвета для каждого агента в популяции
-        let colors = generate_population_colors(pop_size);
+/// Pixel size of one grid cell; cells stretch to fill the window, so they may be non-square.
+#[cfg(not(feature = "gpu-render"))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct CellSize {
+    w: u32,
+    h: u32,
+}
 
-        for &(r, g, b) in colors.iter().take(pop_size) {
-            pop.push(QAgent::new_with_color(r, g, b));
-            games.push(Game::new_with_wrap(true));
+#[cfg(not(feature = "gpu-render"))]
+impl CellSize {
+    /// Cell size that spreads `grid` over a whole `width`×`height` frame.
+    fn for_grid(grid: GridConfig, (width, height): (u32, u32)) -> Self {
+        Self {
+            w: (width / grid.width.max(1)).max(1),
+            h: (height / grid.height.max(1)).max(1),
         }
-        let max_apples = (GRID_WIDTH as usize * GRID_HEIGHT as usize).saturating_sub(3); // 3 is initial snake length
+    }
+}
+
+/// Largest manual-play zoom: the view then spans 1/8 of the board each way.
+#[cfg(not(feature = "gpu-render"))]
+const MAX_ZOOM: u32 = 8;
+
+/// Part of the board shown by the manual game: `1/scale` of it each way, starting at
+/// cell `offset`, stretched over a whole frame of `size` pixels.
+#[cfg(not(feature = "gpu-render"))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Viewport {
+    offset: Pos,
+    scale: u32,
+    size: (u32, u32),
+}
+
+#[cfg(not(feature = "gpu-render"))]
+impl Viewport {
+    /// The full board over a frame of `size` pixels.
+    fn full(size: (u32, u32)) -> Self {
         Self {
-            training: false,
-            solved: false,
-            pop,
-            pop_size,
-            current: 0,
-            epoch: 0,
-            epoch_best: Vec::new(),
-            scores: vec![0; pop_size],
-            step_limit: 4000,
-            steps_taken: 0,
-            target_score: max_apples,
-            best_score: 0,
-            games,
-            champion: None,
-            champion_score: 0,
-            champion_epoch: 0,
-            epochs_without_improvement: 0,
-            restart_count: 0,
-            wrap_world: true,
+            offset: Pos::new(0, 0),
+            scale: 1,
+            size,
         }
     }
 
-    /// Save the current champion (or best of population) to JSON.
-    fn save_best(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        // Save the champion if we have one, otherwise save current best
-        let agent_to_save = if let Some(ref champ) = self.champion {
-            champ
-        } else if !self.pop.is_empty() {
-            let mut idxs: Vec<usize> = (0..self.pop_size).collect();
-            idxs.sort_by_key(|&i| std::cmp::Reverse(self.scores[i]));
-            &self.pop[*idxs.first().unwrap_or(&0)]
-        } else {
-            return Ok(());
-        };
-        let json = serde_json::to_string_pretty(agent_to_save)?;
-        fs::write(path, json)?;
-        Ok(())
+    /// Visible region in cells.
+    fn visible(self, grid: GridConfig) -> GridConfig {
+        GridConfig {
+            width: (grid.width / self.scale.max(1)).max(1),
+            height: (grid.height / self.scale.max(1)).max(1),
+        }
     }
 
-    /// Load a champion agent from JSON and seed the population from it.
-    fn load_best(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        if !Path::new(path).exists() {
-            return Ok(());
+    /// Pixel size of a cell at this zoom.
+    fn cell(self, grid: GridConfig) -> CellSize {
+        CellSize::for_grid(self.visible(grid), self.size)
+    }
+
+    /// `p` relative to the visible region's corner, or None when it is off screen.
+    fn local(self, p: Pos, grid: GridConfig) -> Option<Pos> {
+        let local = Pos::new(p.x - self.offset.x, p.y - self.offset.y);
+        self.visible(grid).contains(local).then_some(local)
+    }
+
+    /// Top-left frame pixel of board cell `p`, or None when it is off screen.
+    fn to_screen(self, p: Pos, grid: GridConfig) -> Option<(u32, u32)> {
+        let local = self.local(p, grid)?;
+        let cell = self.cell(grid);
+        Some((local.x as u32 * cell.w, local.y as u32 * cell.h))
+    }
+
+    /// Board cell under frame pixel (`px`, `py`), or None outside the visible board.
+    fn to_grid(self, px: f32, py: f32, grid: GridConfig) -> Option<Pos> {
+        if px < 0.0 || py < 0.0 {
+            return None;
         }
-        let json = fs::read_to_string(path)?;
-        let agent: QAgent = serde_json::from_str(&json)?;
+        let cell = self.cell(grid);
+        let local = Pos::new((px as u32 / cell.w) as i32, (py as u32 / cell.h) as i32);
+        if !self.visible(grid).contains(local) {
+            return None;
+        }
+        let p = Pos::new(local.x + self.offset.x, local.y + self.offset.y);
+        grid.contains(p).then_some(p)
+    }
 
-        // Генерируем яркие цвета для загруженных агентов
-        let colors = generate_population_colors(self.pop_size);
+    /// Board cells at least partly inside the frame rect `(x, y, w, h)`.
+    fn cells_in_rect(self, grid: GridConfig, (x, y, w, h): (u32, u32, u32, u32)) -> Vec<Pos> {
+        let cell = self.cell(grid);
+        let visible = self.visible(grid);
+        let x_end = (x + w).div_ceil(cell.w).min(visible.width);
+        let y_end = (y + h).div_ceil(cell.h).min(visible.height);
+        (y / cell.h..y_end)
+            .flat_map(|ly| {
+                (x / cell.w..x_end)
+                    .map(move |lx| Pos::new(lx as i32 + self.offset.x, ly as i32 + self.offset.y))
+            })
+            .collect()
+    }
 
-        // Replace all agents with the loaded one + assign colors
-        for (p, &color) in self.pop.iter_mut().zip(colors.iter()).take(self.pop_size) {
-            *p = agent.clone();
-            p.color = color; // устанавливаем уникальный цвет
+    /// Same zoom with the offset moved so the view stays on the board.
+    fn clamped(self, grid: GridConfig) -> Self {
+        let scale = self.scale.clamp(1, MAX_ZOOM);
+        let visible = Self { scale, ..self }.visible(grid);
+        let max_x = grid.width.saturating_sub(visible.width) as i32;
+        let max_y = grid.height.saturating_sub(visible.height) as i32;
+        Self {
+            offset: Pos::new(self.offset.x.clamp(0, max_x), self.offset.y.clamp(0, max_y)),
+            scale,
+            ..self
         }
-        Ok(())
     }
 
-    /// Reset per-epoch counters and restart all games.
-    fn reset_epoch(&mut self) {
-        self.current = 0;
-        self.steps_taken = 0;
-        self.scores.fill(0);
-        for i in 0..self.pop_size {
-            self.games[i] = Game::new_with_wrap(self.wrap_world);
+    /// Double (`steps > 0`) or halve the zoom `steps` times, keeping `focus` at the same
+    /// place on screen as far as the board edges allow.
+    fn zoomed(self, steps: i32, focus: Pos, grid: GridConfig) -> Self {
+        let scale = if steps >= 0 {
+            self.scale << steps.min(3)
+        } else {
+            self.scale >> (-steps).min(3)
+        };
+        let next = Self {
+            scale: scale.clamp(1, MAX_ZOOM),
+            ..self
+        };
+        let (old, new) = (self.visible(grid), next.visible(grid));
+        let rel_x = (focus.x - self.offset.x) as i64 * new.width as i64 / old.width as i64;
+        let rel_y = (focus.y - self.offset.y) as i64 * new.height as i64 / old.height as i64;
+        Self {
+            offset: Pos::new(focus.x - rel_x as i32, focus.y - rel_y as i32),
+            ..next
         }
+        .clamped(grid)
     }
+}
 
-    /// Set wrapping mode and reinitialize all games with the chosen behavior.
-    fn set_wrap_world(&mut self, wrap: bool) {
-        self.wrap_world = wrap;
-        self.reset_epoch();
+/// Screen pixels of the two head "eyes": near the leading edge, at 1/4 and 3/5 of the cell
+/// (5 and 12 px on the default 20 px cell).
+#[cfg(not(feature = "gpu-render"))]
+fn eye_positions(pos: Pos, dir: Dir, cell: CellSize) -> [(u32, u32); 2] {
+    let base_x = pos.x as u32 * cell.w;
+    let base_y = pos.y as u32 * cell.h;
+    let (near_x, far_x) = (base_x + cell.w / 4, base_x + cell.w * 3 / 5);
+    let (near_y, far_y) = (base_y + cell.h / 4, base_y + cell.h * 3 / 5);
+    match dir {
+        Dir::Right => [(far_x, near_y), (far_x, far_y)],
+        Dir::Left => [(near_x, near_y), (near_x, far_y)],
+        Dir::Up => [(near_x, near_y), (far_x, near_y)],
+        Dir::Down => [(near_x, far_y), (far_x, far_y)],
     }
+}
 
-    /// Reproduce a new generation with elitism, mutation, and adaptive restarts.
-    fn reproduce<R: Rng + ?Sized>(&mut self, rng: &mut R, save_path: &str) {
-        let mut idxs: Vec<usize> = (0..self.pop_size).collect();
-        idxs.sort_by_key(|&i| std::cmp::Reverse(self.scores[i]));
-        let best_idx = *idxs.first().unwrap_or(&0);
-        let best_score = self.scores[best_idx];
-        self.epoch_best.push(best_score);
+// ============================
+// Rewards and environment API
+// ============================
 
-        self.best_score = self.best_score.max(best_score);
+/// What happened during one tick, as needed by the reward function.
+#[derive(Clone, Copy, Debug)]
+struct StepOutcome {
+    ate: bool,
+    died: bool,
+    death: DeathCause,
+    dist_before: i32,           // Manhattan distance head→apple before the tick
+    dist_after: i32,            // ... and after it
+    length: usize,              // snake length after the tick
+    step: usize,                // moves made this episode, including this one
+    open_space: Option<f32>,    // share of free cells reachable from the head, if computed
+    wall_adjacency: Option<u8>, // walls next to the head, if computed
+}
 
-        let mut new_champion = false;
-        // Update global champion if this is a new record
-        if best_score > self.champion_score {
-            self.champion_score = best_score;
-            self.champion_epoch = self.epoch;
-            self.champion = Some(self.pop[best_idx].clone());
-            self.epochs_without_improvement = 0; // reset stagnation counter
-            new_champion = true;
-            println!(
-                "🏆 NEW CHAMPION! Score: {} (Epoch {})",
-                best_score, self.epoch
-            );
+/// Reward shaping parameters shared by every training path.
+#[derive(Clone, Copy, Debug)]
+struct RewardConfig {
+    apple: f32,            // base reward for eating
+    apple_per_length: f32, // extra apple reward per body segment
+    death_self: f32,       // self-collision is the heaviest crime
+    death_wall: f32,
+    death_other: f32,
+    step_cost: f32,    // subtracted on every non-eating step
+    grace_steps: u32,  // the first moves of an episode pay no step cost
+    closer_bonus: f32, // moved towards the apple
+    farther_cost: f32, // moved away from the apple
+    near_bonus: f32,   // head within `near_radius` of the apple
+    near_radius: i32,
+    open_space: bool,       // flood-fill the board each step (expensive)
+    open_space_weight: f32, // times the reachable share of free cells
+    wall_hugging: bool,     // penalize heads next to walls (solid-wall edge crawling)
+    wall_hugging_cost: f32, // per wall cell next to the head
+    survival_bonus: f32,    // added on every non-eating step survived (0 = step cost only)
+}
 
-            // Auto-save immediately when new champion found
-            if let Err(e) = self.save_best(save_path) {
-                eprintln!("Failed to save champion: {}", e);
-            } else {
-                println!("✅ Champion saved to {}", save_path);
-            }
-        } else {
-            self.epochs_without_improvement += 1;
+impl Default for RewardConfig {
+    fn default() -> Self {
+        Self {
+            apple: 10.0,
+            apple_per_length: 0.1,
+            death_self: -30.0,
+            death_wall: -20.0,
+            death_other: -12.0,
+            step_cost: 0.005,
+            grace_steps: 0,
+            closer_bonus: 0.05,
+            farther_cost: 0.03,
+            near_bonus: 0.02,
+            near_radius: 3,
+            open_space: false,
+            open_space_weight: 0.02,
+            wall_hugging: false,
+            wall_hugging_cost: 0.01,
+            survival_bonus: 0.0,
         }
+    }
+}
 
-        let mut new_pop: Vec<QAgent> = Vec::with_capacity(self.pop_size);
+/// Compute the shaped reward for a single transition.
+fn compute_reward(cfg: &RewardConfig, o: &StepOutcome) -> f32 {
+    if o.died {
+        return match o.death {
+            DeathCause::SelfCollision => cfg.death_self,
+            DeathCause::Wall => cfg.death_wall,
+            DeathCause::None => cfg.death_other,
+        };
+    }
+    if o.ate {
+        return cfg.apple + o.length as f32 * cfg.apple_per_length;
+    }
+    let mut reward = if o.step <= cfg.grace_steps as usize {
+        0.0
+    } else {
+        -cfg.step_cost
+    };
+    reward += cfg.survival_bonus;
+    if o.dist_after < o.dist_before {
+        reward += cfg.closer_bonus;
+    } else if o.dist_after > o.dist_before {
+        reward -= cfg.farther_cost;
+    }
+    if o.dist_after <= cfg.near_radius {
+        reward += cfg.near_bonus;
+    }
+    if let Some(share) = o.open_space {
+        reward += cfg.open_space_weight * share;
+    }
+    if let Some(walls) = o.wall_adjacency {
+        reward -= cfg.wall_hugging_cost * walls as f32;
+    }
+    reward
+}
 
-        // Adaptive stagnation threshold: increase after each restart to give more time
-        let base_threshold = 1000;
-        let stagnation_threshold = base_threshold + (self.restart_count * 500);
+/// Gym-style environment around a single `Game`: reset/step with shaped rewards,
+/// independent of any rendering. Evaluation episodes (`evaluate_agent`) run through it.
+struct SnakeEnv {
+    game: Game,
+    rewards: RewardConfig,
+    grid: GridConfig,
+    wrap_world: bool,
+}
 
-        // Check for long stagnation
-        if self.epochs_without_improvement >= stagnation_threshold && self.champion.is_some() {
-            // After 5 restarts, cycle back to restart #1 but with even more aggressive exploration
-            if self.restart_count >= 5 {
-                self.restart_count = 0; // cycle back
-                println!("🔄 Max restarts reached. Cycling back with aggressive exploration...");
-            }
+impl SnakeEnv {
+    /// Create an environment; call `reset` before stepping.
+    fn new(grid: GridConfig, wrap_world: bool, rewards: RewardConfig) -> Self {
+        Self {
+            game: Game::new_in(grid, wrap_world, 0),
+            rewards,
+            grid,
+            wrap_world,
+        }
+    }
 
-            self.restart_count += 1;
-            println!(
-                "⚠️ Stagnation detected ({} epochs without improvement). Restart #{} with exploration...",
-                self.epochs_without_improvement, self.restart_count
-            );
-            self.epochs_without_improvement = 0; // reset counter
+    /// Start a new episode and return the initial state key. Its apples all come from
+    /// one seed drawn from `rng`, so they don't depend on the moves made.
+    fn reset<R: Rng + ?Sized>(&mut self, rng: &mut R) -> StateKey {
+        let seed = rng.r#gen();
+        let apples = AppleSequence::generate(seed, self.grid.cells(), self.grid);
+        self.game = Game::new_in(self.grid, self.wrap_world, seed).with_apple_sequence(apples);
+        state_key(&self.game)
+    }
 
-            // Multi-strategy restart based on restart count
-            let champion = self.champion.as_ref().unwrap();
+    /// Apply an action and return `(next_state, reward, done)`.
+    fn step(&mut self, action: usize) -> (StateKey, f32, bool) {
+        let (_, reward) = self.game.step_with_reward(action, &self.rewards);
+        (state_key(&self.game), reward, !self.game.alive)
+    }
 
-            match self.restart_count {
-                1 => {
-                    // First restart: moderate mutation + boost exploration
-                    new_pop.push(champion.clone());
-                    while new_pop.len() < self.pop_size {
-                        let mut child = champion.clone();
-                        child.boost_exploration(); // reset epsilon and alpha
-                        mutate_qagent(&mut child, rng, 0.25); // moderate mutation
-                        // Slightly mutate color for diversity
-                        child.color = mutate_color(champion.color, 20);
-                        new_pop.push(child);
-                    }
-                }
-                2 => {
-                    // Second restart: high mutation + more fresh agents + boost
-                    new_pop.push(champion.clone());
-                    for _ in 1..(self.pop_size / 2) {
-                        // changed from 2/3 to 1/2
-                        let mut child = champion.clone();
-                        child.boost_exploration();
-                        mutate_qagent(&mut child, rng, 0.4); // high mutation
-                        child.color = mutate_color(champion.color, 30);
-                        new_pop.push(child);
-                    }
-                    // Add more fresh random agents (50%) with new colors
-                    let remaining = self.pop_size - new_pop.len();
-                    let new_colors = generate_population_colors(remaining);
-                    for &color in new_colors.iter() {
-                        let mut agent = QAgent::new();
-                        agent.color = color;
-                        new_pop.push(agent);
-                    }
-                }
-                3 => {
-                    // Third restart: 30% champion, 70% fresh agents
-                    new_pop.push(champion.clone());
-                    for _ in 1..(self.pop_size * 3 / 10) {
-                        // 30%
-                        let mut child = champion.clone();
-                        child.boost_exploration();
-                        mutate_qagent(&mut child, rng, 0.35);
-                        child.color = mutate_color(champion.color, 40);
-                        new_pop.push(child);
-                    }
-                    // Add fresh random agents (70%) with new colors
-                    let remaining = self.pop_size - new_pop.len();
-                    let new_colors = generate_population_colors(remaining);
-                    for &color in new_colors.iter() {
-                        let mut agent = QAgent::new();
-                        agent.color = color;
-                        new_pop.push(agent);
-                    }
-                }
-                4 => {
-                    // Fourth restart: 20% champion + 80% fresh agents + boost
-                    new_pop.push(champion.clone());
-                    for _ in 1..(self.pop_size / 5) {
-                        // 20%
-                        let mut child = champion.clone();
-                        child.boost_exploration();
-                        mutate_qagent(&mut child, rng, 0.6); // very high mutation
-                        child.color = mutate_color(champion.color, 50);
-                        new_pop.push(child);
-                    }
-                    // Add mostly fresh random agents (80%) with new colors
-                    let remaining = self.pop_size - new_pop.len();
-                    let new_colors = generate_population_colors(remaining);
-                    for &color in new_colors.iter() {
-                        let mut agent = QAgent::new();
-                        agent.color = color;
-                        new_pop.push(agent);
-                    }
-                }
-                _ => {
-                    // Fifth restart: 10% champion + 90% fresh agents + extreme boost
-                    new_pop.push(champion.clone());
-                    for _ in 1..(self.pop_size / 10) {
-                        // 10%
-                        let mut child = champion.clone();
-                        child.boost_exploration();
-                        mutate_qagent(&mut child, rng, 0.8); // extreme mutation
-                        child.color = mutate_color(champion.color, 60);
-                        new_pop.push(child);
-                    }
-                    // Add mostly fresh random agents (90%) with new colors + boost
-                    let remaining = self.pop_size - new_pop.len();
-                    let new_colors = generate_population_colors(remaining);
-                    for &color in new_colors.iter() {
-                        let mut agent = QAgent::new();
-                        agent.boost_exploration(); // boost fresh agents too
-                        agent.color = color;
-                        new_pop.push(agent);
-                    }
-                }
+    /// Number of discrete actions (turn left, straight, turn right).
+    fn action_space(&self) -> usize {
+        3
+    }
+}
+
+// ============================
+// Simple Q-learning Agent (used inside Evolution only)
+// ============================
+
+/// How the greedy policy picks among actions with equal Q-values.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+enum TieBreak {
+    /// Lowest index wins, i.e. turning left (the original behavior).
+    #[default]
+    FirstIndex,
+    /// Going straight wins, then the lowest index; avoids needless turns.
+    PreferStraight,
+    /// Uniform among the tied actions, drawn from the agent's RNG.
+    Random,
+}
+
+/// How an agent's epsilon moves toward `min_epsilon` each time it decays
+/// (after an episode ends and on mutation).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+enum EpsilonSchedule {
+    /// Multiply by `decay` each time (the original behavior).
+    #[default]
+    Exponential,
+    /// Straight line from the starting epsilon to `min_epsilon` over `steps` decays, then hold.
+    Linear { steps: u64 },
+    /// Swing between the starting epsilon and `min_epsilon` every `period` decays,
+    /// so exploration comes back periodically.
+    Cosine { period: u64 },
+}
+
+/// Best action for a Q-row, with ties resolved by `tie`.
+fn greedy_action<R: Rng + ?Sized>(qs: [f32; 3], tie: TieBreak, rng: &mut R) -> usize {
+    let best = qs[0].max(qs[1]).max(qs[2]);
+    let is_best = |a: usize| qs[a] >= best;
+    match tie {
+        TieBreak::FirstIndex => (0..3).find(|&a| is_best(a)).unwrap_or(1),
+        TieBreak::PreferStraight => [1, 0, 2].into_iter().find(|&a| is_best(a)).unwrap_or(1),
+        TieBreak::Random => {
+            let tied: Vec<usize> = (0..3).filter(|&a| is_best(a)).collect();
+            if tied.len() > 1 {
+                tied[rng.gen_range(0..tied.len())]
+            } else {
+                tied.first().copied().unwrap_or(1)
             }
         }
-        // If we have a new champion, restart population from champion's children
-        else if new_champion && self.champion.is_some() {
-            self.restart_count = 0; // reset restart counter on new champion
-            let champion = self.champion.as_ref().unwrap();
-            // First agent is the champion itself (elitism)
-            new_pop.push(champion.clone());
-            // Rest are mutated versions of the champion with slight color variations
-            while new_pop.len() < self.pop_size {
-                let mut child = champion.clone();
-                mutate_qagent(&mut child, rng, 0.15); // moderate mutation for exploration
-                child.color = mutate_color(champion.color, 25); // slight color variation
-                new_pop.push(child);
-            }
-        } else {
-            // Normal reproduction: 3 элиты + 4 детей + 3 новых (баланс эксплуатации и исследования)
-            let top_k = 3.min(self.pop_size);
+    }
+}
 
-            // 1. Elitism: keep top 3 unchanged (30%)
-            for &idx in idxs.iter().take(top_k) {
-                new_pop.push(self.pop[idx].clone());
-            }
+/// Simple Q-learning agent with epsilon-greedy policy.
+#[derive(Clone, Serialize, Deserialize)]
+struct QAgent {
+    q: AHashMap<u32, [f32; 3]>,
+    epsilon: f32,
+    min_epsilon: f32,
+    decay: f32,
+    alpha: f32,
+    gamma: f32,
+    steps: u64,
+    episodes: u64,
+    #[serde(default)]
+    encoding: StateEncoding, // state keys this Q-table was learned with
+    #[serde(default)]
+    tie_break: TieBreak, // greedy choice among equal Q-values
+    #[serde(default)]
+    epsilon_schedule: EpsilonSchedule,
+    #[serde(default)]
+    epsilon_start: f32, // where Linear/Cosine schedules start from
+    #[serde(default)]
+    epsilon_decays: u64, // decays applied since the schedule (re)started
+    #[serde(default = "default_q_clip")]
+    q_clip: f32, // mutation keeps Q-values within [-q_clip, q_clip]
+    #[serde(default)]
+    mutate_hypers: bool, // mutation may also nudge alpha, gamma and decay
+    #[serde(default)]
+    max_states: Option<usize>, // table cap enforced by `prune_rarely_used`; None = unbounded
+    #[serde(default)]
+    visits: AHashMap<u32, u32>, // learning updates per state, only counted under a cap
+    #[serde(skip)]
+    count_visits: bool, // count `visits` without a cap too (a memory budget may prune)
+    #[serde(skip)]
+    id: u64, // unique per agent created in this run; clones (surviving elites) keep it
+    #[serde(skip)]
+    lineage: u64, // id of the founding ancestor, inherited by offspring
+    #[serde(skip)]
+    color: (u8, u8, u8), // RGB цвет агента (не сохраняется), see `lineage_color`
+}
 
-            // 2. Создаём 4 детей от элиты с мутациями и смешением цветов (40%)
-            let num_children = 4;
-            for _ in 0..num_children {
-                // Выбираем двух случайных родителей из топ-3
-                let parent1_idx = idxs[rng.gen_range(0..top_k)];
-                let parent2_idx = idxs[rng.gen_range(0..top_k)];
+/// Source of `QAgent::id`s.
+static NEXT_AGENT_ID: AtomicU64 = AtomicU64::new(1);
 
-                let mut child = self.pop[parent1_idx].clone();
+fn next_agent_id() -> u64 {
+    NEXT_AGENT_ID.fetch_add(1, Ordering::Relaxed)
+}
 
-                // Умеренная мутация Q-таблицы
-                mutate_qagent(&mut child, rng, 0.15);
+impl QAgent {
+    /// Construct a new agent, founding its own lineage, with balanced hyperparameters
+    /// for the 20-bit state.
+    fn new() -> Self {
+        // Сбалансированные параметры для 20-битного vision
+        // Дефолтный цвет - яркий зелёный (будет перезаписан при создании популяции)
+        let id = next_agent_id();
+        Self {
+            q: AHashMap::new(),
+            epsilon: 0.25,
+            min_epsilon: 0.05,
+            decay: 0.9992,
+            alpha: 0.3,
+            gamma: 0.95,
+            steps: 0,
+            episodes: 0,
+            encoding: StateEncoding::Compact,
+            tie_break: TieBreak::FirstIndex,
+            epsilon_schedule: EpsilonSchedule::Exponential,
+            epsilon_start: 0.25,
+            epsilon_decays: 0,
+            q_clip: default_q_clip(),
+            mutate_hypers: false,
+            max_states: None,
+            visits: AHashMap::new(),
+            count_visits: false,
+            id,
+            lineage: id,
+            color: (100, 220, 100),
+        }
+    }
 
-                // Смешиваем цвета родителей для визуального наследования
-                let ratio = rng.gen_range(0.3..0.7);
-                let c1 = self.pop[parent1_idx].color;
-                let c2 = self.pop[parent2_idx].color;
-                let blended = (
-                    ((c1.0 as f32 * (1.0 - ratio) + c2.0 as f32 * ratio) as u8),
-                    ((c1.1 as f32 * (1.0 - ratio) + c2.1 as f32 * ratio) as u8),
-                    ((c1.2 as f32 * (1.0 - ratio) + c2.2 as f32 * ratio) as u8),
-                );
+    /// Copy of this agent with a fresh id in the same lineage, to be mutated into a child.
+    fn offspring(&self) -> Self {
+        let mut child = self.clone();
+        child.id = next_agent_id();
+        child
+    }
 
-                // Добавляем небольшую мутацию цвета для уникальности каждого ребёнка
-                child.color = mutate_color(blended, 15);
+    /// Get or initialize the Q-values array for a state key.
+    fn get_qs(&mut self, s: StateKey) -> &mut [f32; 3] {
+        self.q.entry(s.raw()).or_insert([0.0, 0.0, 0.0])
+    }
 
-                new_pop.push(child);
-            }
+    /// Select an action index {0:left, 1:straight, 2:right} using epsilon-greedy policy.
+    fn select_action<R: Rng + ?Sized>(&mut self, s: StateKey, rng: &mut R) -> usize {
+        if rng.r#gen::<f32>() < self.epsilon {
+            rng.gen_range(0..3)
+        } else {
+            let qs = *self.get_qs(s);
+            greedy_action(qs, self.tie_break, rng)
+        }
+    }
 
-            // 3. Добавляем 3 новых случайных агента с уникальными цветами (30%)
-            let num_fresh = 3.min(self.pop_size - new_pop.len());
-            let fresh_colors = generate_population_colors(num_fresh);
+    /// Greedy action for `s` without exploring or growing the table; unseen states
+    /// look like all-zero rows. `rng` is only used by `TieBreak::Random`.
+    fn act_greedy<R: Rng + ?Sized>(&self, s: StateKey, rng: &mut R) -> usize {
+        let qs = self.q.get(&s.raw()).copied().unwrap_or([0.0; 3]);
+        greedy_action(qs, self.tie_break, rng)
+    }
 
-            for &color in fresh_colors.iter().take(num_fresh) {
-                let mut agent = QAgent::new();
-                agent.color = color;
-                new_pop.push(agent);
-            }
+    /// Every visited state with its greedy action and Q-row, sorted by state key so dumps
+    /// diff cleanly across runs. `TieBreak::Random` ties resolve to the lowest index here.
+    fn dump_policy(&self) -> Vec<(u32, usize, [f32; 3])> {
+        let tie = match self.tie_break {
+            TieBreak::Random => TieBreak::FirstIndex,
+            tie => tie,
+        };
+        let mut rng = SmallRng::seed_from_u64(0); // unused by the deterministic tie rules
+        let mut rows: Vec<(u32, usize, [f32; 3])> = self
+            .q
+            .iter()
+            .map(|(&s, &qs)| (s, greedy_action(qs, tie, &mut rng), qs))
+            .collect();
+        rows.sort_unstable_by_key(|&(s, _, _)| s);
+        rows
+    }
 
-            // 4. Дозаполняем популяцию до целевого размера
-            if new_pop.len() < self.pop_size {
-                let remaining = self.pop_size - new_pop.len();
-                let extra_colors = generate_population_colors(remaining);
-                for &color in extra_colors.iter().take(remaining) {
-                    let mut agent = QAgent::new();
-                    agent.color = color;
-                    new_pop.push(agent);
-                }
-            } else if new_pop.len() > self.pop_size {
-                new_pop.truncate(self.pop_size);
-            }
+    /// Write `dump_policy` as CSV: `state,action,q_left,q_straight,q_right`.
+    fn dump_policy_csv(&self, path: &Path) -> std::io::Result<()> {
+        let mut out = String::from("state,action,q_left,q_straight,q_right\n");
+        for (s, a, qs) in self.dump_policy() {
+            out.push_str(&format!("{},{},{},{},{}\n", s, a, qs[0], qs[1], qs[2]));
         }
+        fs::write(path, out)
+    }
 
-        self.pop = new_pop;
-        self.epoch += 1;
-        self.reset_epoch();
+    /// Q-learning update for (state, action, reward, next_state, done).
+    fn learn(&mut self, s: StateKey, a: usize, r: f32, ns: StateKey, done: bool) {
+        let next_max = if done {
+            0.0
+        } else {
+            let nqs = self.q.get(&ns.raw()).copied().unwrap_or([0.0; 3]);
+            nqs[0].max(nqs[1]).max(nqs[2])
+        };
+        let alpha = self.alpha;
+        let gamma = self.gamma;
+        let qsa = self.get_qs(s);
+        let td_target = r + gamma * next_max;
+        qsa[a] = qsa[a] + alpha * (td_target - qsa[a]);
+        if self.max_states.is_some() || self.count_visits {
+            *self.visits.entry(s.raw()).or_insert(0) += 1;
+        }
     }
-}
 
-/// Mutate Q-values and decay epsilon slightly; `sigma` controls noise magnitude.
-fn mutate_qagent<R: Rng + ?Sized>(agent: &mut QAgent, rng: &mut R, sigma: f32) {
-    for arr in agent.q.values_mut() {
-        for v in arr.iter_mut() {
-            *v += rng.gen_range(-sigma..sigma);
+    /// Number of states in the Q-table.
+    fn table_size(&self) -> usize {
+        self.q.len()
+    }
+
+    /// Rough heap size of the Q-table and visit counts: one entry plus a control byte
+    /// per allocated bucket.
+    fn table_bytes(&self) -> usize {
+        self.q.capacity() * (std::mem::size_of::<(u32, [f32; 3])>() + 1)
+            + self.visits.capacity() * (std::mem::size_of::<(u32, u32)>() + 1)
+    }
+
+    /// Drop all but the `keep` most-visited states (ties keep the lower key), releasing
+    /// the freed memory. States never learned from count as unvisited.
+    fn prune_rarely_used(&mut self, keep: usize) {
+        if self.q.len() <= keep {
+            return;
+        }
+        let mut ranked: Vec<(u32, u32)> = self
+            .q
+            .keys()
+            .map(|&s| (s, self.visits.get(&s).copied().unwrap_or(0)))
+            .collect();
+        ranked.select_nth_unstable_by(keep, |a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (s, _) in &ranked[keep..] {
+            self.q.remove(s);
+            self.visits.remove(s);
         }
+        self.q.shrink_to_fit();
+        self.visits.shrink_to_fit();
     }
-    agent.epsilon = (agent.epsilon * agent.decay).max(agent.min_epsilon);
-}
 
-/// Rotate direction 90° left.
-fn left_dir(d: Dir) -> Dir {
-    match d {
-        Dir::Up => Dir::Left,
-        Dir::Left => Dir::Down,
-        Dir::Down => Dir::Right,
-        Dir::Right => Dir::Up,
+    /// Move epsilon one step along the agent's schedule.
+    fn decay_epsilon(&mut self) {
+        self.epsilon_decays += 1;
+        let (start, min) = (self.epsilon_start, self.min_epsilon);
+        self.epsilon = match self.epsilon_schedule {
+            EpsilonSchedule::Exponential => (self.epsilon * self.decay).max(min),
+            EpsilonSchedule::Linear { steps } if self.epsilon_decays >= steps => min,
+            EpsilonSchedule::Linear { steps } => {
+                start + (min - start) * (self.epsilon_decays as f32 / steps as f32)
+            }
+            EpsilonSchedule::Cosine { period } => {
+                let phase = (self.epsilon_decays % period.max(1)) as f32 / period.max(1) as f32;
+                min + (start - min) * 0.5 * (1.0 + (std::f32::consts::TAU * phase).cos())
+            }
+        };
     }
-}
-/// Rotate direction 90° right.
-fn right_dir(d: Dir) -> Dir {
-    match d {
-        Dir::Up => Dir::Right,
-        Dir::Right => Dir::Down,
-        Dir::Down => Dir::Left,
-        Dir::Left => Dir::Up,
+
+    /// Switch to `schedule`, restarting it from the current epsilon if it changed.
+    fn set_epsilon_schedule(&mut self, schedule: EpsilonSchedule) {
+        if self.epsilon_schedule != schedule {
+            self.epsilon_schedule = schedule;
+            self.epsilon_start = self.epsilon;
+            self.epsilon_decays = 0;
+        }
     }
-}
-/// Apply an action index to a direction: 0=left, 1=straight, 2=right.
-fn dir_after_action(d: Dir, a: usize) -> Dir {
-    match a {
-        0 => left_dir(d),
-        1 => d,
-        _ => right_dir(d),
+
+    /// Raise epsilon by `by`, up to `EPSILON_BUMP_MAX` (never lowering it), and
+    /// restart the decay schedule from there.
+    fn bump_epsilon(&mut self, by: f32) {
+        self.epsilon = (self.epsilon + by).min(EPSILON_BUMP_MAX).max(self.epsilon);
+        self.epsilon_start = self.epsilon;
+        self.epsilon_decays = 0;
     }
-}
 
-// Генерирует разнообразные цвета для популяции
-/// Generate distinct RGB colors for a population using HSL hue sampling.
-fn generate_population_colors(pop_size: usize) -> Vec<(u8, u8, u8)> {
-    let mut colors = Vec::with_capacity(pop_size);
-    for i in 0..pop_size {
-        let hue = (i as f32 / pop_size as f32) * 360.0;
-        let (r, g, b) = hsl_to_rgb(hue, 0.85, 0.65); // увеличена насыщенность и яркость
-        colors.push((r, g, b));
+    // Reset exploration parameters for more aggressive learning
+    /// Temporarily increase exploration and learning rate (used on restarts).
+    fn boost_exploration(&mut self) {
+        self.epsilon = 0.35; // умеренное увеличение
+        self.alpha = 0.45; // умеренное ускорение обучения
+        self.epsilon_start = self.epsilon;
+        self.epsilon_decays = 0;
     }
-    colors
 }
 
-// Конвертирует HSL в RGB
-/// Convert HSL to RGB (0..=255 per channel).
-fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (u8, u8, u8) {
-    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
-    let h_prime = h / 60.0;
-    let x = c * (1.0 - ((h_prime % 2.0) - 1.0).abs());
-    let (r1, g1, b1) = match h_prime as i32 {
-        0 => (c, x, 0.0),
-        1 => (x, c, 0.0),
-        2 => (0.0, c, x),
-        3 => (0.0, x, c),
-        4 => (x, 0.0, c),
-        5 => (c, 0.0, x),
-        _ => (c, x, 0.0),
-    };
-    let m = l - c / 2.0;
-    (
-        ((r1 + m) * 255.0) as u8,
-        ((g1 + m) * 255.0) as u8,
-        ((b1 + m) * 255.0) as u8,
-    )
-}
+// ============================
+// Evolutionary trainer (population of agents)
+// ============================
+
+/// Epochs per one-cell widening of the apple curriculum radius.
+const CURRICULUM_EPOCHS_PER_CELL: usize = 10;
+
+/// Epsilon added to every agent per Q press (a manual exploration boost).
+const EPSILON_BUMP: f32 = 0.1;
+/// Ceiling for `QAgent::bump_epsilon`.
+const EPSILON_BUMP_MAX: f32 = 0.9;
 
-// Мутирует цвет с небольшим изменением
-/// Slightly mutate an RGB color by ±`range` per channel (clamped to 0..255).
-fn mutate_color(color: (u8, u8, u8), range: i32) -> (u8, u8, u8) {
-    let mut rng: SmallRng = SmallRng::from_entropy();
-    let r = (color.0 as i32 + rng.gen_range(-range..=range)).clamp(0, 255) as u8;
-    let g = (color.1 as i32 + rng.gen_range(-range..=range)).clamp(0, 255) as u8;
-    let b = (color.2 as i32 + rng.gen_range(-range..=range)).clamp(0, 255) as u8;
-    (r, g, b)
+/// Change in the episode step limit per PageUp/PageDown press.
+const STEP_LIMIT_STEP: u32 = 500;
+/// Range the step limit is clamped to.
+const STEP_LIMIT_RANGE: std::ops::RangeInclusive<u32> = 500..=50_000;
+
+/// One logged transition: (state key, action, reward, next state key, done).
+type TransitionRecord = (u32, u8, f32, u32, u8);
+
+/// Bytes per record in a transition log.
+const TRANSITION_RECORD_BYTES: usize = 14;
+
+/// Appends transitions to a binary file as fixed-width little-endian records
+/// (u32 state, u8 action, f32 reward, u32 next state, u8 done). States are raw
+/// tabular keys in the trainer's encoding, whichever path chose the action.
+struct TransitionLogger {
+    out: BufWriter<fs::File>,
 }
 
-/// Build a compact 20-bit state key from the game: 16 bits of local vision, 2 bits
-/// of relative direction to the apple, and 2 bits of distance bucket.
-fn state_key(game: &Game) -> u32 {
-    // Компактный vision-based подход БЕЗ хэширования
-    // Смотрим только на критически важные клетки вокруг головы (3x3 впереди)
-    // Итого: 16 бит для vision + 4 бита для контекста = 20 бит (~1M состояний)
+impl TransitionLogger {
+    /// Open `path` for appending, creating it if needed.
+    fn create(path: &Path) -> std::io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            out: BufWriter::new(file),
+        })
+    }
 
-    let head = game.snake.front().unwrap();
-    let mut k: u32 = 0;
+    fn log(&mut self, (s, a, r, ns, done): TransitionRecord) -> std::io::Result<()> {
+        let mut rec = [0u8; TRANSITION_RECORD_BYTES];
+        rec[0..4].copy_from_slice(&s.to_le_bytes());
+        rec[4] = a;
+        rec[5..9].copy_from_slice(&r.to_le_bytes());
+        rec[9..13].copy_from_slice(&ns.to_le_bytes());
+        rec[13] = done;
+        self.out.write_all(&rec)
+    }
 
-    // Получаем 8 клеток вокруг головы относительно направления движения
-    // Кодируем каждую клетку 2 битами: 00=пусто, 01=опасность(стена/тело), 10=яблоко, 11=unused
-    let checks = [
-        (-1, -1),
-        (0, -1),
-        (1, -1), // left-ahead, ahead, right-ahead (приоритетные)
-        (-1, 0),
-        (1, 0), // left, right
-        (-1, 1),
-        (0, 1),
-        (1, 1), // left-behind, behind, right-behind
-    ];
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
 
-    let mut bit_pos = 0;
-    for (dx, dy) in &checks {
-        // Преобразуем относительные координаты в зависимости от направления
-        let (world_dx, world_dy) = match game.dir {
-            Dir::Right => (*dy, *dx),
-            Dir::Left => (-*dy, -*dx),
-            Dir::Up => (*dx, -*dy),
-            Dir::Down => (-*dx, *dy),
+    /// Log `records` into `slot` if a log is open there; a write error closes it.
+    fn append_to(slot: &mut Option<Self>, records: impl IntoIterator<Item = TransitionRecord>) {
+        let Some(log) = slot.as_mut() else {
+            return;
         };
+        if let Err(e) = records.into_iter().try_for_each(|rec| log.log(rec)) {
+            log_warn!("[log] transition logging stopped: {}", e);
+            *slot = None;
+        }
+    }
+}
 
-        let check_x = head.x + world_dx;
-        let check_y = head.y + world_dy;
+/// Read every complete record of a transition log (a torn trailing record is ignored).
+/// The app itself only writes logs; the tests read them back.
+#[cfg(test)]
+fn read_transitions(path: &Path) -> std::io::Result<Vec<TransitionRecord>> {
+    let bytes = fs::read(path)?;
+    let le_u32 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    Ok(bytes
+        .chunks_exact(TRANSITION_RECORD_BYTES)
+        .map(|rec| {
+            let r = f32::from_bits(le_u32(&rec[5..9]));
+            (le_u32(&rec[0..4]), rec[4], r, le_u32(&rec[9..13]), rec[13])
+        })
+        .collect())
+}
 
-        let cell_value = if check_x < 0
-            || check_x >= GRID_WIDTH as i32
-            || check_y < 0
-            || check_y >= GRID_HEIGHT as i32
-        {
-            1 // стена/граница = опасность
-        } else {
-            let pos = Pos::new(check_x, check_y);
-            if game.snake_set.contains(&pos) {
-                1 // тело змеи = опасность
-            } else if pos == game.apple {
-                2 // яблоко
-            } else {
-                0 // пусто
-            }
-        };
+/// Epochs averaged for the panel's epoch time.
+#[cfg_attr(feature = "gpu-render", allow(dead_code))]
+const EPOCH_TIME_WINDOW: usize = 10;
 
-        k |= (cell_value as u32) << bit_pos;
-        bit_pos += 2;
+/// Wall-clock time of the running epoch, not counting stretches between `pause` and `resume`.
+#[derive(Clone, Copy, Debug)]
+struct EpochTimer {
+    running_since: Option<Instant>, // None while paused
+    banked: Duration,               // time from earlier running stretches
+}
+
+impl EpochTimer {
+    fn start(now: Instant) -> Self {
+        Self {
+            running_since: Some(now),
+            banked: Duration::ZERO,
+        }
     }
 
-    // Биты 16-17: направление к яблоку (left/straight/right относительно текущего направления)
-    let apple_dx = game.apple.x - head.x;
-    let apple_dy = game.apple.y - head.y;
-    let apple_dir = match game.dir {
-        Dir::Right => {
-            if apple_dy < -1 {
-                0
-            }
-            // left
-            else if apple_dy > 1 {
-                2
-            }
-            // right
-            else {
-                1
-            } // straight-ish
+    fn pause(&mut self, now: Instant) {
+        if let Some(since) = self.running_since.take() {
+            self.banked += now.saturating_duration_since(since);
         }
-        Dir::Left => {
-            if apple_dy > 1 {
-                0
-            }
-            // left
-            else if apple_dy < -1 {
-                2
-            }
-            // right
-            else {
-                1
-            } // straight-ish
+    }
+
+    fn resume(&mut self, now: Instant) {
+        self.running_since.get_or_insert(now);
+    }
+
+    fn elapsed(&self, now: Instant) -> Duration {
+        self.banked
+            + self
+                .running_since
+                .map_or(Duration::ZERO, |since| now.saturating_duration_since(since))
+    }
+}
+
+/// Point-in-time view of the trainer for the panel, logs and external tools; see
+/// `EvoTrainer::snapshot`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[cfg_attr(feature = "gpu-render", allow(dead_code))]
+struct TrainingSnapshot {
+    epoch: usize,
+    best_score: usize,
+    champion_score: usize,
+    champion_epoch: usize,
+    epochs_without_improvement: usize,
+    restart_count: usize,
+    stagnation_threshold: usize, // epochs without improvement that trigger a restart
+    alive_count: usize,
+    mean_score: f32, // this epoch's scores across the population
+    max_score: usize,
+    min_score: usize,
+    diversity: f32, // standard deviation of this epoch's scores
+}
+
+/// Evolutionary trainer managing a population of QAgents and parallel games.
+struct EvoTrainer {
+    training: bool,
+    solved: bool,
+    pop: Vec<QAgent>,
+    pop_size: usize,
+    current: usize,
+    epoch: usize,
+    epoch_best: Vec<usize>,
+    epoch_ms: Vec<u64>, // wall-clock length of each finished epoch, pauses excluded
+    epoch_states: Vec<usize>, // champion's Q-table size at the end of each epoch
+    epoch_timer: EpochTimer, // times the running epoch
+    scores: Vec<usize>,
+    episode_rewards: Vec<f32>, // shaped reward collected by each agent this epoch
+    step_limit: u32,
+    next_step_limit: u32, // becomes `step_limit` at the next epoch, see `set_step_limit`
+    steps_taken: u32,
+    target_score: usize,
+    best_score: usize,
+    games: Vec<Game>,         // parallel games for each individual
+    champion: Option<QAgent>, // best agent ever found
+    champion_score: usize,    // best score ever achieved
+    champion_epoch: usize,    // epoch when champion was found
+    champion_efficient: Option<EfficientChampion>, // best apples per step, see `efficient_min_score`
+    efficient_min_score: usize, // score an epoch needs to compete for `champion_efficient`
+    epochs_without_improvement: usize, // counter for stagnation
+    restart_count: usize,       // number of restarts performed
+    stagnation_base: usize,     // epochs before the first restart, see `stagnation_threshold`
+    wrap_modes: Vec<bool>,      // per-game wall mode: true = wrap, false = solid walls
+    curriculum: Option<i32>,    // starting apple radius; widens as epochs pass
+    grid: GridConfig,           // board size for every game
+    fitness_mode: FitnessMode,  // how agents are ranked for selection
+    gamma: f32,                 // discount factor given to every agent
+    elite_fraction: f32,        // share of the population carried over unchanged
+    encoding: StateEncoding,    // state key layout for every agent
+    tie_break: TieBreak,        // greedy tie rule for every agent
+    epsilon_schedule: EpsilonSchedule, // exploration decay shape for every agent
+    parent_selection: ParentSelection, // how children pick parents among the elites
+    death_stats: DeathStats,    // how episodes ended, over the whole run
+    pool: Option<rayon::ThreadPool>, // --threads: private pool, else rayon's global one
+    game_rng: SmallRng,         // seeds each game's apples and exploration
+    palette: Palette,           // color scheme for agent colors
+    rewards: RewardConfig,      // reward shaping used by all training paths
+    action_repeat: usize,       // ticks per decision, see `Game::step_repeated`
+    mutate_hypers: bool,        // let mutation evolve each agent's alpha, gamma and decay
+    max_states: Option<usize>,  // per-agent Q-table cap, pruned every epoch
+    max_qmem: Option<usize>,    // population Q-table budget in bytes, see `enforce_qmem_budget`
+    growth: usize,              // cells each game's snake gains per apple
+    shrink_interval: Option<u32>, // games close their outer ring every this many moves
+    strict_tail: bool,          // games treat the departing tail's cell as deadly
+    freeze_champion: bool,      // keep the champion unchanged in slot 0, see `reproduce`
+    champion_frozen: bool,      // slot 0 currently holds the frozen champion
+    human_action: Option<(usize, usize)>, // (game, action) forced on the next tick, see `override_best`
+    transition_log: Option<TransitionLogger>, // --log-transitions
+}
+
+impl EvoTrainer {
+    /// Create a trainer with `pop_size` agents and parallel games. `target_score`
+    /// defaults to filling the whole board.
+    fn new(pop_size: usize, target_score: Option<usize>) -> Self {
+        let mut pop = Vec::with_capacity(pop_size);
+        let mut games = Vec::with_capacity(pop_size);
+        let mut game_rng = SmallRng::from_entropy();
+        let grid = GridConfig::default();
+
+        for _ in 0..pop_size {
+            let mut agent = QAgent::new();
+            agent.color = lineage_color(agent.id, agent.lineage, Palette::Hsl);
+            pop.push(agent);
+            games.push(Game::new_in(grid, true, game_rng.r#gen()));
         }
-        Dir::Up => {
-            if apple_dx < -1 {
-                0
+        Self {
+            training: false,
+            solved: false,
+            pop,
+            pop_size,
+            current: 0,
+            epoch: 0,
+            epoch_best: Vec::new(),
+            epoch_ms: Vec::new(),
+            epoch_states: Vec::new(),
+            epoch_timer: EpochTimer::start(Instant::now()),
+            scores: vec![0; pop_size],
+            episode_rewards: vec![0.0; pop_size],
+            step_limit: 4000,
+            next_step_limit: 4000,
+            steps_taken: 0,
+            target_score: target_score
+                .unwrap_or(default_target_score(grid, 1))
+                .clamp(1, default_target_score(grid, 1)),
+            best_score: 0,
+            games,
+            champion: None,
+            champion_score: 0,
+            champion_epoch: 0,
+            champion_efficient: None,
+            efficient_min_score: 5,
+            epochs_without_improvement: 0,
+            restart_count: 0,
+            stagnation_base: 1000,
+            wrap_modes: vec![true; pop_size],
+            curriculum: None,
+            grid,
+            game_rng,
+            fitness_mode: FitnessMode::Score,
+            gamma: QAgent::new().gamma,
+            elite_fraction: 0.125,
+            encoding: StateEncoding::Compact,
+            tie_break: TieBreak::FirstIndex,
+            epsilon_schedule: EpsilonSchedule::Exponential,
+            parent_selection: ParentSelection::Uniform,
+            death_stats: DeathStats::default(),
+            pool: None,
+            palette: Palette::Hsl,
+            rewards: RewardConfig::default(),
+            action_repeat: 1,
+            mutate_hypers: false,
+            max_states: None,
+            max_qmem: None,
+            growth: 1,
+            shrink_interval: None,
+            strict_tail: false,
+            freeze_champion: false,
+            champion_frozen: false,
+            human_action: None,
+            transition_log: None,
+        }
+    }
+
+    /// Save the current champion (or best of population) to JSON.
+    fn save_best(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        // Save the champion if we have one, otherwise save current best
+        let agent_to_save = if let Some(ref champ) = self.champion {
+            champ
+        } else if !self.pop.is_empty() {
+            &self.pop[*self.ranked_indices().first().unwrap_or(&0)]
+        } else {
+            return Ok(());
+        };
+        let json = serde_json::to_string_pretty(agent_to_save)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Save the efficient champion (if any) to JSON, in the same format as `save_best`.
+    fn save_efficient(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(c) = self.champion_efficient.as_ref() {
+            fs::write(path, serde_json::to_string_pretty(&c.agent)?)?;
+        }
+        Ok(())
+    }
+
+    /// Load a champion agent from JSON and seed the population from it.
+    fn load_best(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if !Path::new(path).exists() {
+            return Ok(());
+        }
+        let json = fs::read_to_string(path)?;
+        let agent: QAgent = serde_json::from_str(&json)?;
+
+        // Replace all agents with the loaded one, as one lineage founded by slot 0
+        let mut founder = agent.clone();
+        founder.id = next_agent_id();
+        founder.lineage = founder.id;
+        for (i, p) in self.pop.iter_mut().enumerate() {
+            *p = if i == 0 {
+                founder.clone()
+            } else {
+                founder.offspring()
+            };
+            p.color = lineage_color(p.id, p.lineage, self.palette);
+        }
+        self.gamma = agent.gamma;
+        self.encoding = agent.encoding;
+        self.tie_break = agent.tie_break;
+        self.epsilon_schedule = agent.epsilon_schedule;
+        Ok(())
+    }
+
+    /// Add how each game of the finished epoch ended to `death_stats`. Every action
+    /// path (tabular, DQN, NN) ends its epoch through `reproduce`, which calls this.
+    fn record_epoch_deaths(&mut self) {
+        for (g, &score) in self.games.iter().zip(&self.scores) {
+            if g.alive {
+                // Agents that already hit the target were done, not cut off
+                if score < self.target_score {
+                    self.death_stats.timeout += 1;
+                }
+            } else {
+                match g.last_death {
+                    DeathCause::Wall => self.death_stats.wall += 1,
+                    DeathCause::SelfCollision => self.death_stats.self_collision += 1,
+                    DeathCause::None => {} // board filled: a win, not a death
+                }
             }
-            // left
-            else if apple_dx > 1 {
-                2
+        }
+    }
+
+    /// Shares of (wall, self-collision, timeout) episode endings so far.
+    fn death_ratios(&self) -> (f32, f32, f32) {
+        self.death_stats.ratios()
+    }
+
+    /// Reset per-epoch counters and restart all games.
+    fn reset_epoch(&mut self) {
+        self.current = 0;
+        self.steps_taken = 0;
+        self.step_limit = self.next_step_limit;
+        self.epoch_timer = EpochTimer::start(Instant::now());
+        self.scores.fill(0);
+        self.episode_rewards = vec![0.0; self.pop_size];
+        for i in 0..self.pop_size {
+            let wrap = self.wrap_modes.get(i).copied().unwrap_or(true);
+            self.games[i] = Game::new_in(self.grid, wrap, self.game_rng.r#gen())
+                .with_apple_spawn(self.apple_spawn())
+                .with_growth(self.growth)
+                .with_shrink(self.shrink_interval)
+                .with_strict_tail(self.strict_tail);
+        }
+    }
+
+    /// Apple spawn mode for this epoch's games: with a curriculum, apples start within
+    /// its radius of the head and the radius widens by one every
+    /// `CURRICULUM_EPOCHS_PER_CELL` epochs until it spans the board (then uniform).
+    fn apple_spawn(&self) -> AppleSpawn {
+        let Some(start) = self.curriculum else {
+            return AppleSpawn::Uniform;
+        };
+        let radius = start.saturating_add((self.epoch / CURRICULUM_EPOCHS_PER_CELL) as i32);
+        if radius >= (self.grid.width + self.grid.height) as i32 {
+            AppleSpawn::Uniform
+        } else {
+            AppleSpawn::NearHead { radius }
+        }
+    }
+
+    /// Build a trainer from the run configuration (population, board, walls, seed).
+    fn from_config(cfg: &Config) -> Self {
+        let mut evo = Self::new(cfg.pop_size, None);
+        evo.grid = cfg.grid;
+        evo.growth = cfg.growth;
+        evo.shrink_interval = cfg.shrink;
+        evo.strict_tail = cfg.strict_tail;
+        evo.stagnation_base = cfg.stagnation_base;
+        evo.target_score = default_target_score(cfg.grid, cfg.growth);
+        if let Some(target) = cfg.target_score {
+            evo.set_target_score(target);
+        }
+        evo.wrap_modes = vec![cfg.wrap_world; cfg.pop_size];
+        evo.fitness_mode = cfg.fitness;
+        if let Some(agent) = cfg.agent {
+            for a in evo.pop.iter_mut() {
+                agent.apply(a);
             }
-            // right
-            else {
-                1
-            } // straight-ish
+            evo.set_gamma(agent.gamma);
         }
-        Dir::Down => {
-            if apple_dx > 1 {
-                0
+        if let Some(gamma) = cfg.gamma {
+            evo.set_gamma(gamma);
+        }
+        evo.encoding = cfg.encoding;
+        evo.tie_break = cfg.tie_break;
+        evo.epsilon_schedule = cfg.epsilon_schedule;
+        evo.action_repeat = cfg.action_repeat;
+        evo.mutate_hypers = cfg.mutate_hypers;
+        evo.max_states = cfg.max_states;
+        evo.max_qmem = cfg.max_qmem_mb.map(|mb| mb.saturating_mul(1024 * 1024));
+        evo.parent_selection = cfg.selection;
+        if cfg.curriculum.is_some() {
+            evo.curriculum = cfg.curriculum;
+            evo.reset_epoch();
+        }
+        for a in evo.pop.iter_mut() {
+            a.encoding = cfg.encoding;
+            a.tie_break = cfg.tie_break;
+            a.mutate_hypers = cfg.mutate_hypers;
+            a.max_states = cfg.max_states;
+            a.count_visits = evo.max_qmem.is_some();
+            a.set_epsilon_schedule(cfg.epsilon_schedule);
+        }
+        if let Some(seed) = cfg.seed {
+            evo.game_rng = SmallRng::seed_from_u64(seed);
+        }
+        if let Some(path) = &cfg.log_transitions {
+            match TransitionLogger::create(path) {
+                Ok(log) => evo.transition_log = Some(log),
+                Err(e) => log_warn!("[log] not logging transitions to {}: {}", path.display(), e),
             }
-            // left
-            else if apple_dx < -1 {
-                2
+        }
+        if let Some(threads) = cfg.threads {
+            match rayon::ThreadPoolBuilder::new().num_threads(threads).build() {
+                Ok(pool) => evo.pool = Some(pool),
+                Err(e) => log_warn!("[threads] using the default pool: {}", e),
             }
-            // right
-            else {
-                1
-            } // straight-ish
         }
-    };
-    k |= apple_dir << 16;
-
-    // Биты 18-19: дистанция до яблока (4 категории)
-    let dist = apple_dx.abs() + apple_dy.abs();
-    let dist_cat = if dist <= 3 {
-        0
-    } else if dist <= 8 {
-        1
-    } else if dist <= 16 {
-        2
-    } else {
-        3
-    };
-    k |= dist_cat << 18;
+        evo.reset_epoch();
+        evo
+    }
 
-    k
-}
+    /// Set wrapping mode for all games and reinitialize them with the chosen behavior.
+    fn set_wrap_world(&mut self, wrap: bool) {
+        self.wrap_modes = vec![wrap; self.pop_size];
+        self.reset_epoch();
+    }
 
-/// Entry point: sets up the window, renderer, input loop, and optionally runs
-/// evolutionary training.
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    #[cfg(feature = "gpu-nn")]
-    {
-        println!("[mode] GPU NN feature enabled (scaffold)");
+    /// Curriculum: even games wrap around, odd games have solid walls.
+    fn set_mixed_walls(&mut self) {
+        self.wrap_modes = (0..self.pop_size).map(|i| i % 2 == 0).collect();
+        self.reset_epoch();
     }
-    #[cfg(feature = "gpu-nn-experimental")]
-    {
-        println!("[mode] GPU NN experimental backend enabled");
+
+    /// Append transitions to the log, if one is open.
+    #[cfg_attr(
+        not(any(feature = "dqn-gpu", feature = "gpu-nn-experimental")),
+        allow(dead_code)
+    )]
+    fn log_transitions(&mut self, records: impl IntoIterator<Item = TransitionRecord>) {
+        TransitionLogger::append_to(&mut self.transition_log, records);
     }
-    let event_loop = EventLoop::new();
-    let mut input = WinitInputHelper::new();
 
-    let window = WindowBuilder::new()
-        .with_title("🐍 Snake Game")
-        .with_inner_size(LogicalSize::new(WIDTH, HEIGHT))
-        .with_resizable(true) // allow resizing
-        .build(&event_loop)
-        .unwrap();
+    /// Write out buffered transitions (on exit).
+    fn flush_transitions(&mut self) {
+        if let Some(Err(e)) = self.transition_log.as_mut().map(TransitionLogger::flush) {
+            log_warn!("[log] failed to flush transitions: {}", e);
+        }
+    }
 
-    #[cfg(not(feature = "gpu-render"))]
-    let mut pixels = {
-        let window_size = window.inner_size();
-        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(WIDTH, HEIGHT, surface_texture)?
-    };
-    #[cfg(feature = "gpu-render")]
-    let mut gpu = pollster::block_on(gpu_render::GpuRenderer::new(&window, WIDTH, HEIGHT))?;
+    /// Mean length of the last `EPOCH_TIME_WINDOW` epochs in milliseconds, if any finished.
+    #[cfg_attr(feature = "gpu-render", allow(dead_code))]
+    fn mean_epoch_ms(&self) -> Option<f32> {
+        let recent = &self.epoch_ms[self.epoch_ms.len().saturating_sub(EPOCH_TIME_WINDOW)..];
+        (!recent.is_empty()).then(|| recent.iter().sum::<u64>() as f32 / recent.len() as f32)
+    }
 
-    let mut game = Game::new();
-    let mut evo = EvoTrainer::new(24); // увеличенная популяция для более быстрого поиска решений
-    #[cfg(feature = "gpu-nn")]
-    let mut nn_mode: bool = false;
-    #[cfg(feature = "dqn-gpu")]
-    let mut dqn_mode: bool = false; // toggle DQN training
-    #[cfg(feature = "dqn-gpu")]
-    let mut dqn_agent: Option<dqn::DqnAgent> = None;
-    #[cfg(all(feature = "gpu-nn-experimental", feature = "gpu-nn"))]
-    let mut nn_trainer: Option<gpu_nn::GpuTrainer> = Some(gpu_nn::GpuTrainer::new(256, 128, 3));
+    /// Whether the population currently trains with both wall modes.
+    fn has_mixed_walls(&self) -> bool {
+        self.wrap_modes.iter().any(|&w| w) && self.wrap_modes.iter().any(|&w| !w)
+    }
 
-    // Try to load saved agent and auto-start training if found
-    let save_path = "snake_agent.json";
-    let agent_loaded = if let Err(e) = evo.load_best(save_path) {
-        eprintln!("Could not load saved agent: {}", e);
-        false
-    } else {
-        println!("✅ Loaded saved agent from {}", save_path);
-        true
-    };
+    /// Switch the color palette and recolor the current population.
+    fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        for agent in self.pop.iter_mut() {
+            agent.color = lineage_color(agent.id, agent.lineage, palette);
+        }
+    }
 
-    // Auto-start evolution if agent was loaded
-    if agent_loaded {
-        evo.training = true;
-        println!("🚀 Auto-starting evolution with loaded agent");
+    /// Set the discount factor (clamped to 0.8..=0.999) for every agent, including
+    /// agents created by later reproduction.
+    fn set_gamma(&mut self, gamma: f32) {
+        self.gamma = gamma.clamp(0.8, 0.999);
+        for agent in self.pop.iter_mut() {
+            agent.gamma = self.gamma;
+        }
     }
 
-    // If CUDA is available, auto-enable DQN and start evolution
-    #[cfg(all(feature = "dqn-gpu", feature = "dqn-gpu-cuda"))]
-    {
-        if let Ok(cuda_dev) = candle_core::Device::new_cuda(0) {
-            // Initialize DQN agent on CUDA
-            match dqn::DqnAgent::new(1024, 256, &cuda_dev) {
-                Ok(agent) => {
-                    #[cfg(feature = "dqn-gpu")]
-                    {
-                        dqn_mode = true;
-                        dqn_agent = Some(agent);
-                        println!("[DQN] auto-enabled (device: Cuda(0))");
-                        // Prefer solid walls for DQN training
-                        evo.set_wrap_world(false);
-                        println!("[DQN] using solid walls (no wrap) for training");
-                        if !evo.training {
-                            evo.training = true;
-                            println!("[hint] CUDA detected: auto-starting Evolution with DQN");
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("[DQN] auto-enable failed: {}", e);
-                }
-            }
+    /// Raise every agent's epsilon by `by` (see `QAgent::bump_epsilon`): more
+    /// exploration without the stagnation restart wiping the population.
+    fn bump_epsilon(&mut self, by: f32) {
+        for agent in self.pop.iter_mut() {
+            agent.bump_epsilon(by);
         }
     }
 
-    let mut rng: SmallRng = SmallRng::from_entropy();
-    let mut last_update = Instant::now();
-    let mut tick_duration = Duration::from_millis(150);
-    let mut manual_speed_delta_ms: i32 = 0;
-    let mut evo_steps_per_frame: u32 = 1; // начальная скорость = 1 шаг за кадр (медленно для наблюдения)
-    let mut panel_visible: bool = true; // panel visibility toggle
-    let mut frame_counter: u32 = 0; // counter for skipping frames
-    // Evolution step budget to spread very large step counts across ticks
-    let mut evo_pending_steps: u32 = 0;
-    let mut max_steps_per_tick: u32 = 1500; // cap work per tick to keep UI responsive
-    let mut ultra_fast: bool = false; // training ultra-fast mode (disable render, raise cap)
-    let mut show_only_best: bool = false; // render only the best agent during training
-    // GPU detection (wgpu) and accel flags
-    let mut gpu_available: bool = false;
-    let mut gpu_enabled: bool = false;
-    {
-        let instance = Instance::new(wgpu::InstanceDescriptor {
-            backends: Backends::all(),
-            ..Default::default()
-        });
-        if let Some(_adapter) =
-            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: PowerPreference::HighPerformance,
-                compatible_surface: None,
-                force_fallback_adapter: false,
-            }))
-        {
-            gpu_available = true;
-            gpu_enabled = true;
-            max_steps_per_tick = 80_000;
+    /// Epochs without a new champion before `reproduce` restarts the population. Each
+    /// restart so far adds 500, giving the next attempt more time.
+    fn stagnation_threshold(&self) -> usize {
+        self.stagnation_base + self.restart_count * 500
+    }
+
+    /// Current epoch, champion, stagnation and population score statistics in one
+    /// serializable value.
+    #[cfg_attr(feature = "gpu-render", allow(dead_code))]
+    fn snapshot(&self) -> TrainingSnapshot {
+        let n = self.scores.len().max(1) as f32;
+        let mean = self.scores.iter().sum::<usize>() as f32 / n;
+        let var = self
+            .scores
+            .iter()
+            .map(|&s| (s as f32 - mean).powi(2))
+            .sum::<f32>()
+            / n;
+        TrainingSnapshot {
+            epoch: self.epoch,
+            best_score: self.best_score,
+            champion_score: self.champion_score,
+            champion_epoch: self.champion_epoch,
+            epochs_without_improvement: self.epochs_without_improvement,
+            restart_count: self.restart_count,
+            stagnation_threshold: self.stagnation_threshold(),
+            alive_count: self.games.iter().filter(|g| g.alive).count(),
+            mean_score: mean,
+            max_score: self.scores.iter().copied().max().unwrap_or(0),
+            min_score: self.scores.iter().copied().min().unwrap_or(0),
+            diversity: var.sqrt(),
         }
     }
-    // FPS counter state
-    let mut fps_last: Instant = Instant::now();
-    let mut fps_frames: u32 = 0;
-    let mut fps_value: f32 = 0.0;
 
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+    /// How often the best agent of the running epoch chose left, straight and right.
+    /// A heavy skew toward one turn points at a learning problem (e.g. tie-break bias).
+    #[cfg_attr(feature = "gpu-render", allow(dead_code))]
+    fn action_histogram(&self) -> [u64; 3] {
+        self.best_game_index()
+            .map_or([0; 3], |i| self.games[i].actions_taken)
+    }
 
-        if let Event::RedrawRequested(_) = event {
-            #[cfg(not(feature = "gpu-render"))]
-            let frame = pixels.frame_mut();
+    /// Current epsilon of the best running agent and the population mean.
+    fn epsilon_summary(&self) -> (Option<f32>, f32) {
+        let best = self.best_game_index().map(|i| self.pop[i].epsilon);
+        let sum: f32 = self.pop.iter().map(|a| a.epsilon).sum();
+        (best, sum / self.pop.len().max(1) as f32)
+    }
 
-            // Draw the appropriate game(s) [CPU framebuffer path]
-            #[cfg(not(feature = "gpu-render"))]
-            if evo.training {
-                // Rendering strategy tuned for performance at high EVO speeds
-                if ultra_fast {
-                    // Ultra-fast: no render during training
-                    clear_rgba(frame, 10, 10, 15, 255);
-                } else if show_only_best {
-                    // Always render only the best agent
-                    clear_rgba(frame, 10, 10, 15, 255);
-                    if let Some(best_game_idx) = evo
-                        .scores
-                        .iter()
-                        .enumerate()
-                        .max_by_key(|(_, score)| *score)
-                        .map(|(idx, _)| idx)
-                        && best_game_idx < evo.pop.len()
-                        && best_game_idx < evo.games.len()
-                    {
-                        let agent_color = evo.pop[best_game_idx].color;
-                        draw_game_transparent(frame, &evo.games[best_game_idx], 220, agent_color);
+    /// Turn champion freezing on or off. Turning it on takes effect at the next epoch,
+    /// when `reproduce` puts the champion in slot 0; turning it off lets slot 0 learn again.
+    fn set_freeze_champion(&mut self, on: bool) {
+        self.freeze_champion = on;
+        self.champion_frozen &= on;
+    }
+
+    /// Set the episode step limit (clamped to `STEP_LIMIT_RANGE`). The running epoch
+    /// keeps its limit; the new one applies from the next.
+    fn set_step_limit(&mut self, n: u32) {
+        self.next_step_limit = n.clamp(*STEP_LIMIT_RANGE.start(), *STEP_LIMIT_RANGE.end());
+    }
+
+    /// Whether the running epoch should end: every game is done, or the step limit is
+    /// reached and no unique leader is still alive (see `leader_protected`).
+    fn epoch_over(&self, all_done: bool) -> bool {
+        all_done || (self.steps_taken >= self.step_limit && !self.leader_protected())
+    }
+
+    /// Set the score at which training counts as solved (clamped to a reachable range).
+    fn set_target_score(&mut self, n: usize) {
+        self.target_score = n.clamp(1, default_target_score(self.grid, self.growth));
+        self.solved = false;
+    }
+
+    /// Advance every running game one tick with tabular Q-learning (in parallel).
+    /// Returns `true` when no game is left running, i.e. the epoch can end.
+    fn step_tabular(&mut self) -> bool {
+        let target_score = self.target_score;
+        let len = self.pop.len().min(self.games.len()).min(self.scores.len());
+        let len = len.min(self.episode_rewards.len());
+        let (pop_slice, _) = self.pop.split_at_mut(len);
+        let (games_slice, _) = self.games.split_at_mut(len);
+        let (scores_slice, _) = self.scores.split_at_mut(len);
+        let (rewards_slice, _) = self.episode_rewards.split_at_mut(len);
+        let rewards = self.rewards;
+        let repeat = self.action_repeat;
+        let solved_flag = AtomicBool::new(false);
+        let logging = self.transition_log.is_some();
+        let logged = Mutex::new(Vec::new());
+        let frozen = self.champion_frozen;
+        let human = self.human_action.take();
+
+        let mut step = || {
+            pop_slice
+                .par_iter_mut()
+                .zip(games_slice.par_iter_mut())
+                .zip(scores_slice.par_iter_mut())
+                .zip(rewards_slice.par_iter_mut())
+                .enumerate()
+                .for_each(|(i, (((agent, g), score_ref), episode_reward))| {
+                    if !g.alive || *score_ref >= target_score {
+                        return;
                     }
-                } else if evo_steps_per_frame < 8_192 {
-                    // Low/medium speed: draw grid + agents
-                    clear_rgba(frame, 30, 30, 40, 255);
-                    // Draw grid first
-                    for y in 0..GRID_HEIGHT {
-                        for x in 0..GRID_WIDTH {
-                            if (x + y) % 2 == 0 {
-                                let gx = x * GRID_SIZE;
-                                let gy = y * GRID_SIZE;
-                                for py in gy..gy + GRID_SIZE {
-                                    for px in gx..gx + GRID_SIZE {
-                                        if px < WIDTH && py < HEIGHT {
-                                            let idx = ((py * WIDTH + px) * 4) as usize;
-                                            if idx + 3 < frame.len() {
-                                                frame[idx] = 35;
-                                                frame[idx + 1] = 35;
-                                                frame[idx + 2] = 50;
-                                                frame[idx + 3] = 255;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
+                    // A frozen champion plays greedily and never learns, so its table stays put
+                    let learning = !(frozen && i == 0);
+                    let s = state_key_with(g, agent.encoding);
+                    // Explore with the game's own seeded RNG so runs are reproducible
+                    let a_idx = match human {
+                        Some((h, a)) if h == i => a,
+                        _ if learning => agent.select_action(s, &mut g.rng),
+                        _ => agent.act_greedy(s, &mut g.rng),
+                    };
+                    let (outcome, reward) = g.step_repeated(a_idx, repeat, &rewards);
+                    *episode_reward += reward;
+
+                    let ns = state_key_with(g, agent.encoding);
+                    let done = outcome.died || !g.alive;
+                    if learning {
+                        agent.learn(s, a_idx, reward, ns, done);
                     }
-                    // Draw all individuals only when really slow; otherwise only best
-                    if evo_steps_per_frame < 4_096 {
-                        for (agent, g) in evo.pop.iter().zip(evo.games.iter()) {
-                            let agent_color = agent.color;
-                            draw_game_transparent(frame, g, 180, agent_color);
+                    if logging {
+                        let rec = (s.raw(), a_idx as u8, reward, ns.raw(), done as u8);
+                        logged.lock().unwrap().push((i, rec));
+                    }
+                    agent.steps += 1;
+                    if outcome.died {
+                        agent.episodes += 1;
+                        if learning {
+                            agent.decay_epsilon();
                         }
-                    } else if let Some(best_game_idx) = evo
-                        .scores
-                        .iter()
-                        .enumerate()
-                        .max_by_key(|(_, score)| *score)
-                        .map(|(idx, _)| idx)
-                        && best_game_idx < evo.pop.len()
-                        && best_game_idx < evo.games.len()
-                    {
-                        let agent_color = evo.pop[best_game_idx].color;
-                        draw_game_transparent(frame, &evo.games[best_game_idx], 220, agent_color);
                     }
-                } else if evo_steps_per_frame < 20_000 {
-                    // High speed: skip grid entirely; draw best only on plain background
-                    clear_rgba(frame, 10, 10, 15, 255);
-                    if let Some(best_game_idx) = evo
-                        .scores
-                        .iter()
-                        .enumerate()
-                        .max_by_key(|(_, score)| *score)
-                        .map(|(idx, _)| idx)
-                        && best_game_idx < evo.pop.len()
-                        && best_game_idx < evo.games.len()
-                    {
-                        let agent_color = evo.pop[best_game_idx].color;
-                        draw_game_transparent(frame, &evo.games[best_game_idx], 220, agent_color);
+                    if g.alive || g.won {
+                        *score_ref = g.score;
                     }
-                } else {
-                    // Ultra-high speed: don't render agents at all
-                    clear_rgba(frame, 10, 10, 15, 255);
-                }
-            } else {
-                #[cfg(not(feature = "gpu-render"))]
-                game.draw(frame);
+                    if g.score >= target_score {
+                        solved_flag.store(true, Ordering::Relaxed);
+                    }
+                })
+        };
+        // Same per-game work either way; the pool only bounds the worker count
+        match &self.pool {
+            Some(pool) => pool.install(step),
+            None => step(),
+        }
+        if logging {
+            // Games finish in any order; log them by game index (one record each per
+            // tick) so a seeded run writes the same file
+            let mut logged = logged.into_inner().unwrap();
+            logged.sort_unstable_by_key(|&(i, _)| i);
+            TransitionLogger::append_to(
+                &mut self.transition_log,
+                logged.into_iter().map(|(_, rec)| rec),
+            );
+        }
+
+        if solved_flag.load(Ordering::Relaxed) {
+            self.solved = true;
+            self.training = false;
+            log_info!(
+                "🎉 SOLVED! An agent reached the target score {} (epoch {})",
+                target_score,
+                self.epoch
+            );
+            return true;
+        }
+        !scores_slice
+            .iter()
+            .zip(games_slice.iter())
+            .any(|(s, g)| g.alive && *s < target_score)
+    }
+
+    /// Make the best-agent view's snake head toward `dir` on the next tick instead of
+    /// the move its agent would pick; the agent learns from the move as if it had
+    /// chosen it. Returns false when no best game is running or `dir` would reverse.
+    fn override_best(&mut self, dir: Dir) -> bool {
+        let Some(i) = self.best_game_index().filter(|&i| self.games[i].alive) else {
+            return false;
+        };
+        let Some(a) = (0..3).find(|&a| dir_after_action(self.games[i].dir, a) == dir) else {
+            return false;
+        };
+        self.human_action = Some((i, a));
+        true
+    }
+
+    /// Whether a unique, still-alive leader should keep playing past the step limit.
+    fn leader_protected(&self) -> bool {
+        let (mut top1, mut top2, mut top1_idx) = (0usize, 0usize, None::<usize>);
+        for (i, &sc) in self.scores.iter().enumerate() {
+            if sc > top1 {
+                top2 = top1;
+                top1 = sc;
+                top1_idx = Some(i);
+            } else if sc > top2 {
+                top2 = sc;
             }
+        }
+        if let Some(idx) = top1_idx {
+            (top1 > top2) && self.games.get(idx).map(|g| g.alive).unwrap_or(false)
+        } else {
+            false
+        }
+    }
 
-            // Controls overlay (semi-transparent) - only draw if visible
-            #[cfg(not(feature = "gpu-render"))]
-            if panel_visible {
-                let panel_x: u32 = 8;
-                let panel_y: u32 = 8;
-                let panel_w: u32 = 380; // increased from 280
-                let panel_h: u32 = 628; // increased to fit new button line
-                let btn_h: u32 = 32; // increased button height
-                let btn_w: u32 = panel_w - 16;
-                let btn_x: u32 = panel_x + 8;
-                // Chart area inside panel (positioned below HUD option lines)
-                let chart_y: u32 = panel_y + 340; // moved further down to avoid text overlap
-                let chart_h: u32 = 120; // increased chart height
-                let btn1_y: u32 = chart_y + chart_h + 8; // start buttons after chart
-                let btn2_y: u32 = btn1_y + btn_h + 6;
-                let btn3_y: u32 = btn2_y + btn_h + 6;
-                let btn4_y: u32 = btn3_y + btn_h + 6;
-                let btn5_y: u32 = btn4_y + btn_h + 6; // hide button
-                let btn6_y: u32 = btn5_y + btn_h + 6; // show-only-best button
+    /// Game shown in the best-agent view: the highest score, the last one on ties.
+    fn best_game_index(&self) -> Option<usize> {
+        self.scores
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, score)| *score)
+            .map(|(idx, _)| idx)
+            .filter(|&idx| idx < self.pop.len() && idx < self.games.len())
+    }
 
-                fill_rect_rgba(frame, panel_x, panel_y, panel_w, panel_h, 0, 0, 0, 140);
-                stroke_rect_rgba(frame, panel_x, panel_y, panel_w, panel_h, 255, 255, 255, 60);
-                draw_text(
-                    frame,
-                    "CONTROLS",
-                    panel_x + 10,
-                    panel_y + 10,
-                    2,
-                    (180, 220, 255, 255),
-                );
-                // HUD inside panel with extra line spacing
-                draw_text(
-                    frame,
-                    &format!("SCORE: {}", game.score),
-                    panel_x + 10,
-                    panel_y + 40,
-                    2,
-                    (230, 230, 230, 255),
-                );
-                draw_text(
-                    frame,
-                    &format!("LENGTH: {}", game.snake.len()),
-                    panel_x + 10,
-                    panel_y + 70,
-                    2,
-                    (200, 200, 200, 255),
-                );
-                #[cfg(feature = "gpu-nn")]
-                {
-                    let nn_label = if nn_mode { "ON" } else { "OFF" };
-                    draw_text(
-                        frame,
-                        &format!("NN MODE [N]: {}", nn_label),
-                        panel_x + 10,
-                        panel_y + 100,
-                        2,
-                        (180, 220, 180, 255),
-                    );
-                }
-                // Speed indicator (based on tick duration)
-                let ms = tick_duration.as_millis() as f32;
-                let sps = if ms > 0.0 { 1000.0 / ms } else { 0.0 };
-                draw_text(
-                    frame,
-                    &format!("SPEED: {} ms (~{:.1}/s)", ms as i32, sps),
-                    panel_x + 10,
-                    panel_y + 100,
-                    2,
-                    (200, 220, 255, 255),
-                );
+    /// Keep a snapshot of this epoch's agent with the most apples per step, among those
+    /// that reached `efficient_min_score`, if it beats the standing efficient champion.
+    /// Returns whether it did.
+    fn update_efficient_champion(&mut self) -> bool {
+        let rate = |score: usize, steps: usize| score as f32 / steps.max(1) as f32;
+        let best = (0..self.pop_size.min(self.games.len()))
+            .filter(|&i| self.scores[i] >= self.efficient_min_score.max(1))
+            .map(|i| (i, rate(self.scores[i], self.games[i].steps)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        let current = self
+            .champion_efficient
+            .as_ref()
+            .map_or(0.0, |c| rate(c.score, c.steps));
+        if let Some((i, r)) = best
+            && r > current
+        {
+            self.champion_efficient = Some(EfficientChampion {
+                agent: self.pop[i].clone(),
+                score: self.scores[i],
+                steps: self.games[i].steps,
+            });
+            return true;
+        }
+        false
+    }
 
-                // Evolutionary training status
-                draw_text(
-                    frame,
-                    &format!("EVO: {} (E)", if evo.training { "ON" } else { "OFF" }),
-                    panel_x + 10,
-                    panel_y + 130,
-                    2,
-                    (220, 200, 240, 255),
-                );
-                let gpu_str = if gpu_enabled {
-                    "GPU"
-                } else if gpu_available {
-                    "GPU avail"
-                } else {
-                    "CPU"
-                };
-                draw_text(
-                    frame,
-                    &format!("ACCEL: {}  (G)", gpu_str),
-                    panel_x + 200,
-                    panel_y + 130,
-                    2,
-                    (180, 255, 200, 255),
-                );
-                let alive_count = evo.games.iter().filter(|g| g.alive).count();
-                draw_text(
-                    frame,
-                    &format!(
-                        "EPOCH: {}  ALIVE: {}/{}",
-                        evo.epoch, alive_count, evo.pop_size
-                    ),
-                    panel_x + 10,
-                    panel_y + 160,
-                    2,
-                    (220, 200, 240, 255),
-                );
-                draw_text(
-                    frame,
-                    &format!("TARGET: {}  BEST: {}", evo.target_score, evo.best_score),
-                    panel_x + 10,
-                    panel_y + 190,
-                    2,
-                    (220, 200, 240, 255),
-                );
-                // Leader protection HUD: show when unique leader bypasses step limit
-                {
-                    let (mut top1, mut top2, mut top1_idx) = (0usize, 0usize, None::<usize>);
-                    for (i, &sc) in evo.scores.iter().enumerate() {
-                        if sc > top1 {
-                            top2 = top1;
-                            top1 = sc;
-                            top1_idx = Some(i);
-                        } else if sc > top2 {
-                            top2 = sc;
-                        }
-                    }
-                    let leader_protected = if let Some(idx) = top1_idx {
-                        (top1 > top2) && evo.games.get(idx).map(|g| g.alive).unwrap_or(false)
-                    } else {
-                        false
-                    };
-                    if leader_protected {
-                        draw_text(
-                            frame,
-                            "LEADER: protected",
-                            panel_x + 10,
-                            panel_y + 220,
-                            2,
-                            (120, 255, 120, 255),
-                        );
-                    }
-                }
+    /// Population indices ordered best first by the trainer's fitness mode
+    /// (stable, so equal fitness keeps index order).
+    fn ranked_indices(&self) -> Vec<usize> {
+        let fit = |i: usize| match self.fitness_mode {
+            FitnessMode::Score => self.scores[i] as f32,
+            FitnessMode::Efficiency => {
+                fitness(self.scores[i], self.pop[i].steps, self.pop[i].episodes)
+            }
+        };
+        let mut idxs: Vec<usize> = (0..self.pop_size).collect();
+        idxs.sort_by(|&a, &b| fit(b).total_cmp(&fit(a)));
+        idxs
+    }
 
-                // Champion info with epoch
-                if evo.champion_score > 0 {
-                    draw_text(
-                        frame,
-                        &format!(
-                            "CHAMPION: {} (epoch {})",
-                            evo.champion_score, evo.champion_epoch
-                        ),
-                        panel_x + 10,
-                        panel_y + 240,
-                        2,
-                        (255, 215, 0, 255),
-                    );
+    /// Reproduce a new generation with elitism, mutation, and adaptive restarts.
+    fn reproduce<R: Rng + ?Sized>(&mut self, rng: &mut R, save_path: Option<&str>) {
+        self.record_epoch_deaths();
+        let idxs = self.ranked_indices();
+        let best_idx = *idxs.first().unwrap_or(&0);
+        let best_score = self.scores[best_idx];
+        self.epoch_best.push(best_score);
+        let epoch_time = self.epoch_timer.elapsed(Instant::now());
+        self.epoch_ms.push(epoch_time.as_millis() as u64);
+        log_debug!(
+            "[epoch] {} best {} in {} ms",
+            self.epoch,
+            best_score,
+            epoch_time.as_millis()
+        );
+
+        self.best_score = self.best_score.max(best_score);
+
+        let mut new_champion = false;
+        // Update global champion if this is a new record
+        if best_score > self.champion_score {
+            self.champion_score = best_score;
+            self.champion_epoch = self.epoch;
+            self.champion = Some(self.pop[best_idx].clone());
+            self.epochs_without_improvement = 0; // reset stagnation counter
+            new_champion = true;
+            log_info!(
+                "🏆 NEW CHAMPION! Score: {} (Epoch {})",
+                best_score,
+                self.epoch
+            );
+
+            // Auto-save immediately when new champion found
+            if let Some(save_path) = save_path {
+                if let Err(e) = self.save_best(save_path) {
+                    log_warn!("Failed to save champion: {}", e);
                 } else {
-                    draw_text(
-                        frame,
-                        "CHAMPION: None",
-                        panel_x + 10,
-                        panel_y + 240,
-                        2,
-                        (255, 215, 0, 255),
-                    );
+                    log_info!("✅ Champion saved to {}", save_path);
                 }
+            }
+        } else {
+            self.epochs_without_improvement += 1;
+        }
+        // Whether the champion still reaches states it had never seen, or has saturated
+        self.epoch_states
+            .push(self.champion.as_ref().map_or(0, QAgent::table_size));
+        if self.update_efficient_champion()
+            && let Some(c) = self.champion_efficient.as_ref()
+        {
+            log_info!(
+                "⚡ New efficient champion: {} apples in {} steps (Epoch {})",
+                c.score,
+                c.steps,
+                self.epoch
+            );
+            if let Some(save_path) = save_path
+                && let Err(e) = self.save_efficient(&efficient_save_path(save_path))
+            {
+                log_warn!("Failed to save efficient champion: {}", e);
+            }
+        }
 
-                // Stagnation warning
-                if evo.epochs_without_improvement > 0 {
-                    let base_threshold = 1000 + (evo.restart_count * 500);
-                    let color = if evo.epochs_without_improvement > (base_threshold - 200) {
-                        (255, 100, 100, 255)
-                    } else {
-                        (200, 200, 200, 255)
-                    };
-                    draw_text(
-                        frame,
-                        &format!(
-                            "No improvement: {}/{} (restarts: {})",
-                            evo.epochs_without_improvement, base_threshold, evo.restart_count
-                        ),
-                        panel_x + 10,
-                        panel_y + 270,
-                        2,
-                        color,
-                    );
+        let mut new_pop: Vec<QAgent> = Vec::with_capacity(self.pop_size);
+
+        // Check for long stagnation
+        if self.epochs_without_improvement >= self.stagnation_threshold()
+            && let Some(champion) = self.champion.as_ref()
+        {
+            // After 5 restarts, cycle back to restart #1 but with even more aggressive exploration
+            if self.restart_count >= 5 {
+                self.restart_count = 0; // cycle back
+                log_info!("🔄 Max restarts reached. Cycling back with aggressive exploration...");
+            }
+
+            self.restart_count += 1;
+            log_info!(
+                "⚠️ Stagnation detected ({} epochs without improvement). Restart #{} with exploration...",
+                self.epochs_without_improvement,
+                self.restart_count
+            );
+            self.epochs_without_improvement = 0; // reset counter
+
+            // Multi-strategy restart based on restart count
+            match self.restart_count {
+                1 => {
+                    // First restart: moderate mutation + boost exploration
+                    new_pop.push(champion.clone());
+                    while new_pop.len() < self.pop_size {
+                        let mut child = champion.offspring();
+                        child.boost_exploration(); // reset epsilon and alpha
+                        mutate_qagent(&mut child, rng, 0.25); // moderate mutation
+                        new_pop.push(child);
+                    }
+                }
+                2 => {
+                    // Second restart: high mutation + more fresh agents + boost
+                    new_pop.push(champion.clone());
+                    for _ in 1..(self.pop_size / 2) {
+                        // changed from 2/3 to 1/2
+                        let mut child = champion.offspring();
+                        child.boost_exploration();
+                        mutate_qagent(&mut child, rng, 0.4); // high mutation
+                        new_pop.push(child);
+                    }
+                    // Add more fresh random agents (50%), each founding a lineage
+                    let remaining = self.pop_size - new_pop.len();
+                    new_pop.extend((0..remaining).map(|_| QAgent::new()));
+                }
+                3 => {
+                    // Third restart: 30% champion, 70% fresh agents
+                    new_pop.push(champion.clone());
+                    for _ in 1..(self.pop_size * 3 / 10) {
+                        // 30%
+                        let mut child = champion.offspring();
+                        child.boost_exploration();
+                        mutate_qagent(&mut child, rng, 0.35);
+                        new_pop.push(child);
+                    }
+                    // Add fresh random agents (70%), each founding a lineage
+                    let remaining = self.pop_size - new_pop.len();
+                    new_pop.extend((0..remaining).map(|_| QAgent::new()));
+                }
+                4 => {
+                    // Fourth restart: 20% champion + 80% fresh agents + boost
+                    new_pop.push(champion.clone());
+                    for _ in 1..(self.pop_size / 5) {
+                        // 20%
+                        let mut child = champion.offspring();
+                        child.boost_exploration();
+                        mutate_qagent(&mut child, rng, 0.6); // very high mutation
+                        new_pop.push(child);
+                    }
+                    // Add mostly fresh random agents (80%), each founding a lineage
+                    let remaining = self.pop_size - new_pop.len();
+                    new_pop.extend((0..remaining).map(|_| QAgent::new()));
+                }
+                _ => {
+                    // Fifth restart: 10% champion + 90% fresh agents + extreme boost
+                    new_pop.push(champion.clone());
+                    for _ in 1..(self.pop_size / 10) {
+                        // 10%
+                        let mut child = champion.offspring();
+                        child.boost_exploration();
+                        mutate_qagent(&mut child, rng, 0.8); // extreme mutation
+                        new_pop.push(child);
+                    }
+                    // Add mostly fresh random agents (90%), each founding a lineage + boost
+                    let remaining = self.pop_size - new_pop.len();
+                    for _ in 0..remaining {
+                        let mut agent = QAgent::new();
+                        agent.boost_exploration(); // boost fresh agents too
+                        new_pop.push(agent);
+                    }
                 }
+            }
+        }
+        // If we have a new champion, restart population from champion's children
+        else if new_champion && let Some(champion) = self.champion.as_ref() {
+            self.restart_count = 0; // reset restart counter on new champion
+            new_pop = champion_offspring(champion, self.pop_size, rng);
+        } else {
+            // Normal reproduction: элиты + дети + новые (баланс эксплуатации и исследования)
+            let (top_k, num_children) = elite_split(self.pop_size, self.elite_fraction);
 
-                let ultra_str = if ultra_fast { "ON" } else { "OFF" };
-                let best_str = if show_only_best { "ON" } else { "OFF" };
-                // Split into two lines to keep within panel width
-                draw_text(
-                    frame,
-                    &format!("EVO SPD: {} steps/frame (+/-)", evo_steps_per_frame),
-                    panel_x + 10,
-                    panel_y + 300,
-                    2,
-                    (200, 220, 255, 255),
-                );
-                draw_text(
-                    frame,
-                    &format!("ULTRA (U): {}    BEST (B): {}", ultra_str, best_str),
-                    panel_x + 10,
-                    panel_y + 320,
-                    2,
-                    (200, 220, 255, 255),
-                );
-                // Chart of best apples per epoch
-                draw_chart(
-                    frame,
-                    panel_x + 10,
-                    chart_y,
-                    panel_w - 20,
-                    chart_h,
-                    &evo.epoch_best,
-                );
+            // 0. A frozen champion keeps slot 0; the rest of the population challenges it
+            let mut frozen_id = None;
+            if self.freeze_champion
+                && let Some(champion) = self.champion.as_ref()
+            {
+                new_pop.push(champion.clone());
+                frozen_id = Some(champion.id);
+            }
 
-                let paused_label = if game.paused {
-                    "RESUME  P"
-                } else {
-                    "PAUSE   P"
-                };
-                draw_button(frame, btn_x, btn1_y, btn_w, btn_h, paused_label);
-                draw_button(frame, btn_x, btn2_y, btn_w, btn_h, "SPEED+  +");
-                draw_button(frame, btn_x, btn3_y, btn_w, btn_h, "RESTART R");
-                draw_button(frame, btn_x, btn4_y, btn_w, btn_h, "SAVE    S");
-                draw_button(frame, btn_x, btn5_y, btn_w, btn_h, "HIDE    H");
-                let best_label = if show_only_best {
-                    "BEST ON  B"
-                } else {
-                    "BEST OFF B"
-                };
-                draw_button(frame, btn_x, btn6_y, btn_w, btn_h, best_label);
-            } else {
-                // Draw small button to show panel again
-                #[cfg(not(feature = "gpu-render"))]
-                let show_btn_x: u32 = 8;
-                let show_btn_y: u32 = 8;
-                let show_btn_w: u32 = 100;
-                let show_btn_h: u32 = 32;
-                #[cfg(not(feature = "gpu-render"))]
-                draw_button(frame, show_btn_x, show_btn_y, show_btn_w, show_btn_h, "SHOW H");
+            // 1. Elitism: keep the top `elite_fraction` unchanged (the frozen champion only once)
+            for &idx in idxs.iter().take(top_k) {
+                if Some(self.pop[idx].id) != frozen_id {
+                    new_pop.push(self.pop[idx].clone());
+                }
+            }
+
+            // 2. Создаём детей от элиты с мутациями и смешением цветов (4/7 остатка)
+            let elite_scores: Vec<usize> = idxs[..top_k].iter().map(|&i| self.scores[i]).collect();
+            let selection = self.parent_selection;
+            let pick_parent = |rng: &mut R| match selection {
+                ParentSelection::Uniform => idxs[rng.gen_range(0..top_k)],
+                ParentSelection::Roulette => idxs[roulette_index(&elite_scores, rng)],
+            };
+            for _ in 0..num_children {
+                // Выбираем родителя из элиты; the child joins its lineage (nearby color)
+                let mut child = self.pop[pick_parent(rng)].offspring();
+
+                // Умеренная мутация Q-таблицы
+                mutate_qagent(&mut child, rng, 0.15);
+
+                new_pop.push(child);
             }
 
-            // Update and draw FPS counter (top-right, green)
-            fps_frames = fps_frames.wrapping_add(1);
-            let elapsed = fps_last.elapsed();
-            if elapsed.as_secs_f32() >= 1.0 {
-                fps_value = fps_frames as f32 / elapsed.as_secs_f32();
-                fps_frames = 0;
-                fps_last = Instant::now();
+            // 3. Остаток (3/7) — новые случайные агенты, каждый со своей линией и цветом
+            let num_fresh = self.pop_size.saturating_sub(new_pop.len());
+            new_pop.extend((0..num_fresh).map(|_| QAgent::new()));
+
+            // 4. Дозаполняем популяцию до целевого размера
+            if new_pop.len() > self.pop_size {
+                new_pop.truncate(self.pop_size);
             }
-            #[cfg(not(feature = "gpu-render"))]
+        }
+
+        // Every branch above starts the new population with the champion when there is one
+        self.adopt_population(new_pop);
+        self.epoch += 1;
+        self.reset_epoch();
+    }
+
+    /// Manually restart from the global champion: champion + mutated children, with
+    /// the stagnation counters cleared. Returns false (and changes nothing) without a champion.
+    fn reseed_from_champion<R: Rng + ?Sized>(&mut self, rng: &mut R) -> bool {
+        let Some(champion) = self.champion.as_ref() else {
+            return false;
+        };
+        let new_pop = champion_offspring(champion, self.pop_size, rng);
+        self.adopt_population(new_pop);
+        self.epochs_without_improvement = 0;
+        self.restart_count = 0;
+        self.reset_epoch();
+        true
+    }
+
+    /// Install `new_pop`, giving every agent the trainer-wide settings. Slot 0 must
+    /// hold the champion whenever there is one (it is frozen under `freeze_champion`).
+    fn adopt_population(&mut self, mut new_pop: Vec<QAgent>) {
+        let frozen = self.freeze_champion && self.champion.is_some();
+        for (i, agent) in new_pop.iter_mut().enumerate() {
+            // Evolved gammas are the agents' own; otherwise the trainer's applies
+            if !self.mutate_hypers {
+                agent.gamma = self.gamma;
+            }
+            agent.mutate_hypers = self.mutate_hypers;
+            agent.max_states = self.max_states;
+            agent.count_visits = self.max_qmem.is_some();
+            // A frozen champion keeps its whole table
+            if let Some(keep) = self.max_states
+                && !(frozen && i == 0)
             {
-                let fps_text = format!("FPS: {:.0}", fps_value);
-                let scale: u32 = 2;
-                let advance = 5 * scale + scale; // glyph width + spacing
-                let text_w: u32 = fps_text.chars().count() as u32 * advance;
-                let fps_x: u32 = WIDTH.saturating_sub(text_w + 8);
-                let fps_y: u32 = 8;
-                draw_text(frame, &fps_text, fps_x, fps_y, scale, (80, 255, 120, 255));
+                agent.prune_rarely_used(keep);
+            }
+            agent.encoding = self.encoding;
+            agent.tie_break = self.tie_break;
+            agent.set_epsilon_schedule(self.epsilon_schedule);
+            agent.color = lineage_color(agent.id, agent.lineage, self.palette);
+        }
+        self.pop = new_pop;
+        self.champion_frozen = frozen;
+        self.enforce_qmem_budget();
+    }
+
+    /// Q-table memory of the whole population (see `QAgent::table_bytes`).
+    fn table_bytes(&self) -> usize {
+        self.pop.iter().map(QAgent::table_bytes).sum()
+    }
+
+    /// Over the `max_qmem` budget, prune the largest tables down to a common size, the
+    /// largest one that fits, keeping their most-visited states; smaller tables are left
+    /// alone. A frozen champion is never pruned, but its table counts against the
+    /// budget. Returns whether anything was pruned.
+    fn enforce_qmem_budget(&mut self) -> bool {
+        let Some(budget) = self.max_qmem else {
+            return false;
+        };
+        let before = self.table_bytes();
+        let skip = usize::from(self.champion_frozen).min(self.pop.len());
+        let mut keep = usize::MAX;
+        // Freed buckets change the bytes per state, so repeat until the sizes fit
+        while keep > 0 && self.table_bytes() > budget {
+            let fixed: usize = self.pop[..skip].iter().map(QAgent::table_bytes).sum();
+            let rest = &self.pop[skip..];
+            let fits = |keep: usize| {
+                let pruned: usize = rest
+                    .iter()
+                    .map(|a| match a.table_size() {
+                        0 => a.table_bytes(),
+                        n => (a.table_bytes() * n.min(keep)).div_ceil(n),
+                    })
+                    .sum();
+                fixed + pruned <= budget
+            };
+            // Largest per-table size that fits; `fits` only turns false as `keep` grows
+            let (mut lo, mut hi) = (
+                0,
+                rest.iter()
+                    .map(QAgent::table_size)
+                    .max()
+                    .unwrap_or(0)
+                    .min(keep - 1),
+            );
+            while lo < hi {
+                let mid = (lo + hi).div_ceil(2);
+                if fits(mid) {
+                    lo = mid;
+                } else {
+                    hi = mid - 1;
+                }
+            }
+            for agent in self.pop[skip..].iter_mut() {
+                agent.prune_rarely_used(lo);
+            }
+            keep = lo;
+        }
+        if keep == usize::MAX {
+            return false;
+        }
+        log_warn!(
+            "⚠️ Q-tables over the memory budget ({:.1} MB > {:.1} MB): pruned to {} states each, now {:.1} MB",
+            before as f32 / (1024.0 * 1024.0),
+            budget as f32 / (1024.0 * 1024.0),
+            keep,
+            self.table_bytes() as f32 / (1024.0 * 1024.0)
+        );
+        true
+    }
+}
+
+/// `pop_size` agents: the champion itself (elitism), then mutated offspring in its
+/// lineage.
+fn champion_offspring<R: Rng + ?Sized>(
+    champion: &QAgent,
+    pop_size: usize,
+    rng: &mut R,
+) -> Vec<QAgent> {
+    let mut pop = Vec::with_capacity(pop_size);
+    pop.push(champion.clone());
+    while pop.len() < pop_size {
+        let mut child = champion.offspring();
+        mutate_qagent(&mut child, rng, 0.15); // moderate mutation for exploration
+        pop.push(child);
+    }
+    pop
+}
+
+/// How `reproduce` picks the parents of each child among the elites.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ParentSelection {
+    /// Every elite equally likely (default).
+    Uniform,
+    /// Roulette wheel: probability proportional to score + 1.
+    Roulette,
+}
+
+/// Roulette-wheel index into `scores`, weighted by score + 1 (so all-zero scores
+/// are uniform).
+fn roulette_index<R: Rng + ?Sized>(scores: &[usize], rng: &mut R) -> usize {
+    let total: u64 = scores.iter().map(|&s| s as u64 + 1).sum();
+    if total == 0 {
+        return 0;
+    }
+    let mut ticket = rng.gen_range(0..total);
+    for (i, &s) in scores.iter().enumerate() {
+        let w = s as u64 + 1;
+        if ticket < w {
+            return i;
+        }
+        ticket -= w;
+    }
+    scores.len() - 1
+}
+
+/// Split a population into (elites, mutated children); the rest are fresh agents.
+/// Elites are `round(pop_size * elite_fraction)`, at least one and fewer than
+/// `pop_size`; the remainder is shared 4:3 between children and fresh agents.
+fn elite_split(pop_size: usize, elite_fraction: f32) -> (usize, usize) {
+    let top_k = ((pop_size as f32 * elite_fraction).round() as usize)
+        .min(pop_size.saturating_sub(1))
+        .max(1);
+    let rest = pop_size.saturating_sub(top_k);
+    (top_k, (rest * 4 + 3) / 7)
+}
+
+/// Counts of how training episodes ended: hitting a wall, hitting the body, or
+/// still alive when the epoch's step limit ran out.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+struct DeathStats {
+    wall: u64,
+    self_collision: u64,
+    timeout: u64,
+}
+
+impl DeathStats {
+    /// Shares of (wall, self-collision, timeout); all zero before any episode ends.
+    fn ratios(&self) -> (f32, f32, f32) {
+        let total = (self.wall + self.self_collision + self.timeout).max(1) as f32;
+        (
+            self.wall as f32 / total,
+            self.self_collision as f32 / total,
+            self.timeout as f32 / total,
+        )
+    }
+}
+
+/// Where the efficient champion goes next to the score champion's `path`
+/// (`snake_agent.json` -> `snake_agent_efficient.json`).
+fn efficient_save_path(path: &str) -> String {
+    format!(
+        "{}_efficient.json",
+        path.strip_suffix(".json").unwrap_or(path)
+    )
+}
+
+/// `--autosave` on quit: write the champion to `path` (and the efficient champion next
+/// to it). Returns whether anything was written; without a champion nothing is, so
+/// an untrained population never overwrites an earlier save.
+fn save_champion_on_exit(evo: &EvoTrainer, path: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    if evo.champion.is_none() {
+        return Ok(false);
+    }
+    evo.save_best(path)?;
+    if evo.champion_efficient.is_some() {
+        evo.save_efficient(&efficient_save_path(path))?;
+    }
+    Ok(true)
+}
+
+/// Snapshot of the agent with the best apples-per-step epoch so far.
+#[derive(Clone)]
+struct EfficientChampion {
+    agent: QAgent,
+    score: usize,
+    steps: usize, // moves it took to reach `score`
+}
+
+/// Ranking used when selecting elites and the champion.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FitnessMode {
+    /// Apples eaten this epoch (default).
+    Score,
+    /// Apples, with ties broken in favor of fewer steps per apple (see `fitness`).
+    Efficiency,
+}
+
+/// Efficiency fitness: the score plus a bonus below 1 that shrinks with the average
+/// number of steps per apple, so it only reorders agents with equal scores.
+fn fitness(score: usize, steps: u64, episodes: u64) -> f32 {
+    let steps_per_episode = steps as f32 / episodes.max(1) as f32;
+    let steps_per_apple = steps_per_episode / score.max(1) as f32;
+    score as f32 + 1.0 / (1.0 + steps_per_apple)
+}
+
+/// Apples needed to fill the whole board when each one adds `growth` cells (the
+/// default, practically unreachable target).
+fn default_target_score(grid: GridConfig, growth: usize) -> usize {
+    grid.cells().saturating_sub(3).div_ceil(growth.max(1)) // 3 is initial snake length
+}
+
+/// Practical training goals cycled by the T key (the last one, `max`, is "fill the board").
+fn next_target_preset(current: usize, max: usize) -> usize {
+    let presets: Vec<usize> = [10, 25, 50, 100]
+        .into_iter()
+        .filter(|&t| t < max)
+        .chain(std::iter::once(max))
+        .collect();
+    presets
+        .iter()
+        .copied()
+        .find(|&t| t > current)
+        .unwrap_or(presets[0])
+}
+
+/// Indices of the `k` highest scores, best first; ties go to the lower index.
+#[cfg(not(feature = "gpu-render"))]
+fn top_k_indices(scores: &[usize], k: usize) -> Vec<usize> {
+    let k = k.min(scores.len());
+    if k == 0 {
+        return Vec::new();
+    }
+    let cmp = |a: &usize, b: &usize| scores[*b].cmp(&scores[*a]).then(a.cmp(b));
+    let mut idxs: Vec<usize> = (0..scores.len()).collect();
+    if k < idxs.len() {
+        idxs.select_nth_unstable_by(k - 1, cmp);
+        idxs.truncate(k);
+    }
+    idxs.sort_unstable_by(cmp);
+    idxs
+}
+
+/// Default bound on mutated Q-values (`QAgent::q_clip`).
+fn default_q_clip() -> f32 {
+    1000.0
+}
+
+/// Mutate Q-values (clamped to the agent's `q_clip`; no noise if `sigma <= 0`), maybe
+/// the hyperparameters, and decay epsilon slightly; `sigma` controls noise magnitude.
+fn mutate_qagent<R: Rng + ?Sized>(agent: &mut QAgent, rng: &mut R, sigma: f32) {
+    // Noise is derived from (salt, state) rather than drawn in map order: the hash
+    // map's iteration order is randomized, which would break seeded runs.
+    if sigma > 0.0 {
+        let salt: u64 = rng.r#gen();
+        let clip = agent.q_clip;
+        for (&s, arr) in agent.q.iter_mut() {
+            let mut entry_rng =
+                SmallRng::seed_from_u64(salt ^ (s as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            for v in arr.iter_mut() {
+                let mutated = *v + entry_rng.gen_range(-sigma..sigma);
+                // Long runs with big restart sigmas would otherwise drift without bound
+                *v = if mutated.is_nan() {
+                    0.0
+                } else {
+                    mutated.clamp(-clip, clip)
+                };
+                debug_assert!(v.is_finite());
             }
+        }
+    }
+    if agent.mutate_hypers {
+        mutate_hyperparams(agent, rng);
+    }
+    agent.decay_epsilon();
+}
+
+/// Chance that each of alpha, gamma and decay is perturbed in one mutation.
+const HYPER_MUTATION_PROB: f64 = 0.1;
+
+/// Occasionally nudge the learning hyperparameters, clamped to sane ranges, so the
+/// population can tune them itself. Only called for agents with `mutate_hypers`.
+fn mutate_hyperparams<R: Rng + ?Sized>(agent: &mut QAgent, rng: &mut R) {
+    if rng.gen_bool(HYPER_MUTATION_PROB) {
+        agent.alpha = (agent.alpha + rng.gen_range(-0.05..0.05)).clamp(0.01, 1.0);
+    }
+    if rng.gen_bool(HYPER_MUTATION_PROB) {
+        agent.gamma = (agent.gamma + rng.gen_range(-0.01..0.01)).clamp(0.8, 0.999);
+    }
+    if rng.gen_bool(HYPER_MUTATION_PROB) {
+        agent.decay = (agent.decay + rng.gen_range(-0.0005..0.0005)).clamp(0.99, 0.99999);
+    }
+}
+
+/// Rotate direction 90° left.
+fn left_dir(d: Dir) -> Dir {
+    match d {
+        Dir::Up => Dir::Left,
+        Dir::Left => Dir::Down,
+        Dir::Down => Dir::Right,
+        Dir::Right => Dir::Up,
+    }
+}
+/// Rotate direction 90° right.
+fn right_dir(d: Dir) -> Dir {
+    match d {
+        Dir::Up => Dir::Right,
+        Dir::Right => Dir::Down,
+        Dir::Down => Dir::Left,
+        Dir::Left => Dir::Up,
+    }
+}
+/// Apply an action index to a direction: 0=left, 1=straight, 2=right.
+fn dir_after_action(d: Dir, a: usize) -> Dir {
+    match a {
+        0 => left_dir(d),
+        1 => d,
+        _ => right_dir(d),
+    }
+}
+
+/// Relative actions (0=left, 1=straight, 2=right) that don't die on the next tick:
+/// no solid wall and no body cell (a departing tail is free, as in `Game::update`).
+fn legal_actions(game: &Game) -> Vec<usize> {
+    (0..3)
+        .filter(|&a| {
+            game.next_head(dir_after_action(game.dir, a))
+                .is_some_and(|p| !game.hits_body(p))
+        })
+        .collect()
+}
+
+/// Hand-coded, non-learning baseline: among the legal actions, the one that brings
+/// the head closest to the apple (lowest index on ties); straight if every move dies.
+fn greedy_policy_action(game: &Game) -> usize {
+    legal_actions(game)
+        .into_iter()
+        .min_by_key(|&a| {
+            let head = game.next_head(dir_after_action(game.dir, a)).unwrap();
+            game.apple_distance(head)
+        })
+        .unwrap_or(1)
+}
+
+/// Color scheme used when assigning agent colors.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Palette {
+    /// Evenly spaced HSL hues (default).
+    Hsl,
+    /// Curated high-contrast colors that stay distinguishable with color-vision deficiencies.
+    ColorBlindSafe,
+}
+
+impl Palette {
+    /// The other palette (used by the toggle key).
+    fn toggled(self) -> Self {
+        match self {
+            Palette::Hsl => Palette::ColorBlindSafe,
+            Palette::ColorBlindSafe => Palette::Hsl,
+        }
+    }
+}
+
+/// Okabe–Ito palette without black (invisible on the dark background).
+const OKABE_ITO: [(u8, u8, u8); 7] = [
+    (230, 159, 0),   // orange
+    (86, 180, 233),  // sky blue
+    (0, 158, 115),   // bluish green
+    (240, 228, 66),  // yellow
+    (0, 114, 178),   // blue
+    (213, 94, 0),    // vermillion
+    (204, 121, 167), // reddish purple
+];
+
+// Генерирует разнообразные цвета для популяции
+/// Generate distinct RGB colors for a population using the given palette.
+///
+/// `Hsl` samples evenly spaced hues; `ColorBlindSafe` cycles through the Okabe–Ito
+/// colors and alternates lighter/darker variants once the base set is exhausted.
+#[cfg_attr(feature = "gpu-render", allow(dead_code))]
+fn generate_population_colors_with(pop_size: usize, palette: Palette) -> Vec<(u8, u8, u8)> {
+    let mut colors = Vec::with_capacity(pop_size);
+    for i in 0..pop_size {
+        let color = match palette {
+            Palette::Hsl => {
+                let hue = (i as f32 / pop_size as f32) * 360.0;
+                hsl_to_rgb(hue, 0.85, 0.65) // увеличена насыщенность и яркость
+            }
+            Palette::ColorBlindSafe => {
+                let base = OKABE_ITO[i % OKABE_ITO.len()];
+                let round = i / OKABE_ITO.len();
+                if round == 0 {
+                    base
+                } else {
+                    // Odd rounds lighten, even rounds darken, each step a bit further
+                    let amount = (0.3 * round.div_ceil(2) as f32).min(0.75);
+                    let shade = |c: u8| {
+                        if round % 2 == 1 {
+                            (c as f32 + (255.0 - c as f32) * amount) as u8
+                        } else {
+                            (c as f32 * (1.0 - amount)) as u8
+                        }
+                    };
+                    (shade(base.0), shade(base.1), shade(base.2))
+                }
+            }
+        };
+        colors.push(color);
+    }
+    colors
+}
+
+// Конвертирует HSL в RGB
+/// Convert HSL to RGB (0..=255 per channel).
+fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (u8, u8, u8) {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let h_prime = h / 60.0;
+    let x = c * (1.0 - ((h_prime % 2.0) - 1.0).abs());
+    let (r1, g1, b1) = match h_prime as i32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        5 => (c, 0.0, x),
+        _ => (c, x, 0.0),
+    };
+    let m = l - c / 2.0;
+    (
+        ((r1 + m) * 255.0) as u8,
+        ((g1 + m) * 255.0) as u8,
+        ((b1 + m) * 255.0) as u8,
+    )
+}
+
+/// Display color of agent `id` in `lineage`, independent of its slot in the
+/// population. Lineage hues are spread by the golden ratio over lineage ids (so
+/// consecutive founders differ clearly); descendants sit within ±15° of their
+/// lineage's hue, fixed per id. The color-blind-safe palette has too few colors to
+/// vary within a lineage, so descendants share the founder's.
+fn lineage_color(id: u64, lineage: u64, palette: Palette) -> (u8, u8, u8) {
+    match palette {
+        Palette::Hsl => {
+            let base = (lineage as f64 * 0.618_033_988_749_895).fract() as f32 * 360.0;
+            let jitter = if id == lineage {
+                0.0
+            } else {
+                (id.wrapping_mul(0x9E37_79B9_7F4A_7C15) % 31) as f32 - 15.0
+            };
+            hsl_to_rgb((base + jitter).rem_euclid(360.0), 0.85, 0.65)
+        }
+        Palette::ColorBlindSafe => OKABE_ITO[(lineage % OKABE_ITO.len() as u64) as usize],
+    }
+}
+
+/// Classify the 8 cells around the head in the direction-relative frame:
+/// [left-ahead, ahead, right-ahead, left, right, left-behind, behind, right-behind],
+/// each 0 = empty, 1 = danger (wall or body), 2 = apple.
+fn local_vision(game: &Game) -> [u8; 8] {
+    vision_cells(game).map(|pos| vision_code(game, pos) as u8)
+}
+
+/// Classify one vision cell: 0 = empty, 1 = danger (wall or body), 2 = apple.
+#[inline]
+fn vision_code(game: &Game, pos: Pos) -> u32 {
+    if !game.grid.contains(pos) || game.is_obstacle(pos) || game.snake_set.contains(&pos) {
+        1 // стена/граница или тело = опасность
+    } else if pos == game.apple {
+        2 // яблоко
+    } else {
+        0 // пусто
+    }
+}
+
+/// World offsets of the `local_vision` cells for each heading (indexed by `dir as
+/// usize`), rotated once at compile time instead of per cell.
+const VISION_OFFSETS: [[(i32, i32); 8]; 4] = {
+    // (dx, dy) with dx < 0 = left and dy < 0 = ahead
+    let checks = [
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+        (-1, 0),
+        (1, 0),
+        (-1, 1),
+        (0, 1),
+        (1, 1),
+    ];
+    let mut table = [[(0, 0); 8]; 4];
+    let mut d = 0;
+    while d < 4 {
+        let mut i = 0;
+        while i < 8 {
+            let (dx, dy) = checks[i];
+            // Rotate into world coordinates: world = dx * right + (-dy) * forward
+            table[d][i] = match d {
+                0 => (dx, dy),   // Up
+                1 => (-dx, -dy), // Down
+                2 => (dy, -dx),  // Left
+                _ => (-dy, dx),  // Right
+            };
+            i += 1;
+        }
+        d += 1;
+    }
+    table
+};
+
+/// World direction of the snake's right hand for each heading (`dir as usize`).
+const RIGHT_OF: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, -1), (0, 1)];
+
+/// `local_vision` cells in the order `state_key` packs them: the bit pairs predate
+/// `local_vision` and sample the mirrored (behind) row first; the order is kept so
+/// saved Q-tables stay valid.
+const VISION_KEY_ORDER: [usize; 8] = [5, 6, 7, 3, 4, 0, 1, 2];
+
+/// World positions of the 8 cells `local_vision` classifies, in the same order
+/// (may lie outside the grid).
+fn vision_cells(game: &Game) -> [Pos; 8] {
+    let head = game.snake.front().unwrap();
+    VISION_OFFSETS[game.dir as usize].map(|(dx, dy)| Pos::new(head.x + dx, head.y + dy))
+}
+
+/// Full-width tabular state key. Q-tables index by `raw()`; fixed-size inputs (DQN
+/// embedding, NN) must go through `reduced`, so the two can't be mixed up.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct StateKey(u32);
+
+impl StateKey {
+    fn raw(self) -> u32 {
+        self.0
+    }
+
+    /// Key folded into `0..vocab` for a model with `vocab` inputs.
+    #[cfg_attr(
+        not(any(feature = "dqn-gpu", feature = "gpu-nn-experimental")),
+        allow(dead_code)
+    )]
+    fn reduced(self, vocab: u32) -> u32 {
+        self.0 % vocab
+    }
+}
+
+/// Layout of tabular state keys. Q-tables only make sense with the layout they
+/// were learned with, so agents store theirs and saved files default to `Compact`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+enum StateEncoding {
+    /// 20 bits: `state_key`.
+    #[default]
+    Compact,
+    /// 22 bits: `state_key` plus a 2-bit snake length bucket (~4M states).
+    Extended,
+}
+
+/// State key under `encoding`. `Extended` adds bits 20-21 with the snake length
+/// bucketed as <8, <32, <128 and longer, so long bodies are told apart from short ones.
+fn state_key_with(game: &Game, encoding: StateEncoding) -> StateKey {
+    let k = state_key(game);
+    match encoding {
+        StateEncoding::Compact => k,
+        StateEncoding::Extended => {
+            let len_bucket = match game.snake.len() {
+                0..8 => 0,
+                8..32 => 1,
+                32..128 => 2,
+                _ => 3,
+            };
+            StateKey(k.raw() | (len_bucket << 20))
+        }
+    }
+}
+
+/// Manhattan distance on a torus: per axis, the shorter of the direct and the wrapped way.
+fn toroidal_distance(a: Pos, b: Pos, grid: GridConfig) -> i32 {
+    let dx = (a.x - b.x).abs();
+    let dy = (a.y - b.y).abs();
+    dx.min(grid.width as i32 - dx) + dy.min(grid.height as i32 - dy)
+}
+
+/// Build a compact 20-bit state key from the game: 16 bits of local vision, 2 bits
+/// of relative direction to the apple, and 2 bits of distance bucket.
+fn state_key(game: &Game) -> StateKey {
+    // Компактный vision-based подход БЕЗ хэширования
+    // Смотрим только на критически важные клетки вокруг головы (3x3 впереди)
+    // Итого: 16 бит для vision + 4 бита для контекста = 20 бит (~1M состояний)
+    // Hot path (twice per agent per training step): offsets come from precomputed
+    // tables and cells are classified in place, without building `local_vision`.
+
+    let head = *game.snake.front().unwrap();
+    let d = game.dir as usize;
+    let mut k: u32 = 0;
+
+    // Кодируем каждую клетку 2 битами: 00=пусто, 01=опасность(стена/тело), 10=яблоко, 11=unused.
+    let offsets = &VISION_OFFSETS[d];
+    for (pair, cell) in VISION_KEY_ORDER.into_iter().enumerate() {
+        let (dx, dy) = offsets[cell];
+        k |= vision_code(game, Pos::new(head.x + dx, head.y + dy)) << (pair * 2);
+    }
+
+    // Биты 16-17: направление к яблоку (left/straight/right относительно текущего направления)
+    let (rx, ry) = RIGHT_OF[d];
+    let lateral = (game.apple.x - head.x) * rx + (game.apple.y - head.y) * ry;
+    let apple_dir = if lateral < -1 {
+        0 // left
+    } else if lateral > 1 {
+        2 // right
+    } else {
+        1 // straight-ish
+    };
+    k |= apple_dir << 16;
+
+    // Биты 18-19: дистанция до яблока (4 категории)
+    let dist = game.apple_distance(head);
+    let dist_cat = if dist <= 3 {
+        0
+    } else if dist <= 8 {
+        1
+    } else if dist <= 16 {
+        2
+    } else {
+        3
+    };
+    k |= dist_cat << 18;
+
+    StateKey(k)
+}
+
+/// Names of the `local_vision` cells, in its order.
+const VISION_CELL_NAMES: [&str; 8] = [
+    "left-ahead",
+    "ahead",
+    "right-ahead",
+    "left",
+    "right",
+    "left-behind",
+    "behind",
+    "right-behind",
+];
+
+/// `state_key(game)` in binary with its fields decoded from the key bits (not recomputed
+/// from the game), so it can be checked against the board on screen.
+fn describe_state(game: &Game) -> String {
+    let k = state_key(game).raw();
+    let class = |bits: u32| ["empty", "danger", "apple", "unused"][bits as usize];
+    // Bit pair i holds vision cell `VISION_KEY_ORDER[i]`, as in `state_key`
+    let mut cells = [""; 8];
+    for (pair, cell) in VISION_KEY_ORDER.into_iter().enumerate() {
+        cells[cell] = class((k >> (pair * 2)) & 0b11);
+    }
+    let vision: Vec<String> = VISION_CELL_NAMES
+        .iter()
+        .zip(cells)
+        .map(|(name, c)| format!("{}={}", name, c))
+        .collect();
+    let apple_dir = ["left", "straight", "right", "unused"][((k >> 16) & 0b11) as usize];
+    let dist = ["0-3", "4-8", "9-16", "17+"][((k >> 18) & 0b11) as usize];
+    format!(
+        "state {} = {:020b}\n  vision: {}\n  apple: {}, {} cells away",
+        k,
+        k,
+        vision.join(" "),
+        apple_dir,
+        dist
+    )
+}
+
+/// Length of the `state_features` vector.
+const STATE_FEATURES: usize = 33;
+
+/// Float features for neural agents: the 8 vision cells one-hot over
+/// empty/danger/apple (24), apple up/down/left/right of the head (4), Manhattan
+/// distance to the apple normalized to [0, 1] (1) and the current direction one-hot (4).
+#[cfg_attr(not(feature = "dqn-gpu"), allow(dead_code))]
+fn state_features(game: &Game) -> Vec<f32> {
+    let mut f = vec![0.0f32; STATE_FEATURES];
+    for (i, &cell) in local_vision(game).iter().enumerate() {
+        f[i * 3 + cell as usize] = 1.0;
+    }
+    let head = game.snake.front().unwrap();
+    let (dx, dy) = (game.apple.x - head.x, game.apple.y - head.y);
+    f[24] = (dy < 0) as u8 as f32;
+    f[25] = (dy > 0) as u8 as f32;
+    f[26] = (dx < 0) as u8 as f32;
+    f[27] = (dx > 0) as u8 as f32;
+    f[28] = (dx.abs() + dy.abs()) as f32 / (game.grid.width + game.grid.height) as f32;
+    let dir = match game.dir {
+        Dir::Up => 0,
+        Dir::Down => 1,
+        Dir::Left => 2,
+        Dir::Right => 3,
+    };
+    f[29 + dir] = 1.0;
+    f
+}
+
+// ============================
+// Command-line configuration and headless training
+// ============================
+
+/// DQN learning-rate decay from `dqn::LEARNING_RATE`, applied every
+/// `LR_SCHEDULE_EVERY` train steps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LrSchedule {
+    /// Straight line down to `end` over `steps` train steps, then hold.
+    Linear { end: f64, steps: u64 },
+    /// Multiply the rate by `factor` every `every` train steps.
+    Step { factor: f64, every: u64 },
+}
+
+impl LrSchedule {
+    /// Learning rate after `step` train steps, starting from `base`.
+    #[cfg_attr(not(feature = "dqn-gpu"), allow(dead_code))]
+    fn lr_at(self, base: f64, step: u64) -> f64 {
+        match self {
+            LrSchedule::Linear { end, steps } => {
+                let t = (step as f64 / steps.max(1) as f64).min(1.0);
+                base + (end - base) * t
+            }
+            LrSchedule::Step { factor, every } => {
+                base * factor.powi((step / every.max(1)).min(i32::MAX as u64) as i32)
+            }
+        }
+    }
+}
+
+/// Train steps between learning-rate updates when a schedule is set.
+#[cfg(feature = "dqn-gpu")]
+const LR_SCHEDULE_EVERY: u64 = 100;
+
+/// What a DQN toggle did, see `toggle_dqn`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(not(feature = "dqn-gpu"), allow(dead_code))]
+enum DqnToggle {
+    Created, // on, with a new agent
+    Resumed, // on, continuing the paused agent
+    Paused,  // off; the agent is kept
+}
+
+/// Flip DQN mode. Turning it off only pauses training: the agent (weights, replay
+/// and optimizer state) stays in `agent` and is resumed on the next toggle; `create`
+/// is called only when there is none. On error the mode stays off.
+#[cfg_attr(not(feature = "dqn-gpu"), allow(dead_code))]
+fn toggle_dqn<A, E>(
+    mode: &mut bool,
+    agent: &mut Option<A>,
+    create: impl FnOnce() -> Result<A, E>,
+) -> Result<DqnToggle, E> {
+    if *mode {
+        *mode = false;
+        return Ok(DqnToggle::Paused);
+    }
+    let toggle = if agent.is_some() {
+        DqnToggle::Resumed
+    } else {
+        *agent = Some(create()?);
+        DqnToggle::Created
+    };
+    *mode = true;
+    Ok(toggle)
+}
+
+/// Run configuration assembled from command-line flags.
+#[derive(Clone, Debug, PartialEq)]
+struct Config {
+    pop_size: usize,
+    seed: Option<u64>,
+    wrap_world: bool,
+    headless: bool,
+    no_render: bool, // start in ultra-fast mode, redrawing only the panel now and then
+    epochs: usize,   // headless runs only
+    grid: GridConfig,
+    fitness: FitnessMode,
+    gamma: Option<f32>,
+    agent: Option<AgentConfig>, // hyperparameters for the initial population
+    early_stop: EarlyStop,      // headless runs only
+    encoding: StateEncoding,
+    tie_break: TieBreak,
+    threads: Option<usize>, // training worker threads; None = rayon's default
+    selection: ParentSelection,
+    epsilon_schedule: EpsilonSchedule,
+    curriculum: Option<i32>,     // starting apple radius around the head
+    target_score: Option<usize>, // stop once an agent scores this; None = the full board
+    window: (u32, u32),          // window and render buffer size in pixels
+    title: String,
+    action_repeat: usize,             // game ticks per training decision
+    mutate_hypers: bool,              // mutation also perturbs alpha, gamma and decay
+    max_states: Option<usize>,        // prune each Q-table to this many states per epoch
+    max_qmem_mb: Option<usize>,       // prune the largest Q-tables past this population total
+    growth: usize,                    // cells the snake gains per apple
+    shrink: Option<u32>,              // close the board's outer ring every n moves
+    strict_tail: bool,                // moving into the departing tail's cell kills
+    stagnation_base: usize,           // stagnant epochs before the first population restart
+    dump_policy: Option<PathBuf>,     // write the saved agent's policy as CSV and exit
+    export_gif: Option<PathBuf>,      // record one episode of the saved agent and exit
+    compare_baseline: Option<usize>,  // episodes for a saved agent vs greedy baseline comparison
+    log_transitions: Option<PathBuf>, // append training transitions to this binary file
+    autosave: bool,                   // save the champion (and DQN weights) on quit
+    log_level: LogLevel,              // hide console messages below this level
+    normalize_rewards: bool,          // DQN: standardize rewards before the replay buffer
+    lr_schedule: Option<LrSchedule>,  // DQN: learning-rate decay; None keeps it fixed
+    max_grad_norm: Option<f32>,       // DQN: gradient-norm clip; None turns clipping off
+    arena: Vec<PathBuf>,              // saved agents to race instead of manual play
+    sweep: Vec<AgentConfig>,          // hyperparameter sets to train headless side by side
+}
+
+/// Stop a headless run once the champion score has not grown by at least
+/// `min_delta` for `patience` epochs. Unlike the stagnation restarts inside
+/// `reproduce`, this ends the run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct EarlyStop {
+    patience: usize, // usize::MAX = never stop early
+    min_delta: usize,
+}
+
+impl Default for EarlyStop {
+    fn default() -> Self {
+        Self {
+            patience: usize::MAX,
+            min_delta: 1,
+        }
+    }
+}
+
+/// Tunable `QAgent` hyperparameters, applied to the initial population.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AgentConfig {
+    epsilon: f32,
+    min_epsilon: f32,
+    decay: f32,
+    alpha: f32,
+    gamma: f32,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        let a = QAgent::new();
+        Self {
+            epsilon: a.epsilon,
+            min_epsilon: a.min_epsilon,
+            decay: a.decay,
+            alpha: a.alpha,
+            gamma: a.gamma,
+        }
+    }
+}
+
+impl AgentConfig {
+    fn apply(&self, agent: &mut QAgent) {
+        agent.epsilon = self.epsilon;
+        agent.epsilon_start = self.epsilon;
+        agent.min_epsilon = self.min_epsilon;
+        agent.decay = self.decay;
+        agent.alpha = self.alpha;
+        agent.gamma = self.gamma;
+    }
+
+    /// Mix the hyperparameters into `seed`, so each config gets its own
+    /// reproducible seed independent of its position in a sweep.
+    fn derive_seed(&self, seed: u64) -> u64 {
+        [
+            self.epsilon,
+            self.min_epsilon,
+            self.decay,
+            self.alpha,
+            self.gamma,
+        ]
+        .iter()
+        .fold(seed ^ 0xcbf2_9ce4_8422_2325, |h, x| {
+            (h ^ x.to_bits() as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+}
+
+/// Every combination of the comma-separated lists in `<epsilons>:<alphas>:<gammas>`,
+/// epsilon varying slowest; other hyperparameters keep their defaults.
+fn parse_sweep(spec: &str) -> Result<Vec<AgentConfig>, String> {
+    let bad = || {
+        format!(
+            "--sweep expects <epsilons>:<alphas>:<gammas>, comma-separated values in [0, 1], got {}",
+            spec
+        )
+    };
+    let lists = spec
+        .split(':')
+        .map(|list| {
+            list.split(',')
+                .map(|v| {
+                    v.trim()
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|x| (0.0..=1.0).contains(x))
+                })
+                .collect::<Option<Vec<f32>>>()
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(bad)?;
+    let [epsilons, alphas, gammas] = lists.as_slice() else {
+        return Err(bad());
+    };
+    let mut configs = Vec::new();
+    for &epsilon in epsilons {
+        for &alpha in alphas {
+            for &gamma in gammas {
+                configs.push(AgentConfig {
+                    epsilon,
+                    alpha,
+                    gamma,
+                    ..AgentConfig::default()
+                });
+            }
+        }
+    }
+    Ok(configs)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            pop_size: 24,
+            seed: None,
+            wrap_world: true,
+            headless: false,
+            no_render: false,
+            epochs: 100,
+            grid: GridConfig::default(),
+            fitness: FitnessMode::Score,
+            gamma: None,
+            agent: None,
+            early_stop: EarlyStop::default(),
+            encoding: StateEncoding::Compact,
+            tie_break: TieBreak::FirstIndex,
+            threads: None,
+            selection: ParentSelection::Uniform,
+            epsilon_schedule: EpsilonSchedule::Exponential,
+            curriculum: None,
+            target_score: None,
+            window: (WIDTH, HEIGHT),
+            title: "🐍 Snake Game".to_string(),
+            action_repeat: 1,
+            mutate_hypers: false,
+            max_states: None,
+            max_qmem_mb: None,
+            growth: 1,
+            shrink: None,
+            strict_tail: false,
+            stagnation_base: 1000,
+            dump_policy: None,
+            export_gif: None,
+            compare_baseline: None,
+            log_transitions: None,
+            autosave: false,
+            log_level: LogLevel::Info,
+            normalize_rewards: false,
+            lr_schedule: None,
+            max_grad_norm: Some(10.0),
+            arena: Vec::new(),
+            sweep: Vec::new(),
+        }
+    }
+}
+
+const USAGE: &str = "\
+Usage: snake [OPTIONS]
+
+Options:
+  --pop <n>       population size (default 24)
+  --seed <u64>    seed games, exploration and mutation for reproducible runs
+  --no-wrap       solid walls instead of wrapping around the edges
+  --headless      train without a window and print a report
+  --no-render     open the window but start in ultra-fast mode: training isn't
+                  drawn and the panel refreshes about once a second
+  --epochs <n>    epochs to run in headless mode (default 100)
+  --grid <WxH>    board size in cells (default 40x30)
+  --fitness <m>   selection ranking: score (default) or efficiency
+  --gamma <f>     Q-learning discount factor, clamped to 0.8..0.999 (default 0.95)
+  --patience <n>  headless: stop after n epochs without a new champion
+  --state <s>     state key layout: compact (default) or extended (adds snake length)
+  --tie-break <t> greedy ties among equal Q-values: first (default), straight or random
+  --threads <n>   worker threads for training (default: one per core)
+  --selection <s> parent choice among elites: uniform (default) or roulette (by score)
+  --curriculum <r> spawn apples within r cells of the head, widening every 10 epochs
+  --epsilon <e>   epsilon schedule: exp (default), linear:<decays> or cosine:<period>
+  --window <WxH>  window and render size in pixels (default 800x600)
+  --title <s>     window title
+  --action-repeat <k> training agents turn once, then go straight for k-1 more
+                  ticks before choosing again (default 1)
+  --growth <n>    the snake gains n cells per apple, one per tick (default 1)
+  --shrink <n>    challenge mode: every n moves the board's outermost open ring
+                  turns into wall, killing a snake caught in it
+  --strict-tail   moving into the cell the tail is leaving kills the snake (the old
+                  rule; by default the snake may follow its own tail)
+  --stagnation-base <n> epochs without a new champion before the population is
+                  restarted from it (default 1000; each restart adds 500)
+  --mutate-hypers mutation also occasionally nudges each agent's alpha, gamma and
+                  epsilon decay, so they evolve with the Q-tables
+  --max-states <n> cap each agent's Q-table: every epoch, keep only the n states it
+                  learned from most
+  --max-qmem-mb <n> budget for all Q-tables together (per hash-table slot, 17 bytes
+                  for Q-values and 9 for visit counts): past it, the largest
+                  tables are pruned every epoch, with a warning
+  --dump-policy <csv> write the saved agent's (snake_agent.json) greedy action and
+                  Q-values per visited state to a CSV file, then exit
+  --export-gif <path> play one greedy episode of the saved agent (snake_agent.json)
+                  and write it as an animated GIF, then exit
+  --compare-baseline <n> play n greedy episodes of the saved agent and of the
+                  hand-coded greedy baseline on the same apples, print both, exit
+  --sweep <e:a:g> train every combination of comma-separated epsilon, alpha and
+                  gamma lists headless in parallel for --epochs, print one line
+                  each, exit (e.g. 1.0,0.5:0.1,0.3:0.9,0.99)
+  --log-transitions <file> append every training transition (state, action, reward,
+                  next state, done) to a binary file
+  --autosave      on quit (Esc or closing the window), save the champion to
+                  snake_agent.json and, with DQN, its weights
+  --log-level <debug|info|warn> hide console messages below this level (default
+                  info; debug adds a line per epoch, warn keeps only problems)
+  --normalize-rewards DQN (dqn-gpu builds): standardize rewards with a running mean
+                  and std before they enter the replay buffer
+  --lr-decay <s>  DQN (dqn-gpu builds): learning-rate decay, linear:<end>:<steps>
+                  or step:<factor>:<every> (in train steps); default fixed
+  --max-grad-norm <x|off> DQN (dqn-gpu builds): clip the gradient norm of each
+                  train step to x (default 10), or off to disable clipping
+  --arena <file>  race saved agents side by side instead of manual play; repeat
+                  for each agent (4 boards per page, Tab for the next page)
+  --train-to <n>  train headless until an agent scores n (or --epochs run out);
+                  exits 0 if solved, 1 otherwise
+  -h, --help      print this help";
+
+/// Parse command-line arguments (without the program name) into a `Config`.
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Config, String> {
+    fn value<T: std::str::FromStr>(flag: &str, v: Option<String>) -> Result<T, String> {
+        let v = v.ok_or_else(|| format!("{} needs a value", flag))?;
+        v.parse()
+            .map_err(|_| format!("invalid value for {}: {}", flag, v))
+    }
+
+    fn dims(flag: &str, v: String) -> Result<(u32, u32), String> {
+        let (w, h) = v
+            .split_once(['x', 'X'])
+            .ok_or_else(|| format!("{} expects WxH, got {}", flag, v))?;
+        Ok((
+            value(flag, Some(w.to_string()))?,
+            value(flag, Some(h.to_string()))?,
+        ))
+    }
+
+    let mut cfg = Config::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pop" => cfg.pop_size = value(&arg, args.next())?,
+            "--seed" => cfg.seed = Some(value(&arg, args.next())?),
+            "--no-wrap" => cfg.wrap_world = false,
+            "--headless" => cfg.headless = true,
+            "--no-render" => cfg.no_render = true,
+            "--epochs" => cfg.epochs = value(&arg, args.next())?,
+            "--grid" => {
+                let (width, height) = dims(&arg, value(&arg, args.next())?)?;
+                cfg.grid = GridConfig { width, height };
+            }
+            "--window" => cfg.window = dims(&arg, value(&arg, args.next())?)?,
+            "--title" => cfg.title = value(&arg, args.next())?,
+            "--action-repeat" => cfg.action_repeat = value(&arg, args.next())?,
+            "--mutate-hypers" => cfg.mutate_hypers = true,
+            "--max-states" => cfg.max_states = Some(value(&arg, args.next())?),
+            "--max-qmem-mb" => cfg.max_qmem_mb = Some(value(&arg, args.next())?),
+            "--growth" => cfg.growth = value(&arg, args.next())?,
+            "--shrink" => cfg.shrink = Some(value(&arg, args.next())?),
+            "--strict-tail" => cfg.strict_tail = true,
+            "--stagnation-base" => cfg.stagnation_base = value(&arg, args.next())?,
+            "--log-transitions" => {
+                cfg.log_transitions = Some(value::<String>(&arg, args.next())?.into())
+            }
+            "--dump-policy" => cfg.dump_policy = Some(value::<String>(&arg, args.next())?.into()),
+            "--normalize-rewards" => cfg.normalize_rewards = true,
+            "--autosave" => cfg.autosave = true,
+            "--log-level" => {
+                let v: String = value(&arg, args.next())?;
+                cfg.log_level = match v.as_str() {
+                    "debug" => LogLevel::Debug,
+                    "info" => LogLevel::Info,
+                    "warn" => LogLevel::Warn,
+                    _ => {
+                        return Err(format!(
+                            "--log-level expects debug, info or warn, got {}",
+                            v
+                        ));
+                    }
+                };
+            }
+            "--lr-decay" => {
+                let v: String = value(&arg, args.next())?;
+                let bad = || {
+                    format!(
+                        "--lr-decay expects linear:<end>:<steps> or step:<factor>:<every>, got {}",
+                        v
+                    )
+                };
+                let mut parts = v.splitn(3, ':');
+                let (kind, a, b) = match (parts.next(), parts.next(), parts.next()) {
+                    (Some(kind), Some(a), Some(b)) => (kind, a, b),
+                    _ => return Err(bad()),
+                };
+                let rate: f64 = a.parse().map_err(|_| bad())?;
+                let steps: u64 = b.parse().map_err(|_| bad())?;
+                if !rate.is_finite() || rate <= 0.0 || steps == 0 {
+                    return Err(bad());
+                }
+                cfg.lr_schedule = Some(match kind {
+                    "linear" => LrSchedule::Linear { end: rate, steps },
+                    "step" => LrSchedule::Step {
+                        factor: rate,
+                        every: steps,
+                    },
+                    _ => return Err(bad()),
+                });
+            }
+            "--max-grad-norm" => {
+                let v: String = value(&arg, args.next())?;
+                cfg.max_grad_norm = match v.as_str() {
+                    "off" => None,
+                    _ => match v.parse::<f32>() {
+                        Ok(x) if x.is_finite() && x > 0.0 => Some(x),
+                        _ => {
+                            return Err(format!(
+                                "--max-grad-norm expects a positive number or off, got {}",
+                                v
+                            ));
+                        }
+                    },
+                };
+            }
+            "--arena" => cfg.arena.push(value::<String>(&arg, args.next())?.into()),
+            "--sweep" => cfg.sweep = parse_sweep(&value::<String>(&arg, args.next())?)?,
+            "--export-gif" => cfg.export_gif = Some(value::<String>(&arg, args.next())?.into()),
+            "--compare-baseline" => cfg.compare_baseline = Some(value(&arg, args.next())?),
+            "--gamma" => cfg.gamma = Some(value(&arg, args.next())?),
+            "--patience" => cfg.early_stop.patience = value(&arg, args.next())?,
+            "--curriculum" => cfg.curriculum = Some(value(&arg, args.next())?),
+            "--train-to" => {
+                cfg.target_score = Some(value(&arg, args.next())?);
+                cfg.headless = true;
+            }
+            "--state" => {
+                let v: String = value(&arg, args.next())?;
+                cfg.encoding = match v.as_str() {
+                    "compact" => StateEncoding::Compact,
+                    "extended" => StateEncoding::Extended,
+                    _ => return Err(format!("--state expects compact or extended, got {}", v)),
+                };
+            }
+            "--threads" => cfg.threads = Some(value(&arg, args.next())?),
+            "--selection" => {
+                let v: String = value(&arg, args.next())?;
+                cfg.selection = match v.as_str() {
+                    "uniform" => ParentSelection::Uniform,
+                    "roulette" => ParentSelection::Roulette,
+                    _ => {
+                        return Err(format!(
+                            "--selection expects uniform or roulette, got {}",
+                            v
+                        ));
+                    }
+                };
+            }
+            "--epsilon" => {
+                let v: String = value(&arg, args.next())?;
+                let bad = || format!("--epsilon expects exp, linear:<n> or cosine:<n>, got {}", v);
+                cfg.epsilon_schedule = match v.split_once(':') {
+                    None if v == "exp" => EpsilonSchedule::Exponential,
+                    Some(("linear", n)) => EpsilonSchedule::Linear {
+                        steps: n.parse().map_err(|_| bad())?,
+                    },
+                    Some(("cosine", n)) => EpsilonSchedule::Cosine {
+                        period: n.parse().map_err(|_| bad())?,
+                    },
+                    _ => return Err(bad()),
+                };
+            }
+            "--tie-break" => {
+                let v: String = value(&arg, args.next())?;
+                cfg.tie_break = match v.as_str() {
+                    "first" => TieBreak::FirstIndex,
+                    "straight" => TieBreak::PreferStraight,
+                    "random" => TieBreak::Random,
+                    _ => {
+                        return Err(format!(
+                            "--tie-break expects first, straight or random, got {}",
+                            v
+                        ));
+                    }
+                };
+            }
+            "--fitness" => {
+                let v: String = value(&arg, args.next())?;
+                cfg.fitness = match v.as_str() {
+                    "score" => FitnessMode::Score,
+                    "efficiency" => FitnessMode::Efficiency,
+                    _ => return Err(format!("--fitness expects score or efficiency, got {}", v)),
+                };
+            }
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    if cfg.gamma.is_some_and(|g| !g.is_finite()) {
+        return Err("--gamma must be a number".to_string());
+    }
+    if cfg.pop_size == 0 {
+        return Err("--pop must be at least 1".to_string());
+    }
+    if cfg.action_repeat == 0 {
+        return Err("--action-repeat must be at least 1".to_string());
+    }
+    if cfg.growth == 0 {
+        return Err("--growth must be at least 1".to_string());
+    }
+    if cfg.shrink == Some(0) {
+        return Err("--shrink must be at least 1".to_string());
+    }
+    if cfg.threads == Some(0) {
+        return Err("--threads must be at least 1".to_string());
+    }
+    if cfg.stagnation_base == 0 {
+        return Err("--stagnation-base must be at least 1".to_string());
+    }
+    if cfg.grid.width < 4 || cfg.grid.height < 4 {
+        return Err("--grid must be at least 4x4".to_string());
+    }
+    // Room for the centered game-over text and the right-hand stat boxes
+    if cfg.window.0 < 400 || cfg.window.1 < 300 {
+        return Err("--window must be at least 400x300".to_string());
+    }
+    Ok(cfg)
+}
+
+/// Summary of a headless training run.
+#[derive(Clone, Debug)]
+struct TrainingReport {
+    epochs: usize,
+    champion_score: usize,
+    champion_epoch: usize,
+    efficient: Option<(usize, usize)>, // efficient champion's (score, steps)
+    epoch_best: Vec<usize>,
+    epoch_ms: Vec<u64>,
+    epoch_states: Vec<usize>,
+    solved: bool,
+    solved_epoch: Option<usize>, // epoch in which an agent reached the target score
+    early_stopped: bool,
+    death_ratios: (f32, f32, f32), // wall, self-collision, timeout
+}
+
+/// Train tabular agents without a window for `cfg.epochs` epochs (or until solved).
+/// New champions are saved to `save_path` (if any), as in the windowed app.
+fn run_headless(cfg: &Config, save_path: Option<&str>) -> TrainingReport {
+    let mut evo = EvoTrainer::from_config(cfg);
+    let mut rng = evolution_rng(cfg.seed);
+    evo.training = true;
+    let (mut plateau_best, mut plateau_epochs) = (0, 0);
+    let mut early_stopped = false;
+    let mut solved_epoch = None;
+    while evo.epoch < cfg.epochs {
+        loop {
+            let all_done = evo.step_tabular();
+            evo.steps_taken += 1;
+            // Unlike the windowed loop, cap a protected leader so a looping agent can't stall the run
+            let hard_cap = evo.steps_taken >= evo.step_limit.saturating_mul(4);
+            if hard_cap || evo.epoch_over(all_done) {
+                break;
+            }
+        }
+        let epoch = evo.epoch;
+        evo.reproduce(&mut rng, save_path);
+        if evo.solved {
+            solved_epoch = Some(epoch);
+            break;
+        }
+        if evo.champion_score >= plateau_best + cfg.early_stop.min_delta {
+            (plateau_best, plateau_epochs) = (evo.champion_score, 0);
+        } else {
+            plateau_epochs += 1;
+            if plateau_epochs >= cfg.early_stop.patience {
+                early_stopped = true;
+                break;
+            }
+        }
+    }
+    evo.flush_transitions();
+    TrainingReport {
+        epochs: evo.epoch,
+        champion_score: evo.champion_score,
+        champion_epoch: evo.champion_epoch,
+        efficient: evo.champion_efficient.as_ref().map(|c| (c.score, c.steps)),
+        death_ratios: evo.death_ratios(),
+        epoch_best: evo.epoch_best,
+        epoch_ms: evo.epoch_ms,
+        epoch_states: evo.epoch_states,
+        solved: evo.solved,
+        solved_epoch,
+        early_stopped,
+    }
+}
+
+/// Train one headless run per hyperparameter set in parallel (one rayon task each,
+/// nothing shared between them). Champions are not saved.
+fn sweep(
+    configs: Vec<AgentConfig>,
+    epochs: usize,
+    seed: u64,
+) -> Vec<(AgentConfig, TrainingReport)> {
+    configs
+        .into_par_iter()
+        .map(|agent| {
+            let cfg = Config {
+                seed: Some(agent.derive_seed(seed)),
+                headless: true,
+                epochs,
+                agent: Some(agent),
+                ..Config::default()
+            };
+            (agent, run_headless(&cfg, None))
+        })
+        .collect()
+}
+
+/// Moves allowed per evaluation episode, so a looping policy still ends.
+const EVAL_MAX_STEPS: usize = 4000;
+
+/// Aggregate results of `evaluate_agent`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct EvalStats {
+    episodes: usize,
+    mean_score: f32,
+    max_score: usize,
+    min_score: usize,
+    std_score: f32, // population standard deviation
+    mean_steps: f32,
+}
+
+impl EvalStats {
+    /// Summarize per-episode `(score, steps)` pairs.
+    fn from_episodes(runs: &[(usize, usize)]) -> Self {
+        if runs.is_empty() {
+            return Self::default();
+        }
+        let n = runs.len() as f32;
+        let mean_score = runs.iter().map(|&(s, _)| s as f32).sum::<f32>() / n;
+        let var = runs
+            .iter()
+            .map(|&(s, _)| (s as f32 - mean_score).powi(2))
+            .sum::<f32>()
+            / n;
+        Self {
+            episodes: runs.len(),
+            mean_score,
+            max_score: runs.iter().map(|&(s, _)| s).max().unwrap_or(0),
+            min_score: runs.iter().map(|&(s, _)| s).min().unwrap_or(0),
+            std_score: var.sqrt(),
+            mean_steps: runs.iter().map(|&(_, st)| st as f32).sum::<f32>() / n,
+        }
+    }
+}
+
+/// Play `episodes` evaluation games with `policy` picking every move (no exploration,
+/// no learning) and summarize them. Episode `i` is reset from `seed + i`, so every
+/// policy meets the same apples and the same seed always gives the same stats.
+fn evaluate_agent<P>(
+    policy: P,
+    grid: GridConfig,
+    wrap: bool,
+    episodes: usize,
+    seed: u64,
+) -> EvalStats
+where
+    P: Fn(&mut Game) -> usize + Sync,
+{
+    let runs: Vec<(usize, usize)> = (0..episodes as u64)
+        .into_par_iter()
+        .map(|i| {
+            let mut env = SnakeEnv::new(grid, wrap, RewardConfig::default());
+            env.reset(&mut SmallRng::seed_from_u64(seed.wrapping_add(i)));
+            let mut done = false;
+            while !done && env.game.steps < EVAL_MAX_STEPS {
+                let a = policy(&mut env.game);
+                debug_assert!(a < env.action_space());
+                (_, _, done) = env.step(a);
+            }
+            (env.game.score, env.game.steps)
+        })
+        .collect();
+    EvalStats::from_episodes(&runs)
+}
+
+/// Evaluate the hand-coded `greedy_policy_action` and `champion` on identical apple
+/// sequences; returns `(baseline, champion)` stats.
+fn compare_to_baseline(
+    champion: &QAgent,
+    grid: GridConfig,
+    wrap: bool,
+    episodes: usize,
+    seed: u64,
+) -> (EvalStats, EvalStats) {
+    let baseline = evaluate_agent(|g| greedy_policy_action(g), grid, wrap, episodes, seed);
+    let learned = evaluate_agent(
+        |g| champion.act_greedy(state_key_with(g, champion.encoding), &mut g.rng),
+        grid,
+        wrap,
+        episodes,
+        seed,
+    );
+    (baseline, learned)
+}
+
+/// Load a saved agent, compare it with the greedy baseline and print both summaries.
+fn print_baseline_comparison(
+    agent_path: &str,
+    grid: GridConfig,
+    wrap: bool,
+    episodes: usize,
+    seed: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let agent: QAgent = serde_json::from_str(&fs::read_to_string(agent_path)?)?;
+    let (baseline, learned) = compare_to_baseline(&agent, grid, wrap, episodes, seed);
+    println!("[compare] {} episodes, seed {}", episodes, seed);
+    for (name, s) in [("baseline", baseline), ("champion", learned)] {
+        println!(
+            "  {}: mean {:.2} ± {:.2}  min {}  max {}  mean steps {:.0}",
+            name, s.mean_score, s.std_score, s.min_score, s.max_score, s.mean_steps
+        );
+    }
+    println!(
+        "  champion - baseline: {:+.2} apples per episode",
+        learned.mean_score - baseline.mean_score
+    );
+    Ok(())
+}
+
+/// RNG driving reproduction (mutation, parent choice); seeded runs offset it from the game seeds.
+fn evolution_rng(seed: Option<u64>) -> SmallRng {
+    match seed {
+        Some(seed) => SmallRng::seed_from_u64(seed.wrapping_add(1)),
+        None => SmallRng::from_entropy(),
+    }
+}
+
+// ============================
+// Arena: saved agents racing side by side
+// ============================
+
+/// Boards per arena page (a 2x2 grid).
+#[cfg(not(feature = "gpu-render"))]
+const ARENA_PAGE: usize = 4;
+
+/// Several saved agents, each playing greedily on its own board. Every round all boards
+/// share one seeded `AppleSequence`, so the agents see the same apples.
+#[cfg(not(feature = "gpu-render"))]
+struct Arena {
+    names: Vec<String>, // file stems, as labels
+    agents: Vec<QAgent>,
+    games: Vec<Game>,
+    grid: GridConfig,
+    wrap: bool,
+    seed: u64, // seed of the current round
+    page: usize,
+}
+
+#[cfg(not(feature = "gpu-render"))]
+impl Arena {
+    /// Load every agent file; fails on the first unreadable one.
+    fn load(paths: &[PathBuf], grid: GridConfig, wrap: bool, seed: u64) -> Result<Self, String> {
+        let mut names = Vec::new();
+        let mut agents = Vec::new();
+        let colors = generate_population_colors_with(paths.len(), Palette::Hsl);
+        for (path, color) in paths.iter().zip(colors) {
+            let text =
+                fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let mut agent: QAgent =
+                serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+            agent.color = color;
+            agents.push(agent);
+            let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned());
+            names.push(stem.unwrap_or_else(|| path.display().to_string()));
+        }
+        let mut arena = Self {
+            names,
+            agents,
+            games: Vec::new(),
+            grid,
+            wrap,
+            seed,
+            page: 0,
+        };
+        arena.restart();
+        Ok(arena)
+    }
+
+    /// New round: a fresh board per agent, all with the round's seed and apples.
+    fn restart(&mut self) {
+        let apples = AppleSequence::generate(self.seed, self.grid.cells(), self.grid);
+        self.games = (0..self.agents.len())
+            .map(|_| {
+                Game::new_in(self.grid, self.wrap, self.seed).with_apple_sequence(apples.clone())
+            })
+            .collect();
+    }
+
+    /// One greedy move on every running board; starts the next round once all are over.
+    fn step(&mut self) {
+        let mut running = false;
+        for (agent, g) in self.agents.iter().zip(self.games.iter_mut()) {
+            if g.alive && !g.won && g.steps < EVAL_MAX_STEPS {
+                let a = agent.act_greedy(state_key_with(g, agent.encoding), &mut g.rng);
+                g.apply_action(a);
+                running = true;
+            }
+        }
+        if !running {
+            self.seed = self.seed.wrapping_add(1);
+            self.restart();
+        }
+    }
+
+    fn pages(&self) -> usize {
+        self.agents.len().div_ceil(ARENA_PAGE).max(1)
+    }
+
+    fn next_page(&mut self) {
+        self.page = (self.page + 1) % self.pages();
+    }
+
+    /// Indices of the agents on the current page.
+    fn shown(&self) -> std::ops::Range<usize> {
+        let start = self.page * ARENA_PAGE;
+        start..(start + ARENA_PAGE).min(self.agents.len())
+    }
+}
+
+/// Sub-viewports (x, y, w, h) for `n` boards (at most `ARENA_PAGE`) over a `w`×`h` frame:
+/// one board fills it, two sit side by side, three or four form a 2x2 grid.
+#[cfg(not(feature = "gpu-render"))]
+fn arena_layout(n: usize, (w, h): (u32, u32)) -> Vec<(u32, u32, u32, u32)> {
+    let n = n.min(ARENA_PAGE) as u32;
+    let cols = n.clamp(1, 2);
+    let rows = n.div_ceil(cols).max(1);
+    let (sw, sh) = (w / cols, h / rows);
+    (0..n)
+        .map(|i| ((i % cols) * sw, (i / cols) * sh, sw, sh))
+        .collect()
+}
+
+/// Draw the arena's current page: each board in its sub-viewport, labeled with the
+/// agent's file name and live score.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_arena(frame: &mut Canvas, arena: &Arena) {
+    clear_rgba(frame, 10, 10, 15, 255);
+    let shown = arena.shown();
+    let mut board = Vec::new();
+    for (i, (x, y, w, h)) in arena_layout(shown.len(), frame.size())
+        .into_iter()
+        .enumerate()
+    {
+        let idx = shown.start + i;
+        let (game, agent) = (&arena.games[idx], &arena.agents[idx]);
+        board.resize((w * h * 4) as usize, 0);
+        let sub = &mut Canvas::new(&mut board, w, h);
+        clear_rgba(sub, 30, 30, 40, 255);
+        draw_game_transparent(sub, game, 255, agent.color, None);
+        blit_rgba(frame, x, y, &board, w, h);
+        stroke_rect_rgba(frame, x, y, w, h, 255, 255, 255, 60);
+        let state = if game.won {
+            " WIN"
+        } else if !game.alive {
+            " DEAD"
+        } else {
+            ""
+        };
+        let (r, g, b) = agent.color;
+        let label = format!("{}: {}{}", arena.names[idx], game.score, state);
+        draw_text(frame, &label, x + 8, y + 8, 2, (r, g, b, 255));
+    }
+    if arena.pages() > 1 {
+        let height = frame.height;
+        let text = format!("PAGE {}/{} (TAB)", arena.page + 1, arena.pages());
+        draw_text(frame, &text, 8, height - 22, 2, (200, 200, 200, 255));
+    }
+}
+
+// ============================
+// Key bindings
+// ============================
+
+/// Semantic keyboard actions of the event loop.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Action {
+    Quit,
+    Restart,
+    Pause,
+    Step,
+    ToggleEvo,
+    Save,
+    TogglePanel,
+    ToggleNn,
+    UltraFast,
+    ToggleAccel,
+    ShowBest,
+    Reseed,
+    FreezeChampion,
+    ExploreBoost,
+    StepLimitUp,
+    StepLimitDown,
+    AutoSpeed,
+    Hint,
+    Smooth,
+    Coords,
+    DescribeState,
+    ArenaPage,
+    Vision,
+    Baseline,
+    FastForward,
+    EvalSpeed,
+    Takeover,
+    Screenshot,
+    CycleTarget,
+    Palette,
+    GammaUp,
+    GammaDown,
+    MixedWalls,
+    ToggleDqn,
+    SpeedUp,
+    SpeedDown,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+}
+
+/// Every action with its name in `keybindings.toml` and its default keys.
+const DEFAULT_BINDINGS: &[(Action, &str, &[VirtualKeyCode])] = {
+    use VirtualKeyCode as K;
+    &[
+        (Action::Quit, "Quit", &[K::Escape]),
+        (Action::Restart, "Restart", &[K::R]),
+        (Action::Pause, "Pause", &[K::P]),
+        (Action::Step, "Step", &[K::Period]),
+        (Action::ToggleEvo, "ToggleEvo", &[K::E]),
+        (Action::Save, "Save", &[K::S]),
+        (Action::TogglePanel, "TogglePanel", &[K::H]),
+        (Action::ToggleNn, "ToggleNn", &[K::N]),
+        (Action::UltraFast, "UltraFast", &[K::U]),
+        (Action::ToggleAccel, "ToggleAccel", &[K::G]),
+        (Action::ShowBest, "ShowBest", &[K::B]),
+        (Action::Reseed, "Reseed", &[K::Z]),
+        (Action::FreezeChampion, "FreezeChampion", &[K::Y]),
+        (Action::ExploreBoost, "ExploreBoost", &[K::Q]),
+        (Action::StepLimitUp, "StepLimitUp", &[K::PageUp]),
+        (Action::StepLimitDown, "StepLimitDown", &[K::PageDown]),
+        (Action::AutoSpeed, "AutoSpeed", &[K::Home]),
+        (Action::Hint, "Hint", &[K::I]),
+        (Action::Smooth, "Smooth", &[K::L]),
+        (Action::Coords, "Coords", &[K::O]),
+        (Action::DescribeState, "DescribeState", &[K::Slash]),
+        (Action::ArenaPage, "ArenaPage", &[K::Tab]),
+        (Action::Vision, "Vision", &[K::V]),
+        (Action::Baseline, "Baseline", &[K::K]),
+        (Action::FastForward, "FastForward", &[K::F]),
+        (Action::EvalSpeed, "EvalSpeed", &[K::X]),
+        (Action::Takeover, "Takeover", &[K::Insert]),
+        (Action::Screenshot, "Screenshot", &[K::F12]),
+        (Action::CycleTarget, "CycleTarget", &[K::T]),
+        (Action::Palette, "Palette", &[K::C]),
+        (Action::GammaUp, "GammaUp", &[K::RBracket]),
+        (Action::GammaDown, "GammaDown", &[K::LBracket]),
+        (Action::MixedWalls, "MixedWalls", &[K::M]),
+        (Action::ToggleDqn, "ToggleDqn", &[K::J]),
+        (Action::SpeedUp, "SpeedUp", &[K::NumpadAdd, K::Equals]),
+        (
+            Action::SpeedDown,
+            "SpeedDown",
+            &[K::NumpadSubtract, K::Minus],
+        ),
+        (Action::MoveUp, "MoveUp", &[K::Up, K::W]),
+        (Action::MoveDown, "MoveDown", &[K::Down, K::S]),
+        (Action::MoveLeft, "MoveLeft", &[K::Left, K::A]),
+        (Action::MoveRight, "MoveRight", &[K::Right, K::D]),
+    ]
+};
+
+/// Key name as written in `keybindings.toml` (the `VirtualKeyCode` variant name).
+fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    macro_rules! keys {
+        ($($k:ident),* $(,)?) => {
+            match name {
+                $(stringify!($k) => Some(VirtualKeyCode::$k),)*
+                _ => None,
+            }
+        };
+    }
+    keys!(
+        A,
+        B,
+        C,
+        D,
+        E,
+        F,
+        G,
+        H,
+        I,
+        J,
+        K,
+        L,
+        M,
+        N,
+        O,
+        P,
+        Q,
+        R,
+        S,
+        T,
+        U,
+        V,
+        W,
+        X,
+        Y,
+        Z,
+        Key0,
+        Key1,
+        Key2,
+        Key3,
+        Key4,
+        Key5,
+        Key6,
+        Key7,
+        Key8,
+        Key9,
+        F1,
+        F2,
+        F3,
+        F4,
+        F5,
+        F6,
+        F7,
+        F8,
+        F9,
+        F10,
+        F11,
+        F12,
+        Up,
+        Down,
+        Left,
+        Right,
+        Space,
+        Escape,
+        Return,
+        Tab,
+        Back,
+        Delete,
+        Home,
+        End,
+        PageUp,
+        PageDown,
+        Insert,
+        Period,
+        Comma,
+        Minus,
+        Equals,
+        Slash,
+        Backslash,
+        Semicolon,
+        Apostrophe,
+        Grave,
+        LBracket,
+        RBracket,
+        NumpadAdd,
+        NumpadSubtract,
+        Numpad0,
+        Numpad1,
+        Numpad2,
+        Numpad3,
+        Numpad4,
+        Numpad5,
+        Numpad6,
+        Numpad7,
+        Numpad8,
+        Numpad9,
+    )
+}
+
+/// Keys for each action. Defaults match the README; `keybindings.toml` may override
+/// any action with one key name or a list, e.g. `Pause = "Space"` or
+/// `SpeedUp = ["Equals", "NumpadAdd"]`.
+#[derive(Clone, Debug)]
+struct KeyBindings {
+    keys: AHashMap<Action, Vec<VirtualKeyCode>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            keys: DEFAULT_BINDINGS
+                .iter()
+                .map(|&(action, _, keys)| (action, keys.to_vec()))
+                .collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Defaults with the overrides from `path` applied. A missing file is not an error;
+    /// an unreadable one or a new key conflict is reported and falls back/continues.
+    fn load(path: &str) -> Self {
+        let Ok(text) = fs::read_to_string(path) else {
+            return Self::default();
+        };
+        match Self::from_toml(&text) {
+            Ok(bindings) => {
+                let defaults = Self::default().conflicts();
+                for (key, a, b) in bindings.conflicts() {
+                    if !defaults.contains(&(key, a, b)) {
+                        log_warn!("[keys] {:?} is bound to both {:?} and {:?}", key, a, b);
+                    }
+                }
+                bindings
+            }
+            Err(e) => {
+                log_warn!("[keys] ignoring {}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Defaults with the overrides from a `keybindings.toml` document applied.
+    fn from_toml(text: &str) -> Result<Self, String> {
+        let doc = toml_edit::Document::parse(text.to_string()).map_err(|e| e.to_string())?;
+        let mut bindings = Self::default();
+        for (name, item) in doc.as_table().iter() {
+            let &(action, _, _) = DEFAULT_BINDINGS
+                .iter()
+                .find(|(_, n, _)| *n == name)
+                .ok_or_else(|| format!("unknown action {}", name))?;
+            let names: Vec<&str> = match (item.as_str(), item.as_array()) {
+                (Some(s), _) => vec![s],
+                (_, Some(list)) => list.iter().filter_map(|v| v.as_str()).collect(),
+                _ => return Err(format!("{} expects a key name or a list of them", name)),
+            };
+            let keys = names
+                .iter()
+                .map(|n| key_from_name(n).ok_or_else(|| format!("unknown key {} for {}", n, name)))
+                .collect::<Result<Vec<_>, _>>()?;
+            bindings.keys.insert(action, keys);
+        }
+        Ok(bindings)
+    }
+
+    /// Keys bound to `action`.
+    fn keys(&self, action: Action) -> &[VirtualKeyCode] {
+        self.keys.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether any key bound to `action` was pressed this frame.
+    fn pressed(&self, input: &WinitInputHelper, action: Action) -> bool {
+        self.keys(action).iter().any(|&k| input.key_pressed(k))
+    }
+
+    /// Every key bound to two actions, as (key, first, second) in `DEFAULT_BINDINGS` order.
+    /// The defaults have one on purpose: S both saves and steers down.
+    fn conflicts(&self) -> Vec<(VirtualKeyCode, Action, Action)> {
+        let mut seen: Vec<(VirtualKeyCode, Action)> = Vec::new();
+        let mut out = Vec::new();
+        for &(action, _, _) in DEFAULT_BINDINGS {
+            for &key in self.keys(action) {
+                if let Some(&(_, first)) = seen.iter().find(|(k, _)| *k == key) {
+                    out.push((key, first, action));
+                } else {
+                    seen.push((key, action));
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Adapter requests tried in order until one succeeds, as (power preference,
+/// force fallback): a high-performance GPU, a low-power one, then wgpu's fallback
+/// (software) adapter.
+const ADAPTER_ATTEMPTS: [(PowerPreference, bool); 3] = [
+    (PowerPreference::HighPerformance, false),
+    (PowerPreference::LowPower, false),
+    (PowerPreference::LowPower, true),
+];
+
+/// Run `request(power, force_fallback)` over `ADAPTER_ATTEMPTS` and return the first
+/// adapter it yields.
+async fn request_adapter_with_fallback<A, F, Fut>(mut request: F) -> Option<A>
+where
+    F: FnMut(PowerPreference, bool) -> Fut,
+    Fut: std::future::Future<Output = Option<A>>,
+{
+    for (power, force_fallback) in ADAPTER_ATTEMPTS {
+        if let Some(adapter) = request(power, force_fallback).await {
+            return Some(adapter);
+        }
+    }
+    None
+}
+
+/// One-line adapter description for the log and the panel, e.g. "NVIDIA GeForce
+/// RTX 3060 (Vulkan)".
+fn describe_adapter(info: &wgpu::AdapterInfo) -> String {
+    format!("{} ({:?})", info.name, info.backend)
+}
+
+/// Load a saved agent and write its policy CSV; returns the number of states written.
+fn dump_saved_policy(agent_path: &str, csv: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    let agent: QAgent = serde_json::from_str(&fs::read_to_string(agent_path)?)?;
+    agent.dump_policy_csv(csv)?;
+    Ok(agent.table_size())
+}
+
+/// Entry point: parses flags, then either trains headless or sets up the window,
+/// renderer, input loop, and optionally runs evolutionary training.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let cfg = match parse_args(args) {
+        Ok(cfg) => cfg,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            std::process::exit(2);
+        }
+    };
+    logging::set_level(cfg.log_level);
+    if let Some(csv) = &cfg.dump_policy {
+        match dump_saved_policy("snake_agent.json", csv) {
+            Ok(states) => println!("[policy] {} states written to {}", states, csv.display()),
+            Err(e) => {
+                eprintln!("Failed to dump policy: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    if let Some(episodes) = cfg.compare_baseline {
+        let seed = cfg.seed.unwrap_or_else(|| SmallRng::from_entropy().r#gen());
+        if let Err(e) =
+            print_baseline_comparison("snake_agent.json", cfg.grid, cfg.wrap_world, episodes, seed)
+        {
+            eprintln!("Failed to compare with the baseline: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if !cfg.sweep.is_empty() {
+        let seed = cfg.seed.unwrap_or_else(|| SmallRng::from_entropy().r#gen());
+        println!(
+            "[sweep] {} configs, {} epochs, seed {}",
+            cfg.sweep.len(),
+            cfg.epochs,
+            seed
+        );
+        for (agent, report) in sweep(cfg.sweep.clone(), cfg.epochs, seed) {
+            println!(
+                "[sweep] epsilon {:.3}  alpha {:.3}  gamma {:.3}: champion {} (epoch {})  epochs {}  solved: {}",
+                agent.epsilon,
+                agent.alpha,
+                agent.gamma,
+                report.champion_score,
+                report.champion_epoch,
+                report.epochs,
+                report.solved
+            );
+        }
+        return Ok(());
+    }
+    if let Some(gif) = &cfg.export_gif {
+        #[cfg(not(feature = "gpu-render"))]
+        {
+            let seed = cfg.seed.unwrap_or_else(|| SmallRng::from_entropy().r#gen());
+            match export_champion_gif("snake_agent.json", gif, cfg.grid, cfg.wrap_world, seed) {
+                Ok(frames) => println!("[gif] {} frames written to {}", frames, gif.display()),
+                Err(e) => {
+                    eprintln!("Failed to export GIF: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        #[cfg(feature = "gpu-render")]
+        {
+            eprintln!("--export-gif {} needs the CPU renderer", gif.display());
+            std::process::exit(2);
+        }
+    }
+    if cfg.headless {
+        let report = run_headless(&cfg, Some("snake_agent.json"));
+        println!(
+            "[headless] epochs: {}  champion: {} (epoch {})  solved: {}  early stop: {}",
+            report.epochs,
+            report.champion_score,
+            report.champion_epoch,
+            report.solved,
+            report.early_stopped
+        );
+        if let Some((score, steps)) = report.efficient {
+            println!(
+                "[headless] efficient champion: {} apples in {} steps",
+                score, steps
+            );
+        }
+        let (wall, body, timeout) = report.death_ratios;
+        println!(
+            "[headless] episode ends: wall {:.0}%  self {:.0}%  timeout {:.0}%",
+            wall * 100.0,
+            body * 100.0,
+            timeout * 100.0
+        );
+        if !report.epoch_ms.is_empty() {
+            let total: u64 = report.epoch_ms.iter().sum();
+            println!(
+                "[headless] epoch time: mean {} ms, total {:.1} s",
+                total / report.epoch_ms.len() as u64,
+                total as f32 / 1000.0
+            );
+        }
+        let tail = report.epoch_best.len().saturating_sub(10);
+        println!(
+            "[headless] best per epoch (last 10): {:?}",
+            &report.epoch_best[tail..]
+        );
+        println!(
+            "[headless] champion Q-table states per epoch (last 10): {:?}",
+            &report.epoch_states[tail..]
+        );
+        if let Some(target) = cfg.target_score {
+            match report.solved_epoch {
+                Some(epoch) => println!("[headless] reached {} in epoch {}", target, epoch),
+                None => {
+                    println!(
+                        "[headless] {} not reached in {} epochs",
+                        target, report.epochs
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        return Ok(());
+    }
+    // The CPU renderer stretches cells to the window; the GPU one draws fixed 20 px cells
+    #[cfg(feature = "gpu-render")]
+    if cfg.grid.width * GRID_SIZE > cfg.window.0 || cfg.grid.height * GRID_SIZE > cfg.window.1 {
+        eprintln!(
+            "--grid {}x{} does not fit the {}x{} window (max {}x{})",
+            cfg.grid.width,
+            cfg.grid.height,
+            cfg.window.0,
+            cfg.window.1,
+            cfg.window.0 / GRID_SIZE,
+            cfg.window.1 / GRID_SIZE
+        );
+        std::process::exit(2);
+    }
+    #[cfg(feature = "gpu-nn")]
+    {
+        log_info!("[mode] GPU NN feature enabled (scaffold)");
+    }
+    #[cfg(feature = "gpu-nn-experimental")]
+    {
+        log_info!("[mode] GPU NN experimental backend enabled");
+    }
+    let event_loop = EventLoop::new();
+    let mut input = WinitInputHelper::new();
+
+    let (width, height) = cfg.window;
+    let window = WindowBuilder::new()
+        .with_title(cfg.title.as_str())
+        .with_inner_size(LogicalSize::new(width, height))
+        .with_resizable(true) // allow resizing
+        .build(&event_loop)
+        .unwrap();
+
+    #[cfg(not(feature = "gpu-render"))]
+    let mut pixels = {
+        let window_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
+        Pixels::new(width, height, surface_texture)?
+    };
+    #[cfg(feature = "gpu-render")]
+    let mut gpu = pollster::block_on(gpu_render::GpuRenderer::new(&window, width, height))?;
+
+    let mut game = Game::new_manual(cfg.grid, cfg.wrap_world)
+        .with_growth(cfg.growth)
+        .with_shrink(cfg.shrink)
+        .with_strict_tail(cfg.strict_tail);
+    let mut evo = EvoTrainer::from_config(&cfg); // популяция 24 по умолчанию (--pop)
+    #[cfg(feature = "gpu-nn")]
+    let mut nn_mode: bool = false;
+    #[cfg(feature = "dqn-gpu")]
+    let mut dqn_mode: bool = false; // toggle DQN training
+    #[cfg(feature = "dqn-gpu")]
+    let mut dqn_agent: Option<dqn::DqnAgent> = None;
+    #[cfg(all(feature = "gpu-nn-experimental", feature = "gpu-nn"))]
+    let mut nn_trainer: Option<gpu_nn::GpuTrainer> = Some(gpu_nn::GpuTrainer::new(256, 128, 3));
+
+    // Try to load saved agent and auto-start training if found
+    let save_path = "snake_agent.json";
+    let keys = KeyBindings::load("keybindings.toml");
+    let agent_loaded = if let Err(e) = evo.load_best(save_path) {
+        log_warn!("Could not load saved agent: {}", e);
+        false
+    } else {
+        log_info!("✅ Loaded saved agent from {}", save_path);
+        true
+    };
+
+    // An explicit --gamma wins over the one stored with the loaded agent
+    if let Some(gamma) = cfg.gamma {
+        evo.set_gamma(gamma);
+    }
+    // ...but the state layout can't change under a learned Q-table
+    if evo.encoding != cfg.encoding {
+        log_info!(
+            "[state] keeping the loaded agent's {:?} encoding (--state {:?} ignored)",
+            evo.encoding,
+            cfg.encoding
+        );
+    }
+
+    // Auto-start evolution if agent was loaded
+    if agent_loaded {
+        evo.training = true;
+        log_info!("🚀 Auto-starting evolution with loaded agent");
+    }
+
+    // If CUDA is available, auto-enable DQN and start evolution
+    #[cfg(all(feature = "dqn-gpu", feature = "dqn-gpu-cuda"))]
+    {
+        if let Ok(cuda_dev) = candle_core::Device::new_cuda(0) {
+            // Initialize DQN agent on CUDA
+            match dqn::DqnAgent::new_features(STATE_FEATURES, 256, dqn::REPLAY_CAPACITY, &cuda_dev)
+            {
+                Ok(mut agent) => {
+                    #[cfg(feature = "dqn-gpu")]
+                    {
+                        if cfg.normalize_rewards {
+                            agent.reward_norm = Some(dqn::RewardNormalizer::default());
+                        }
+                        agent.max_grad_norm = cfg.max_grad_norm;
+                        agent.action_repeat = cfg.action_repeat;
+                        dqn_mode = true;
+                        dqn_agent = Some(agent);
+                        log_info!("[DQN] auto-enabled (device: Cuda(0))");
+                        // Prefer solid walls for DQN training
+                        evo.set_wrap_world(false);
+                        log_info!("[DQN] using solid walls (no wrap) for training");
+                        if !evo.training {
+                            evo.training = true;
+                            log_info!("[hint] CUDA detected: auto-starting Evolution with DQN");
+                        }
+                    }
+                }
+                Err(e) => {
+                    log_warn!("[DQN] auto-enable failed: {}", e);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "gpu-render")]
+    if !cfg.arena.is_empty() {
+        eprintln!("--arena needs the CPU renderer");
+        std::process::exit(2);
+    }
+    #[cfg(not(feature = "gpu-render"))]
+    let mut arena = if cfg.arena.is_empty() {
+        None
+    } else {
+        let seed = cfg.seed.unwrap_or_else(|| SmallRng::from_entropy().r#gen());
+        match Arena::load(&cfg.arena, cfg.grid, cfg.wrap_world, seed) {
+            Ok(arena) => {
+                // The arena replaces manual play; nothing trains meanwhile
+                evo.training = false;
+                log_info!(
+                    "[arena] {} agents, {} page(s)",
+                    arena.agents.len(),
+                    arena.pages()
+                );
+                Some(arena)
+            }
+            Err(e) => {
+                eprintln!("Failed to load arena agent {}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let mut rng: SmallRng = evolution_rng(cfg.seed);
+    let mut last_update = Instant::now();
+    let mut tick_duration = Duration::from_millis(150);
+    let mut manual_speed_delta_ms: i32 = 0;
+    let mut evo_steps_per_frame: u32 = 1; // начальная скорость = 1 шаг за кадр (медленно для наблюдения)
+    let speed_schedule = SpeedSchedule::default();
+    let mut auto_speed: bool = false; // Home: `speed_schedule` sets `evo_steps_per_frame`
+    let mut panel_visible: bool = true; // panel visibility toggle
+    let mut frame_counter: u32 = 0; // counter for skipping frames
+    let mut fast_forward: bool = false; // best-agent view: run silently until it eats
+    let mut apple_watch = ScoreWatch::default();
+    let mut replay_frames: u32 = 0; // frames left to show around the last apple
+    let mut eval_speed: bool = false; // best-agent view: train at a watchable pace
+    let mut takeover: bool = false; // best-agent view: arrow keys steer the shown agent
+    let mut last_eval_step = Instant::now();
+    // Evolution step budget to spread very large step counts across ticks
+    let mut evo_pending_steps: u32 = 0;
+    let mut ultra_fast: bool = cfg.no_render; // training ultra-fast mode (disable render, raise cap)
+    let mut max_steps_per_tick: u32 = step_budget(ultra_fast, false); // cap work per tick to keep UI responsive
+    let mut last_panel_redraw = Instant::now(); // --no-render: throttles panel-only redraws
+    let mut show_only_best: bool = false; // render only the best agent during training
+    let mut show_hint: bool = false; // manual play: arrow from the head toward the apple
+    let mut smooth: bool = false; // manual play: interpolate the snake between ticks
+    let mut show_coords: bool = false; // manual play: tooltip for the cell under the mouse
+    #[cfg(not(feature = "gpu-render"))]
+    let mut view = Viewport::full(cfg.window); // manual play: wheel zooms, right-drag pans
+    #[cfg(not(feature = "gpu-render"))]
+    let mut manual_redraw = DirtyRedraw::default(); // manual play: repaint only changed cells
+    #[cfg(not(feature = "gpu-render"))]
+    let mut drag: Option<((f32, f32), Pos)> = None; // pan start: frame pixel and view offset
+    let mut show_vision: bool = false; // best-agent view: overlay the 8 cells the agent sees
+    let mut baseline: bool = false; // manual game is driven by `greedy_policy_action`
+    let mut high_score = load_high_score(HIGH_SCORE_PATH); // manual play only
+    // GPU detection (wgpu) and accel flags
+    let mut gpu_available: bool = false;
+    let mut gpu_enabled: bool = false;
+    // Adapter description shown in the panel
+    #[cfg_attr(feature = "gpu-render", allow(unused_variables))]
+    let gpu_adapter: Option<String> = {
+        let instance = Instance::new(wgpu::InstanceDescriptor {
+            backends: Backends::all(),
+            ..Default::default()
+        });
+        let adapter = pollster::block_on(request_adapter_with_fallback(|power, force_fallback| {
+            instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: power,
+                compatible_surface: None,
+                force_fallback_adapter: force_fallback,
+            })
+        }));
+        match adapter {
+            Some(adapter) => {
+                let info = adapter.get_info();
+                log_info!(
+                    "[gpu] adapter: {} [{:?}]",
+                    describe_adapter(&info),
+                    info.device_type
+                );
+                // A software adapter is no reason to raise the training budget
+                if info.device_type != wgpu::DeviceType::Cpu {
+                    gpu_available = true;
+                    gpu_enabled = true;
+                    max_steps_per_tick = step_budget(ultra_fast, gpu_enabled);
+                }
+                Some(describe_adapter(&info))
+            }
+            None => {
+                log_info!(
+                    "[gpu] no adapter (tried high-performance, low-power and fallback); using the CPU budget"
+                );
+                None
+            }
+        }
+    };
+    // FPS counter and measured environment steps per second during evolution
+    let mut fps = RateMeter::new(Instant::now());
+    let mut step_rate = RateMeter::new(Instant::now());
+    let mut screenshot_requested: bool = false; // set by F12, handled on next redraw
+
+    event_loop.run(move |event, _, control_flow| {
+        // Control flow is chosen once per frame below: Poll while training (or
+        // interpolating), WaitUntil the next tick in manual play. Input events still wake us.
+
+        if let Event::LoopDestroyed = event {
+            evo.flush_transitions();
+            return;
+        }
+
+        // A minimized window has a 0x0 surface: keep polling but don't draw
+        let size = window.inner_size();
+        if let Event::RedrawRequested(_) = event
+            && !should_skip_render(size.width, size.height)
+        {
+            // Mouse position in frame pixels; the surface may be scaled and letterboxed after a resize
+            #[cfg(not(feature = "gpu-render"))]
+            let hover = input
+                .mouse()
+                .filter(|_| show_coords)
+                .and_then(|pos| pixels.window_pos_to_pixel(pos).ok());
+            #[cfg(not(feature = "gpu-render"))]
+            let frame = &mut Canvas::new(pixels.frame_mut(), width, height);
+
+            // Draw the appropriate game(s) [CPU framebuffer path]
+            #[cfg(not(feature = "gpu-render"))]
+            if evo.training {
+                manual_redraw.invalidate();
+                // Rendering strategy tuned for performance at high EVO speeds
+                if ultra_fast {
+                    // Ultra-fast: no render during training; --no-render keeps the old
+                    // frame and only repaints the panel over it
+                    if !cfg.no_render {
+                        clear_rgba(frame, 10, 10, 15, 255);
+                    }
+                } else if show_only_best {
+                    // Always render only the best agent
+                    clear_rgba(frame, 10, 10, 15, 255);
+                    if let Some(best_game_idx) = evo.best_game_index() {
+                        let agent_color = evo.pop[best_game_idx].color;
+                        draw_game_transparent(
+                            frame,
+                            &evo.games[best_game_idx],
+                            220,
+                            agent_color,
+                            None,
+                        );
+                        if show_vision {
+                            draw_vision(frame, &evo.games[best_game_idx]);
+                        }
+                    }
+                } else if evo_steps_per_frame < 8_192 {
+                    // Low/medium speed: draw grid + agents
+                    clear_rgba(frame, 30, 30, 40, 255);
+                    // Draw grid first
+                    let cell = CellSize::for_grid(evo.grid, frame.size());
+                    let (width, height) = frame.size();
+                    for y in 0..evo.grid.height {
+                        for x in 0..evo.grid.width {
+                            if (x + y) % 2 == 0 {
+                                let gx = x * cell.w;
+                                let gy = y * cell.h;
+                                for py in gy..gy + cell.h {
+                                    for px in gx..gx + cell.w {
+                                        if px < width && py < height {
+                                            let idx = ((py * width + px) * 4) as usize;
+                                            if idx + 3 < frame.len() {
+                                                frame[idx] = 35;
+                                                frame[idx + 1] = 35;
+                                                frame[idx + 2] = 50;
+                                                frame[idx + 3] = 255;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    // Draw all individuals only when really slow; otherwise only best
+                    if evo_steps_per_frame < 4_096 {
+                        for (agent, g) in evo.pop.iter().zip(evo.games.iter()) {
+                            let agent_color = agent.color;
+                            // The neck is where the head was a tick ago
+                            draw_game_transparent(
+                                frame,
+                                g,
+                                180,
+                                agent_color,
+                                g.snake.get(1).copied(),
+                            );
+                        }
+                    } else if let Some(best_game_idx) = evo
+                        .scores
+                        .iter()
+                        .enumerate()
+                        .max_by_key(|(_, score)| *score)
+                        .map(|(idx, _)| idx)
+                        && best_game_idx < evo.pop.len()
+                        && best_game_idx < evo.games.len()
+                    {
+                        let agent_color = evo.pop[best_game_idx].color;
+                        draw_game_transparent(
+                            frame,
+                            &evo.games[best_game_idx],
+                            220,
+                            agent_color,
+                            None,
+                        );
+                    }
+                } else if evo_steps_per_frame < 20_000 {
+                    // High speed: skip grid entirely; draw best only on plain background
+                    clear_rgba(frame, 10, 10, 15, 255);
+                    if let Some(best_game_idx) = evo
+                        .scores
+                        .iter()
+                        .enumerate()
+                        .max_by_key(|(_, score)| *score)
+                        .map(|(idx, _)| idx)
+                        && best_game_idx < evo.pop.len()
+                        && best_game_idx < evo.games.len()
+                    {
+                        let agent_color = evo.pop[best_game_idx].color;
+                        draw_game_transparent(
+                            frame,
+                            &evo.games[best_game_idx],
+                            220,
+                            agent_color,
+                            None,
+                        );
+                    }
+                } else {
+                    // Ultra-high speed: don't render agents at all
+                    clear_rgba(frame, 10, 10, 15, 255);
+                }
+            } else {
+                #[cfg(not(feature = "gpu-render"))]
+                if let Some(arena) = &arena {
+                    manual_redraw.invalidate();
+                    draw_arena(frame, arena);
+                } else {
+                    if smooth {
+                        manual_redraw.invalidate();
+                        let t = last_update.elapsed().as_secs_f32() / tick_duration.as_secs_f32();
+                        draw_interpolated(frame, &game, t, view);
+                    } else {
+                        // Panel (or its SHOW button) and the FPS counter; must match what is drawn below
+                        let panel = if panel_visible { PANEL_RECT } else { SHOW_BUTTON_RECT };
+                        let fps_area = (frame.width.saturating_sub(8 + 140), 8, 140, 16);
+                        let plain = !show_hint && hover.is_none() && view.scale <= 1;
+                        manual_redraw.draw(frame, &game, view, plain, vec![panel, fps_area]);
+                    }
+                    if show_hint && game.alive {
+                        game.draw_hint(frame, view);
+                    }
+                    draw_minimap(frame, &game, view);
+                    if let Some((px, py)) = hover
+                        && let Some(cell) = view.to_grid(px as f32, py as f32, game.grid)
+                    {
+                        let label = format!("({},{}): {}", cell.x, cell.y, game.cell_contents(cell));
+                        draw_tooltip(frame, px as u32 + 12, py as u32 + 12, &label);
+                    }
+                }
+            }
+
+            // Controls overlay (semi-transparent) - only draw if visible
+            #[cfg(not(feature = "gpu-render"))]
+            if panel_visible {
+                let (panel_x, panel_y, panel_w, panel_h) = PANEL_RECT;
+                let btn_h: u32 = 32; // increased button height
+                let btn_w: u32 = panel_w - 16;
+                let btn_x: u32 = panel_x + 8;
+                // Chart area inside panel (positioned below HUD option lines)
+                let chart_y: u32 = panel_y + 360; // moved further down to avoid text overlap
+                let chart_h: u32 = 120; // increased chart height
+                let btn1_y: u32 = chart_y + chart_h + 8; // start buttons after chart
+                let btn2_y: u32 = btn1_y + btn_h + 6;
+                let btn3_y: u32 = btn2_y + btn_h + 6;
+                let btn4_y: u32 = btn3_y + btn_h + 6;
+                let btn5_y: u32 = btn4_y + btn_h + 6; // hide button
+                let btn6_y: u32 = btn5_y + btn_h + 6; // show-only-best button
+
+                fill_rect_rgba(frame, panel_x, panel_y, panel_w, panel_h, 0, 0, 0, 140);
+                stroke_rect_rgba(frame, panel_x, panel_y, panel_w, panel_h, 255, 255, 255, 60);
+                draw_text(
+                    frame,
+                    "CONTROLS",
+                    panel_x + 10,
+                    panel_y + 10,
+                    2,
+                    (180, 220, 255, 255),
+                );
+                if let Some(adapter) = &gpu_adapter {
+                    let text: String = adapter.chars().take(60).collect();
+                    draw_text(
+                        frame,
+                        &text,
+                        panel_x + 10,
+                        panel_y + 28,
+                        1,
+                        (150, 170, 200, 255),
+                    );
+                }
+                // Estimated memory held by the population's Q-tables (see --max-states)
+                let q_bytes: usize = evo.pop.iter().map(QAgent::table_bytes).sum();
+                draw_text(
+                    frame,
+                    &format!("Q MEM: {:.1}MB", q_bytes as f32 / (1024.0 * 1024.0)),
+                    panel_x + 200,
+                    panel_y + 10,
+                    2,
+                    (180, 220, 255, 255),
+                );
+                // HUD inside panel with extra line spacing
+                draw_text(
+                    frame,
+                    &format!("SCORE: {}   HIGH: {}", game.score, high_score),
+                    panel_x + 10,
+                    panel_y + 40,
+                    2,
+                    (230, 230, 230, 255),
+                );
+                draw_text(
+                    frame,
+                    &format!("LENGTH: {}   GAMMA: {:.2}", game.snake.len(), evo.gamma),
+                    panel_x + 10,
+                    panel_y + 70,
+                    2,
+                    (200, 200, 200, 255),
+                );
+                #[cfg(feature = "gpu-nn")]
+                {
+                    let nn_label = if nn_mode { "ON" } else { "OFF" };
+                    draw_text(
+                        frame,
+                        &format!("NN MODE [N]: {}", nn_label),
+                        panel_x + 10,
+                        panel_y + 100,
+                        2,
+                        (180, 220, 180, 255),
+                    );
+                }
+                // Speed indicator: measured steps/s while training, tick duration otherwise
+                let speed = if evo.training {
+                    let epoch = evo
+                        .mean_epoch_ms()
+                        .map_or_else(|| "-".to_string(), |ms| format!("{:.2}S", ms / 1000.0));
+                    format!("SPEED: {:.0}/S  EPOCH: {}", step_rate.rate(), epoch)
+                } else {
+                    let ms = tick_duration.as_millis() as f32;
+                    let sps = if ms > 0.0 { 1000.0 / ms } else { 0.0 };
+                    format!("SPEED: {} ms (~{:.1}/s)", ms as i32, sps)
+                };
+                draw_text(
+                    frame,
+                    &speed,
+                    panel_x + 10,
+                    panel_y + 100,
+                    2,
+                    (200, 220, 255, 255),
+                );
+
+                // Evolutionary training status
+                draw_text(
+                    frame,
+                    &format!("EVO: {} (E)", if evo.training { "ON" } else { "OFF" }),
+                    panel_x + 10,
+                    panel_y + 130,
+                    2,
+                    (220, 200, 240, 255),
+                );
+                let gpu_str = if gpu_enabled {
+                    "GPU"
+                } else if gpu_available {
+                    "GPU avail"
+                } else {
+                    "CPU"
+                };
+                draw_text(
+                    frame,
+                    &format!("ACCEL: {}  (G)", gpu_str),
+                    panel_x + 200,
+                    panel_y + 130,
+                    2,
+                    (180, 255, 200, 255),
+                );
+                let snap = evo.snapshot();
+                draw_text(
+                    frame,
+                    &format!(
+                        "EPOCH: {}  ALIVE: {}/{}",
+                        snap.epoch, snap.alive_count, evo.pop_size
+                    ),
+                    panel_x + 10,
+                    panel_y + 160,
+                    2,
+                    (220, 200, 240, 255),
+                );
+                draw_text(
+                    frame,
+                    &format!("TARGET: {}  BEST: {}", evo.target_score, snap.best_score),
+                    panel_x + 10,
+                    panel_y + 190,
+                    2,
+                    (220, 200, 240, 255),
+                );
+                // Exploration: the best running agent's epsilon and the population mean
+                let (best_eps, mean_eps) = evo.epsilon_summary();
+                let best_eps = best_eps.map_or_else(|| "-".to_string(), |e| format!("{:.2}", e));
+                draw_text(
+                    frame,
+                    &format!("EPSILON: {}  AVG: {:.2} (Q)", best_eps, mean_eps),
+                    panel_x + 10,
+                    panel_y + 205,
+                    2,
+                    (220, 200, 240, 255),
+                );
+                // Leader protection HUD: show when unique leader bypasses step limit
+                {
+                    let (mut top1, mut top2, mut top1_idx) = (0usize, 0usize, None::<usize>);
+                    for (i, &sc) in evo.scores.iter().enumerate() {
+                        if sc > top1 {
+                            top2 = top1;
+                            top1 = sc;
+                            top1_idx = Some(i);
+                        } else if sc > top2 {
+                            top2 = sc;
+                        }
+                    }
+                    let leader_protected = if let Some(idx) = top1_idx {
+                        (top1 > top2) && evo.games.get(idx).map(|g| g.alive).unwrap_or(false)
+                    } else {
+                        false
+                    };
+                    // Shaped reward the best agent has collected this epoch (any backend)
+                    if let Some(reward) = evo
+                        .best_game_index()
+                        .and_then(|i| evo.episode_rewards.get(i))
+                    {
+                        draw_text(
+                            frame,
+                            &format!("REWARD: {:.1}", reward),
+                            panel_x + 10,
+                            panel_y + 220,
+                            2,
+                            (220, 200, 240, 255),
+                        );
+                    }
+                    if leader_protected {
+                        draw_text(
+                            frame,
+                            "LEADER: protected",
+                            panel_x + 170,
+                            panel_y + 220,
+                            2,
+                            (120, 255, 120, 255),
+                        );
+                    }
+                }
+
+                // Champion info with epoch
+                if snap.champion_score > 0 {
+                    draw_text(
+                        frame,
+                        &format!(
+                            "CHAMPION: {} (epoch {})",
+                            snap.champion_score, snap.champion_epoch
+                        ),
+                        panel_x + 10,
+                        panel_y + 240,
+                        2,
+                        (255, 215, 0, 255),
+                    );
+                } else {
+                    draw_text(
+                        frame,
+                        "CHAMPION: None",
+                        panel_x + 10,
+                        panel_y + 240,
+                        2,
+                        (255, 215, 0, 255),
+                    );
+                }
+
+                // Stagnation warning
+                if snap.epochs_without_improvement > 0 {
+                    let threshold = snap.stagnation_threshold;
+                    let color = if snap.epochs_without_improvement > threshold.saturating_sub(200) {
+                        (255, 100, 100, 255)
+                    } else {
+                        (200, 200, 200, 255)
+                    };
+                    draw_text(
+                        frame,
+                        &format!(
+                            "No improvement: {}/{} (restarts: {})",
+                            snap.epochs_without_improvement, threshold, snap.restart_count
+                        ),
+                        panel_x + 10,
+                        panel_y + 275,
+                        2,
+                        color,
+                    );
+                }
+
+                let limit = if evo.next_step_limit != evo.step_limit {
+                    format!("STEP LIMIT: {} (NEXT: {})", evo.step_limit, evo.next_step_limit)
+                } else {
+                    format!("STEP LIMIT: {} (PGUP/PGDN)", evo.step_limit)
+                };
+                draw_text(
+                    frame,
+                    &limit,
+                    panel_x + 10,
+                    panel_y + 257,
+                    2,
+                    (220, 200, 240, 255),
+                );
+
+                let ultra_str = if ultra_fast { "ON" } else { "OFF" };
+                let best_str = if show_only_best { "ON" } else { "OFF" };
+                // Split into two lines to keep within panel width
+                draw_text(
+                    frame,
+                    &format!(
+                        "EVO SPD: {} steps/frame ({})",
+                        evo_steps_per_frame,
+                        if auto_speed { "AUTO" } else { "+/-" }
+                    ),
+                    panel_x + 10,
+                    panel_y + 300,
+                    2,
+                    (200, 220, 255, 255),
+                );
+                draw_text(
+                    frame,
+                    &format!("ULTRA (U): {}    BEST (B): {}", ultra_str, best_str),
+                    panel_x + 10,
+                    panel_y + 320,
+                    2,
+                    (200, 220, 255, 255),
+                );
+                #[cfg(feature = "dqn-gpu")]
+                if let Some(agent) = dqn_agent.as_ref().filter(|_| dqn_mode) {
+                    let loss = if agent.in_warmup() {
+                        "WARMUP".to_string()
+                    } else {
+                        agent
+                            .last_loss
+                            .map_or_else(|| "-".to_string(), |l| format!("{:.4}", l))
+                    };
+                    draw_text(
+                        frame,
+                        &format!(
+                            "DQN LOSS: {}  BUF: {:.0}%  LR: {:.1e}",
+                            loss,
+                            agent.replay.fill_ratio() * 100.0,
+                            agent.learning_rate()
+                        ),
+                        panel_x + 10,
+                        panel_y + 340,
+                        2,
+                        (180, 255, 200, 255),
+                    );
+                }
+                // Chart of best apples per epoch
+                draw_chart(
+                    frame,
+                    panel_x + 10,
+                    chart_y,
+                    panel_w - 20,
+                    chart_h,
+                    &evo.epoch_best,
+                );
+                draw_chart_line(
+                    frame,
+                    panel_x + 10,
+                    chart_y,
+                    panel_w - 20,
+                    chart_h,
+                    &evo.epoch_states,
+                );
+
+                let paused_label = if game.paused {
+                    "RESUME  P"
+                } else {
+                    "PAUSE   P"
+                };
+                draw_button(frame, btn_x, btn1_y, btn_w, btn_h, paused_label);
+                draw_button(frame, btn_x, btn2_y, btn_w, btn_h, "SPEED+  +");
+                draw_button(frame, btn_x, btn3_y, btn_w, btn_h, "RESTART R");
+                draw_button(frame, btn_x, btn4_y, btn_w, btn_h, "SAVE    S");
+                draw_button(frame, btn_x, btn5_y, btn_w, btn_h, "HIDE    H");
+                let best_label = if show_only_best {
+                    "BEST ON  B"
+                } else {
+                    "BEST OFF B"
+                };
+                draw_button(frame, btn_x, btn6_y, btn_w, btn_h, best_label);
+
+                // Leaderboard of the current epoch (top-right, below FPS)
+                if evo.training {
+                    let entries: Vec<(usize, usize, (u8, u8, u8))> =
+                        top_k_indices(&evo.scores, 5)
+                            .into_iter()
+                            .filter(|&i| i < evo.pop.len())
+                            .map(|i| (i, evo.scores[i], evo.pop[i].color))
+                            .collect();
+                    let right = frame.width.saturating_sub(8 + 140);
+                    draw_leaderboard(frame, right, 30, &entries);
+                    let below = 30 + 30 + entries.len() as u32 * 20 + 8;
+                    draw_death_stats(frame, right, below, evo.death_ratios());
+                    let efficient = evo.champion_efficient.as_ref().map(|c| (c.score, c.steps));
+                    draw_champions(
+                        frame,
+                        right,
+                        below + 98,
+                        evo.champion_score,
+                        efficient,
+                    );
+                    draw_action_histogram(frame, right, below + 216, evo.action_histogram());
+                }
+            } else {
+                // Draw small button to show panel again
+                let (show_btn_x, show_btn_y, show_btn_w, show_btn_h) = SHOW_BUTTON_RECT;
+                draw_button(frame, show_btn_x, show_btn_y, show_btn_w, show_btn_h, "SHOW H");
+            }
+
+            // Update and draw FPS counter (top-right, green); paused frames don't count
+            if game.paused {
+                fps.restart(Instant::now());
+            } else {
+                fps.add(1, Instant::now());
+            }
+            #[cfg(not(feature = "gpu-render"))]
+            {
+                let fps_text = if game.paused {
+                    "PAUSED".to_string()
+                } else {
+                    format!("FPS: {:.0}", fps.rate())
+                };
+                let scale: u32 = 2;
+                let advance = 5 * scale + scale; // glyph width + spacing
+                let text_w: u32 = fps_text.chars().count() as u32 * advance;
+                let fps_x: u32 = frame.width.saturating_sub(text_w + 8);
+                let fps_y: u32 = 8;
+                draw_text(frame, &fps_text, fps_x, fps_y, scale, (80, 255, 120, 255));
+            }
+
+            #[cfg(not(feature = "gpu-render"))]
+            {
+                // Capture after the overlay is drawn so the panel is part of the shot
+                if screenshot_requested {
+                    screenshot_requested = false;
+                    let path = screenshot_path();
+                    match save_frame_png(pixels.frame(), width, height, &path) {
+                        Ok(()) => log_info!("📸 Screenshot saved to {}", path.display()),
+                        Err(e) => log_warn!("Failed to save screenshot: {}", e),
+                    }
+                }
+                if pixels.render().is_err() {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            #[cfg(feature = "gpu-render")]
+            {
+                // Build instances for gpu renderer
+                let mut instances: Vec<gpu_render::Instance> = Vec::with_capacity(1024);
+                // Background grid is drawn in shader. Add apple and snakes.
+                let push_snake = |g: &Game, alpha: f32, color: (u8,u8,u8), out: &mut Vec<gpu_render::Instance>| {
+                    if !g.alive { return; }
+                    // Apple
+                    out.push(gpu_render::Instance { gx: g.apple.x as u32, gy: g.apple.y as u32, r: 0.86, g: 0.2, b: 0.2, a: alpha });
+                    // Snake segments
+                    let (cr, cg, cb) = (color.0 as f32 / 255.0, color.1 as f32 / 255.0, color.2 as f32 / 255.0);
+                    for (i, &pos) in g.snake.iter().enumerate() {
+                        let fade = if i == 0 { 1.3 } else { (1.0 - (i as f32 * 0.015)).clamp(0.5, 1.0) };
+                        out.push(gpu_render::Instance {
+                            gx: pos.x as u32,
+                            gy: pos.y as u32,
+                            r: (cr * fade).min(1.0),
+                            g: (cg * fade).min(1.0),
+                            b: (cb * fade).min(1.0),
+                            a: alpha,
+                        });
+                    }
+                };
+
+                if evo.training {
+                    if ultra_fast {
+                        // nothing to draw
+                    } else if show_only_best {
+                        if let Some(best_game_idx) = evo
+                            .scores
+                            .iter()
+                            .enumerate()
+                            .max_by_key(|(_, score)| *score)
+                            .map(|(idx, _)| idx)
+                            && best_game_idx < evo.pop.len()
+                            && best_game_idx < evo.games.len()
+                        {
+                            let agent_color = evo.pop[best_game_idx].color;
+                            push_snake(&evo.games[best_game_idx], 0.86, agent_color, &mut instances);
+                        }
+                    } else if evo_steps_per_frame < 4_096 {
+                        for (agent, g) in evo.pop.iter().zip(evo.games.iter()) {
+                            push_snake(g, 0.7, agent.color, &mut instances);
+                        }
+                    } else if let Some(best_game_idx) = evo
+                        .scores
+                        .iter()
+                        .enumerate()
+                        .max_by_key(|(_, score)| *score)
+                        .map(|(idx, _)| idx)
+                        && best_game_idx < evo.pop.len()
+                        && best_game_idx < evo.games.len()
+                    {
+                        let agent_color = evo.pop[best_game_idx].color;
+                        push_snake(&evo.games[best_game_idx], 0.86, agent_color, &mut instances);
+                    }
+                } else {
+                    // Normal game
+                    push_snake(&game, 1.0, (80, 220, 80), &mut instances);
+                }
+
+                if screenshot_requested {
+                    screenshot_requested = false;
+                    let path = screenshot_path();
+                    match gpu.render_and_capture(&instances) {
+                        Ok((w, h, rgba)) => match save_frame_png(&rgba, w, h, &path) {
+                            Ok(()) => log_info!("📸 Screenshot saved to {}", path.display()),
+                            Err(e) => log_warn!("Failed to save screenshot: {}", e),
+                        },
+                        Err(e) => log_warn!("Failed to capture screenshot: {e}"),
+                    }
+                } else if let Err(e) = gpu.render(&instances) {
+                    log_warn!("gpu present failed: {e}");
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+        }
+
+        // Handle window resize
+        if let Event::WindowEvent {
+            event: winit::event::WindowEvent::Resized(new_size),
+            ..
+        } = &event
+        {
+            #[cfg(not(feature = "gpu-render"))]
+            if !should_skip_render(new_size.width, new_size.height)
+                && let Err(e) = pixels.resize_surface(new_size.width, new_size.height)
+            {
+                log_warn!("Failed to resize surface: {}", e);
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+            #[cfg(feature = "gpu-render")]
+            {
+                gpu.resize(new_size.width, new_size.height);
+            }
+        }
+
+        if input.update(&event) {
+            // Handle quit
+            if keys.pressed(&input, Action::Quit)
+                || input.close_requested()
+                || input.destroyed()
+            {
+                if cfg.autosave {
+                    match save_champion_on_exit(&evo, save_path) {
+                        Ok(true) => log_info!("Champion saved to {} on exit", save_path),
+                        Ok(false) => log_info!("No champion yet; nothing saved on exit"),
+                        Err(e) => log_warn!("Failed to save champion on exit: {}", e),
+                    }
+                    #[cfg(feature = "dqn-gpu")]
+                    if let Some(agent) = dqn_agent.as_ref() {
+                        let dqn_path = Path::new("snake_dqn.safetensors");
+                        match agent.export_weights(dqn_path) {
+                            Ok(()) => log_info!("DQN weights saved to {}", dqn_path.display()),
+                            Err(e) => log_warn!("Failed to save DQN weights: {}", e),
+                        }
+                    }
+                }
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+
+            // Handle restart (at any time in manual play)
+            if keys.pressed(&input, Action::Restart) && !evo.training {
+                if !baseline {
+                    record_high_score(HIGH_SCORE_PATH, &mut high_score, game.score);
+                }
+                game = game.restarted();
+                tick_duration = Duration::from_millis(150);
+                last_update = Instant::now();
+            }
+
+            // Handle pause
+            if keys.pressed(&input, Action::Pause) {
+                game.paused = !game.paused;
+            }
+            // Single-step the paused manual game (independent of the tick timer)
+            if keys.pressed(&input, Action::Step) && game.paused && !evo.training {
+                game.step_once();
+                window.request_redraw();
+            }
+
+            // Evolution toggle only
+            if keys.pressed(&input, Action::ToggleEvo) {
+                evo.training = !evo.training;
+                if evo.training {
+                    evo.solved = false;
+                    evo.reset_epoch();
+                    evo.epoch = 0;
+                    evo.epoch_best.clear();
+                    evo.epoch_ms.clear();
+                    evo.epoch_states.clear();
+                    evo.best_score = 0;
+                    evo.epochs_without_improvement = 0;
+                    game = Game::new_manual(cfg.grid, cfg.wrap_world)
+                        .with_growth(cfg.growth)
+                        .with_shrink(cfg.shrink)
+                        .with_strict_tail(cfg.strict_tail);
+                }
+            }
+
+            // Save agent
+            if keys.pressed(&input, Action::Save) {
+                if let Err(e) = evo.save_best(save_path) {
+                    log_warn!("Failed to save agent: {}", e);
+                } else {
+                    log_info!("Agent saved to {}", save_path);
+                }
+                if evo.champion_efficient.is_some() {
+                    let path = efficient_save_path(save_path);
+                    match evo.save_efficient(&path) {
+                        Ok(()) => log_info!("Efficient champion saved to {}", path),
+                        Err(e) => log_warn!("Failed to save efficient champion: {}", e),
+                    }
+                }
+                #[cfg(feature = "dqn-gpu")]
+                if let Some(agent) = dqn_agent.as_ref() {
+                    let dqn_path = Path::new("snake_dqn.safetensors");
+                    match agent.export_weights(dqn_path) {
+                        Ok(()) => log_info!("DQN weights saved to {}", dqn_path.display()),
+                        Err(e) => log_warn!("Failed to save DQN weights: {}", e),
+                    }
+                }
+            }
+
+            // Toggle panel visibility
+            if keys.pressed(&input, Action::TogglePanel) {
+                panel_visible = !panel_visible;
+            }
+            #[cfg(feature = "gpu-nn")]
+            {
+                if keys.pressed(&input, Action::ToggleNn) {
+                    nn_mode = !nn_mode;
+                    if nn_mode {
+                        log_info!("[gpu-nn] Enabled NN mode (experimental)");
+                    } else {
+                        log_info!("[gpu-nn] Disabled NN mode");
+                    }
+                }
+            }
+            // Ultra-fast toggle
+            if keys.pressed(&input, Action::UltraFast) {
+                ultra_fast = !ultra_fast;
+                max_steps_per_tick = step_budget(ultra_fast, gpu_enabled);
+            }
+            // Toggle GPU acceleration mode (just adjusts training budget for now)
+            if keys.pressed(&input, Action::ToggleAccel) && gpu_available {
+                gpu_enabled = !gpu_enabled;
+                max_steps_per_tick = step_budget(ultra_fast, gpu_enabled);
+                    log_info!(
+                        "[hint] G toggles step budget only (not GPU learning). Use J to toggle DQN, and E to start training."
+                    );
+            }
+            if keys.pressed(&input, Action::ShowBest) {
+                show_only_best = !show_only_best;
+            }
+            if keys.pressed(&input, Action::Reseed) && evo.training {
+                if evo.reseed_from_champion(&mut rng) {
+                    log_info!(
+                        "[reseed] population rebuilt from the champion (score {})",
+                        evo.champion_score
+                    );
+                } else {
+                    log_info!("[reseed] no champion yet, nothing to restore");
+                }
+            }
+            if keys.pressed(&input, Action::FreezeChampion) {
+                evo.set_freeze_champion(!evo.freeze_champion);
+                log_info!(
+                    "[freeze] champion {}",
+                    if evo.freeze_champion {
+                        "frozen from the next epoch"
+                    } else {
+                        "unfrozen"
+                    }
+                );
+            }
+            if keys.pressed(&input, Action::ExploreBoost) {
+                evo.bump_epsilon(EPSILON_BUMP);
+                let (_, mean) = evo.epsilon_summary();
+                log_info!("[explore] epsilon raised, population mean now {:.2}", mean);
+            }
+            for (action, delta) in [
+                (Action::StepLimitUp, STEP_LIMIT_STEP as i64),
+                (Action::StepLimitDown, -(STEP_LIMIT_STEP as i64)),
+            ] {
+                if keys.pressed(&input, action) {
+                    let n = (evo.next_step_limit as i64 + delta).max(0) as u32;
+                    evo.set_step_limit(n);
+                    log_info!("[steps] episode limit {} from the next epoch", evo.next_step_limit);
+                }
+            }
+            if keys.pressed(&input, Action::Hint) {
+                show_hint = !show_hint;
+            }
+            if keys.pressed(&input, Action::Smooth) {
+                smooth = !smooth;
+            }
+            if keys.pressed(&input, Action::Coords) {
+                show_coords = !show_coords;
+            }
+            if keys.pressed(&input, Action::DescribeState) && !evo.training && game.alive {
+                println!("[state] {}", describe_state(&game));
+            }
+            #[cfg(not(feature = "gpu-render"))]
+            if keys.pressed(&input, Action::ArenaPage)
+                && let Some(arena) = &mut arena
+            {
+                arena.next_page();
+            }
+            if keys.pressed(&input, Action::Baseline) && !evo.training {
+                baseline = !baseline;
+                log_info!("[baseline] greedy policy {}", if baseline { "on" } else { "off" });
+            }
+            if keys.pressed(&input, Action::EvalSpeed) {
+                eval_speed = !eval_speed;
+                log_info!(
+                    "[view] evaluation speed {}",
+                    if eval_speed { "on (best-agent view)" } else { "off" }
+                );
+            }
+            if keys.pressed(&input, Action::Takeover) {
+                takeover = !takeover;
+                log_info!(
+                    "[view] takeover {}",
+                    if takeover { "on (arrow keys steer the best agent)" } else { "off" }
+                );
+            }
+            if keys.pressed(&input, Action::FastForward) {
+                fast_forward = !fast_forward;
+                replay_frames = 0;
+                log_info!(
+                    "[view] fast-forward to next apple {}",
+                    if fast_forward { "on (best-agent view)" } else { "off" }
+                );
+            }
+            if keys.pressed(&input, Action::Vision) {
+                show_vision = !show_vision;
+            }
+            if keys.pressed(&input, Action::Screenshot) {
+                screenshot_requested = true;
+                window.request_redraw();
+            }
+            if keys.pressed(&input, Action::CycleTarget) {
+                evo.set_target_score(next_target_preset(
+                    evo.target_score,
+                    default_target_score(evo.grid, evo.growth),
+                ));
+                log_info!("[target] training stops once an agent scores {}", evo.target_score);
+            }
+            if keys.pressed(&input, Action::Palette) {
+                evo.set_palette(evo.palette.toggled());
+                log_info!("[palette] {:?}", evo.palette);
+            }
+            // Nudge the discount factor for the whole population (and the DQN)
+            let gamma_step = if keys.pressed(&input, Action::GammaUp) {
+                0.01
+            } else if keys.pressed(&input, Action::GammaDown) {
+                -0.01
+            } else {
+                0.0
+            };
+            if gamma_step != 0.0 {
+                evo.set_gamma(evo.gamma + gamma_step);
+                #[cfg(feature = "dqn-gpu")]
+                if let Some(agent) = dqn_agent.as_mut() {
+                    agent.gamma = evo.gamma;
+                }
+                log_info!("[gamma] {:.3}", evo.gamma);
+                if evo.training && evo.steps_taken > 0 {
+                    log_warn!("[gamma] warning: changed in the middle of an epoch");
+                }
+            }
+            if keys.pressed(&input, Action::MixedWalls) {
+                if evo.has_mixed_walls() {
+                    evo.set_wrap_world(true);
+                    log_info!("[walls] all games wrap around");
+                } else {
+                    evo.set_mixed_walls();
+                    log_info!("[walls] mixed: half the games wrap, half have solid walls");
+                }
+            }
+
+            #[cfg(feature = "dqn-gpu")]
+            {
+                if keys.pressed(&input, Action::ToggleDqn) {
+                    let dev = dqn::preferred_device();
+                    let dev_print = format!("{:?}", dev);
+                    let toggled = toggle_dqn(&mut dqn_mode, &mut dqn_agent, || {
+                        let mut agent = dqn::DqnAgent::new_features(STATE_FEATURES, 256, dqn::REPLAY_CAPACITY, &dev)?;
+                        if cfg.normalize_rewards {
+                            agent.reward_norm = Some(dqn::RewardNormalizer::default());
+                        }
+                        agent.max_grad_norm = cfg.max_grad_norm;
+                        agent.action_repeat = cfg.action_repeat;
+                        Ok::<_, candle_core::Error>(agent)
+                    });
+                    match toggled {
+                        Ok(DqnToggle::Paused) => {
+                            log_info!("[DQN] paused (agent kept; J resumes it)");
+                            // Restore default wrap mode when DQN is off
+                            evo.set_wrap_world(true);
+                        }
+                        Ok(toggle) => {
+                            if toggle == DqnToggle::Created {
+                                log_info!("[DQN] enabled (device: {})", dev_print);
+                            } else if let Some(agent) = dqn_agent.as_ref() {
+                                log_info!("[DQN] resumed after {} train steps", agent.train_steps);
+                            }
+                            evo.set_wrap_world(false);
+                            log_info!("[DQN] using solid walls (no wrap) for training");
+                            if !evo.training {
+                                log_info!(
+                                    "[hint] DQN is active only during Evolution. Press E to start training."
+                                );
+                            }
+                        }
+                        Err(e) => log_warn!("[DQN] init failed: {}", e),
+                    }
+                }
+            }
+
+            if keys.pressed(&input, Action::AutoSpeed) {
+                auto_speed = !auto_speed;
+                log_info!("[speed] automatic schedule {}", if auto_speed { "on" } else { "off" });
+            }
+            // Speed controls (keyboard)
+            if evo.training {
+                let manual = keys.pressed(&input, Action::SpeedUp)
+                    || keys.pressed(&input, Action::SpeedDown);
+                if manual && auto_speed {
+                    auto_speed = false; // a manual speed change takes over
+                    log_info!("[speed] automatic schedule off");
+                }
+                if keys.pressed(&input, Action::SpeedUp)
+                {
+                    evo_steps_per_frame = (evo_steps_per_frame.saturating_mul(2)).min(100_000); // increased max from 10_000 to 100_000
+                }
+                if keys.pressed(&input, Action::SpeedDown)
+                {
+                    evo_steps_per_frame = (evo_steps_per_frame / 2).max(1);
+                }
+            } else {
+                if keys.pressed(&input, Action::SpeedUp)
+                {
+                    manual_speed_delta_ms = (manual_speed_delta_ms - 10).max(-150);
+                }
+                if keys.pressed(&input, Action::SpeedDown)
+                {
+                    manual_speed_delta_ms = (manual_speed_delta_ms + 10).min(300);
+                }
+            }
+
+            // Manual play: mouse wheel zooms around the cursor, right-drag pans
+            #[cfg(not(feature = "gpu-render"))]
+            if !evo.training {
+                let mouse = input
+                    .mouse()
+                    .and_then(|pos| pixels.window_pos_to_pixel(pos).ok())
+                    .map(|(x, y)| (x as f32, y as f32));
+                let scroll = input.scroll_diff();
+                if scroll != 0.0 {
+                    let center = Pos::new(
+                        view.offset.x + view.visible(game.grid).width as i32 / 2,
+                        view.offset.y + view.visible(game.grid).height as i32 / 2,
+                    );
+                    let focus = mouse
+                        .and_then(|(x, y)| view.to_grid(x, y, game.grid))
+                        .unwrap_or(center);
+                    view = view.zoomed(scroll.signum() as i32, focus, game.grid);
+                }
+                if input.mouse_pressed(1) {
+                    drag = mouse.map(|start| (start, view.offset));
+                } else if !input.mouse_held(1) {
+                    drag = None;
+                }
+                if let (Some(((sx, sy), start)), Some((x, y))) = (drag, mouse) {
+                    let cell = view.cell(game.grid);
+                    let dx = ((sx - x) / cell.w as f32).round() as i32;
+                    let dy = ((sy - y) / cell.h as f32).round() as i32;
+                    view = Viewport {
+                        offset: Pos::new(start.x + dx, start.y + dy),
+                        ..view
+                    }
+                    .clamped(game.grid);
+                }
+            }
+
+            // Handle direction changes; with takeover they steer the best agent instead
+            let steer_best = takeover && evo.training && show_only_best;
+            for (action, dir) in [
+                (Action::MoveUp, Dir::Up),
+                (Action::MoveDown, Dir::Down),
+                (Action::MoveLeft, Dir::Left),
+                (Action::MoveRight, Dir::Right),
+            ] {
+                if !keys.pressed(&input, action) {
+                    continue;
+                }
+                if steer_best {
+                    evo.override_best(dir);
+                } else {
+                    game.change_dir(dir);
+                }
+            }
+
+            // Mouse clicks on overlay buttons
+            #[cfg(not(feature = "gpu-render"))]
+            if let Some((mx, my)) = input
+                .mouse()
+                .and_then(|pos| pixels.window_pos_to_pixel(pos).ok())
+                && input.mouse_pressed(0)
+            {
+                let mx = mx as u32;
+                let my = my as u32;
+
+                if panel_visible {
+                    let (panel_x, panel_y, panel_w, _) = PANEL_RECT;
+                    let btn_h: u32 = 32;
+                    let btn_w: u32 = panel_w - 16;
+                    let btn_x: u32 = panel_x + 8;
+                    let chart_y: u32 = panel_y + 360; // must match the drawn panel
+                    let chart_h: u32 = 120;
+                    let btn1_y: u32 = chart_y + chart_h + 8;
+                    let btn2_y: u32 = btn1_y + btn_h + 6;
+                    let btn3_y: u32 = btn2_y + btn_h + 6;
+                    let btn4_y: u32 = btn3_y + btn_h + 6;
+                    let btn5_y: u32 = btn4_y + btn_h + 6;
+                    let btn6_y: u32 = btn5_y + btn_h + 6;
+                    if point_in_rect(mx, my, btn_x, btn1_y, btn_w, btn_h) {
+                        game.paused = !game.paused;
+                    } else if point_in_rect(mx, my, btn_x, btn2_y, btn_w, btn_h) {
+                        if evo.training {
+                            evo_steps_per_frame =
+                                (evo_steps_per_frame.saturating_mul(2)).min(100_000);
+                        }
+                        // increased max from 10_000
+                        else {
+                            manual_speed_delta_ms = (manual_speed_delta_ms - 10).max(-150);
+                        }
+                    } else if point_in_rect(mx, my, btn_x, btn3_y, btn_w, btn_h) {
+                        if !baseline {
+                            record_high_score(HIGH_SCORE_PATH, &mut high_score, game.score);
+                        }
+                        game = Game::new_manual(cfg.grid, cfg.wrap_world)
+                            .with_growth(cfg.growth)
+                            .with_shrink(cfg.shrink)
+                            .with_strict_tail(cfg.strict_tail);
+                        tick_duration = Duration::from_millis(150);
+                    } else if point_in_rect(mx, my, btn_x, btn4_y, btn_w, btn_h) {
+                        if let Err(e) = evo.save_best(save_path) {
+                            log_warn!("Failed to save agent: {}", e);
+                        } else {
+                            log_info!("Agent saved to {}", save_path);
+                        }
+                    } else if point_in_rect(mx, my, btn_x, btn5_y, btn_w, btn_h) {
+                        panel_visible = false;
+                    } else if point_in_rect(mx, my, btn_x, btn6_y, btn_w, btn_h) {
+                        show_only_best = !show_only_best;
+                    }
+                } else {
+                    // Check if clicked on show button
+                    let (show_btn_x, show_btn_y, show_btn_w, show_btn_h) = SHOW_BUTTON_RECT;
+                    if point_in_rect(mx, my, show_btn_x, show_btn_y, show_btn_w, show_btn_h) {
+                        panel_visible = true;
+                    }
+                }
+            }
+
+                    // Evolutionary training loop (population of agents)
+                    if evo.training {
+                *control_flow = ControlFlow::Poll;
+                let evaluating = eval_speed && show_only_best && !ultra_fast;
+                let watching = fast_forward && show_only_best && !ultra_fast && !evaluating;
+                if let Some(interval) = training_step_interval(evaluating)
+                    && !game.paused
+                {
+                    // Hold the whole population back so the shown agent can be followed
+                    if last_eval_step.elapsed() < interval {
+                        *control_flow = ControlFlow::WaitUntil(last_eval_step + interval);
+                        return;
+                    }
+                    last_eval_step = Instant::now();
+                    evo_pending_steps = 0;
+                }
+                if auto_speed {
+                    evo_steps_per_frame =
+                        speed_schedule.steps_per_frame(evo.best_score, max_steps_per_tick);
+                }
+                let steps_per_frame: u32 = if evaluating || (watching && replay_frames > 0) {
+                    1 // slow down so the agent can be seen
+                } else {
+                    evo_steps_per_frame.max(1)
+                };
+                if game.paused {
+                    step_rate.restart(Instant::now());
+                    evo.epoch_timer.pause(Instant::now());
+                    window.request_redraw();
+                    return;
+                }
+                evo.epoch_timer.resume(Instant::now());
+
+                // Accumulate desired work and process in chunks to avoid long UI stalls
+                evo_pending_steps = evo_pending_steps.saturating_add(steps_per_frame);
+                let to_run = evo_pending_steps.min(max_steps_per_tick);
+                let mut ran_steps: u32 = 0;
+                for _ in 0..to_run {
+                    let mut all_done = true;
+                    let target_score = evo.target_score;
+                    #[cfg(any(
+                        feature = "dqn-gpu",
+                        all(feature = "gpu-nn-experimental", feature = "gpu-nn")
+                    ))]
+                    let len = evo.pop.len().min(evo.games.len()).min(evo.scores.len());
+                    // Two paths: GPU NN inference (sequential/batched) vs CPU tabular Q-learning (parallel)
+                    #[cfg(all(feature = "gpu-nn-experimental", feature = "gpu-nn"))]
+                    let nn_active = nn_mode && nn_trainer.is_some();
+                    #[cfg(not(all(feature = "gpu-nn-experimental", feature = "gpu-nn")))]
+                    let nn_active = false;
+
+                    if nn_active {
+                        // Batched GPU policy inference for alive agents
+                        #[cfg(all(feature = "gpu-nn-experimental", feature = "gpu-nn"))]
+                        {
+                            let trainer = nn_trainer.as_ref().unwrap();
+                            // Collect indices and states
+                            let mut idxs: Vec<usize> = Vec::with_capacity(len);
+                            let mut states: Vec<u32> = Vec::with_capacity(len);
+                            for i in 0..len {
+                                if evo.games[i].alive && evo.scores[i] < target_score {
+                                    idxs.push(i);
+                                    // keep within input size used at init
+                                    states.push(state_key(&evo.games[i]).reduced(256));
+                                }
+                            }
+                            if !idxs.is_empty() {
+                                let probs = trainer.infer_to_vec(&states, 256, 3);
+                                // Apply actions and update each game sequentially
+                                let mut logged = Vec::new();
+                                for (k, &i) in idxs.iter().enumerate() {
+                                    let p0 = probs[k * 3];
+                                    let p1 = probs[k * 3 + 1];
+                                    let p2 = probs[k * 3 + 2];
+                                    let a_idx = if p0 >= p1 && p0 >= p2 { 0 } else if p1 >= p2 { 1 } else { 2 };
+
+                                    let g = &mut evo.games[i];
+                                    let key = evo.transition_log.is_some().then(|| state_key_with(g, evo.encoding));
+                                    // Inference only: the reward is tracked for the panel, not learned from
+                                    let (outcome, reward) = g.step_repeated(a_idx, evo.action_repeat, &evo.rewards);
+                                    if let Some(total) = evo.episode_rewards.get_mut(i) {
+                                        *total += reward;
+                                    }
+                                    if let Some(key) = key {
+                                        let next = state_key_with(g, evo.encoding);
+                                        let done = outcome.died || !g.alive;
+                                        logged.push((key.raw(), a_idx as u8, reward, next.raw(), done as u8));
+                                    }
+
+                                    if g.alive || g.won {
+                                        evo.scores[i] = g.score;
+                                    }
+                                }
+                                evo.log_transitions(logged);
+                            }
+
+                            // Check solved/all_done
+                            if evo.scores.iter().zip(evo.games.iter()).any(|(s, g)| g.alive && *s < target_score) {
+                                all_done = false;
+                            }
+                        }
+                    } else {
+                        #[cfg(feature = "dqn-gpu")]
+                        let dqn_active = dqn_mode && dqn_agent.is_some();
+                        #[cfg(not(feature = "dqn-gpu"))]
+                        let dqn_active = false;
+
+                        // If DQN mode is enabled, use a single shared DQN for action selection & learning
+                        if dqn_active {
+                            #[cfg(feature = "dqn-gpu")]
+                            if let Some(agent) = dqn_agent.as_mut() {
+                                // Iterate sequentially to accumulate transitions
+                                let mut logged = Vec::new();
+                                for i in 0..len {
+                                    let g = &mut evo.games[i];
+                                    if !g.alive || evo.scores[i] >= target_score { continue; }
+                                    let key = evo.transition_log.is_some().then(|| state_key_with(g, evo.encoding));
+                                    let s = state_features(g);
+                                    // Greedy action from DQN
+                                    let a_idx = agent.select_action_features(&s).unwrap_or(1);
+                                    // Reward shaping identical to tabular path
+                                    let (outcome, reward) = g.step_repeated(a_idx, evo.action_repeat, &evo.rewards);
+                                    if let Some(total) = evo.episode_rewards.get_mut(i) {
+                                        *total += reward;
+                                    }
+                                    let ns = state_features(g);
+                                    let done = outcome.died || !g.alive;
+                                    agent.push_transition_features(i, &s, a_idx, reward, &ns, done);
+                                    if let Some(key) = key {
+                                        let next = state_key_with(g, evo.encoding);
+                                        logged.push((key.raw(), a_idx as u8, reward, next.raw(), done as u8));
+                                    }
+                                    if g.alive || g.won { evo.scores[i] = g.score; }
+                                }
+                                evo.log_transitions(logged);
+                                // Train a few steps per iteration
+                                if let Ok(Some(_)) = agent.train_step(256)
+                                    && let Some(schedule) = cfg.lr_schedule
+                                    && agent.train_steps % LR_SCHEDULE_EVERY == 0
+                                {
+                                    agent.set_learning_rate(
+                                        schedule.lr_at(dqn::LEARNING_RATE, agent.train_steps),
+                                    );
+                                }
+                            }
+                            // all_done check
+                            if evo.scores.iter().zip(evo.games.iter()).any(|(s, g)| g.alive && *s < target_score) {
+                                all_done = false;
+                            }
+                        } else {
+                        // CPU tabular Q-learning path (parallel)
+                        all_done = evo.step_tabular();
+                        }
+                    }
+
+                    evo.steps_taken += 1;
+                    ran_steps += 1;
+                    // A unique leading agent still alive bypasses the step limit
+                    if evo.epoch_over(all_done) {
+                        // All individuals finished or step limit reached - start new epoch
+                        #[cfg(feature = "dqn-gpu")]
+                        if let Some(agent) = dqn_agent.as_mut() {
+                            agent.clear_pending();
+                        }
+                        evo.reproduce(&mut rng, Some(save_path));
+                        evo_pending_steps = 0; // reset pending work on epoch change
+                        break;
+                    }
+                    if watching
+                        && let Some(i) = evo.best_game_index()
+                        && apple_watch.observe(i, evo.scores[i])
+                    {
+                        replay_frames = FAST_FORWARD_REPLAY_FRAMES;
+                        evo_pending_steps = 0; // drop queued work and show the apple
+                        break;
+                    }
+                }
+                // Reduce pending work by the amount actually processed
+                evo_pending_steps = evo_pending_steps.saturating_sub(ran_steps);
+                step_rate.add(ran_steps as u64, Instant::now());
+
+                // Update screen less frequently on high speeds to improve performance
+                frame_counter += 1;
+                let frames_to_skip = if ultra_fast {
+                    8
+                } else if evo_steps_per_frame >= 65_536 {
+                    20 // update screen every 20th iteration
+                } else if evo_steps_per_frame >= 40_000 {
+                    12 // every 12th
+                } else if evo_steps_per_frame >= 20_000 {
+                    6 // every 6th
+                } else if evo_steps_per_frame >= 8_192 {
+                    3 // every 3rd
+                } else if evo_steps_per_frame >= 4_096 {
+                    2 // every 2nd
+                } else {
+                    1 // update every iteration
+                };
+
+                if evaluating {
+                    window.request_redraw();
+                } else if watching {
+                    if replay_frames > 0 {
+                        replay_frames -= 1;
+                        window.request_redraw();
+                    }
+                } else if !ultra_fast && frame_counter >= frames_to_skip {
+                    frame_counter = 0;
+                    window.request_redraw();
+                } else if ultra_fast
+                    && cfg.no_render
+                    && last_panel_redraw.elapsed() >= NO_RENDER_PANEL_EVERY
+                {
+                    last_panel_redraw = Instant::now();
+                    window.request_redraw();
+                }
+                return;
+            }
+
+            // (Removed) standalone Q-learning training loop
+
+            // Update game logic (real-time); manual play only outside evolution
+            #[cfg(not(feature = "gpu-render"))]
+            if let Some(arena) = &mut arena {
+                if last_update.elapsed() >= tick_duration {
+                    arena.step();
+                    last_update = Instant::now();
+                    tick_duration = Duration::from_millis(
+                        (100 + manual_speed_delta_ms).clamp(10, 500) as u64,
+                    );
+                    window.request_redraw();
+                }
+                *control_flow = ControlFlow::WaitUntil(next_wait_instant(last_update, tick_duration));
+                return;
+            }
+            if last_update.elapsed() >= tick_duration {
+                let was_alive = game.alive;
+                if baseline && game.alive && !game.paused {
+                    game.change_dir(dir_after_action(game.dir, greedy_policy_action(&game)));
+                    game.update();
+                    if !game.alive {
+                        log_info!("[baseline] greedy policy scored {}", game.score);
+                    }
+                } else {
+                    game.update();
+                }
+                // The baseline's runs don't count toward the player's record
+                if was_alive && !game.alive && !baseline {
+                    record_high_score(HIGH_SCORE_PATH, &mut high_score, game.score);
+                }
+                last_update = Instant::now();
+
+                // Combine base speed with manual delta
+                let base_ms = 150 - game.score.min(30) as i32 * 4;
+                let total_ms = (base_ms + manual_speed_delta_ms).clamp(30, 500) as u64;
+                tick_duration = Duration::from_millis(total_ms);
+            }
+
+            // Sleep until the next tick instead of spinning; smooth movement needs every frame
+            *control_flow = if smooth {
+                ControlFlow::Poll
+            } else {
+                ControlFlow::WaitUntil(next_wait_instant(last_update, tick_duration))
+            };
+            window.request_redraw();
+        }
+    });
+}
+
+// ============================
+// Rendering helpers and UI
+// ============================
+
+/// Write an RGBA8 frame buffer of `w`×`h` pixels to a PNG file.
+fn save_frame_png(
+    frame: &[u8],
+    w: u32,
+    h: u32,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let expected = (w as usize) * (h as usize) * 4;
+    if frame.len() != expected {
+        return Err(format!(
+            "frame is {} bytes, expected {} for {}x{}",
+            frame.len(),
+            expected,
+            w,
+            h
+        )
+        .into());
+    }
+    image::save_buffer(path, frame, w, h, image::ColorType::Rgba8)?;
+    Ok(())
+}
+
+/// Draw `game` (whole board, no overlays) into a caller-owned `w`×`h` RGBA8 buffer with
+/// the same helpers as the window, e.g. for exporting frames without one.
+#[cfg(not(feature = "gpu-render"))]
+fn render_to_buffer(game: &Game, buf: &mut [u8], w: u32, h: u32) {
+    assert_eq!(
+        buf.len(),
+        (w * h * 4) as usize,
+        "buffer must be {}x{} RGBA",
+        w,
+        h
+    );
+    game.draw(&mut Canvas::new(buf, w, h), Viewport::full((w, h)));
+}
+
+/// Copy a `w`×`h` RGBA8 image into the frame with its top-left corner at (`x`, `y`).
+#[cfg(not(feature = "gpu-render"))]
+fn blit_rgba(frame: &mut Canvas, x: u32, y: u32, img: &[u8], w: u32, h: u32) {
+    let (width, height) = frame.size();
+    let cols = w.min(width.saturating_sub(x)) as usize;
+    for row in 0..h.min(height.saturating_sub(y)) {
+        let src = (row * w) as usize * 4;
+        let dst = (((y + row) * width + x) * 4) as usize;
+        if dst + cols * 4 <= frame.len() {
+            frame[dst..dst + cols * 4].copy_from_slice(&img[src..src + cols * 4]);
+        }
+    }
+}
+
+/// Pixel size of a cell in exported GIFs (the default board is 320x240).
+#[cfg(not(feature = "gpu-render"))]
+const GIF_CELL: u32 = 16;
+/// Display time of one exported GIF frame.
+#[cfg(not(feature = "gpu-render"))]
+const GIF_FRAME_MS: u32 = 80;
+/// Longest exported episode, in moves (80 s of animation): a looping agent never dies.
+#[cfg(not(feature = "gpu-render"))]
+const GIF_MAX_STEPS: usize = 1000;
+
+/// Collects equally sized RGBA frames and writes them as a looping animated GIF.
+#[cfg(not(feature = "gpu-render"))]
+struct GifExporter {
+    w: u32,
+    h: u32,
+    frames: Vec<image::Frame>,
+}
+
+#[cfg(not(feature = "gpu-render"))]
+impl GifExporter {
+    fn new(w: u32, h: u32) -> Self {
+        Self {
+            w,
+            h,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Append a copy of a `w`×`h` RGBA8 frame.
+    fn push(&mut self, rgba: &[u8]) {
+        let img = image::RgbaImage::from_raw(self.w, self.h, rgba.to_vec())
+            .expect("frame size must match the exporter");
+        let delay = image::Delay::from_numer_denom_ms(GIF_FRAME_MS, 1);
+        self.frames.push(image::Frame::from_parts(img, 0, 0, delay));
+    }
+
+    /// Encode all frames to `path`.
+    fn write(self, path: &Path) -> image::ImageResult<()> {
+        let mut encoder =
+            image::codecs::gif::GifEncoder::new(BufWriter::new(fs::File::create(path)?));
+        encoder.set_repeat(image::codecs::gif::Repeat::Infinite)?;
+        encoder.encode_frames(self.frames)
+    }
+}
+
+/// Play one greedy episode of the agent saved at `agent_path` and write it as a GIF;
+/// returns the number of frames. `seed` fixes the apples.
+#[cfg(not(feature = "gpu-render"))]
+fn export_champion_gif(
+    agent_path: &str,
+    gif: &Path,
+    grid: GridConfig,
+    wrap: bool,
+    seed: u64,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let agent: QAgent = serde_json::from_str(&fs::read_to_string(agent_path)?)?;
+    let (w, h) = (grid.width * GIF_CELL, grid.height * GIF_CELL);
+    let mut buf = vec![0u8; (w * h * 4) as usize];
+    let mut exporter = GifExporter::new(w, h);
+    let mut g = Game::new_in(grid, wrap, seed);
+    render_to_buffer(&g, &mut buf, w, h);
+    exporter.push(&buf);
+    while g.alive && !g.won && g.steps < GIF_MAX_STEPS {
+        let a = agent.act_greedy(state_key_with(&g, agent.encoding), &mut g.rng);
+        g.apply_action(a);
+        render_to_buffer(&g, &mut buf, w, h);
+        exporter.push(&buf);
+    }
+    let frames = exporter.frames.len();
+    exporter.write(gif)?;
+    Ok(frames)
+}
+
+/// Timestamped screenshot file name in the working directory.
+fn screenshot_path() -> PathBuf {
+    let ts = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    PathBuf::from(format!("snake_{}.png", ts))
+}
+
+/// Clear the entire frame buffer to a single RGBA color.
+#[cfg(not(feature = "gpu-render"))]
+fn clear_rgba(frame: &mut Canvas, r: u8, g: u8, b: u8, a: u8) {
+    for px in frame.chunks_exact_mut(4) {
+        px[0] = r;
+        px[1] = g;
+        px[2] = b;
+        px[3] = a;
+    }
+}
+
+/// Training steps per second in the best-agent view with evaluation speed on.
+const EVAL_STEPS_PER_SEC: u64 = 10;
+
+/// Minimum time between training steps: a watchable interval while evaluating,
+/// none otherwise (as fast as `evo_steps_per_frame` allows).
+fn training_step_interval(evaluating: bool) -> Option<Duration> {
+    evaluating.then(|| Duration::from_millis(1000 / EVAL_STEPS_PER_SEC))
+}
+
+/// Training steps per tick: 80k with the GPU budget, 50k in ultra-fast mode (nothing
+/// is drawn), else 1500 so the UI stays responsive.
+fn step_budget(ultra_fast: bool, gpu_enabled: bool) -> u32 {
+    if gpu_enabled {
+        80_000
+    } else if ultra_fast {
+        50_000
+    } else {
+        1500
+    }
+}
+
+/// Automatic training speed: slow while agents learn the basics, faster as the
+/// run's best score climbs.
+#[derive(Clone, Debug, PartialEq)]
+struct SpeedSchedule {
+    stages: Vec<(usize, u32)>, // (best score reached, steps per frame), ascending by score
+}
+
+impl Default for SpeedSchedule {
+    fn default() -> Self {
+        Self {
+            stages: vec![(0, 16), (5, 256), (15, 4_096), (30, 32_768)],
+        }
+    }
+}
+
+impl SpeedSchedule {
+    /// Steps per frame for the last stage `best_score` has reached, capped at
+    /// `max_steps_per_tick` (at least 1).
+    fn steps_per_frame(&self, best_score: usize, max_steps_per_tick: u32) -> u32 {
+        let steps = self
+            .stages
+            .iter()
+            .take_while(|&&(score, _)| score <= best_score)
+            .last()
+            .map_or(1, |&(_, steps)| steps);
+        steps.min(max_steps_per_tick).max(1)
+    }
+}
+
+/// How often the panel is redrawn in ultra-fast mode started with `--no-render`.
+const NO_RENDER_PANEL_EVERY: Duration = Duration::from_secs(1);
+
+/// Frames drawn at one step per frame after the watched agent eats in fast-forward.
+const FAST_FORWARD_REPLAY_FRAMES: u32 = 24;
+
+/// Follows one agent's score and reports when it goes up (an apple eaten).
+#[derive(Default)]
+struct ScoreWatch {
+    last: Option<(usize, usize)>, // (agent index, score) seen last time
+}
+
+impl ScoreWatch {
+    /// Record agent `idx` at `score`; true only if the same agent was seen last time
+    /// with a lower score. Switching agents or a score drop (new epoch) just re-arms.
+    fn observe(&mut self, idx: usize, score: usize) -> bool {
+        let fired = matches!(self.last, Some((i, s)) if i == idx && score > s);
+        self.last = Some((idx, score));
+        fired
+    }
+}
+
+/// When the manual game is next due to tick; the event loop sleeps until then.
+fn next_wait_instant(last_update: Instant, tick_duration: Duration) -> Instant {
+    last_update + tick_duration
+}
+
+/// Events per second over roughly one-second windows (frames for FPS, environment steps
+/// during evolution). The published rate changes only when a window closes.
+struct RateMeter {
+    window_start: Instant,
+    count: u64,
+    rate: f32,
+}
+
+impl RateMeter {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            count: 0,
+            rate: 0.0,
+        }
+    }
+
+    /// Count `n` events at `now`; closes the window once a second has passed.
+    fn add(&mut self, n: u64, now: Instant) {
+        self.count += n;
+        let elapsed = now
+            .saturating_duration_since(self.window_start)
+            .as_secs_f32();
+        if elapsed >= 1.0 {
+            self.rate = self.count as f32 / elapsed;
+            self.count = 0;
+            self.window_start = now;
+        }
+    }
+
+    /// Forget the current window and rate (e.g. while paused).
+    fn restart(&mut self, now: Instant) {
+        *self = Self::new(now);
+    }
+
+    #[cfg_attr(feature = "gpu-render", allow(dead_code))]
+    fn rate(&self) -> f32 {
+        self.rate
+    }
+}
+
+/// Best manual-play score, kept apart from the evolution champion.
+const HIGH_SCORE_PATH: &str = "high_score.txt";
+
+/// High score from the file's contents; anything unparsable counts as no record (0).
+fn parse_high_score(text: &str) -> usize {
+    text.trim().parse().unwrap_or(0)
+}
+
+fn load_high_score(path: &str) -> usize {
+    fs::read_to_string(path).map_or(0, |text| parse_high_score(&text))
+}
+
+fn save_high_score(path: &str, score: usize) -> std::io::Result<()> {
+    fs::write(path, format!("{}\n", score))
+}
+
+/// Raise `high` to `score` if the game beat it and save the new record to `path`.
+fn record_high_score(path: &str, high: &mut usize, score: usize) {
+    if score > *high {
+        *high = score;
+        if let Err(e) = save_high_score(path, score) {
+            log_warn!("Failed to save high score: {}", e);
+        }
+    }
+}
+
+/// True when the window surface has no area (e.g. minimized) and a frame can't be presented.
+fn should_skip_render(width: u32, height: u32) -> bool {
+    width == 0 || height == 0
+}
+
+/// Alpha-blend a pixel into the frame at (x,y).
+#[cfg(not(feature = "gpu-render"))]
+fn blend_pixel(frame: &mut Canvas, x: u32, y: u32, r: u8, g: u8, b: u8, a: u8) {
+    let (width, height) = frame.size();
+    if x >= width || y >= height {
+        return;
+    }
+    let idx = ((y * width + x) * 4) as usize;
+    if idx + 3 >= frame.len() {
+        return;
+    }
+    let ar = a as u16;
+    let iar = (255 - a) as u16;
+    let dr = frame[idx] as u16;
+    let dg = frame[idx + 1] as u16;
+    let db = frame[idx + 2] as u16;
+    frame[idx] = (((r as u16) * ar + dr * iar) / 255) as u8;
+    frame[idx + 1] = (((g as u16) * ar + dg * iar) / 255) as u8;
+    frame[idx + 2] = (((b as u16) * ar + db * iar) / 255) as u8;
+    frame[idx + 3] = 255;
+}
+
+/// Fill an axis-aligned rectangle with an RGBA color (alpha-blended per pixel).
+#[allow(clippy::too_many_arguments)]
+#[cfg(not(feature = "gpu-render"))]
+fn fill_rect_rgba(frame: &mut Canvas, x: u32, y: u32, w: u32, h: u32, r: u8, g: u8, b: u8, a: u8) {
+    let (width, height) = frame.size();
+    let x2 = (x + w).min(width);
+    let y2 = (y + h).min(height);
+    for py in y..y2 {
+        for px in x..x2 {
+            blend_pixel(frame, px, py, r, g, b, a);
+        }
+    }
+}
+
+/// Draw a rectangle border with an RGBA color.
+#[allow(clippy::too_many_arguments)]
+#[cfg(not(feature = "gpu-render"))]
+fn stroke_rect_rgba(
+    frame: &mut Canvas,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    r: u8,
+    g: u8,
+    b: u8,
+    a: u8,
+) {
+    if w == 0 || h == 0 {
+        return;
+    }
+    let (width, height) = frame.size();
+    let x2 = (x + w - 1).min(width - 1);
+    let y2 = (y + h - 1).min(height - 1);
+    for px in x..=x2 {
+        blend_pixel(frame, px, y, r, g, b, a);
+        blend_pixel(frame, px, y2, r, g, b, a);
+    }
+    for py in y..=y2 {
+        blend_pixel(frame, x, py, r, g, b, a);
+        blend_pixel(frame, x2, py, r, g, b, a);
+    }
+}
+
+/// Fill a single grid cell with an opaque RGB color.
+#[allow(clippy::too_many_arguments)]
+#[cfg(not(feature = "gpu-render"))]
+fn fill_cell_rgb(
+    frame: &mut Canvas,
+    cell: CellSize,
+    grid_x: u32,
+    grid_y: u32,
+    r: u8,
+    g: u8,
+    b: u8,
+) {
+    fill_cell_rgba(frame, cell, grid_x, grid_y, r, g, b, 255);
+}
+
+/// Fill a single grid cell with an RGBA color.
+#[allow(clippy::too_many_arguments)]
+#[cfg(not(feature = "gpu-render"))]
+fn fill_cell_rgba(
+    frame: &mut Canvas,
+    cell: CellSize,
+    grid_x: u32,
+    grid_y: u32,
+    r: u8,
+    g: u8,
+    b: u8,
+    a: u8,
+) {
+    fill_rect_rgba(
+        frame,
+        grid_x * cell.w,
+        grid_y * cell.h,
+        cell.w,
+        cell.h,
+        r,
+        g,
+        b,
+        a,
+    );
+}
+
+/// Cell the head moves into next if it keeps going the way it came from `prev`
+/// (one cell behind it, possibly across a wrapped edge). None when the two don't
+/// form a move or the next cell is outside solid walls.
+#[cfg_attr(feature = "gpu-render", allow(dead_code))]
+fn leading_edge(head: Pos, prev: Pos, grid: GridConfig, wrap: bool) -> Option<Pos> {
+    // A jump longer than one cell is a wrap, so the real step points the other way
+    let unit = |d: i32| if d.abs() > 1 { -d.signum() } else { d };
+    let (dx, dy) = (unit(head.x - prev.x), unit(head.y - prev.y));
+    if (dx, dy) == (0, 0) {
+        return None;
+    }
+    let next = Pos::new(head.x + dx, head.y + dy);
+    if wrap {
+        let (w, h) = (grid.width as i32, grid.height as i32);
+        Some(Pos::new(next.x.rem_euclid(w), next.y.rem_euclid(h)))
+    } else {
+        Some(next).filter(|&p| grid.contains(p))
+    }
+}
+
+/// Draw the game semi-transparently, tinting the snake by `color` (used to show many agents).
+/// With `motion` (the head's previous cell) a faint glow marks the cell ahead of the head.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_game_transparent(
+    frame: &mut Canvas,
+    game: &Game,
+    alpha: u8,
+    color: (u8, u8, u8),
+    motion: Option<Pos>,
+) {
+    if !game.alive {
+        return;
+    }
+    let cell = CellSize::for_grid(game.grid, frame.size());
+
+    // Draw apple semi-transparent
+    fill_cell_rgba(
+        frame,
+        cell,
+        game.apple.x as u32,
+        game.apple.y as u32,
+        220,
+        50,
+        50,
+        alpha,
+    );
+
+    let (base_r, base_g, base_b) = color;
+
+    // Draw snake with agent's color
+    for (i, &pos) in game.snake.iter().enumerate() {
+        if i == 0 {
+            // Head - brighter version of agent's color
+            let bright_r = (base_r as u16 * 130 / 100).min(255) as u8;
+            let bright_g = (base_g as u16 * 130 / 100).min(255) as u8;
+            let bright_b = (base_b as u16 * 130 / 100).min(255) as u8;
+            fill_cell_rgba(
+                frame,
+                cell,
+                pos.x as u32,
+                pos.y as u32,
+                bright_r,
+                bright_g,
+                bright_b,
+                alpha,
+            );
+            if let Some(ahead) =
+                motion.and_then(|prev| leading_edge(pos, prev, game.grid, game.wrap_world))
+            {
+                fill_cell_rgba(
+                    frame,
+                    cell,
+                    ahead.x as u32,
+                    ahead.y as u32,
+                    bright_r,
+                    bright_g,
+                    bright_b,
+                    alpha / 3,
+                );
+            }
+        } else {
+            // Body with gradient - darker with distance from head
+            let fade = 1.0 - (i as f32 * 0.015).min(0.5);
+            let body_r = (base_r as f32 * fade) as u8;
+            let body_g = (base_g as f32 * fade) as u8;
+            let body_b = (base_b as f32 * fade) as u8;
+            fill_cell_rgba(
+                frame,
+                cell,
+                pos.x as u32,
+                pos.y as u32,
+                body_r,
+                body_g,
+                body_b,
+                alpha,
+            );
+        }
+    }
+}
+
+/// Small text box with its top-left corner at (`x`, `y`), shifted left/up to stay on screen.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_tooltip(frame: &mut Canvas, x: u32, y: u32, text: &str) {
+    let (w, h) = (text.len() as u32 * 12 + 8, 22);
+    let (width, height) = frame.size();
+    let x = x.min(width.saturating_sub(w));
+    let y = y.min(height.saturating_sub(h));
+    fill_rect_rgba(frame, x, y, w, h, 0, 0, 0, 200);
+    stroke_rect_rgba(frame, x, y, w, h, 255, 255, 255, 90);
+    draw_text(frame, text, x + 4, y + 4, 2, (240, 240, 240, 255));
+}
+
+/// Control panel rect `(x, y, w, h)` in frame pixels, for drawing, clicks and `DirtyRedraw`.
+#[cfg(not(feature = "gpu-render"))]
+const PANEL_RECT: (u32, u32, u32, u32) = (8, 8, 380, 648);
+
+/// The SHOW button drawn in place of the hidden panel.
+#[cfg(not(feature = "gpu-render"))]
+const SHOW_BUTTON_RECT: (u32, u32, u32, u32) = (8, 8, 100, 32);
+
+/// Draw a simple UI button with a text label.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_button(frame: &mut Canvas, x: u32, y: u32, w: u32, h: u32, label: &str) {
+    fill_rect_rgba(frame, x, y, w, h, 40, 40, 60, 160);
+    stroke_rect_rgba(frame, x, y, w, h, 200, 200, 220, 120);
+    draw_text(
+        frame,
+        label,
+        x + 10,
+        y + (h / 2 - 6),
+        2,
+        (230, 240, 255, 255),
+    );
+}
+
+/// Draw the "TOP 5" box: one `index: score` line per entry in the agent's color.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_leaderboard(frame: &mut Canvas, x: u32, y: u32, entries: &[(usize, usize, (u8, u8, u8))]) {
+    let line_h: u32 = 20;
+    let h = 30 + entries.len() as u32 * line_h;
+    fill_rect_rgba(frame, x, y, 140, h, 0, 0, 0, 140);
+    stroke_rect_rgba(frame, x, y, 140, h, 255, 255, 255, 60);
+    draw_text(frame, "TOP 5", x + 10, y + 8, 2, (180, 220, 255, 255));
+    for (row, &(idx, score, (r, g, b))) in entries.iter().enumerate() {
+        draw_text(
+            frame,
+            &format!("{:>2}: {}", idx, score),
+            x + 10,
+            y + 30 + row as u32 * line_h,
+            2,
+            (r, g, b, 255),
+        );
+    }
+}
+
+/// Draw the "DEATHS" box under the leaderboard: wall / self-collision / timeout shares.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_death_stats(frame: &mut Canvas, x: u32, y: u32, (wall, body, timeout): (f32, f32, f32)) {
+    fill_rect_rgba(frame, x, y, 140, 90, 0, 0, 0, 140);
+    stroke_rect_rgba(frame, x, y, 140, 90, 255, 255, 255, 60);
+    draw_text(frame, "DEATHS", x + 10, y + 8, 2, (180, 220, 255, 255));
+    for (row, (label, share)) in [("WALL", wall), ("SELF", body), ("TO", timeout)]
+        .into_iter()
+        .enumerate()
+    {
+        draw_text(
+            frame,
+            &format!("{:<4} {:>3.0}%", label, share * 100.0),
+            x + 10,
+            y + 30 + row as u32 * 20,
+            2,
+            (220, 220, 220, 255),
+        );
+    }
+}
+
+/// Both champions: the best score, and the efficient one's score and steps.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_champions(
+    frame: &mut Canvas,
+    x: u32,
+    y: u32,
+    score: usize,
+    efficient: Option<(usize, usize)>,
+) {
+    fill_rect_rgba(frame, x, y, 140, 110, 0, 0, 0, 140);
+    stroke_rect_rgba(frame, x, y, 140, 110, 255, 255, 255, 60);
+    draw_text(frame, "CHAMPIONS", x + 10, y + 8, 2, (180, 220, 255, 255));
+    let (eff, steps) = efficient.map_or(("-".to_string(), "-".to_string()), |(s, n)| {
+        (s.to_string(), n.to_string())
+    });
+    for (row, (label, value)) in [("TOP", score.to_string()), ("EFF", eff), ("STEPS", steps)]
+        .into_iter()
+        .enumerate()
+    {
+        draw_text(
+            frame,
+            &format!("{:<5} {}", label, value),
+            x + 10,
+            y + 30 + row as u32 * 20,
+            2,
+            (255, 215, 0, 255),
+        );
+    }
+}
+
+/// The "ACTIONS" box under the champions: a bar per relative action (left, straight,
+/// right) with its share of the best agent's decisions this epoch.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_action_histogram(frame: &mut Canvas, x: u32, y: u32, counts: [u64; 3]) {
+    fill_rect_rgba(frame, x, y, 140, 90, 0, 0, 0, 140);
+    stroke_rect_rgba(frame, x, y, 140, 90, 255, 255, 255, 60);
+    draw_text(frame, "ACTIONS", x + 10, y + 8, 2, (180, 220, 255, 255));
+    let total = counts.iter().sum::<u64>().max(1) as f32;
+    for (row, (label, count)) in ["L", "S", "R"].into_iter().zip(counts).enumerate() {
+        let share = count as f32 / total;
+        let row_y = y + 30 + row as u32 * 20;
+        draw_text(frame, label, x + 10, row_y, 2, (220, 220, 220, 255));
+        fill_rect_rgba(frame, x + 28, row_y, 70, 14, 255, 255, 255, 30);
+        fill_rect_rgba(
+            frame,
+            x + 28,
+            row_y,
+            (share * 70.0).round() as u32,
+            14,
+            120,
+            200,
+            255,
+            220,
+        );
+        draw_text(
+            frame,
+            &format!("{:>2.0}", (share * 100.0).min(99.0)),
+            x + 104,
+            row_y,
+            2,
+            (220, 220, 220, 255),
+        );
+    }
+}
+
+/// Check whether a point lies within a rectangle.
+#[cfg(not(feature = "gpu-render"))]
+fn point_in_rect(px: u32, py: u32, x: u32, y: u32, w: u32, h: u32) -> bool {
+    px >= x && py >= y && px < x + w && py < y + h
+}
+
+/// Returns a 5x7 bitmap glyph for a limited set of characters (ASCII-like UI font).
+#[cfg(not(feature = "gpu-render"))]
+fn glyph_5x7(ch: char) -> Option<[u8; 7]> {
+    let c = ch.to_ascii_uppercase();
+    Some(match c {
+        'A' => [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'B' => [
+            0b11110, 0b10001, 0b11110, 0b10001, 0b10001, 0b10001, 0b11110,
+        ],
+        'C' => [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+        'D' => [
+            0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100,
+        ],
+        'E' => [
+            0b11111, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000, 0b11111,
+        ],
+        'F' => [
+            0b11111, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000, 0b10000,
+        ],
+        'G' => [
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01110,
+        ],
+        'H' => [
+            0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001, 0b10001,
+        ],
+        'I' => [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b11111,
+        ],
+        'J' => [
+            0b00111, 0b00010, 0b00010, 0b00010, 0b10010, 0b10010, 0b01100,
+        ],
+        'K' => [
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ],
+        'L' => [
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ],
+        'M' => [
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ],
+        'N' => [
+            0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001, 0b10001,
+        ],
+        'O' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'P' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'Q' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ],
+        'R' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ],
+        'S' => [
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ],
+        'T' => [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'U' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'V' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ],
+        'W' => [
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b11011, 0b10001,
+        ],
+        'X' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ],
+        'Y' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'Z' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ],
+        '0' => [
+            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+        ],
+        '1' => [
+            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        '2' => [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+        ],
+        '3' => [
+            0b11110, 0b00001, 0b00001, 0b01110, 0b00001, 0b00001, 0b11110,
+        ],
+        '4' => [
+            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+        ],
+        '5' => [
+            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+        ],
+        '6' => [
+            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+        ],
+        '7' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+        ],
+        '8' => [
+            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+        ],
+        '9' => [
+            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+        ],
+        ':' => [
+            0b00000, 0b00100, 0b00000, 0b00000, 0b00100, 0b00000, 0b00000,
+        ],
+        '.' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100,
+        ],
+        '%' => [
+            0b11001, 0b11010, 0b00010, 0b00100, 0b01000, 0b01011, 0b10011,
+        ],
+        '+' => [
+            0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
+        ],
+        '-' => [
+            0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
+        ],
+        '/' => [
+            0b00001, 0b00010, 0b00010, 0b00100, 0b01000, 0b01000, 0b10000,
+        ],
+        '(' => [
+            0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010,
+        ],
+        ')' => [
+            0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000,
+        ],
+        ',' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00110, 0b00100, 0b01000,
+        ],
+        ' ' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000,
+        ],
+        '_' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111,
+        ],
+        _ => return None,
+    })
+}
+
+/// Draw a single bitmap character and return its advance in pixels.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_char(
+    frame: &mut Canvas,
+    ch: char,
+    x: u32,
+    y: u32,
+    scale: u32,
+    col: (u8, u8, u8, u8),
+) -> u32 {
+    if let Some(rows) = glyph_5x7(ch) {
+        for (ry, row) in rows.iter().enumerate() {
+            for rx in 0..5 {
+                if (row >> (4 - rx)) & 1 == 1 {
+                    for sy in 0..scale {
+                        for sx in 0..scale {
+                            blend_pixel(
+                                frame,
+                                x + rx as u32 * scale + sx,
+                                y + ry as u32 * scale + sy,
+                                col.0,
+                                col.1,
+                                col.2,
+                                col.3,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        5 * scale + scale
+    } else {
+        5 * scale + scale
+    }
+}
+
+/// Draw a text string using the 5x7 glyph font.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_text(frame: &mut Canvas, text: &str, x: u32, y: u32, scale: u32, col: (u8, u8, u8, u8)) {
+    let mut cx = x;
+    for ch in text.chars() {
+        cx += draw_char(frame, ch, cx, y, scale, col);
+    }
+}
+
+/// Draw a simple bar chart of best scores per epoch.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_chart(frame: &mut Canvas, x: u32, y: u32, w: u32, h: u32, data: &[usize]) {
+    stroke_rect_rgba(frame, x, y, w, h, 200, 200, 200, 120);
+    if data.is_empty() {
+        return;
+    }
+    if w < 4 || h < 4 {
+        return;
+    }
+    // Baseline along the bottom edge
+    fill_rect_rgba(frame, x + 1, y + h - 2, w - 2, 1, 200, 200, 200, 160);
+    let max_val = *data.iter().max().unwrap_or(&1) as u32;
+    if max_val == 0 {
+        return;
+    }
+    let inner_w = w - 2;
+    let inner_h = h - 3;
+    // Whole history is bucketed into at most one bar per 6 px
+    let bars = downsample_max(data, (inner_w as usize / 6).max(1));
+    let bar_w = (inner_w / bars.len() as u32).max(2);
+    for (i, &v) in bars.iter().enumerate() {
+        let bh = ((v as u64 * inner_h as u64) / max_val as u64) as u32;
+        let bx = x + 1 + i as u32 * bar_w;
+        let by = (y + h - 2).saturating_sub(bh);
+        fill_rect_rgba(frame, bx, by, bar_w - 1, bh, 120, 180, 255, 160);
+    }
+    // Scale label: value at the top of the chart
+    draw_text(
+        frame,
+        &max_val.to_string(),
+        x + 3,
+        y + 3,
+        1,
+        (200, 200, 200, 200),
+    );
+}
+
+/// Overlay a second series on a `draw_chart` area as a stepped orange line with its
+/// own scale, bucketed like the bars; the maximum is labeled top-right.
+#[cfg(not(feature = "gpu-render"))]
+fn draw_chart_line(frame: &mut Canvas, x: u32, y: u32, w: u32, h: u32, data: &[usize]) {
+    let max_val = data.iter().copied().max().unwrap_or(0);
+    if max_val == 0 || w < 4 || h < 4 {
+        return;
+    }
+    let inner_w = w - 2;
+    let inner_h = h - 3;
+    let points = downsample_max(data, (inner_w as usize / 6).max(1));
+    let step_w = (inner_w / points.len() as u32).max(2);
+    for (i, &v) in points.iter().enumerate() {
+        let lh = ((v as u64 * inner_h as u64) / max_val as u64).max(1) as u32;
+        let lx = x + 1 + i as u32 * step_w;
+        let ly = (y + h - 2).saturating_sub(lh);
+        fill_rect_rgba(frame, lx, ly, step_w, 2, 255, 170, 60, 230);
+    }
+    let label = format!("{} states", max_val);
+    let label_x = (x + w).saturating_sub(label.len() as u32 * 6 + 3);
+    draw_text(frame, &label, label_x, y + 3, 1, (255, 170, 60, 220));
+}
+
+/// Reduce `data` to at most `buckets` values, each the max of a contiguous chunk.
+/// The last bucket always ends at the most recent value.
+#[cfg(not(feature = "gpu-render"))]
+fn downsample_max(data: &[usize], buckets: usize) -> Vec<usize> {
+    let n = data.len();
+    let buckets = buckets.min(n);
+    if buckets == 0 {
+        return Vec::new();
+    }
+    (0..buckets)
+        .map(|i| {
+            let start = i * n / buckets;
+            let end = (i + 1) * n / buckets;
+            data[start..end].iter().copied().max().unwrap_or(0)
+        })
+        .collect()
+}
+
+// ============================
+// Tests
+// ============================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_dir_rotation() {
+        assert_eq!(left_dir(Dir::Up), Dir::Left);
+        assert_eq!(right_dir(Dir::Up), Dir::Right);
+        assert_eq!(left_dir(Dir::Right), Dir::Up);
+        assert_eq!(right_dir(Dir::Left), Dir::Up);
+    }
+
+    #[test]
+    fn test_dir_after_action() {
+        assert_eq!(dir_after_action(Dir::Up, 0), Dir::Left);
+        assert_eq!(dir_after_action(Dir::Up, 1), Dir::Up);
+        assert_eq!(dir_after_action(Dir::Up, 2), Dir::Right);
+    }
+
+    #[test]
+    fn test_dirty_cells_after_plain_move() {
+        let body = [Pos::new(5, 5), Pos::new(4, 5), Pos::new(3, 5)];
+        let mut g = Game::from_layout(&body, Dir::Right, Pos::new(9, 9), true).unwrap();
+        let before = g.board_cells();
+        g.update();
+        assert!(g.alive && g.score == 0);
+        let after = g.board_cells();
+        assert_eq!(
+            dirty_cells(&before, &after),
+            vec![Pos::new(3, 5), Pos::new(6, 5)]
+        );
+        assert!(dirty_cells(&after, &after).is_empty());
+    }
+
+    #[test]
+    #[cfg(not(feature = "gpu-render"))]
+    fn test_dirty_redraw_repaints_manual_game_with_trail() {
+        let mut game = Game::new_manual(GridConfig::default(), true);
+        game.apple = Pos::new(0, 0);
+        let view = Viewport::full((WIDTH, HEIGHT));
+        let mut redraw = DirtyRedraw::default();
+        let mut pixels = vec![0u8; (WIDTH * HEIGHT * 4) as usize];
+        let frame = &mut Canvas::new(&mut pixels, WIDTH, HEIGHT);
+        assert!(!redraw.draw(frame, &game, view, true, Vec::new()));
+        for _ in 0..3 {
+            game.update();
+            assert!(redraw.draw(frame, &game, view, true, Vec::new()));
+        }
+        assert!(!game.trail.is_empty());
+        // Same pixels as drawing the whole frame
+        let mut full = vec![0u8; pixels.len()];
+        game.draw(&mut Canvas::new(&mut full, WIDTH, HEIGHT), view);
+        assert!(pixels == full);
+    }
+
+    #[test]
+    fn test_wrap_on_wall() {
+        // Place head at left edge and move left: should wrap to rightmost column
+        let mut g = Game::from_layout(&[Pos::new(0, 5)], Dir::Left, Pos::new(9, 9), true).unwrap();
+        g.update();
+        assert!(g.alive);
+        let head = g.snake.front().unwrap();
+        assert_eq!(head.x, GRID_WIDTH as i32 - 1);
+        assert_eq!(head.y, 5);
+    }
+
+    #[test]
+    fn test_self_collision_death_cause() {
+        // Body directly to the right of the head; moving right runs into it
+        let body = [Pos::new(2, 2), Pos::new(3, 2)];
+        let mut g = Game::from_layout(&body, Dir::Right, Pos::new(9, 9), true).unwrap();
+        g.update();
+        assert!(!g.alive);
+        assert_eq!(g.last_death, DeathCause::SelfCollision);
+    }
+
+    #[test]
+    fn test_from_layout_builds_and_validates() {
+        let body = [
+            Pos::new(5, 5),
+            Pos::new(4, 5),
+            Pos::new(4, 6),
+            Pos::new(3, 6),
+        ];
+        let g = Game::from_layout(&body, Dir::Right, Pos::new(8, 5), false).unwrap();
+        assert_eq!(g.snake, VecDeque::from(body));
+        assert_eq!(g.snake_set, body.into_iter().collect::<AHashSet<_>>());
+        assert_eq!(
+            (g.dir, g.apple, g.wrap_world),
+            (Dir::Right, Pos::new(8, 5), false)
+        );
+        assert_eq!(state_key(&g).raw() >> 16 & 3, 1); // apple straight ahead
+
+        let apple = Pos::new(9, 9);
+        let overlap = [Pos::new(2, 2), Pos::new(3, 2), Pos::new(2, 2)];
+        assert!(Game::from_layout(&overlap, Dir::Left, apple, false).is_err());
+        let gap = [Pos::new(2, 2), Pos::new(4, 2)];
+        assert!(Game::from_layout(&gap, Dir::Left, apple, false).is_err());
+        assert!(Game::from_layout(&[], Dir::Left, apple, false).is_err());
+        assert!(Game::from_layout(&body, Dir::Right, Pos::new(4, 5), false).is_err());
+        // Across the edge is adjacent only when the world wraps
+        let w = GRID_WIDTH as i32;
+        let edge = [Pos::new(0, 3), Pos::new(w - 1, 3)];
+        assert!(Game::from_layout(&edge, Dir::Left, apple, false).is_err());
+        assert!(Game::from_layout(&edge, Dir::Left, apple, true).is_ok());
+    }
+
+    #[test]
+    fn test_adapter_fallback_order() {
+        // Simulated outcomes per attempt: which requests find an adapter
+        let pick = |found: [bool; 3]| {
+            let mut tried = Vec::new();
+            let adapter = pollster::block_on(request_adapter_with_fallback(|power, fallback| {
+                tried.push((power, fallback));
+                std::future::ready(found[tried.len() - 1].then_some(tried.len() - 1))
+            }));
+            (adapter, tried.len())
+        };
+        assert_eq!(pick([true, true, true]), (Some(0), 1));
+        assert_eq!(pick([false, true, true]), (Some(1), 2));
+        assert_eq!(pick([false, false, true]), (Some(2), 3));
+        assert_eq!(pick([false, false, false]), (None, 3));
+        assert_eq!(
+            ADAPTER_ATTEMPTS[0],
+            (PowerPreference::HighPerformance, false)
+        );
+        assert!(ADAPTER_ATTEMPTS[2].1); // the last resort is the fallback adapter
+    }
 
-            #[cfg(not(feature = "gpu-render"))]
-            {
-                if pixels.render().is_err() {
-                    *control_flow = ControlFlow::Exit;
-                }
-            }
-            #[cfg(feature = "gpu-render")]
-            {
-                // Build instances for gpu renderer
-                let mut instances: Vec<gpu_render::Instance> = Vec::with_capacity(1024);
-                // Background grid is drawn in shader. Add apple and snakes.
-                let push_snake = |g: &Game, alpha: f32, color: (u8,u8,u8), out: &mut Vec<gpu_render::Instance>| {
-                    if !g.alive { return; }
-                    // Apple
-                    out.push(gpu_render::Instance { gx: g.apple.x as u32, gy: g.apple.y as u32, r: 0.86, g: 0.2, b: 0.2, a: alpha });
-                    // Snake segments
-                    let (cr, cg, cb) = (color.0 as f32 / 255.0, color.1 as f32 / 255.0, color.2 as f32 / 255.0);
-                    for (i, &pos) in g.snake.iter().enumerate() {
-                        let fade = if i == 0 { 1.3 } else { (1.0 - (i as f32 * 0.015)).clamp(0.5, 1.0) };
-                        out.push(gpu_render::Instance {
-                            gx: pos.x as u32,
-                            gy: pos.y as u32,
-                            r: (cr * fade).min(1.0),
-                            g: (cg * fade).min(1.0),
-                            b: (cb * fade).min(1.0),
-                            a: alpha,
-                        });
-                    }
-                };
+    #[test]
+    fn test_next_wait_instant_is_one_tick_after_last_update() {
+        let last = Instant::now();
+        let tick = Duration::from_millis(150);
+        assert_eq!(next_wait_instant(last, tick), last + tick);
+        assert_eq!(next_wait_instant(last, tick).duration_since(last), tick);
+    }
 
-                if evo.training {
-                    if ultra_fast {
-                        // nothing to draw
-                    } else if show_only_best {
-                        if let Some(best_game_idx) = evo
-                            .scores
-                            .iter()
-                            .enumerate()
-                            .max_by_key(|(_, score)| *score)
-                            .map(|(idx, _)| idx)
-                            && best_game_idx < evo.pop.len()
-                            && best_game_idx < evo.games.len()
-                        {
-                            let agent_color = evo.pop[best_game_idx].color;
-                            push_snake(&evo.games[best_game_idx], 0.86, agent_color, &mut instances);
-                        }
-                    } else if evo_steps_per_frame < 4_096 {
-                        for (agent, g) in evo.pop.iter().zip(evo.games.iter()) {
-                            push_snake(g, 0.7, agent.color, &mut instances);
-                        }
-                    } else if let Some(best_game_idx) = evo
-                        .scores
-                        .iter()
-                        .enumerate()
-                        .max_by_key(|(_, score)| *score)
-                        .map(|(idx, _)| idx)
-                        && best_game_idx < evo.pop.len()
-                        && best_game_idx < evo.games.len()
-                    {
-                        let agent_color = evo.pop[best_game_idx].color;
-                        push_snake(&evo.games[best_game_idx], 0.86, agent_color, &mut instances);
-                    }
-                } else {
-                    // Normal game
-                    push_snake(&game, 1.0, (80, 220, 80), &mut instances);
-                }
+    #[test]
+    fn test_toroidal_distance_crosses_wrapped_edge() {
+        let grid = GridConfig::default();
+        let w = grid.width as i32;
+        let a = Pos::new(0, 5);
+        let b = Pos::new(w - 1, 5);
+        assert_eq!(toroidal_distance(a, b, grid), 1);
+
+        let mut g = Game::new_seeded(true, 1);
+        g.apple = b;
+        assert_eq!(g.apple_distance(a), 1);
+        g.wrap_world = false;
+        assert_eq!(g.apple_distance(a), w - 1);
+    }
 
-                if let Err(e) = gpu.render(&instances) {
-                    eprintln!("gpu present failed: {e}");
-                    *control_flow = ControlFlow::Exit;
-                }
-            }
-        }
+    #[test]
+    fn test_greedy_policy_approaches_apple_without_dying() {
+        let grid = GridConfig {
+            width: 10,
+            height: 10,
+        };
+        let mut g = Game::new_in(grid, false, 1);
+        // Heading right along the top wall; the body blocks going down
+        g.snake = VecDeque::from([
+            Pos::new(5, 0),
+            Pos::new(4, 0),
+            Pos::new(4, 1),
+            Pos::new(5, 1),
+            Pos::new(6, 1),
+        ]);
+        g.snake_set = g.snake.iter().copied().collect();
+        g.dir = Dir::Right;
+        g.apple = Pos::new(5, 5);
+        // Left runs into the wall and right into the body: only straight survives,
+        // even though it doesn't bring the apple closer
+        assert_eq!(legal_actions(&g), vec![1]);
+        assert_eq!(greedy_policy_action(&g), 1);
+
+        // Free board: turning toward the apple wins
+        g.snake = VecDeque::from([Pos::new(5, 3), Pos::new(4, 3), Pos::new(3, 3)]);
+        g.snake_set = g.snake.iter().copied().collect();
+        assert_eq!(legal_actions(&g), vec![0, 1, 2]);
+        assert_eq!(greedy_policy_action(&g), 2); // right turn = down, toward (5, 5)
+        g.change_dir(dir_after_action(g.dir, 2));
+        g.update();
+        assert!(g.alive);
+        assert_eq!(g.apple_distance(*g.snake.front().unwrap()), 1);
+    }
 
-        // Handle window resize
-        if let Event::WindowEvent { event, .. } = &event {
-            if let winit::event::WindowEvent::Resized(new_size) = event {
-                #[cfg(not(feature = "gpu-render"))]
-                if let Err(e) = pixels.resize_surface(new_size.width, new_size.height) {
-                    eprintln!("Failed to resize surface: {}", e);
-                    *control_flow = ControlFlow::Exit;
-                    return;
-                }
-                #[cfg(feature = "gpu-render")]
-                {
-                    gpu.resize(new_size.width, new_size.height);
-                }
-            }
+    #[test]
+    fn test_action_histogram_counts_best_agent_decisions() {
+        let mut evo = EvoTrainer::new(3, None);
+        let rewards = RewardConfig::default();
+        assert_eq!(evo.action_histogram(), [0; 3]);
+        // Game 1 leads; games 0 and 2 make other choices that must not be counted
+        evo.scores.copy_from_slice(&[1, 4, 0]);
+        for a in [1, 1, 2, 1, 0, 1] {
+            evo.games[1].step_repeated(a, 1, &rewards);
+            evo.games[0].step_repeated(0, 1, &rewards);
         }
+        evo.games[1].step_repeated(2, 3, &rewards); // one decision, even over three ticks
+        assert!(evo.games[1].alive);
+        assert_eq!(evo.action_histogram(), [1, 4, 2]);
+        evo.scores[0] = 9;
+        assert_eq!(evo.action_histogram(), [6, 0, 0]);
+        evo.reset_epoch();
+        assert_eq!(evo.action_histogram(), [0; 3]);
+    }
 
-        if input.update(&event) {
-            // Handle quit
-            if input.key_pressed(VirtualKeyCode::Escape)
-                || input.close_requested()
-                || input.destroyed()
-            {
-                *control_flow = ControlFlow::Exit;
-                return;
+    #[test]
+    fn test_epoch_states_track_champion_table_growth() {
+        let mut evo = EvoTrainer::new(3, None);
+        let mut rng = SmallRng::seed_from_u64(2);
+        let learn = |agent: &mut QAgent, states: u32| {
+            for s in 0..states {
+                agent.q.insert(s, [0.1, 0.0, 0.0]);
             }
+        };
+        learn(&mut evo.pop[1], 2);
+        evo.scores.copy_from_slice(&[0, 4, 0]);
+        evo.reproduce(&mut rng, None);
+        learn(&mut evo.pop[0], 5);
+        evo.scores.copy_from_slice(&[7, 0, 0]);
+        evo.reproduce(&mut rng, None);
+        evo.scores.copy_from_slice(&[1, 1, 1]); // no new champion: the size holds
+        evo.reproduce(&mut rng, None);
+
+        assert_eq!(evo.epoch_states.len(), evo.epoch);
+        assert_eq!(evo.epoch_states, vec![2, 5, 5]);
+        assert!(evo.epoch_states.windows(2).all(|w| w[0] <= w[1]));
+    }
 
-            // Handle restart
-            if input.key_pressed(VirtualKeyCode::R) && !game.alive {
-                game = Game::new();
-                tick_duration = Duration::from_millis(150);
+    #[test]
+    fn test_snapshot_reflects_scripted_epoch() {
+        let mut evo = EvoTrainer::new(4, None);
+        let mut rng = SmallRng::seed_from_u64(1);
+        evo.scores.copy_from_slice(&[6, 2, 6, 2]);
+        evo.reproduce(&mut rng, None); // epoch 0 ends with a new champion
+        // Second epoch, part-way: two games dead, no new record yet
+        evo.scores.copy_from_slice(&[1, 3, 5, 3]);
+        evo.games[1].alive = false;
+        evo.games[3].alive = false;
+        evo.epochs_without_improvement = 1;
+
+        let snap = evo.snapshot();
+        assert_eq!(
+            snap,
+            TrainingSnapshot {
+                epoch: 1,
+                best_score: 6,
+                champion_score: 6,
+                champion_epoch: 0,
+                epochs_without_improvement: 1,
+                restart_count: 0,
+                stagnation_threshold: 1000,
+                alive_count: 2,
+                mean_score: 3.0,
+                max_score: 5,
+                min_score: 1,
+                diversity: 2f32.sqrt(), // deviations -2, 0, 2, 0
             }
+        );
+        let json = serde_json::to_value(&snap).unwrap();
+        assert_eq!(json["champion_score"], 6);
+        assert_eq!(json["alive_count"], 2);
+    }
 
-            // Handle pause
-            if input.key_pressed(VirtualKeyCode::P) {
-                game.paused = !game.paused;
-            }
+    #[test]
+    fn test_save_champion_on_exit_only_with_champion() {
+        let path = std::env::temp_dir().join("snake_exit_save_test.json");
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+        let mut evo = EvoTrainer::new(2, None);
+        assert!(!save_champion_on_exit(&evo, &path).unwrap());
+        assert!(!Path::new(&path).exists());
+
+        let mut champion = evo.pop[1].clone();
+        champion.q.insert(42, [1.0, 2.0, 3.0]);
+        evo.champion = Some(champion);
+        assert!(save_champion_on_exit(&evo, &path).unwrap());
+        let saved: QAgent = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(saved.q.get(&42), Some(&[1.0, 2.0, 3.0]));
+        assert!(!Path::new(&efficient_save_path(&path)).exists()); // no efficient champion
+    }
 
-            // Evolution toggle only
-            if input.key_pressed(VirtualKeyCode::E) {
-                evo.training = !evo.training;
-                if evo.training {
-                    evo.solved = false;
-                    evo.reset_epoch();
-                    evo.epoch = 0;
-                    evo.epoch_best.clear();
-                    evo.best_score = 0;
-                    evo.epochs_without_improvement = 0;
-                    game = Game::new();
-                }
-            }
+    #[test]
+    fn test_score_and_efficient_champions_diverge() {
+        let mut evo = EvoTrainer::new(4, None);
+        let mut rng = SmallRng::seed_from_u64(1);
+        for (i, agent) in evo.pop.iter_mut().enumerate() {
+            agent.q.insert(i as u32, [i as f32; 3]); // tag each agent
+        }
+        // Agent 0 scores 20 slowly, agent 1 scores 18 fast, agent 2 is fast but below the bar
+        for (i, (score, steps)) in [(20, 4000), (18, 500), (2, 10), (0, 50)]
+            .into_iter()
+            .enumerate()
+        {
+            evo.scores[i] = score;
+            evo.games[i].steps = steps;
+        }
+        evo.reproduce(&mut rng, None);
+
+        assert_eq!(evo.champion_score, 20);
+        assert_eq!(evo.champion.as_ref().unwrap().q.get(&0), Some(&[0.0; 3]));
+        let eff = evo.champion_efficient.as_ref().unwrap();
+        assert_eq!((eff.score, eff.steps), (18, 500));
+        assert_eq!(eff.agent.q.get(&1), Some(&[1.0; 3]));
+        assert_eq!(
+            efficient_save_path("snake_agent.json"),
+            "snake_agent_efficient.json"
+        );
+    }
 
-            // Save agent
-            if input.key_pressed(VirtualKeyCode::S) {
-                if let Err(e) = evo.save_best(save_path) {
-                    eprintln!("Failed to save agent: {}", e);
-                } else {
-                    println!("Agent saved to {}", save_path);
-                }
-            }
+    #[test]
+    fn test_rate_meter_publishes_per_window() {
+        let t0 = Instant::now();
+        let at = |ms| t0 + Duration::from_millis(ms);
+        let mut meter = RateMeter::new(t0);
+        meter.add(30, at(500));
+        assert_eq!(meter.rate(), 0.0); // window still open
+        meter.add(30, at(1000));
+        assert!((meter.rate() - 60.0).abs() < 1e-3);
+        meter.add(10, at(3000)); // a slow two-second window
+        assert!((meter.rate() - 5.0).abs() < 1e-3);
+        meter.restart(at(3500));
+        assert_eq!(meter.rate(), 0.0);
+        meter.add(2000, at(4000));
+        assert_eq!(meter.rate(), 0.0);
+        meter.add(0, at(4500));
+        assert!((meter.rate() - 2000.0).abs() < 1e-3);
+    }
 
-            // Toggle panel visibility
-            if input.key_pressed(VirtualKeyCode::H) {
-                panel_visible = !panel_visible;
-            }
-            #[cfg(feature = "gpu-nn")]
-            {
-                if input.key_pressed(VirtualKeyCode::N) {
-                    nn_mode = !nn_mode;
-                    if nn_mode {
-                        println!("[gpu-nn] Enabled NN mode (experimental)");
-                    } else {
-                        println!("[gpu-nn] Disabled NN mode");
-                    }
-                }
-            }
-            // Ultra-fast toggle
-            if input.key_pressed(VirtualKeyCode::U) {
-                ultra_fast = !ultra_fast;
-                max_steps_per_tick = if ultra_fast { 50_000 } else { 1500 };
-            }
-            // Toggle GPU acceleration mode (just adjusts training budget for now)
-            if input.key_pressed(VirtualKeyCode::G) && gpu_available {
-                gpu_enabled = !gpu_enabled;
-                max_steps_per_tick = if gpu_enabled {
-                    80_000
-                } else if ultra_fast {
-                    50_000
-                } else {
-                    1500
-                };
-                    println!(
-                        "[hint] G toggles step budget only (not GPU learning). Use J to toggle DQN, and E to start training."
-                    );
-            }
-            if input.key_pressed(VirtualKeyCode::B) {
-                show_only_best = !show_only_best;
-            }
+    #[test]
+    #[cfg(not(feature = "gpu-render"))]
+    fn test_viewport_to_grid_maps_pixels_to_cells() {
+        let grid = GridConfig::default();
+        let center = Pos::new(grid.width as i32 / 2, grid.height as i32 / 2);
+        let full = Viewport::full((WIDTH, HEIGHT));
+        assert_eq!(
+            full.to_grid(WIDTH as f32 / 2.0, HEIGHT as f32 / 2.0, grid),
+            Some(center)
+        );
+        assert_eq!(full.to_grid(0.0, 0.0, grid), Some(Pos::new(0, 0)));
+        assert_eq!(full.to_grid(WIDTH as f32, 10.0, grid), None);
+        assert_eq!(full.to_grid(-1.0, 10.0, grid), None);
+    }
 
-            #[cfg(feature = "dqn-gpu")]
-            {
-                if input.key_pressed(VirtualKeyCode::J) {
-                    dqn_mode = !dqn_mode;
-                    if dqn_mode {
-                        let dev = dqn::preferred_device();
-                        let dev_print = format!("{:?}", dev);
-                        match dqn::DqnAgent::new(1024, 256, &dev) {
-                            Ok(agent) => {
-                                dqn_agent = Some(agent);
-                                println!("[DQN] enabled (device: {})", dev_print);
-                                evo.set_wrap_world(false);
-                                println!("[DQN] using solid walls (no wrap) for training");
-                                if !evo.training {
-                                    println!(
-                                        "[hint] DQN is active only during Evolution. Press E to start training."
-                                    );
-                                }
-                            }
-                            Err(e) => {
-                                dqn_mode = false;
-                                eprintln!("[DQN] init failed: {}", e);
-                            }
-                        }
-                    } else {
-                        dqn_agent = None;
-                        println!("[DQN] disabled");
-                        // Restore default wrap mode when DQN is off
-                        evo.set_wrap_world(true);
-                    }
-                }
-            }
+    #[test]
+    #[cfg(not(feature = "gpu-render"))]
+    fn test_viewport_round_trips_cells() {
+        let grid = GridConfig {
+            width: 80,
+            height: 60,
+        };
+        let view = Viewport {
+            offset: Pos::new(30, 20),
+            scale: 4,
+            size: (WIDTH, HEIGHT),
+        };
+        let p = Pos::new(37, 31);
+        let (x, y) = view.to_screen(p, grid).unwrap();
+        assert_eq!(view.to_grid(x as f32, y as f32, grid), Some(p));
+        let cell = view.cell(grid);
+        let (cx, cy) = ((x + cell.w - 1) as f32, (y + cell.h - 1) as f32);
+        assert_eq!(view.to_grid(cx, cy, grid), Some(p));
+        // Outside the 20x15 visible region
+        assert_eq!(view.to_screen(Pos::new(29, 31), grid), None);
+        assert_eq!(view.to_screen(Pos::new(50, 31), grid), None);
+
+        // Clamped to the board; zooming keeps the focus cell in view
+        let far = Viewport {
+            offset: Pos::new(100, -5),
+            ..view
+        };
+        assert_eq!(far.clamped(grid).offset, Pos::new(60, 0));
+        let zoomed = Viewport::full((WIDTH, HEIGHT)).zoomed(1, p, grid);
+        assert_eq!(zoomed.scale, 2);
+        assert!(zoomed.to_screen(p, grid).is_some());
+        assert_eq!(zoomed.zoomed(-1, p, grid), Viewport::full((WIDTH, HEIGHT)));
+    }
 
-            // Speed controls (keyboard)
-            if evo.training {
-                if input.key_pressed(VirtualKeyCode::NumpadAdd)
-                    || input.key_pressed(VirtualKeyCode::Equals)
-                {
-                    evo_steps_per_frame = (evo_steps_per_frame.saturating_mul(2)).min(100_000); // increased max from 10_000 to 100_000
-                }
-                if input.key_pressed(VirtualKeyCode::NumpadSubtract)
-                    || input.key_pressed(VirtualKeyCode::Minus)
-                {
-                    evo_steps_per_frame = (evo_steps_per_frame / 2).max(1);
-                }
-            } else {
-                if input.key_pressed(VirtualKeyCode::NumpadAdd)
-                    || input.key_pressed(VirtualKeyCode::Equals)
-                {
-                    manual_speed_delta_ms = (manual_speed_delta_ms - 10).max(-150);
-                }
-                if input.key_pressed(VirtualKeyCode::NumpadSubtract)
-                    || input.key_pressed(VirtualKeyCode::Minus)
-                {
-                    manual_speed_delta_ms = (manual_speed_delta_ms + 10).min(300);
-                }
-            }
+    #[test]
+    fn test_leading_edge_follows_motion() {
+        let grid = GridConfig::default();
+        let (w, h) = (grid.width as i32, grid.height as i32);
+        let ahead = |head, prev, wrap| leading_edge(head, prev, grid, wrap);
+        assert_eq!(
+            ahead(Pos::new(5, 5), Pos::new(4, 5), false),
+            Some(Pos::new(6, 5))
+        );
+        assert_eq!(
+            ahead(Pos::new(5, 5), Pos::new(5, 6), false),
+            Some(Pos::new(5, 4))
+        );
+        // Just wrapped from the right edge to x=0: still heading right
+        assert_eq!(
+            ahead(Pos::new(0, 3), Pos::new(w - 1, 3), true),
+            Some(Pos::new(1, 3))
+        );
+        // About to wrap over the top edge
+        assert_eq!(
+            ahead(Pos::new(2, 0), Pos::new(2, 1), true),
+            Some(Pos::new(2, h - 1))
+        );
+        // Solid walls: nothing ahead of a head facing the wall
+        assert_eq!(ahead(Pos::new(2, 0), Pos::new(2, 1), false), None);
+        assert_eq!(ahead(Pos::new(2, 2), Pos::new(2, 2), true), None);
+    }
 
-            // Handle direction changes
-            if input.key_pressed(VirtualKeyCode::Up) || input.key_pressed(VirtualKeyCode::W) {
-                game.change_dir(Dir::Up);
-            }
-            if input.key_pressed(VirtualKeyCode::Down) || input.key_pressed(VirtualKeyCode::S) {
-                game.change_dir(Dir::Down);
-            }
-            if input.key_pressed(VirtualKeyCode::Left) || input.key_pressed(VirtualKeyCode::A) {
-                game.change_dir(Dir::Left);
-            }
-            if input.key_pressed(VirtualKeyCode::Right) || input.key_pressed(VirtualKeyCode::D) {
-                game.change_dir(Dir::Right);
-            }
+    #[test]
+    fn test_evaluate_agent_stats() {
+        let same = EvalStats::from_episodes(&[(4, 30), (4, 30), (4, 30)]);
+        assert_eq!(
+            (same.mean_score, same.std_score, same.mean_steps),
+            (4.0, 0.0, 30.0)
+        );
+        assert_eq!((same.min_score, same.max_score), (4, 4));
+
+        let agent = QAgent::new();
+        let greedy = |g: &mut Game| agent.act_greedy(state_key_with(g, agent.encoding), &mut g.rng);
+        let grid = GridConfig::default();
+        let stats = evaluate_agent(greedy, grid, true, 8, 42);
+        assert_eq!(stats.episodes, 8);
+        assert!(stats.mean_steps > 0.0 && stats.mean_steps <= EVAL_MAX_STEPS as f32);
+        assert!(stats.min_score as f32 <= stats.mean_score);
+        assert!(stats.mean_score <= stats.max_score as f32);
+        assert!(stats.std_score >= 0.0);
+        assert_eq!(evaluate_agent(greedy, grid, true, 8, 42), stats);
+        // Greedy evaluation leaves the table alone
+        assert!(agent.q.is_empty());
+    }
 
-            // Mouse clicks on overlay buttons
-            #[cfg(not(feature = "gpu-render"))]
-            if let Some((mx, my)) = input.mouse()
-                && input.mouse_pressed(0)
-            {
-                let mx = mx as u32;
-                let my = my as u32;
+    #[test]
+    fn test_compare_to_baseline_uses_the_same_apples() {
+        let grid = GridConfig::default();
+        let mut a = SnakeEnv::new(grid, true, RewardConfig::default());
+        let mut b = SnakeEnv::new(grid, true, RewardConfig::default());
+        a.reset(&mut SmallRng::seed_from_u64(14));
+        b.reset(&mut SmallRng::seed_from_u64(14));
+        assert_eq!(a.game.apple_sequence, b.game.apple_sequence);
+        assert_eq!(a.game.apple, b.game.apple);
+
+        let agent = QAgent::new();
+        let (baseline, learned) = compare_to_baseline(&agent, grid, true, 6, 11);
+        assert_eq!((baseline.episodes, learned.episodes), (6, 6));
+        assert!(baseline.mean_steps > 0.0 && learned.mean_steps > 0.0);
+        // The baseline heads for the apple, so it eats at least once somewhere
+        assert!(baseline.max_score > 0);
+        assert_eq!(
+            compare_to_baseline(&agent, grid, true, 6, 11),
+            (baseline, learned)
+        );
+
+        // The board and walls come from the caller
+        let small = GridConfig {
+            width: 8,
+            height: 6,
+        };
+        let mut env = SnakeEnv::new(small, false, RewardConfig::default());
+        env.reset(&mut SmallRng::seed_from_u64(14));
+        assert_eq!((env.game.grid, env.game.wrap_world), (small, false));
+        let (small_base, _) = compare_to_baseline(&agent, small, false, 6, 11);
+        assert!(small_base.max_score < small.cells());
+    }
 
-                if panel_visible {
-                    let panel_x: u32 = 8;
-                    let panel_y: u32 = 8;
-                    let panel_w: u32 = 380;
-                    let btn_h: u32 = 32;
-                    let btn_w: u32 = panel_w - 16;
-                    let btn_x: u32 = panel_x + 8;
-                    let chart_y: u32 = panel_y + 310;
-                    let chart_h: u32 = 120;
-                    let btn1_y: u32 = chart_y + chart_h + 8;
-                    let btn2_y: u32 = btn1_y + btn_h + 6;
-                    let btn3_y: u32 = btn2_y + btn_h + 6;
-                    let btn4_y: u32 = btn3_y + btn_h + 6;
-                    let btn5_y: u32 = btn4_y + btn_h + 6;
-                    let btn6_y: u32 = btn5_y + btn_h + 6;
-                    if point_in_rect(mx, my, btn_x, btn1_y, btn_w, btn_h) {
-                        game.paused = !game.paused;
-                    } else if point_in_rect(mx, my, btn_x, btn2_y, btn_w, btn_h) {
-                        if evo.training {
-                            evo_steps_per_frame =
-                                (evo_steps_per_frame.saturating_mul(2)).min(100_000);
-                        }
-                        // increased max from 10_000
-                        else {
-                            manual_speed_delta_ms = (manual_speed_delta_ms - 10).max(-150);
-                        }
-                    } else if point_in_rect(mx, my, btn_x, btn3_y, btn_w, btn_h) {
-                        game = Game::new();
-                        tick_duration = Duration::from_millis(150);
-                    } else if point_in_rect(mx, my, btn_x, btn4_y, btn_w, btn_h) {
-                        if let Err(e) = evo.save_best(save_path) {
-                            eprintln!("Failed to save agent: {}", e);
-                        } else {
-                            println!("Agent saved to {}", save_path);
-                        }
-                    } else if point_in_rect(mx, my, btn_x, btn5_y, btn_w, btn_h) {
-                        panel_visible = false;
-                    } else if point_in_rect(mx, my, btn_x, btn6_y, btn_w, btn_h) {
-                        show_only_best = !show_only_best;
-                    }
-                } else {
-                    // Check if clicked on show button
-                    let show_btn_x: u32 = 8;
-                    let show_btn_y: u32 = 8;
-                    let show_btn_w: u32 = 100;
-                    let show_btn_h: u32 = 32;
-                    if point_in_rect(mx, my, show_btn_x, show_btn_y, show_btn_w, show_btn_h) {
-                        panel_visible = true;
-                    }
-                }
-            }
+    #[test]
+    fn test_mutation_keeps_q_values_finite_and_clipped() {
+        let mut agent = QAgent::new();
+        let mut rng = SmallRng::seed_from_u64(9);
+        for s in 0..50u32 {
+            agent.q.insert(s, [0.0, f32::INFINITY, f32::NAN]);
+        }
+        for _ in 0..200 {
+            mutate_qagent(&mut agent, &mut rng, 1e6);
+        }
+        assert!(
+            agent
+                .q
+                .values()
+                .flatten()
+                .all(|v| v.is_finite() && v.abs() <= agent.q_clip)
+        );
+
+        let before = agent.q.clone();
+        mutate_qagent(&mut agent, &mut rng, 0.0);
+        mutate_qagent(&mut agent, &mut rng, -1.0);
+        assert_eq!(agent.q, before);
+    }
+
+    #[test]
+    fn test_table_bytes_counts_hash_table_slots() {
+        // 128 and 64 buckets hold 7/8 of their slots: exactly 112 and 56 entries
+        let mut agent = QAgent::new();
+        agent.q = AHashMap::with_capacity(112);
+        agent.visits = AHashMap::with_capacity(56);
+        for s in 0..112u32 {
+            agent.q.insert(s, [0.0; 3]);
+        }
+        for s in 0..56u32 {
+            agent.visits.insert(s, 1);
+        }
+        assert_eq!((agent.q.capacity(), agent.visits.capacity()), (112, 56));
+        assert_eq!(agent.table_bytes(), 112 * 17 + 56 * 9);
+        let mut evo = EvoTrainer::new(2, None);
+        evo.pop[1] = agent;
+        assert_eq!(evo.table_bytes(), 112 * 17 + 56 * 9);
+    }
 
-                    // Evolutionary training loop (population of agents)
-                    if evo.training {
-                let steps_per_frame: u32 = evo_steps_per_frame.max(1);
-                if game.paused {
-                    window.request_redraw();
-                    return;
-                }
+    #[test]
+    fn test_qmem_budget_prunes_the_largest_tables() {
+        let fill = |evo: &mut EvoTrainer| {
+            for (agent, n) in evo.pop.iter_mut().zip([1000u32, 400, 10, 1000]) {
+                agent.q = (0..n).map(|s| (s, [0.0; 3])).collect();
+                agent.q.shrink_to_fit();
+            }
+        };
+        let mut evo = EvoTrainer::new(4, None);
+        fill(&mut evo);
+        let total = evo.table_bytes();
+        assert!(!evo.enforce_qmem_budget()); // no budget set
+
+        let budget = total / 2;
+        evo.max_qmem = Some(budget);
+        assert!(evo.enforce_qmem_budget());
+        let sizes: Vec<usize> = evo.pop.iter().map(QAgent::table_size).collect();
+        assert!(
+            sizes[0] == sizes[3] && sizes[0] < 1000 && sizes[1] <= sizes[0],
+            "{:?}",
+            sizes
+        );
+        assert_eq!(sizes[2], 10); // small tables are left alone
+        assert!(evo.table_bytes() <= budget);
+        assert!(!evo.enforce_qmem_budget()); // already within budget
+
+        // A frozen champion in slot 0 keeps its whole table but still uses up budget
+        fill(&mut evo);
+        evo.champion_frozen = true;
+        assert!(evo.enforce_qmem_budget());
+        assert_eq!(evo.pop[0].table_size(), 1000);
+        assert!(evo.pop[3].table_size() < sizes[3]);
+        assert!(evo.table_bytes() <= budget);
+    }
 
-                // Accumulate desired work and process in chunks to avoid long UI stalls
-                evo_pending_steps = evo_pending_steps.saturating_add(steps_per_frame);
-                let to_run = evo_pending_steps.min(max_steps_per_tick);
-                let mut ran_steps: u32 = 0;
-                for _ in 0..to_run {
-                    let mut all_done = true;
-                    let target_score = evo.target_score;
-                    let len = evo.pop.len().min(evo.games.len()).min(evo.scores.len());
-                    // Two paths: GPU NN inference (sequential/batched) vs CPU tabular Q-learning (parallel)
-                    #[cfg(all(feature = "gpu-nn-experimental", feature = "gpu-nn"))]
-                    let nn_active = nn_mode && nn_trainer.is_some();
-                    #[cfg(not(all(feature = "gpu-nn-experimental", feature = "gpu-nn")))]
-                    let nn_active = false;
+    #[test]
+    fn test_pruning_keeps_most_visited_states() {
+        let mut agent = QAgent::new();
+        for s in 0..500u32 {
+            agent.q.insert(s, [s as f32; 3]);
+            agent.visits.insert(s, (s * 37) % 500); // distinct, shuffled counts
+        }
+        let before = agent.table_bytes();
+        agent.prune_rarely_used(50);
+        assert_eq!(agent.table_size(), 50);
+        assert!(agent.q.keys().all(|s| agent.visits[s] >= 450));
+        assert!(agent.table_bytes() < before);
+        agent.prune_rarely_used(100); // already fits
+        assert_eq!(agent.table_size(), 50);
+
+        // Visits are only counted under a cap
+        let (s, ns) = (StateKey(1), StateKey(2));
+        let mut free = QAgent::new();
+        free.learn(s, 0, 1.0, ns, false);
+        assert!(free.visits.is_empty());
+        free.max_states = Some(10);
+        free.learn(s, 0, 1.0, ns, false);
+        free.learn(s, 1, 1.0, ns, false);
+        assert_eq!(free.visits.get(&1), Some(&2));
+    }
 
-                    if nn_active {
-                        // Batched GPU policy inference for alive agents
-                        #[cfg(all(feature = "gpu-nn-experimental", feature = "gpu-nn"))]
-                        {
-                            let trainer = nn_trainer.as_ref().unwrap();
-                            // Collect indices and states
-                            let mut idxs: Vec<usize> = Vec::with_capacity(len);
-                            let mut states: Vec<u32> = Vec::with_capacity(len);
-                            for i in 0..len {
-                                if evo.games[i].alive && evo.scores[i] < target_score {
-                                    idxs.push(i);
-                                    let s = state_key(&evo.games[i]);
-                                    states.push(s % 256); // keep within input size used at init
-                                }
-                            }
-                            if !idxs.is_empty() {
-                                let probs = trainer.infer_to_vec(&states, 256, 3);
-                                // Apply actions and update each game sequentially
-                                for (k, &i) in idxs.iter().enumerate() {
-                                    let p0 = probs[k * 3];
-                                    let p1 = probs[k * 3 + 1];
-                                    let p2 = probs[k * 3 + 2];
-                                    let a_idx = if p0 >= p1 && p0 >= p2 { 0 } else if p1 >= p2 { 1 } else { 2 };
+    #[test]
+    fn test_hyperparameter_mutation_is_opt_in_and_bounded() {
+        let parent = QAgent::new();
+        let mut rng = SmallRng::seed_from_u64(3);
+        let mut child = parent.clone();
+        for _ in 0..100 {
+            mutate_qagent(&mut child, &mut rng, 0.1);
+        }
+        assert_eq!(
+            (child.alpha, child.gamma, child.decay),
+            (parent.alpha, parent.gamma, parent.decay)
+        );
+
+        child = parent.clone();
+        child.mutate_hypers = true;
+        for _ in 0..100 {
+            mutate_qagent(&mut child, &mut rng, 0.1);
+            for h in [child.alpha, child.gamma, child.decay] {
+                assert!((0.0..=1.0).contains(&h));
+            }
+        }
+        assert_ne!(child.alpha, parent.alpha);
+    }
 
-                                    let g = &mut evo.games[i];
-                                    g.change_dir(dir_after_action(g.dir, a_idx));
-                                    let before_score = g.score;
-                                    let was_alive = g.alive;
-                                    let head0 = *g.snake.front().unwrap();
-                                    let d0 = (g.apple.x - head0.x).abs() + (g.apple.y - head0.y).abs();
-                                    g.update();
-                                    let ate = g.score > before_score;
-                                    let died = was_alive && !g.alive;
-                                    let head1 = *g.snake.front().unwrap();
-                                    let d1 = (g.apple.x - head1.x).abs() + (g.apple.y - head1.y).abs();
-                                    let length1 = g.snake.len();
-
-                                    // Reward (used only for epoch/score decisions here)
-                                    let mut _reward = if died {
-                                        match g.last_death {
-                                            DeathCause::SelfCollision => -30.0,
-                                            DeathCause::Wall => -20.0,
-                                            DeathCause::None => -12.0,
-                                        }
-                                    } else if ate {
-                                        10.0 + (length1 as f32 * 0.1)
-                                    } else {
-                                        -0.005
-                                    };
-                                    if !died && !ate {
-                                        if d1 < d0 { _reward += 0.05; } else if d1 > d0 { _reward -= 0.03; }
-                                        if d1 <= 3 && !ate { _reward += 0.02; }
-                                    }
+    #[test]
+    fn test_training_step_interval_in_eval_mode() {
+        assert_eq!(training_step_interval(false), None);
+        assert_eq!(
+            training_step_interval(true),
+            Some(Duration::from_millis(100))
+        );
+    }
 
-                                    if g.alive {
-                                        evo.scores[i] = g.score;
-                                    }
-                                }
-                            }
+    #[test]
+    fn test_score_watch_fires_on_tracked_increase() {
+        let mut w = ScoreWatch::default();
+        assert!(!w.observe(3, 2)); // first sighting only arms
+        assert!(!w.observe(3, 2));
+        assert!(w.observe(3, 3));
+        assert!(!w.observe(5, 4)); // a different leader re-arms instead of firing
+        assert!(!w.observe(5, 0)); // new epoch: score drops
+        assert!(w.observe(5, 1));
+    }
 
-                            // Check solved/all_done
-                            if evo.scores.iter().zip(evo.games.iter()).any(|(s, g)| g.alive && *s < target_score) {
-                                all_done = false;
-                            }
-                        }
-                    } else {
-                        // If DQN mode is enabled, use a single shared DQN for action selection & learning
-                        #[cfg(feature = "dqn-gpu")]
-                        if dqn_mode {
-                            if let Some(agent) = dqn_agent.as_mut() {
-                                // Iterate sequentially to accumulate transitions
-                                for i in 0..len {
-                                    let g = &mut evo.games[i];
-                                    if !g.alive || evo.scores[i] >= target_score { continue; }
-                                    let s = state_key(g) % agent.input_vocab as u32;
-                                    // Greedy action from DQN
-                                    let a_idx = agent.select_action(s).unwrap_or(1);
-                                    g.change_dir(dir_after_action(g.dir, a_idx));
-                                    let before_score = g.score;
-                                    let was_alive = g.alive;
-                                    let head0 = *g.snake.front().unwrap();
-                                    let d0 = (g.apple.x - head0.x).abs() + (g.apple.y - head0.y).abs();
-                                    g.update();
-                                    let ate = g.score > before_score;
-                                    let died = was_alive && !g.alive;
-                                    let head1 = *g.snake.front().unwrap();
-                                    let d1 = (g.apple.x - head1.x).abs() + (g.apple.y - head1.y).abs();
-                                    let length1 = g.snake.len();
-                                    // Reward shaping identical to tabular path
-                                    let mut reward = if died {
-                                        match g.last_death {
-                                            DeathCause::SelfCollision => -30.0,
-                                            DeathCause::Wall => -20.0,
-                                            DeathCause::None => -12.0,
-                                        }
-                                    } else if ate {
-                                        10.0 + (length1 as f32 * 0.1)
-                                    } else { -0.005 };
-                                    if !died && !ate {
-                                        if d1 < d0 { reward += 0.05; } else if d1 > d0 { reward -= 0.03; }
-                                        if d1 <= 3 && !ate { reward += 0.02; }
-                                    }
-                                    let ns = state_key(g) % agent.input_vocab as u32;
-                                    agent.push_transition(s, a_idx, reward, ns, died || !g.alive);
-                                    if g.alive { evo.scores[i] = g.score; }
-                                }
-                                // Train a few steps per iteration
-                                let _ = agent.train_step(256);
-                            }
-                            // all_done check
-                            if evo.scores.iter().zip(evo.games.iter()).any(|(s, g)| g.alive && *s < target_score) {
-                                all_done = false;
-                            }
-                        } else {
-                        // CPU tabular Q-learning path (parallel)
-                        let (pop_slice, _) = evo.pop.split_at_mut(len);
-                        let (games_slice, _) = evo.games.split_at_mut(len);
-                        let (scores_slice, _) = evo.scores.split_at_mut(len);
-                        let solved_flag = AtomicBool::new(false);
-
-                        pop_slice
-                            .par_iter_mut()
-                            .zip(games_slice.par_iter_mut())
-                            .zip(scores_slice.par_iter_mut())
-                            .for_each(|((agent, g), score_ref)| {
-                                if !g.alive || *score_ref >= target_score {
-                                    return;
-                                }
-                                // local RNG per thread (SmallRng)
-                                let mut local_rng = SmallRng::from_entropy();
-                                let s = state_key(g);
-                                let a_idx = agent.select_action(s, &mut local_rng);
-                                g.change_dir(dir_after_action(g.dir, a_idx));
-                                let before_score = g.score;
-                                let was_alive = g.alive;
-                                let head0 = *g.snake.front().unwrap();
-                                let d0 = (g.apple.x - head0.x).abs() + (g.apple.y - head0.y).abs();
-                                g.update();
-                                let ate = g.score > before_score;
-                                let died = was_alive && !g.alive;
-                                let head1 = *g.snake.front().unwrap();
-                                let d1 = (g.apple.x - head1.x).abs() + (g.apple.y - head1.y).abs();
-                                let length1 = g.snake.len();
-
-                                // Death penalties: self-collision is the heaviest crime
-                                let mut reward = if died {
-                                    match g.last_death {
-                                        DeathCause::SelfCollision => -30.0,
-                                        DeathCause::Wall => -20.0,
-                                        DeathCause::None => -12.0,
-                                    }
-                                } else if ate {
-                                    10.0 + (length1 as f32 * 0.1)
-                                } else {
-                                    -0.005
-                                };
-                                if !died && !ate {
-                                    if d1 < d0 {
-                                        reward += 0.05;
-                                    } else if d1 > d0 {
-                                        reward -= 0.03;
-                                    }
-                                    if d1 <= 3 && !ate {
-                                        reward += 0.02;
-                                    }
-                                }
+    #[test]
+    fn test_near_head_apple_spawn() {
+        let grid = GridConfig {
+            width: 20,
+            height: 15,
+        };
+        for seed in 0..20 {
+            let g = Game::new_in(grid, false, seed)
+                .with_apple_spawn(AppleSpawn::NearHead { radius: 2 });
+            let head = *g.snake.front().unwrap();
+            assert!(
+                g.apple_distance(head) <= 2,
+                "seed {}: apple {:?}",
+                seed,
+                g.apple
+            );
+            assert!(!g.snake_set.contains(&g.apple));
+        }
 
-                                let ns = state_key(g);
-                                agent.learn(s, a_idx, reward, ns, died || !g.alive);
-                                agent.steps += 1;
-                                if died {
-                                    agent.episodes += 1;
-                                    agent.epsilon =
-                                        (agent.epsilon * agent.decay).max(agent.min_epsilon);
-                                }
-                                if g.alive {
-                                    *score_ref = g.score;
-                                }
-                                if g.score >= target_score {
-                                    solved_flag.store(true, Ordering::Relaxed);
-                                }
-                            });
+        let mut evo = EvoTrainer::new(4, None);
+        evo.curriculum = Some(2);
+        assert_eq!(evo.apple_spawn(), AppleSpawn::NearHead { radius: 2 });
+        evo.epoch = 3 * CURRICULUM_EPOCHS_PER_CELL;
+        assert_eq!(evo.apple_spawn(), AppleSpawn::NearHead { radius: 5 });
+        evo.epoch = 1000 * CURRICULUM_EPOCHS_PER_CELL;
+        assert_eq!(evo.apple_spawn(), AppleSpawn::Uniform);
+    }
 
-                        if solved_flag.load(Ordering::Relaxed) {
-                            evo.solved = true;
-                            evo.training = false;
-                        } else if scores_slice
-                            .iter()
-                            .zip(games_slice.iter())
-                            .any(|(s, g)| g.alive && *s < target_score)
-                        {
-                            all_done = false;
-                        }
-                        }
-                    }
+    #[test]
+    fn test_parse_high_score() {
+        assert_eq!(parse_high_score("42\n"), 42);
+        assert_eq!(parse_high_score(""), 0);
+        assert_eq!(parse_high_score("not a score"), 0);
+        assert_eq!(parse_high_score("-3"), 0);
+    }
 
-                    // Determine if there is a unique leading agent who should bypass the step limit
-                    let (mut top1, mut top2, mut top1_idx) = (0usize, 0usize, None::<usize>);
-                    for (i, &sc) in evo.scores.iter().enumerate() {
-                        if sc > top1 {
-                            top2 = top1;
-                            top1 = sc;
-                            top1_idx = Some(i);
-                        } else if sc > top2 {
-                            top2 = sc;
-                        }
-                    }
-                    let leader_protected = if let Some(idx) = top1_idx {
-                        (top1 > top2) && evo.games.get(idx).map(|g| g.alive).unwrap_or(false)
-                    } else {
-                        false
-                    };
+    #[test]
+    fn test_record_high_score_saves_only_a_new_record() {
+        let path = std::env::temp_dir().join("snake_high_score_test.txt");
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+        let mut high = 5;
+        record_high_score(&path, &mut high, 3);
+        assert_eq!(high, 5);
+        assert!(!Path::new(&path).exists());
+        record_high_score(&path, &mut high, 7);
+        let saved = load_high_score(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!((high, saved), (7, 7));
+    }
 
-                    evo.steps_taken += 1;
-                    ran_steps += 1;
-                    if all_done || (evo.steps_taken >= evo.step_limit && !leader_protected) {
-                        // All individuals finished or step limit reached - start new epoch
-                        evo.reproduce(&mut rng, save_path);
-                        evo_pending_steps = 0; // reset pending work on epoch change
-                        break;
-                    }
-                }
-                // Reduce pending work by the amount actually processed
-                evo_pending_steps = evo_pending_steps.saturating_sub(ran_steps);
+    #[test]
+    fn test_scripted_sequence_scores_and_dies() {
+        let grid = GridConfig {
+            width: 20,
+            height: 15,
+        };
+        let mut g = Game::new_in(grid, false, 1);
+        let head = *g.snake.front().unwrap();
+        // Straight, then turn left (up) onto the apple
+        g.apple = Pos::new(head.x + 1, head.y - 1);
+        assert_eq!(g.apply_action_sequence(&[1, 0]), (1, DeathCause::None, 2));
+
+        // Straight up into the top wall; the rest of the script is never played
+        let (score, death, steps) = g.apply_action_sequence(&[1; 20]);
+        assert_eq!((score, death), (1, DeathCause::Wall));
+        assert_eq!(steps, (head.y - 1) as usize + 1);
+        assert!(!g.alive);
+    }
 
-                // Update screen less frequently on high speeds to improve performance
-                frame_counter += 1;
-                let frames_to_skip = if ultra_fast {
-                    8
-                } else if evo_steps_per_frame >= 65_536 {
-                    20 // update screen every 20th iteration
-                } else if evo_steps_per_frame >= 40_000 {
-                    12 // every 12th
-                } else if evo_steps_per_frame >= 20_000 {
-                    6 // every 6th
-                } else if evo_steps_per_frame >= 8_192 {
-                    3 // every 3rd
-                } else if evo_steps_per_frame >= 4_096 {
-                    2 // every 2nd
-                } else {
-                    1 // update every iteration
-                };
+    #[test]
+    fn test_restart_live_game_resets_score_and_length() {
+        let grid = GridConfig {
+            width: 20,
+            height: 15,
+        };
+        let mut g = Game::new_manual(grid, false);
+        let head = *g.snake.front().unwrap();
+        g.apple = Pos::new(head.x + 1, head.y);
+        g.update();
+        assert!(g.alive);
+        assert_eq!(g.score, 1);
+        let start_len = Game::new_manual(grid, false).snake.len();
+        assert!(g.snake.len() > start_len);
+
+        let r = g.restarted();
+        assert!(r.alive);
+        assert_eq!(r.score, 0);
+        assert_eq!(r.snake.len(), start_len);
+        assert_eq!(r.grid, grid);
+        assert!(!r.wrap_world);
+    }
 
-                if !ultra_fast && frame_counter >= frames_to_skip {
-                    frame_counter = 0;
-                    window.request_redraw();
-                }
-                return;
+    #[test]
+    fn test_thread_count_does_not_change_results() {
+        let run = |threads| {
+            let cfg = Config {
+                pop_size: 6,
+                seed: Some(13),
+                grid: GridConfig {
+                    width: 10,
+                    height: 8,
+                },
+                threads: Some(threads),
+                ..Config::default()
+            };
+            let mut evo = EvoTrainer::from_config(&cfg);
+            for _ in 0..300 {
+                evo.step_tabular();
             }
+            let heads: Vec<Pos> = evo.games.iter().map(|g| g.snake[0]).collect();
+            let tables: Vec<usize> = evo.pop.iter().map(|a| a.q.len()).collect();
+            (evo.scores.clone(), heads, tables)
+        };
+        assert_eq!(run(1), run(2));
+        assert!(parse_args(["--threads", "0"].map(String::from)).is_err());
+    }
 
-            // (Removed) standalone Q-learning training loop
+    #[test]
+    fn test_roulette_favors_high_scores() {
+        let mut rng = SmallRng::seed_from_u64(17);
+        let mut counts = [0usize; 3];
+        for _ in 0..10_000 {
+            counts[roulette_index(&[10, 1, 0], &mut rng)] += 1;
+        }
+        // Expected shares 11/14, 2/14, 1/14
+        assert!(counts[0] > 5 * counts[2], "{:?}", counts);
+        assert!(counts[2] > 0);
+        // All-zero scores degrade to uniform
+        let mut zero = [0usize; 4];
+        for _ in 0..4_000 {
+            zero[roulette_index(&[0, 0, 0, 0], &mut rng)] += 1;
+        }
+        assert!(zero.iter().all(|&c| (800..1200).contains(&c)), "{:?}", zero);
+    }
 
-            // Update game logic (real-time); manual play only outside evolution
-            if last_update.elapsed() >= tick_duration {
-                game.update();
-                last_update = Instant::now();
+    #[test]
+    fn test_reseed_from_champion() {
+        let mut evo = EvoTrainer::new(6, None);
+        let mut rng = SmallRng::seed_from_u64(9);
+        assert!(!evo.reseed_from_champion(&mut rng));
+
+        let mut champion = QAgent::new();
+        champion.q.insert(42, [1.0, 2.0, 3.0]);
+        evo.champion = Some(champion.clone());
+        evo.epochs_without_improvement = 700;
+        evo.restart_count = 3;
+        assert!(evo.reseed_from_champion(&mut rng));
+        assert_eq!(evo.pop.len(), 6);
+        assert_eq!(evo.pop[0].q, champion.q);
+        assert_eq!((evo.epochs_without_improvement, evo.restart_count), (0, 0));
+    }
 
-                // Combine base speed with manual delta
-                let base_ms = 150 - game.score.min(30) as i32 * 4;
-                let total_ms = (base_ms + manual_speed_delta_ms).clamp(30, 500) as u64;
-                tick_duration = Duration::from_millis(total_ms);
-            }
+    #[test]
+    fn test_override_best_applies_and_teaches_the_human_action() {
+        let mut evo = EvoTrainer::new(2, None);
+        let body = [Pos::new(5, 5), Pos::new(4, 5), Pos::new(3, 5)];
+        for g in evo.games.iter_mut() {
+            *g = Game::from_layout(&body, Dir::Right, Pos::new(9, 9), true).unwrap();
+        }
+        evo.scores = vec![0, 1]; // game 1 is the best-agent view
+        assert!(!evo.override_best(Dir::Left)); // would reverse
+        assert!(evo.override_best(Dir::Down));
+        let s = state_key_with(&evo.games[1], evo.pop[1].encoding);
+
+        evo.step_tabular();
+        assert_eq!(evo.human_action, None);
+        assert_eq!(evo.games[1].dir, Dir::Down);
+        assert_eq!(evo.games[1].snake.front(), Some(&Pos::new(5, 6)));
+        // Only the forced action (right turn) was updated for that state
+        let q = evo.pop[1].q[&s.raw()];
+        assert!(q[0] == 0.0 && q[1] == 0.0 && q[2] != 0.0);
+    }
 
-            window.request_redraw();
+    #[test]
+    fn test_stagnation_threshold_grows_per_restart() {
+        let cfg = Config {
+            pop_size: 2,
+            stagnation_base: 40,
+            ..Config::default()
+        };
+        let mut evo = EvoTrainer::from_config(&cfg);
+        assert_eq!(evo.stagnation_threshold(), 40);
+        evo.restart_count = 1;
+        assert_eq!(evo.stagnation_threshold(), 540);
+        evo.restart_count = 3;
+        assert_eq!(evo.stagnation_threshold(), 1540);
+        assert_eq!(evo.snapshot().stagnation_threshold, 1540);
+        assert_eq!(EvoTrainer::new(2, None).stagnation_threshold(), 1000);
+        let parse = |v: &str| parse_args(["--stagnation-base", v].map(String::from));
+        assert_eq!(parse("40").unwrap().stagnation_base, 40);
+        assert!(parse("0").is_err());
+    }
+
+    #[test]
+    fn test_frozen_champion_survives_reproduction() {
+        let cfg = Config {
+            pop_size: 6,
+            seed: Some(5),
+            grid: GridConfig {
+                width: 10,
+                height: 8,
+            },
+            ..Config::default()
+        };
+        let mut evo = EvoTrainer::from_config(&cfg);
+        let mut rng = SmallRng::seed_from_u64(5);
+        let mut champion = QAgent::new();
+        for s in 0..64u32 {
+            champion.q.insert(s, [s as f32, 1.0, -1.0]);
         }
-    });
-}
+        evo.champion = Some(champion);
+        evo.champion_score = usize::MAX; // never beaten, so never replaced
+        evo.set_freeze_champion(true);
+        evo.max_states = Some(16); // prunes the challengers, not the frozen champion
+        let frozen = serde_json::to_string(&evo.champion).unwrap();
+        for _ in 0..4 {
+            for _ in 0..200 {
+                if evo.step_tabular() {
+                    break;
+                }
+            }
+            if evo.champion_frozen {
+                assert_eq!(evo.pop[0].q, evo.champion.as_ref().unwrap().q); // played, not learned
+            }
+            evo.reproduce(&mut rng, None);
+            assert_eq!(serde_json::to_string(&evo.champion).unwrap(), frozen);
+            assert_eq!(evo.pop[0].q, evo.champion.as_ref().unwrap().q);
+            assert!(evo.pop[1..].iter().all(|a| a.table_size() <= 16));
+            let ids: AHashSet<u64> = evo.pop.iter().map(|a| a.id).collect();
+            assert_eq!(ids.len(), evo.pop.len());
+            assert_eq!(evo.pop.len(), 6);
+        }
+        // Topping the epoch doesn't earn the frozen champion a second slot as an elite
+        evo.scores = vec![0; 6];
+        evo.scores[0] = 50;
+        evo.reproduce(&mut rng, None);
+        let champion_id = evo.champion.as_ref().unwrap().id;
+        assert_eq!(evo.pop.iter().filter(|a| a.id == champion_id).count(), 1);
+
+        evo.set_freeze_champion(false);
+        assert!(!evo.champion_frozen);
+    }
 
-// ============================
-// Rendering helpers and UI
-// ============================
+    #[test]
+    fn test_death_stats_count_wall_self_and_timeout() {
+        let mut evo = EvoTrainer::new(3, None);
+        // Game 0 drives into the left wall
+        let wall_body = [Pos::new(0, 5), Pos::new(1, 5)];
+        let mut wall = Game::from_layout(&wall_body, Dir::Left, Pos::new(9, 9), false).unwrap();
+        wall.update();
+        // Game 1 turns into its own body
+        let body_cells = [Pos::new(2, 2), Pos::new(3, 2)];
+        let mut body = Game::from_layout(&body_cells, Dir::Right, Pos::new(9, 9), false).unwrap();
+        body.update();
+        assert_eq!(
+            (wall.last_death, body.last_death),
+            (DeathCause::Wall, DeathCause::SelfCollision)
+        );
+        evo.games[0] = wall;
+        evo.games[1] = body;
+        // Game 2 is still running when the epoch ends
+
+        evo.reproduce(&mut SmallRng::seed_from_u64(3), None);
+        assert_eq!(
+            evo.death_stats,
+            DeathStats {
+                wall: 1,
+                self_collision: 1,
+                timeout: 1
+            }
+        );
+        let (w, s, t) = evo.death_ratios();
+        assert!((w - 1.0 / 3.0).abs() < 1e-6 && (s - w).abs() < 1e-6 && (t - w).abs() < 1e-6);
+        assert_eq!(DeathStats::default().ratios(), (0.0, 0.0, 0.0));
+    }
 
-/// Clear the entire frame buffer to a single RGBA color.
-#[cfg(not(feature = "gpu-render"))]
-fn clear_rgba(frame: &mut [u8], r: u8, g: u8, b: u8, a: u8) {
-    for px in frame.chunks_exact_mut(4) {
-        px[0] = r;
-        px[1] = g;
-        px[2] = b;
-        px[3] = a;
+    #[test]
+    fn test_ghost_trail_ages_and_cap() {
+        let mut g = Game::new_manual(GridConfig::default(), true);
+        g.apple = Pos::new(0, 0);
+        for _ in 0..5 {
+            g.update();
+        }
+        assert!(g.alive);
+        assert_eq!(g.trail.len(), 5);
+        let ages: Vec<u8> = g.trail.iter().map(|&(_, age)| age).collect();
+        assert_eq!(ages, vec![0, 1, 2, 3, 4]);
+        // Newest entry is the cell the tail just left
+        let tail = *g.snake.back().unwrap();
+        assert_eq!(g.trail[0].0, Pos::new(tail.x - 1, tail.y));
+
+        for _ in 0..TRAIL_LEN * 2 {
+            g.update();
+        }
+        assert_eq!(g.trail.len(), TRAIL_LEN);
+        assert_eq!(g.trail.back().unwrap().1 as usize, TRAIL_LEN - 1);
+
+        // Training games keep no trail
+        let mut t = Game::new_with_wrap(true);
+        t.apple = Pos::new(0, 0);
+        t.update();
+        assert!(t.trail.is_empty());
     }
-}
 
-/// Alpha-blend a pixel into the frame at (x,y).
-#[cfg(not(feature = "gpu-render"))]
-fn blend_pixel(frame: &mut [u8], x: u32, y: u32, r: u8, g: u8, b: u8, a: u8) {
-    if x >= WIDTH || y >= HEIGHT {
-        return;
+    #[test]
+    #[cfg(not(feature = "gpu-render"))]
+    fn test_chart_downsampling_long_history() {
+        let data: Vec<usize> = (0..10_000).map(|i| (i * 7) % 50).chain([123]).collect();
+        let bars = downsample_max(&data, 80);
+        assert_eq!(bars.len(), 80);
+        assert_eq!(*bars.last().unwrap(), 123);
+        assert_eq!(downsample_max(&[1, 2, 3], 80), vec![1, 2, 3]);
+
+        let mut pixels = vec![0u8; (WIDTH * HEIGHT * 4) as usize];
+        let frame = &mut Canvas::new(&mut pixels, WIDTH, HEIGHT);
+        draw_chart(frame, 10, 10, 500, 60, &data);
+        draw_chart(frame, 10, 10, 500, 2, &data);
     }
-    let idx = ((y * WIDTH + x) * 4) as usize;
-    if idx + 3 >= frame.len() {
-        return;
+
+    #[test]
+    fn test_mixed_wall_modes_survive_reset_epoch() {
+        let mut evo = EvoTrainer::new(4, None);
+        evo.set_mixed_walls();
+        evo.reset_epoch();
+        assert!(evo.games[0].wrap_world);
+        assert!(!evo.games[1].wrap_world);
+        assert!(evo.has_mixed_walls());
+
+        evo.set_wrap_world(false);
+        assert!(evo.games.iter().all(|g| !g.wrap_world));
+        assert!(!evo.has_mixed_walls());
     }
-    let ar = a as u16;
-    let iar = (255 - a) as u16;
-    let dr = frame[idx] as u16;
-    let dg = frame[idx + 1] as u16;
-    let db = frame[idx + 2] as u16;
-    frame[idx] = (((r as u16) * ar + dr * iar) / 255) as u8;
-    frame[idx + 1] = (((g as u16) * ar + dg * iar) / 255) as u8;
-    frame[idx + 2] = (((b as u16) * ar + db * iar) / 255) as u8;
-    frame[idx + 3] = 255;
-}
 
-/// Fill an axis-aligned rectangle with an RGBA color (alpha-blended per pixel).
-#[allow(clippy::too_many_arguments)]
-#[cfg(not(feature = "gpu-render"))]
-fn fill_rect_rgba(frame: &mut [u8], x: u32, y: u32, w: u32, h: u32, r: u8, g: u8, b: u8, a: u8) {
-    let x2 = (x + w).min(WIDTH);
-    let y2 = (y + h).min(HEIGHT);
-    for py in y..y2 {
-        for px in x..x2 {
-            blend_pixel(frame, px, py, r, g, b, a);
+    /// Game whose snake covers every cell but one, with the apple there and the head
+    /// facing it.
+    fn nearly_full_game() -> Game {
+        let mut g = Game::new_seeded(false, 1);
+        // Serpentine path over the whole grid; all but its last cell is snake
+        let mut path = Vec::new();
+        for y in 0..GRID_HEIGHT as i32 {
+            for i in 0..GRID_WIDTH as i32 {
+                let x = if y % 2 == 0 {
+                    i
+                } else {
+                    GRID_WIDTH as i32 - 1 - i
+                };
+                path.push(Pos::new(x, y));
+            }
         }
+        let last = *path.last().unwrap();
+        let before = path[path.len() - 2];
+        g.snake = path[..path.len() - 1].iter().rev().copied().collect();
+        g.snake_set = g.snake.iter().copied().collect();
+        g.apple = last;
+        g.dir = if last.x < before.x {
+            Dir::Left
+        } else {
+            Dir::Right
+        };
+        g
+    }
+
+    #[test]
+    fn test_full_board_is_a_win() {
+        let mut g = nearly_full_game();
+        let outcome = g.apply_action(1);
+        assert!(g.won);
+        assert!(!g.alive);
+        assert_eq!(g.last_death, DeathCause::None);
+        assert_eq!(g.snake.len(), (GRID_WIDTH * GRID_HEIGHT) as usize);
+        assert!(outcome.ate && !outcome.died);
+    }
+
+    #[test]
+    fn test_winning_apple_counts_toward_the_score() {
+        let mut evo = EvoTrainer::new(1, None);
+        evo.games[0] = nearly_full_game();
+        evo.human_action = Some((0, 1)); // straight into the last free cell
+        evo.step_tabular();
+        assert!(evo.games[0].won);
+        assert_eq!(evo.scores[0], 1);
     }
-}
 
-/// Draw a rectangle border with an RGBA color.
-#[allow(clippy::too_many_arguments)]
-#[cfg(not(feature = "gpu-render"))]
-fn stroke_rect_rgba(frame: &mut [u8], x: u32, y: u32, w: u32, h: u32, r: u8, g: u8, b: u8, a: u8) {
-    if w == 0 || h == 0 {
-        return;
+    #[test]
+    fn test_speed_schedule_follows_best_score() {
+        let schedule = SpeedSchedule::default();
+        let at = |best| schedule.steps_per_frame(best, 80_000);
+        assert_eq!(
+            [at(0), at(4), at(5), at(14), at(15), at(29)],
+            [16, 16, 256, 256, 4_096, 4_096]
+        );
+        assert_eq!(at(30), 32_768);
+        assert_eq!(at(1_000), 32_768);
+        // Never above the per-tick cap
+        assert_eq!(schedule.steps_per_frame(40, 1500), 1500);
+        // Scores below the first stage run at one step per frame
+        let late = SpeedSchedule {
+            stages: vec![(10, 512)],
+        };
+        assert_eq!(
+            (
+                late.steps_per_frame(3, 1500),
+                late.steps_per_frame(10, 1500)
+            ),
+            (1, 512)
+        );
     }
-    let x2 = (x + w - 1).min(WIDTH - 1);
-    let y2 = (y + h - 1).min(HEIGHT - 1);
-    for px in x..=x2 {
-        blend_pixel(frame, px, y, r, g, b, a);
-        blend_pixel(frame, px, y2, r, g, b, a);
+
+    #[test]
+    fn test_no_render_starts_ultra_fast() {
+        let cfg = parse_args(["--no-render"].map(String::from)).unwrap();
+        assert!(cfg.no_render && !cfg.headless);
+        // `main` starts with `ultra_fast = cfg.no_render` and this budget
+        assert_eq!(step_budget(cfg.no_render, false), 50_000);
+        assert_eq!(step_budget(Config::default().no_render, false), 1500);
+        assert_eq!(step_budget(cfg.no_render, true), 80_000);
     }
-    for py in y..=y2 {
-        blend_pixel(frame, x, py, r, g, b, a);
-        blend_pixel(frame, x2, py, r, g, b, a);
+
+    #[test]
+    fn test_parse_args_maps_flags() {
+        let argv = "--pop 8 --seed 42 --no-wrap --headless --epochs 5 --grid 20x15";
+        let cfg = parse_args(argv.split_whitespace().map(String::from)).unwrap();
+        assert_eq!(
+            cfg,
+            Config {
+                pop_size: 8,
+                seed: Some(42),
+                wrap_world: false,
+                headless: true,
+                no_render: false,
+                epochs: 5,
+                grid: GridConfig {
+                    width: 20,
+                    height: 15
+                },
+                fitness: FitnessMode::Score,
+                gamma: None,
+                agent: None,
+                early_stop: EarlyStop::default(),
+                encoding: StateEncoding::Compact,
+                tie_break: TieBreak::FirstIndex,
+                threads: None,
+                selection: ParentSelection::Uniform,
+                epsilon_schedule: EpsilonSchedule::Exponential,
+                curriculum: None,
+                target_score: None,
+                window: (WIDTH, HEIGHT),
+                title: "🐍 Snake Game".to_string(),
+                action_repeat: 1,
+                mutate_hypers: false,
+                max_states: None,
+                max_qmem_mb: None,
+                growth: 1,
+                shrink: None,
+                strict_tail: false,
+                stagnation_base: 1000,
+                dump_policy: None,
+                export_gif: None,
+                compare_baseline: None,
+                log_transitions: None,
+                autosave: false,
+                log_level: LogLevel::Info,
+                normalize_rewards: false,
+                lr_schedule: None,
+                max_grad_norm: Some(10.0),
+                arena: Vec::new(),
+                sweep: Vec::new(),
+            }
+        );
+        let win =
+            parse_args(["--window", "1920x1080", "--title", "rec"].map(String::from)).unwrap();
+        assert_eq!((win.window, win.title.as_str()), ((1920, 1080), "rec"));
+        assert!(parse_args(["--window", "1920"].map(String::from)).is_err());
+        assert!(parse_args(["--window", "100x100"].map(String::from)).is_err());
+        let to = parse_args(["--train-to", "5"].map(String::from)).unwrap();
+        assert_eq!((to.target_score, to.headless), (Some(5), true));
+        let ext = parse_args(["--state", "extended"].map(String::from)).unwrap();
+        assert_eq!(ext.encoding, StateEncoding::Extended);
+        let eff = parse_args(["--fitness", "efficiency"].map(String::from)).unwrap();
+        assert_eq!(eff.fitness, FitnessMode::Efficiency);
+        assert_eq!(parse_args(Vec::new()).unwrap(), Config::default());
+        assert!(parse_args(vec!["--bogus".to_string()]).is_err());
+        assert!(parse_args(vec!["--grid".to_string(), "20".to_string()]).is_err());
+        assert!(parse_args(vec!["--pop".to_string()]).is_err());
     }
-}
 
-/// Fill a single grid cell with an opaque RGB color.
-#[cfg(not(feature = "gpu-render"))]
-fn fill_cell_rgb(frame: &mut [u8], grid_x: u32, grid_y: u32, r: u8, g: u8, b: u8) {
-    let x = grid_x * GRID_SIZE;
-    let y = grid_y * GRID_SIZE;
-    fill_rect_rgba(frame, x, y, GRID_SIZE, GRID_SIZE, r, g, b, 255);
-}
+    #[test]
+    fn test_toggle_dqn_keeps_the_agent_across_pause() {
+        let (mut mode, mut agent) = (false, None::<Box<u32>>);
+        let created = std::cell::Cell::new(0);
+        let create = || -> Result<Box<u32>, String> {
+            created.set(created.get() + 1);
+            Ok(Box::new(7))
+        };
+        assert_eq!(
+            toggle_dqn(&mut mode, &mut agent, create),
+            Ok(DqnToggle::Created)
+        );
+        assert!(mode);
+        let instance: *const u32 = &**agent.as_ref().unwrap();
+        **agent.as_mut().unwrap() = 8; // "training" progress
+
+        assert_eq!(
+            toggle_dqn(&mut mode, &mut agent, create),
+            Ok(DqnToggle::Paused)
+        );
+        assert!(!mode);
+        assert_eq!(
+            toggle_dqn(&mut mode, &mut agent, create),
+            Ok(DqnToggle::Resumed)
+        );
+        assert!(mode);
+        assert!(std::ptr::eq(&**agent.as_ref().unwrap(), instance));
+        assert_eq!(**agent.as_ref().unwrap(), 8);
+        assert_eq!(created.get(), 1);
+
+        // After a reset drops the agent, a failed init leaves DQN off
+        toggle_dqn(&mut mode, &mut agent, create).unwrap();
+        agent = None;
+        let failed = toggle_dqn(&mut mode, &mut agent, || {
+            Err::<Box<u32>, _>("no device".to_string())
+        });
+        assert_eq!(failed, Err("no device".to_string()));
+        assert!(!mode && agent.is_none());
+    }
 
-/// Fill a single grid cell with an RGBA color.
-#[cfg(not(feature = "gpu-render"))]
-fn fill_cell_rgba(frame: &mut [u8], grid_x: u32, grid_y: u32, r: u8, g: u8, b: u8, a: u8) {
-    let x = grid_x * GRID_SIZE;
-    let y = grid_y * GRID_SIZE;
-    fill_rect_rgba(frame, x, y, GRID_SIZE, GRID_SIZE, r, g, b, a);
-}
+    #[test]
+    fn test_lr_schedule_reaches_target() {
+        let linear = LrSchedule::Linear {
+            end: 1e-4,
+            steps: 1000,
+        };
+        assert_eq!(linear.lr_at(1e-3, 0), 1e-3);
+        assert!((linear.lr_at(1e-3, 500) - 5.5e-4).abs() < 1e-12);
+        assert!((linear.lr_at(1e-3, 1000) - 1e-4).abs() < 1e-12);
+        assert!((linear.lr_at(1e-3, 5000) - 1e-4).abs() < 1e-12);
+        let step = LrSchedule::Step {
+            factor: 0.5,
+            every: 100,
+        };
+        assert_eq!(step.lr_at(1e-3, 99), 1e-3);
+        assert_eq!(step.lr_at(1e-3, 300), 1.25e-4);
+        let cfg = parse_args(["--lr-decay", "step:0.5:100"].map(String::from)).unwrap();
+        assert_eq!(cfg.lr_schedule, Some(step));
+        let cfg = parse_args(["--lr-decay", "linear:1e-4:1000"].map(String::from)).unwrap();
+        assert_eq!(cfg.lr_schedule, Some(linear));
+        for bad in ["linear:1e-4", "cosine:1:2", "step:0.5:0", "linear:-1:10"] {
+            assert!(parse_args(["--lr-decay", bad].map(String::from)).is_err());
+        }
+    }
 
-/// Draw the game semi-transparently, tinting the snake by `color` (used to show many agents).
-#[cfg(not(feature = "gpu-render"))]
-fn draw_game_transparent(frame: &mut [u8], game: &Game, alpha: u8, color: (u8, u8, u8)) {
-    if !game.alive {
-        return;
+    #[test]
+    fn test_parse_max_grad_norm() {
+        let clip = |v: &str| parse_args(["--max-grad-norm", v].map(String::from));
+        assert_eq!(clip("2.5").unwrap().max_grad_norm, Some(2.5));
+        assert_eq!(clip("off").unwrap().max_grad_norm, None);
+        for bad in ["0", "-1", "NaN", "inf", "x"] {
+            assert!(clip(bad).is_err());
+        }
     }
 
-    // Draw apple semi-transparent
-    fill_cell_rgba(
-        frame,
-        game.apple.x as u32,
-        game.apple.y as u32,
-        220,
-        50,
-        50,
-        alpha,
-    );
+    #[test]
+    fn test_headless_run_is_reproducible_with_seed() {
+        let cfg = Config {
+            pop_size: 4,
+            seed: Some(7),
+            headless: true,
+            epochs: 3,
+            grid: GridConfig {
+                width: 10,
+                height: 8,
+            },
+            ..Config::default()
+        };
+        let mut p: PathBuf = std::env::temp_dir();
+        p.push("snake_headless_test.json");
+        let save_path = p.to_string_lossy().to_string();
+        let a = run_headless(&cfg, Some(&save_path));
+        let b = run_headless(&cfg, Some(&save_path));
+        let _ = fs::remove_file(&save_path);
+        assert_eq!(a.epochs, 3);
+        assert_eq!(a.epoch_best.len(), 3);
+        assert_eq!(a.epoch_best, b.epoch_best);
+        assert_eq!(a.champion_score, b.champion_score);
+    }
 
-    let (base_r, base_g, base_b) = color;
+    #[test]
+    fn test_train_to_reports_solving_epoch() {
+        let mut cfg = Config {
+            pop_size: 4,
+            seed: Some(3),
+            headless: true,
+            epochs: 30,
+            grid: GridConfig {
+                width: 6,
+                height: 6,
+            },
+            target_score: Some(2),
+            ..Config::default()
+        };
+        let report = run_headless(&cfg, None);
+        let first = report.epoch_best.iter().position(|&b| b >= 2);
+        assert!(report.solved);
+        assert_eq!(report.solved_epoch, first);
+        assert_eq!(report.epochs, first.unwrap() + 1);
+
+        cfg.target_score = Some(usize::MAX); // clamped to the full board: not reachable in 2 epochs
+        cfg.epochs = 2;
+        let report = run_headless(&cfg, None);
+        assert_eq!(
+            (report.solved, report.solved_epoch, report.epochs),
+            (false, None, 2)
+        );
+    }
 
-    // Draw snake with agent's color
-    for (i, &pos) in game.snake.iter().enumerate() {
-        if i == 0 {
-            // Head - brighter version of agent's color
-            let bright_r = (base_r as u16 * 130 / 100).min(255) as u8;
-            let bright_g = (base_g as u16 * 130 / 100).min(255) as u8;
-            let bright_b = (base_b as u16 * 130 / 100).min(255) as u8;
-            fill_cell_rgba(
-                frame,
-                pos.x as u32,
-                pos.y as u32,
-                bright_r,
-                bright_g,
-                bright_b,
-                alpha,
-            );
-        } else {
-            // Body with gradient - darker with distance from head
-            let fade = 1.0 - (i as f32 * 0.015).min(0.5);
-            let body_r = (base_r as f32 * fade) as u8;
-            let body_g = (base_g as f32 * fade) as u8;
-            let body_b = (base_b as f32 * fade) as u8;
-            fill_cell_rgba(
-                frame,
-                pos.x as u32,
-                pos.y as u32,
-                body_r,
-                body_g,
-                body_b,
-                alpha,
-            );
+    #[test]
+    fn test_early_stop_fires_on_plateau() {
+        let mut cfg = Config {
+            pop_size: 4,
+            seed: Some(11),
+            headless: true,
+            epochs: 40,
+            grid: GridConfig {
+                width: 8,
+                height: 6,
+            },
+            ..Config::default()
+        };
+        let full = run_headless(&cfg, None);
+        assert!(!full.early_stopped);
+
+        // Replay the champion history to find where 3 epochs without gain end
+        let patience = 3;
+        let (mut best, mut since, mut champion) = (0, 0, 0);
+        let mut expected = None;
+        for (epoch, &score) in full.epoch_best.iter().enumerate() {
+            champion = champion.max(score);
+            if champion > best {
+                (best, since) = (champion, 0);
+            } else {
+                since += 1;
+                if since == patience {
+                    expected = Some(epoch + 1);
+                    break;
+                }
+            }
         }
+        let expected = expected.expect("seeded run should plateau");
+
+        cfg.early_stop.patience = patience;
+        let stopped = run_headless(&cfg, None);
+        assert!(stopped.early_stopped);
+        assert_eq!(stopped.epochs, expected);
+        assert_eq!(stopped.epoch_best[..], full.epoch_best[..expected]);
     }
-}
 
-/// Draw a simple UI button with a text label.
-#[cfg(not(feature = "gpu-render"))]
-fn draw_button(frame: &mut [u8], x: u32, y: u32, w: u32, h: u32, label: &str) {
-    fill_rect_rgba(frame, x, y, w, h, 40, 40, 60, 160);
-    stroke_rect_rgba(frame, x, y, w, h, 200, 200, 220, 120);
-    draw_text(
-        frame,
-        label,
-        x + 10,
-        y + (h / 2 - 6),
-        2,
-        (230, 240, 255, 255),
-    );
-}
+    #[test]
+    fn test_sweep_returns_report_per_config() {
+        let configs: Vec<AgentConfig> = [(0.1, 0.2), (0.1, 0.4), (0.3, 0.2), (0.3, 0.4)]
+            .into_iter()
+            .map(|(epsilon, alpha)| AgentConfig {
+                epsilon,
+                alpha,
+                ..AgentConfig::default()
+            })
+            .collect();
+        let results = sweep(configs.clone(), 1, 42);
+        assert_eq!(results.len(), 4);
+        for ((cfg, report), expected) in results.iter().zip(&configs) {
+            assert_eq!(cfg, expected);
+            assert_eq!(report.epochs, 1);
+        }
+        assert_ne!(configs[0].derive_seed(42), configs[1].derive_seed(42));
+    }
 
-/// Check whether a point lies within a rectangle.
-#[cfg(not(feature = "gpu-render"))]
-fn point_in_rect(px: u32, py: u32, x: u32, y: u32, w: u32, h: u32) -> bool {
-    px >= x && py >= y && px < x + w && py < y + h
-}
+    #[test]
+    fn test_parse_sweep_builds_every_combination() {
+        let cfg = parse_args(["--sweep", "1.0,0.5:0.1:0.9,0.99"].map(String::from)).unwrap();
+        let combos: Vec<(f32, f32, f32)> = cfg
+            .sweep
+            .iter()
+            .map(|a| (a.epsilon, a.alpha, a.gamma))
+            .collect();
+        assert_eq!(
+            combos,
+            [
+                (1.0, 0.1, 0.9),
+                (1.0, 0.1, 0.99),
+                (0.5, 0.1, 0.9),
+                (0.5, 0.1, 0.99)
+            ]
+        );
+        assert_eq!(cfg.sweep[0].decay, AgentConfig::default().decay);
+        for bad in [
+            "0.1:0.2",
+            "0.1:0.2:0.9:1",
+            "0.1::0.9",
+            "0.1:x:0.9",
+            "0.1:0.2:1.5",
+        ] {
+            assert!(
+                parse_args(["--sweep", bad].map(String::from)).is_err(),
+                "{}",
+                bad
+            );
+        }
+    }
 
-/// Returns a 5x7 bitmap glyph for a limited set of characters (ASCII-like UI font).
-#[cfg(not(feature = "gpu-render"))]
-fn glyph_5x7(ch: char) -> Option<[u8; 7]> {
-    let c = ch.to_ascii_uppercase();
-    Some(match c {
-        'A' => [
-            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
-        ],
-        'B' => [
-            0b11110, 0b10001, 0b11110, 0b10001, 0b10001, 0b10001, 0b11110,
-        ],
-        'C' => [
-            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
-        ],
-        'D' => [
-            0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100,
-        ],
-        'E' => [
-            0b11111, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000, 0b11111,
-        ],
-        'F' => [
-            0b11111, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000, 0b10000,
-        ],
-        'G' => [
-            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01110,
-        ],
-        'H' => [
-            0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001, 0b10001,
-        ],
-        'I' => [
-            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b11111,
-        ],
-        'J' => [
-            0b00111, 0b00010, 0b00010, 0b00010, 0b10010, 0b10010, 0b01100,
-        ],
-        'K' => [
-            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
-        ],
-        'L' => [
-            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
-        ],
-        'M' => [
-            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
-        ],
-        'N' => [
-            0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001, 0b10001,
-        ],
-        'O' => [
-            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
-        ],
-        'P' => [
-            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
-        ],
-        'Q' => [
-            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
-        ],
-        'R' => [
-            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
-        ],
-        'S' => [
-            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
-        ],
-        'T' => [
-            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
-        ],
-        'U' => [
-            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
-        ],
-        'V' => [
-            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
-        ],
-        'W' => [
-            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b11011, 0b10001,
-        ],
-        'X' => [
-            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
-        ],
-        'Y' => [
-            0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100,
-        ],
-        'Z' => [
-            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
-        ],
-        '0' => [
-            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
-        ],
-        '1' => [
-            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
-        ],
-        '2' => [
-            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
-        ],
-        '3' => [
-            0b11110, 0b00001, 0b00001, 0b01110, 0b00001, 0b00001, 0b11110,
-        ],
-        '4' => [
-            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
-        ],
-        '5' => [
-            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
-        ],
-        '6' => [
-            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
-        ],
-        '7' => [
-            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
-        ],
-        '8' => [
-            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
-        ],
-        '9' => [
-            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
-        ],
-        ':' => [
-            0b00000, 0b00100, 0b00000, 0b00000, 0b00100, 0b00000, 0b00000,
-        ],
-        '+' => [
-            0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
-        ],
-        '-' => [
-            0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
-        ],
-        ' ' => [
-            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000,
-        ],
-        _ => return None,
-    })
-}
+    #[test]
+    fn test_reachable_cells_boxed_in_and_open() {
+        let mut g = Game::new_seeded(false, 3);
+        assert_eq!(
+            g.reachable_cells_from_head(),
+            (GRID_WIDTH * GRID_HEIGHT) as usize - g.snake.len()
+        );
+
+        // Head in the top-left corner with a body wall enclosing a single free cell
+        g.snake = VecDeque::from([
+            Pos::new(0, 0),
+            Pos::new(1, 0),
+            Pos::new(2, 0),
+            Pos::new(2, 1),
+            Pos::new(1, 1),
+            Pos::new(1, 2),
+            Pos::new(0, 2),
+        ]);
+        g.snake_set = g.snake.iter().copied().collect();
+        assert_eq!(g.reachable_cells_from_head(), 1); // only (0, 1)
+
+        // Wrapping opens an exit across the edges
+        g.wrap_world = true;
+        assert!(g.reachable_cells_from_head() > 100);
+
+        // Opt-in reward term scales with the reachable share
+        let mut cfg = RewardConfig::default();
+        let base = StepOutcome {
+            ate: false,
+            died: false,
+            death: DeathCause::None,
+            dist_before: 10,
+            dist_after: 10,
+            length: 3,
+            step: 50,
+            open_space: None,
+            wall_adjacency: None,
+        };
+        let open = StepOutcome {
+            open_space: Some(1.0),
+            ..base
+        };
+        cfg.open_space = true;
+        let diff = compute_reward(&cfg, &open) - compute_reward(&cfg, &base);
+        assert!((diff - cfg.open_space_weight).abs() < 1e-6);
+    }
 
-/// Draw a single bitmap character and return its advance in pixels.
-#[cfg(not(feature = "gpu-render"))]
-fn draw_char(frame: &mut [u8], ch: char, x: u32, y: u32, scale: u32, col: (u8, u8, u8, u8)) -> u32 {
-    if let Some(rows) = glyph_5x7(ch) {
-        for (ry, row) in rows.iter().enumerate() {
-            for rx in 0..5 {
-                if (row >> (4 - rx)) & 1 == 1 {
-                    for sy in 0..scale {
-                        for sx in 0..scale {
-                            blend_pixel(
-                                frame,
-                                x + rx as u32 * scale + sx,
-                                y + ry as u32 * scale + sy,
-                                col.0,
-                                col.1,
-                                col.2,
-                                col.3,
-                            );
-                        }
-                    }
+    #[test]
+    fn test_survival_bonus_outweighs_step_cost() {
+        // Far from the apple and no closer: only the step cost and the bonus apply
+        let step = StepOutcome {
+            ate: false,
+            died: false,
+            death: DeathCause::None,
+            dist_before: 10,
+            dist_after: 10,
+            length: 3,
+            step: 50,
+            open_space: None,
+            wall_adjacency: None,
+        };
+        let default = RewardConfig::default();
+        assert_eq!(compute_reward(&default, &step), -default.step_cost);
+        let cfg = RewardConfig {
+            survival_bonus: 0.01,
+            ..default
+        };
+        assert!(compute_reward(&cfg, &step) > 0.0);
+        assert!((compute_reward(&cfg, &step) - 0.005).abs() < 1e-6);
+        // Dying and eating are unaffected
+        let died = StepOutcome {
+            died: true,
+            death: DeathCause::Wall,
+            ..step
+        };
+        assert_eq!(compute_reward(&cfg, &died), cfg.death_wall);
+    }
+
+    #[test]
+    fn test_wall_adjacency_penalty() {
+        let corner = [Pos::new(0, 0), Pos::new(1, 0), Pos::new(2, 0)];
+        let g = Game::from_layout(&corner, Dir::Left, Pos::new(9, 9), false).unwrap();
+        assert_eq!(g.wall_adjacency(), 2);
+        let edge = [Pos::new(5, 0), Pos::new(6, 0)];
+        let g = Game::from_layout(&edge, Dir::Left, Pos::new(9, 9), false).unwrap();
+        assert_eq!(g.wall_adjacency(), 1);
+        let center = [Pos::new(10, 10), Pos::new(11, 10)];
+        let g = Game::from_layout(&center, Dir::Left, Pos::new(9, 9), false).unwrap();
+        assert_eq!(g.wall_adjacency(), 0); // the neck next to the head isn't a wall
+        // Wrapping edges aren't walls
+        let g = Game::from_layout(&corner, Dir::Left, Pos::new(9, 9), true).unwrap();
+        assert_eq!(g.wall_adjacency(), 0);
+
+        // The opt-in penalty scales with the count
+        let cfg = RewardConfig {
+            wall_hugging: true,
+            ..RewardConfig::default()
+        };
+        let at = |walls| StepOutcome {
+            ate: false,
+            died: false,
+            death: DeathCause::None,
+            dist_before: 10,
+            dist_after: 10,
+            length: 3,
+            step: 50,
+            open_space: None,
+            wall_adjacency: walls,
+        };
+        let base = compute_reward(&cfg, &at(None));
+        assert_eq!(compute_reward(&cfg, &at(Some(0))), base);
+        let two = base - compute_reward(&cfg, &at(Some(2)));
+        assert!((two - 2.0 * cfg.wall_hugging_cost).abs() < 1e-6);
+        // Only computed when enabled
+        let mut g = Game::from_layout(&edge, Dir::Left, Pos::new(9, 9), false).unwrap();
+        assert_eq!(
+            g.apply_action_for(1, &RewardConfig::default())
+                .wall_adjacency,
+            None
+        );
+        assert_eq!(g.apply_action_for(1, &cfg).wall_adjacency, Some(1));
+    }
+
+    #[test]
+    fn test_step_cost_waived_during_grace_period() {
+        let cfg = RewardConfig {
+            grace_steps: 5,
+            closer_bonus: 0.0,
+            farther_cost: 0.0,
+            near_bonus: 0.0,
+            ..RewardConfig::default()
+        };
+        let at = |step| StepOutcome {
+            ate: false,
+            died: false,
+            death: DeathCause::None,
+            dist_before: 10,
+            dist_after: 10,
+            length: 3,
+            step,
+            open_space: None,
+            wall_adjacency: None,
+        };
+        assert_eq!(compute_reward(&cfg, &at(1)), 0.0);
+        assert_eq!(compute_reward(&cfg, &at(5)), 0.0);
+        assert_eq!(compute_reward(&cfg, &at(6)), -cfg.step_cost);
+
+        // Deaths are still punished inside the window
+        let died = StepOutcome {
+            died: true,
+            death: DeathCause::Wall,
+            ..at(1)
+        };
+        assert_eq!(compute_reward(&cfg, &died), cfg.death_wall);
+    }
+
+    #[test]
+    #[cfg(not(feature = "gpu-render"))]
+    fn test_eye_positions_fit_non_square_cell() {
+        let cell = CellSize { w: 30, h: 15 };
+        let pos = Pos::new(3, 2);
+        let (x0, y0) = (3 * 30, 2 * 15);
+        for dir in [Dir::Up, Dir::Down, Dir::Left, Dir::Right] {
+            let eyes = eye_positions(pos, dir, cell);
+            for (x, y) in eyes {
+                assert!(
+                    x >= x0 && x < x0 + cell.w,
+                    "{:?}: x {} outside cell",
+                    dir,
+                    x
+                );
+                assert!(
+                    y >= y0 && y < y0 + cell.h,
+                    "{:?}: y {} outside cell",
+                    dir,
+                    y
+                );
+            }
+            assert_ne!(eyes[0], eyes[1]);
+        }
+        // Default cell keeps the original 5/12 px offsets
+        let square = CellSize::for_grid(GridConfig::default(), (WIDTH, HEIGHT));
+        assert_eq!(square, CellSize { w: 20, h: 20 });
+        assert_eq!(
+            eye_positions(Pos::new(0, 0), Dir::Right, square),
+            [(12, 5), (12, 12)]
+        );
+    }
+
+    #[test]
+    fn test_step_once_moves_one_cell_while_paused() {
+        for wrap in [true, false] {
+            let mut g = Game::new_manual(GridConfig::default(), wrap);
+            g.apple = Pos::new(0, 0);
+            g.paused = true;
+            g.dir = Dir::Down;
+            let head = *g.snake.front().unwrap();
+            g.update(); // paused: no movement
+            assert_eq!(*g.snake.front().unwrap(), head);
+            g.step_once();
+            assert_eq!(*g.snake.front().unwrap(), Pos::new(head.x, head.y + 1));
+            assert!(g.paused);
+            assert_eq!(g.snake.len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_efficiency_fitness_breaks_score_ties() {
+        assert!(fitness(5, 400, 2) > fitness(5, 2000, 2));
+        assert!(fitness(6, 100_000, 1) > fitness(5, 10, 1));
+
+        let mut evo = EvoTrainer::new(3, None);
+        evo.scores = vec![5, 5, 2];
+        evo.pop[0].steps = 3000;
+        evo.pop[1].steps = 300;
+        for a in evo.pop.iter_mut() {
+            a.episodes = 1;
+        }
+        assert_eq!(evo.ranked_indices(), vec![0, 1, 2]);
+        evo.fitness_mode = FitnessMode::Efficiency;
+        assert_eq!(evo.ranked_indices(), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_set_gamma_propagates_to_population() {
+        let mut evo = EvoTrainer::new(6, None);
+        evo.set_gamma(0.97);
+        assert!(evo.pop.iter().all(|a| a.gamma == 0.97));
+        evo.set_gamma(2.0);
+        assert!(evo.pop.iter().all(|a| a.gamma == 0.999));
+        evo.set_gamma(0.1);
+        assert_eq!(evo.gamma, 0.8);
+
+        // Fresh agents created by reproduction inherit it too
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut p: PathBuf = std::env::temp_dir();
+        p.push("snake_gamma_test.json");
+        evo.reproduce(&mut rng, Some(&p.to_string_lossy()));
+        let _ = fs::remove_file(&p);
+        assert!(evo.pop.iter().all(|a| a.gamma == 0.8));
+    }
+
+    #[test]
+    fn test_local_vision_ahead_and_left_for_each_direction() {
+        // (direction, cell ahead of a head at (5, 5), left wall position of the head)
+        let cases = [
+            (Dir::Up, Pos::new(5, 4), Pos::new(0, 5)),
+            (
+                Dir::Down,
+                Pos::new(5, 6),
+                Pos::new(GRID_WIDTH as i32 - 1, 5),
+            ),
+            (
+                Dir::Left,
+                Pos::new(4, 5),
+                Pos::new(5, GRID_HEIGHT as i32 - 1),
+            ),
+            (Dir::Right, Pos::new(6, 5), Pos::new(5, 0)),
+        ];
+        for (dir, ahead, wall_head) in cases {
+            let mut g = Game::new_seeded(false, 1);
+            g.snake = VecDeque::from([Pos::new(5, 5)]);
+            g.snake_set = g.snake.iter().copied().collect();
+            g.dir = dir;
+            g.apple = ahead;
+            let v = local_vision(&g);
+            assert_eq!(v[1], 2, "{:?}: apple ahead", dir);
+            assert_eq!(v[3], 0, "{:?}: nothing to the left", dir);
+
+            // Head against the wall on its left side
+            g.snake = VecDeque::from([wall_head]);
+            g.snake_set = g.snake.iter().copied().collect();
+            g.apple = Pos::new(20, 15);
+            let v = local_vision(&g);
+            assert_eq!((v[0], v[3], v[5]), (1, 1, 1), "{:?}: wall left", dir);
+            assert_eq!(v[4], 0, "{:?}: open right", dir);
+        }
+    }
+
+    #[test]
+    fn test_vision_cells_rotate_with_direction() {
+        let neighbors: HashSet<Pos> = (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| Pos::new(10 + dx, 10 + dy)))
+            .filter(|&p| p != Pos::new(10, 10))
+            .collect();
+        for (dir, ahead, left) in [
+            (Dir::Up, Pos::new(10, 9), Pos::new(9, 10)),
+            (Dir::Down, Pos::new(10, 11), Pos::new(11, 10)),
+            (Dir::Left, Pos::new(9, 10), Pos::new(10, 11)),
+            (Dir::Right, Pos::new(11, 10), Pos::new(10, 9)),
+        ] {
+            let mut g = Game::new_seeded(false, 4);
+            g.snake = VecDeque::from([Pos::new(10, 10)]);
+            g.snake_set = g.snake.iter().copied().collect();
+            g.dir = dir;
+            g.apple = ahead;
+            let cells = vision_cells(&g);
+            assert_eq!(cells.iter().copied().collect::<HashSet<_>>(), neighbors);
+            assert_eq!((cells[1], cells[3]), (ahead, left), "{:?}", dir);
+            // The highlighted classes are exactly what the state key encodes
+            assert_eq!(local_vision(&g)[1], 2, "{:?}", dir);
+        }
+    }
+
+    #[test]
+    fn test_state_key_bit_layout_unchanged() {
+        // Keys produced by the pre-`local_vision` implementation for the same states
+        let expected = [87393, 82321, 87072, 21];
+        for (i, dir) in [Dir::Up, Dir::Down, Dir::Left, Dir::Right]
+            .into_iter()
+            .enumerate()
+        {
+            let mut g = Game::new_seeded(false, 1);
+            g.snake = VecDeque::from([
+                Pos::new(0, 5 + i as i32),
+                Pos::new(1, 4),
+                Pos::new(1, 5),
+                Pos::new(0, 4),
+            ]);
+            g.snake_set = g.snake.iter().copied().collect();
+            g.apple = Pos::new(1, 6);
+            g.dir = dir;
+            assert_eq!(state_key(&g).raw(), expected[i], "{:?}", dir);
+        }
+    }
+
+    /// `state_key` as first written: per-cell rotation `match` and per-direction apple
+    /// checks. The optimized version must stay bit-identical to it.
+    fn reference_state_key(game: &Game) -> u32 {
+        let head = *game.snake.front().unwrap();
+        let checks = [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ];
+        let vision = checks.map(|(dx, dy)| {
+            let (wx, wy) = match game.dir {
+                Dir::Up => (dx, dy),
+                Dir::Down => (-dx, -dy),
+                Dir::Left => (dy, -dx),
+                Dir::Right => (-dy, dx),
+            };
+            let pos = Pos::new(head.x + wx, head.y + wy);
+            if !game.grid.contains(pos) || game.snake_set.contains(&pos) {
+                1
+            } else if pos == game.apple {
+                2
+            } else {
+                0
+            }
+        });
+        let mut k = 0u32;
+        for (pair, cell) in [5, 6, 7, 3, 4, 0, 1, 2].into_iter().enumerate() {
+            k |= vision[cell] << (pair * 2);
+        }
+        let (dx, dy) = (game.apple.x - head.x, game.apple.y - head.y);
+        let (left, right) = match game.dir {
+            Dir::Right => (dy < -1, dy > 1),
+            Dir::Left => (dy > 1, dy < -1),
+            Dir::Up => (dx < -1, dx > 1),
+            Dir::Down => (dx > 1, dx < -1),
+        };
+        k |= if left {
+            0
+        } else if right {
+            2
+        } else {
+            1
+        } << 16;
+        let dist = game.apple_distance(head);
+        let dist_cat = match dist {
+            ..=3 => 0,
+            4..=8 => 1,
+            9..=16 => 2,
+            _ => 3,
+        };
+        k | dist_cat << 18
+    }
+
+    #[test]
+    fn test_state_key_matches_reference() {
+        let mut rng = SmallRng::seed_from_u64(11);
+        let mut checked = 0;
+        for seed in 0..40u64 {
+            let grid = GridConfig {
+                width: rng.gen_range(4..24),
+                height: rng.gen_range(4..24),
+            };
+            let mut g = Game::new_in(grid, seed % 2 == 0, seed).with_growth(1 + seed as usize % 3);
+            while g.alive && g.steps < 400 {
+                // Random apples too, so every direction/distance combination shows up
+                if rng.gen_bool(0.2) {
+                    g.apple = Pos::new(
+                        rng.gen_range(0..grid.width as i32),
+                        rng.gen_range(0..grid.height as i32),
+                    );
                 }
+                assert_eq!(state_key(&g).raw(), reference_state_key(&g));
+                checked += 1;
+                let a = if rng.gen_bool(0.7) {
+                    greedy_policy_action(&g)
+                } else {
+                    rng.gen_range(0..3)
+                };
+                g.apply_action(a);
             }
         }
-        5 * scale + scale
-    } else {
-        5 * scale + scale
+        assert!(checked > 2000);
     }
-}
 
-/// Draw a text string using the 5x7 glyph font.
-#[cfg(not(feature = "gpu-render"))]
-fn draw_text(frame: &mut [u8], text: &str, x: u32, y: u32, scale: u32, col: (u8, u8, u8, u8)) {
-    let mut cx = x;
-    for ch in text.chars() {
-        cx += draw_char(frame, ch, cx, y, scale, col);
+    #[test]
+    fn test_describe_state_decodes_vision() {
+        // Head at the left wall facing up, boxed in by its body, apple behind-right
+        let mut g = Game::new_seeded(false, 1);
+        g.snake = VecDeque::from([
+            Pos::new(0, 5),
+            Pos::new(1, 4),
+            Pos::new(1, 5),
+            Pos::new(0, 4),
+        ]);
+        g.snake_set = g.snake.iter().copied().collect();
+        g.apple = Pos::new(1, 6);
+        g.dir = Dir::Up;
+        let text = describe_state(&g);
+        assert!(text.starts_with(&format!("state {} = ", state_key(&g).raw())));
+        for part in [
+            " left-ahead=danger",
+            " ahead=danger",
+            " right=danger",
+            " behind=empty",
+            " right-behind=apple",
+            "apple: straight, 0-3 cells away",
+        ] {
+            assert!(text.contains(part), "{:?} not in {}", part, text);
+        }
     }
-}
 
-/// Draw a simple bar chart of best scores per epoch.
-#[cfg(not(feature = "gpu-render"))]
-fn draw_chart(frame: &mut [u8], x: u32, y: u32, w: u32, h: u32, data: &[usize]) {
-    stroke_rect_rgba(frame, x, y, w, h, 200, 200, 200, 120);
-    if data.is_empty() {
-        return;
+    #[test]
+    fn test_linear_epsilon_schedule_reaches_min() {
+        let mut agent = QAgent::new();
+        agent.epsilon = 0.45;
+        agent.min_epsilon = 0.05;
+        agent.set_epsilon_schedule(EpsilonSchedule::Linear { steps: 100 });
+        for _ in 0..50 {
+            agent.decay_epsilon();
+        }
+        assert!(
+            (agent.epsilon - 0.25).abs() < 1e-6,
+            "midpoint: {}",
+            agent.epsilon
+        );
+        for _ in 50..100 {
+            agent.decay_epsilon();
+        }
+        assert_eq!(agent.epsilon, agent.min_epsilon);
+        agent.decay_epsilon();
+        assert_eq!(agent.epsilon, agent.min_epsilon);
+
+        let cfg = parse_args(["--epsilon", "cosine:40"].map(String::from)).unwrap();
+        assert_eq!(cfg.epsilon_schedule, EpsilonSchedule::Cosine { period: 40 });
+        assert!(parse_args(["--epsilon", "linear:x"].map(String::from)).is_err());
     }
-    let max_val = *data.iter().max().unwrap_or(&1) as u32;
-    if max_val == 0 {
-        return;
+
+    #[test]
+    fn test_keybindings_file_overrides_defaults() {
+        let keys = KeyBindings::from_toml(
+            "# rebinds\nPause = \"Space\"\nSpeedUp = [\"K\", \"NumpadAdd\"]\n",
+        )
+        .unwrap();
+        assert_eq!(keys.keys(Action::Pause), &[VirtualKeyCode::Space]);
+        assert_eq!(
+            keys.keys(Action::SpeedUp),
+            &[VirtualKeyCode::K, VirtualKeyCode::NumpadAdd]
+        );
+        assert_eq!(keys.keys(Action::Restart), &[VirtualKeyCode::R]);
+
+        // S saves and steers down by default; binding P to Step adds a new conflict
+        let defaults = KeyBindings::default().conflicts();
+        assert_eq!(
+            defaults,
+            vec![(VirtualKeyCode::S, Action::Save, Action::MoveDown)]
+        );
+        let clash = KeyBindings::from_toml("Pause = \"Space\"\nStep = \"Space\"").unwrap();
+        assert!(
+            clash
+                .conflicts()
+                .contains(&(VirtualKeyCode::Space, Action::Pause, Action::Step))
+        );
+
+        assert!(KeyBindings::from_toml("Jump = \"Space\"").is_err());
+        assert!(KeyBindings::from_toml("Pause = \"Spacebar\"").is_err());
     }
-    let bars = data.len().min(w as usize / 6);
-    let bar_w = (w / bars as u32).max(2);
-    for i in 0..bars {
-        let v = data[data.len() - bars + i] as u32;
-        let bh = (v * (h - 2)) / max_val;
-        let bx = x + 1 + i as u32 * bar_w;
-        let by = y + h - 1 - bh;
-        fill_rect_rgba(frame, bx, by, bar_w - 1, bh, 120, 180, 255, 160);
+
+    #[test]
+    fn test_greedy_tie_break_modes() {
+        let mut rng = SmallRng::seed_from_u64(5);
+        let flat = [0.5, 0.5, 0.5];
+        assert_eq!(greedy_action(flat, TieBreak::FirstIndex, &mut rng), 0);
+        assert_eq!(greedy_action(flat, TieBreak::PreferStraight, &mut rng), 1);
+        // Left/right tie without straight: PreferStraight falls back to the lower index
+        assert_eq!(
+            greedy_action([1.0, 0.0, 1.0], TieBreak::PreferStraight, &mut rng),
+            0
+        );
+        // A strict maximum wins regardless of the rule
+        for tie in [
+            TieBreak::FirstIndex,
+            TieBreak::PreferStraight,
+            TieBreak::Random,
+        ] {
+            assert_eq!(greedy_action([0.1, 0.2, 0.9], tie, &mut rng), 2);
+        }
+        let picks: HashSet<usize> = (0..64)
+            .map(|_| greedy_action(flat, TieBreak::Random, &mut rng))
+            .collect();
+        assert_eq!(picks.len(), 3);
+
+        let mut agent = QAgent::new();
+        agent.epsilon = 0.0;
+        agent.tie_break = TieBreak::PreferStraight;
+        assert_eq!(agent.select_action(StateKey(7), &mut rng), 1);
     }
-}
 
-// ============================
-// Tests
-// ============================
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::path::PathBuf;
+    #[test]
+    fn test_extended_key_separates_lengths() {
+        let make = |len: i32| {
+            let mut g = Game::new_seeded(false, 2);
+            // Straight body trailing left of the head in row 20, heading right
+            g.snake = (0..len).map(|i| Pos::new(35 - i, 20)).collect();
+            g.snake_set = g.snake.iter().copied().collect();
+            g.dir = Dir::Right;
+            g.apple = Pos::new(37, 5);
+            g
+        };
+        let (short, long) = (make(4), make(30));
+        assert_eq!(local_vision(&short), local_vision(&long));
+        assert_eq!(state_key(&short), state_key(&long));
+        assert_eq!(
+            state_key_with(&short, StateEncoding::Compact),
+            state_key(&short)
+        );
+        let (ks, kl) = (
+            state_key_with(&short, StateEncoding::Extended),
+            state_key_with(&long, StateEncoding::Extended),
+        );
+        assert_ne!(ks, kl);
+        assert_eq!(ks.raw() & 0xFFFFF, kl.raw() & 0xFFFFF);
+        assert!(kl.raw() < 1 << 22);
+    }
 
     #[test]
-    fn test_dir_rotation() {
-        assert_eq!(left_dir(Dir::Up), Dir::Left);
-        assert_eq!(right_dir(Dir::Up), Dir::Right);
-        assert_eq!(left_dir(Dir::Right), Dir::Up);
-        assert_eq!(right_dir(Dir::Left), Dir::Up);
+    fn test_state_key_reduced_matches_modulo() {
+        for raw in [0, 7, 255, 256, 1 << 20, (1 << 22) - 1, u32::MAX] {
+            for vocab in [1, 3, 256, 1 << 20] {
+                assert_eq!(StateKey(raw).reduced(vocab), raw % vocab);
+            }
+            assert_eq!(StateKey(raw).raw(), raw);
+        }
     }
 
     #[test]
-    fn test_dir_after_action() {
-        assert_eq!(dir_after_action(Dir::Up, 0), Dir::Left);
-        assert_eq!(dir_after_action(Dir::Up, 1), Dir::Up);
-        assert_eq!(dir_after_action(Dir::Up, 2), Dir::Right);
+    fn test_state_features_shape_and_distance_range() {
+        let grid = GridConfig {
+            width: 12,
+            height: 9,
+        };
+        let mut g = Game::new_in(grid, false, 3);
+        for step in 0..200 {
+            if !g.alive {
+                g = Game::new_in(grid, false, step);
+            }
+            let f = state_features(&g);
+            assert_eq!(f.len(), STATE_FEATURES);
+            assert!((0.0..=1.0).contains(&f[28]), "distance {}", f[28]);
+            for cell in f[..24].chunks(3) {
+                assert_eq!(cell.iter().sum::<f32>(), 1.0);
+            }
+            assert_eq!(f[29..].iter().sum::<f32>(), 1.0);
+            g.apply_action((step % 3) as usize);
+        }
     }
 
     #[test]
-    fn test_wrap_on_wall() {
-        let mut g = Game::new();
-        // Place head at left edge and move left: should wrap to rightmost column
-        g.snake.clear();
-        g.snake_set.clear();
-        g.snake.push_front(Pos::new(0, 5));
-        g.snake_set.insert(Pos::new(0, 5));
+    fn test_agent_color_follows_id_through_reproduce() {
+        let mut evo = EvoTrainer::new(8, None);
+        let mut rng = SmallRng::seed_from_u64(3);
+        evo.scores = vec![9, 1, 2, 3, 4, 5, 6, 7];
+        evo.reproduce(&mut rng, None); // new champion: champion + offspring
+        evo.scores = vec![1, 2, 8, 3, 0, 0, 0, 0]; // no new record: normal reproduction
+        let elite = evo.pop[2].clone();
+        let before: Vec<(u64, (u8, u8, u8))> = evo.pop.iter().map(|a| (a.id, a.color)).collect();
+        evo.reproduce(&mut rng, None);
+
+        // The elite survives in another slot, with its id and color
+        let kept = evo
+            .pop
+            .iter()
+            .find(|a| a.id == elite.id)
+            .expect("elite survives");
+        assert_eq!(kept.color, elite.color);
+        for agent in &evo.pop {
+            assert_eq!(
+                agent.color,
+                lineage_color(agent.id, agent.lineage, Palette::Hsl)
+            );
+            if let Some(&(_, color)) = before.iter().find(|(id, _)| *id == agent.id) {
+                assert_eq!(agent.color, color);
+            }
+        }
+        // Offspring get new ids in the parent's lineage; fresh agents found their own
+        let ids: AHashSet<u64> = evo.pop.iter().map(|a| a.id).collect();
+        assert_eq!(ids.len(), evo.pop.len());
+        assert!(
+            evo.pop
+                .iter()
+                .any(|a| a.lineage == elite.lineage && a.id != elite.id)
+        );
+        assert!(evo.pop.iter().any(|a| a.lineage == a.id && a.id > elite.id));
+
+        // Recoloring by palette keeps colors tied to ids, not slots
+        evo.set_palette(Palette::ColorBlindSafe);
+        let same_lineage: Vec<_> = evo
+            .pop
+            .iter()
+            .filter(|a| a.lineage == elite.lineage)
+            .collect();
+        assert!(same_lineage.windows(2).all(|w| w[0].color == w[1].color));
+    }
+
+    #[test]
+    fn test_color_blind_palette_size_and_adjacency() {
+        for pop_size in [1, 7, 24, 50] {
+            let colors = generate_population_colors_with(pop_size, Palette::ColorBlindSafe);
+            assert_eq!(colors.len(), pop_size);
+            for pair in colors.windows(2) {
+                assert_ne!(pair[0], pair[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_snake_env_random_episode() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        let mut env = SnakeEnv::new(GridConfig::default(), false, RewardConfig::default());
+        assert_eq!(env.action_space(), 3);
+        env.reset(&mut rng);
+        let mut done = false;
+        for _ in 0..10_000 {
+            let (_, _, d) = env.step(rng.gen_range(0..env.action_space()));
+            assert_eq!(d, !env.game.alive);
+            if d {
+                done = true;
+                break;
+            }
+        }
+        assert!(done, "solid-wall episode should end under a random policy");
+    }
+
+    #[test]
+    fn test_all_paths_share_the_reward() {
+        // The inline shaping the GPU NN path used to compute by hand
+        let legacy = |o: &StepOutcome| {
+            if o.died {
+                return match o.death {
+                    DeathCause::SelfCollision => -30.0,
+                    DeathCause::Wall => -20.0,
+                    DeathCause::None => -12.0,
+                };
+            }
+            if o.ate {
+                return 10.0 + o.length as f32 * 0.1;
+            }
+            let mut r = -0.005;
+            if o.dist_after < o.dist_before {
+                r += 0.05;
+            } else if o.dist_after > o.dist_before {
+                r -= 0.03;
+            }
+            if o.dist_after <= 3 {
+                r += 0.02;
+            }
+            r
+        };
+        let rewards = RewardConfig::default();
+        let mut env = SnakeEnv::new(GridConfig::default(), false, rewards);
+        env.game = Game::new_seeded(false, 21);
+        let mut g = Game::new_seeded(false, 21);
+        let mut rng = SmallRng::seed_from_u64(4);
+        while g.alive {
+            let a = rng.gen_range(0..3);
+            let (outcome, reward) = g.step_with_reward(a, &rewards);
+            let (_, env_reward, _) = env.step(a);
+            assert_eq!(reward, env_reward);
+            assert!((reward - legacy(&outcome)).abs() < 1e-6);
+        }
+
+        let mut evo = EvoTrainer::new(3, None);
+        evo.step_tabular();
+        assert!(evo.episode_rewards.iter().all(|&r| r != 0.0));
+        evo.reset_epoch();
+        assert_eq!(evo.episode_rewards, vec![0.0; 3]);
+    }
+
+    #[test]
+    fn test_action_repeat_moves_k_cells_per_decision() {
+        let rewards = RewardConfig::default();
+        let mut g = Game::new_seeded(true, 5);
+        g.apple = Pos::new(0, 0); // out of the way of a straight run
+        let mut single = Game::new_seeded(true, 5);
+        single.apple = g.apple;
+        let head = *g.snake.front().unwrap();
+
+        let (_, total) = g.step_repeated(1, 3, &rewards);
+        assert_eq!(*g.snake.front().unwrap(), Pos::new(head.x + 3, head.y));
+        let expected: f32 = (0..3).map(|_| single.step_with_reward(1, &rewards).1).sum();
+        assert!((total - expected).abs() < 1e-6);
+
+        // A turn is taken once, then the snake keeps going straight
+        let head = *g.snake.front().unwrap();
+        g.step_repeated(0, 3, &rewards);
+        assert_eq!(g.dir, Dir::Up);
+        assert_eq!(*g.snake.front().unwrap(), Pos::new(head.x, head.y - 3));
+    }
+
+    #[test]
+    fn test_dump_policy_is_sorted_with_greedy_actions() {
+        let mut agent = QAgent::new();
+        agent.q.insert(9, [0.0, 2.0, 1.0]);
+        agent.q.insert(3, [5.0, -1.0, 0.0]);
+        agent.q.insert(7, [0.0, 0.0, 4.0]);
+        agent.q.insert(1, [1.0, 1.0, 1.0]); // tie: lowest index under FirstIndex
+        let actions: Vec<(u32, usize)> = agent
+            .dump_policy()
+            .iter()
+            .map(|&(s, a, _)| (s, a))
+            .collect();
+        assert_eq!(actions, vec![(1, 0), (3, 0), (7, 2), (9, 1)]);
+        agent.tie_break = TieBreak::PreferStraight;
+        assert_eq!(agent.dump_policy()[0].1, 1);
+
+        let path = std::env::temp_dir().join("snake_policy_dump_test.csv");
+        agent.dump_policy_csv(&path).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "state,action,q_left,q_straight,q_right");
+        assert_eq!(lines[2], "3,0,5,-1,0");
+    }
+
+    #[test]
+    fn test_epoch_timer_excludes_pauses() {
+        let t0 = Instant::now();
+        let at = |ms| t0 + Duration::from_millis(ms);
+        let mut timer = EpochTimer::start(t0);
+        assert_eq!(timer.elapsed(at(100)), Duration::from_millis(100));
+        timer.pause(at(100));
+        timer.pause(at(300)); // repeated pauses change nothing
+        assert_eq!(timer.elapsed(at(600)), Duration::from_millis(100));
+        timer.resume(at(600));
+        timer.resume(at(650)); // already running
+        assert_eq!(timer.elapsed(at(700)), Duration::from_millis(200));
+
+        let mut evo = EvoTrainer::new(2, None);
+        assert_eq!(evo.mean_epoch_ms(), None);
+        evo.epoch_ms = (1..=12).map(|i| i * 10).collect(); // only the last 10 count
+        assert_eq!(evo.mean_epoch_ms(), Some(75.0));
+    }
+
+    #[test]
+    fn test_transition_log_round_trip() {
+        let path = std::env::temp_dir().join("snake_transitions_test.bin");
+        let _ = fs::remove_file(&path);
+        let records: Vec<TransitionRecord> = (0..100u32)
+            .map(|i| {
+                (
+                    i * 7919,
+                    (i % 3) as u8,
+                    i as f32 * -0.25,
+                    i ^ 0xABCD,
+                    (i % 10 == 9) as u8,
+                )
+            })
+            .collect();
+        let mut log = TransitionLogger::create(&path).unwrap();
+        for &rec in &records {
+            log.log(rec).unwrap();
+        }
+        log.flush().unwrap();
+        let read = read_transitions(&path).unwrap();
+        assert_eq!(read, records);
+        assert_eq!(
+            fs::metadata(&path).unwrap().len(),
+            100 * TRANSITION_RECORD_BYTES as u64
+        );
+
+        // Training through the trainer appends one record per agent step, in game order
+        let mut evo = EvoTrainer::new(3, None);
+        let keys: Vec<u32> = evo
+            .games
+            .iter()
+            .zip(&evo.pop)
+            .map(|(g, agent)| state_key_with(g, agent.encoding).raw())
+            .collect();
+        evo.transition_log = Some(TransitionLogger::create(&path).unwrap());
+        evo.step_tabular();
+        evo.flush_transitions();
+        let read = read_transitions(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(read.len(), 103);
+        assert_eq!(read[..100], records[..]);
+        assert_eq!(read[100..].iter().map(|r| r.0).collect::<Vec<_>>(), keys);
+    }
+
+    #[test]
+    fn test_should_skip_render_zero_sized_surface() {
+        assert!(should_skip_render(0, 0));
+        assert!(should_skip_render(0, 600));
+        assert!(should_skip_render(800, 0));
+        assert!(!should_skip_render(800, 600));
+        assert!(!should_skip_render(1, 1));
+    }
+
+    #[test]
+    #[cfg(not(feature = "gpu-render"))]
+    fn test_lerp_cell_endpoints_and_wrap_snap() {
+        let (a, b) = (Pos::new(3, 4), Pos::new(4, 4));
+        assert_eq!(lerp_cell(a, b, 0.0), (3.0, 4.0));
+        assert_eq!(lerp_cell(a, b, 1.0), (4.0, 4.0));
+        assert_eq!(lerp_cell(a, b, 0.5), (3.5, 4.0));
+        assert_eq!(lerp_cell(a, b, 7.0), (4.0, 4.0));
+        // Wrapping from the right edge to the left snaps instead of sliding across
+        assert_eq!(lerp_cell(Pos::new(39, 4), Pos::new(0, 4), 0.0), (0.0, 4.0));
+    }
+
+    #[test]
+    #[cfg(not(feature = "gpu-render"))]
+    fn test_hint_direction_dominant_axis() {
+        let head = Pos::new(10, 10);
+        assert_eq!(hint_direction(head, Pos::new(15, 12)), Dir::Right);
+        assert_eq!(hint_direction(head, Pos::new(4, 9)), Dir::Left);
+        assert_eq!(hint_direction(head, Pos::new(11, 3)), Dir::Up);
+        assert_eq!(hint_direction(head, Pos::new(8, 20)), Dir::Down);
+        // Ties prefer the horizontal axis
+        assert_eq!(hint_direction(head, Pos::new(13, 7)), Dir::Right);
+        assert_eq!(hint_direction(head, Pos::new(10, 11)), Dir::Down);
+    }
+
+    #[test]
+    #[cfg(not(feature = "gpu-render"))]
+    fn test_top_k_indices_ordering_and_ties() {
+        let scores = [3, 9, 0, 9, 5, 1, 7];
+        assert_eq!(top_k_indices(&scores, 5), vec![1, 3, 6, 4, 0]);
+        assert_eq!(top_k_indices(&scores[..2], 5), vec![1, 0]);
+        assert!(top_k_indices(&[], 5).is_empty());
+    }
+
+    #[test]
+    fn test_target_score_solves_training() {
+        let mut evo = EvoTrainer::new(1, None);
+        evo.set_target_score(5);
+        evo.training = true;
+        // Greedy agent with an empty table turns left (first index wins ties)
+        evo.pop[0].epsilon = 0.0;
+        let g = &mut evo.games[0];
+        g.score = 4;
+        evo.scores[0] = 4;
+        let head = *g.snake.front().unwrap();
+        g.dir = Dir::Right;
+        g.apple = Pos::new(head.x, head.y - 1);
+        assert!(evo.step_tabular());
+        assert_eq!(evo.games[0].score, 5);
+        assert!(evo.solved);
+        assert!(!evo.training);
+    }
+
+    #[test]
+    fn test_apple_sequence_gives_identical_apples() {
+        let grid = GridConfig::default();
+        let seq = AppleSequence::generate(7, 50, grid);
+        assert_eq!(seq, AppleSequence::generate(7, 50, grid));
+        assert!(seq.cells.iter().all(|p| !start_body(grid).contains(p)));
+        // Different game seeds: only the sequence decides where apples go
+        let mut a = Game::new_in(grid, true, 1).with_apple_sequence(seq.clone());
+        let mut b = Game::new_in(grid, true, 2).with_apple_sequence(seq.clone());
+        assert_eq!(a.apple, seq.cells[0]);
+        for _ in 0..300 {
+            if !a.alive {
+                break;
+            }
+            let act = greedy_policy_action(&a);
+            a.apply_action(act);
+            b.apply_action(act);
+            assert_eq!((a.apple, a.score), (b.apple, b.score));
+        }
+        assert!(a.score >= 3);
+    }
+
+    #[test]
+    fn test_shrink_closes_the_outer_ring() {
+        let grid = GridConfig {
+            width: 20,
+            height: 15,
+        };
+        let mut g = Game::new_in(grid, false, 1).with_shrink(Some(5));
+        g.apple = Pos::new(10, 2);
+        for _ in 0..4 {
+            g.update();
+        }
+        assert_eq!(g.closed_rings, 0);
+        g.update();
+        assert!(g.alive);
+        assert_eq!(g.closed_rings, 1);
+        let ring: Vec<Pos> = (0..20)
+            .flat_map(|x| [Pos::new(x, 0), Pos::new(x, 14)])
+            .chain((0..15).flat_map(|y| [Pos::new(0, y), Pos::new(19, y)]))
+            .collect();
+        assert!(ring.iter().all(|&p| g.is_obstacle(p)));
+        assert!(!g.is_obstacle(Pos::new(1, 1)));
+        assert_eq!(g.open_cells(), 18 * 13);
+        // The closed ring is danger in the state key, like the wall
+        g.snake = VecDeque::from([Pos::new(1, 7), Pos::new(2, 7), Pos::new(3, 7)]);
+        g.snake_set = g.snake.iter().copied().collect();
         g.dir = Dir::Left;
+        assert_eq!(local_vision(&g)[1], 1);
+        g.update();
+        assert_eq!((g.alive, g.last_death), (false, DeathCause::Wall));
+
+        // A snake lying on the ring when it closes is caught by it
+        let edge = [Pos::new(5, 0), Pos::new(4, 0), Pos::new(3, 0)];
+        let mut caught = Game::from_layout(&edge, Dir::Right, Pos::new(9, 9), true)
+            .unwrap()
+            .with_shrink(Some(1));
+        caught.update();
+        assert_eq!((caught.alive, caught.last_death), (false, DeathCause::Wall));
+    }
+
+    #[test]
+    fn test_head_may_follow_the_departing_tail() {
+        // A 2x2 loop: the head at (6, 5) moves up into (6, 4), which the tail leaves
+        let body = [
+            Pos::new(6, 5),
+            Pos::new(5, 5),
+            Pos::new(5, 4),
+            Pos::new(6, 4),
+        ];
+        let mut g = Game::from_layout(&body, Dir::Up, Pos::new(0, 0), false).unwrap();
+        assert_eq!(legal_actions(&g), vec![1, 2]); // up into the tail, or right (left is the neck)
         g.update();
         assert!(g.alive);
-        let head = g.snake.front().unwrap();
-        assert_eq!(head.x, GRID_WIDTH as i32 - 1);
-        assert_eq!(head.y, 5);
+        assert_eq!(g.last_death, DeathCause::None);
+        assert_eq!(
+            g.snake,
+            VecDeque::from([
+                Pos::new(6, 4),
+                Pos::new(6, 5),
+                Pos::new(5, 5),
+                Pos::new(5, 4)
+            ])
+        );
+        assert_eq!(g.snake_set, g.snake.iter().copied().collect());
+
+        // The old rule, and a growing snake whose tail stays put, both die there
+        let mut strict = Game::from_layout(&body, Dir::Up, Pos::new(0, 0), false)
+            .unwrap()
+            .with_strict_tail(true);
+        strict.update();
+        assert_eq!(
+            (strict.alive, strict.last_death),
+            (false, DeathCause::SelfCollision)
+        );
+        let mut growing = Game::from_layout(&body, Dir::Up, Pos::new(0, 0), false).unwrap();
+        growing.pending_growth = 1;
+        growing.update();
+        assert!(!growing.alive);
     }
 
     #[test]
-    fn test_self_collision_death_cause() {
-        let mut g = Game::new();
-        // Create a body segment in front of the head to force self-collision
-        g.snake.clear();
-        g.snake_set.clear();
-        g.snake.push_front(Pos::new(2, 2)); // head
-        g.snake.push_back(Pos::new(3, 2)); // body directly to the right
-        g.snake_set.insert(Pos::new(2, 2));
-        g.snake_set.insert(Pos::new(3, 2));
-        g.dir = Dir::Right; // move into (3,2)
+    fn test_growth_per_apple_spreads_over_ticks() {
+        let mut g = Game::new_seeded(true, 1).with_growth(3);
+        let head = *g.snake.front().unwrap();
+        g.apple = Pos::new(head.x + 1, head.y);
         g.update();
-        assert!(!g.alive);
-        assert_eq!(g.last_death, DeathCause::SelfCollision);
+        assert_eq!((g.score, g.snake.len(), g.pending_growth), (1, 4, 2));
+        let mut lens = Vec::new();
+        for _ in 0..3 {
+            g.apple = Pos::new(0, 0); // keep the apple out of the way
+            g.update();
+            lens.push(g.snake.len());
+        }
+        assert_eq!(lens, [5, 6, 6]);
+        assert_eq!(g.pending_growth, 0);
+        // Each apple is worth three cells, so fewer apples fill the board
+        let grid = GridConfig::default();
+        assert_eq!(
+            default_target_score(grid, 3),
+            default_target_score(grid, 1).div_ceil(3)
+        );
+    }
+
+    #[test]
+    fn test_bump_epsilon_raises_every_agent() {
+        let mut evo = EvoTrainer::new(4, None);
+        for (i, agent) in evo.pop.iter_mut().enumerate() {
+            agent.epsilon = [0.05, 0.3, 0.85, 0.95][i];
+        }
+        evo.bump_epsilon(EPSILON_BUMP);
+        let eps: Vec<f32> = evo.pop.iter().map(|a| a.epsilon).collect();
+        assert!((eps[0] - 0.15).abs() < 1e-6);
+        assert!((eps[1] - 0.4).abs() < 1e-6);
+        assert_eq!(eps[2], EPSILON_BUMP_MAX); // clamped
+        assert_eq!(eps[3], 0.95); // already above the ceiling: left alone
+        // Decay restarts from the bumped value
+        assert!(
+            evo.pop
+                .iter()
+                .all(|a| a.epsilon_start == a.epsilon && a.epsilon_decays == 0)
+        );
+        let (_, mean) = evo.epsilon_summary();
+        assert!((mean - (0.15 + 0.4 + 0.9 + 0.95) / 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_step_limit_applies_from_next_epoch() {
+        let mut evo = EvoTrainer::new(4, None);
+        evo.set_step_limit(1200);
+        assert_eq!((evo.step_limit, evo.next_step_limit), (4000, 1200));
+        evo.set_step_limit(10);
+        assert_eq!(evo.next_step_limit, *STEP_LIMIT_RANGE.start());
+        evo.set_step_limit(u32::MAX);
+        assert_eq!(evo.next_step_limit, *STEP_LIMIT_RANGE.end());
+
+        evo.set_step_limit(500);
+        evo.reset_epoch();
+        assert_eq!(evo.step_limit, 500);
+        evo.steps_taken = 499;
+        assert!(!evo.epoch_over(false));
+        assert!(evo.epoch_over(true));
+        evo.steps_taken = 500;
+        assert!(evo.epoch_over(false));
+        // A unique leader still alive keeps the epoch running past the limit
+        evo.scores[2] = 3;
+        assert!(!evo.epoch_over(false));
+    }
+
+    #[test]
+    fn test_next_target_preset_cycles() {
+        let max = default_target_score(GridConfig::default(), 1);
+        assert_eq!(next_target_preset(0, max), 10);
+        assert_eq!(next_target_preset(10, max), 25);
+        assert_eq!(next_target_preset(100, max), max);
+        assert_eq!(next_target_preset(max, max), 10);
+        // Small boards never cycle past "fill the board".
+        assert_eq!(next_target_preset(10, 30), 25);
+        assert_eq!(next_target_preset(25, 30), 30);
+        assert_eq!(next_target_preset(30, 30), 10);
+        assert_eq!(next_target_preset(0, 6), 6);
+        assert_eq!(next_target_preset(6, 6), 6);
+    }
+
+    #[test]
+    fn test_save_frame_png_dimensions() {
+        let (w, h) = (16u32, 9u32);
+        let frame: Vec<u8> = (0..w * h * 4).map(|i| (i % 251) as u8).collect();
+        let mut p: PathBuf = std::env::temp_dir();
+        p.push("snake_frame_test.png");
+        save_frame_png(&frame, w, h, &p).unwrap();
+        assert_eq!(image::image_dimensions(&p).unwrap(), (w, h));
+        let _ = fs::remove_file(&p);
+        // Mismatched buffer size is rejected rather than written
+        assert!(save_frame_png(&frame[..10], w, h, &p).is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "gpu-render"))]
+    fn test_arena_layout_two_by_two() {
+        let slots = arena_layout(4, (800, 600));
+        assert_eq!(
+            slots,
+            vec![
+                (0, 0, 400, 300),
+                (400, 0, 400, 300),
+                (0, 300, 400, 300),
+                (400, 300, 400, 300)
+            ]
+        );
+        // Three boards keep the 2x2 grid, one fills the frame
+        assert_eq!(arena_layout(3, (800, 600))[2], (0, 300, 400, 300));
+        assert_eq!(arena_layout(1, (800, 600)), vec![(0, 0, 800, 600)]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "gpu-render"))]
+    fn test_render_to_buffer_draws_the_apple() {
+        let grid = GridConfig {
+            width: 20,
+            height: 15,
+        };
+        let mut g = Game::new_in(grid, false, 1);
+        g.apple = Pos::new(3, 4);
+        let (w, h) = (200, 150); // 10 px cells
+        let mut buf = vec![0u8; (w * h * 4) as usize];
+        render_to_buffer(&g, &mut buf, w, h);
+        let px = |x: u32, y: u32| {
+            let i = ((y * w + x) * 4) as usize;
+            [buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]
+        };
+        assert_eq!(px(35, 45), [220, 50, 50, 255]);
+        assert!(buf.chunks(4).all(|p| p[3] == 255)); // every pixel drawn
+
+        let mut gif = GifExporter::new(w, h);
+        gif.push(&buf);
+        gif.push(&buf);
+        let path = std::env::temp_dir().join("snake_gif_export_test.gif");
+        gif.write(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(bytes.starts_with(b"GIF89a"));
     }
 
     #[test]
     fn test_evo_reproduce_keeps_population_size() {
-        let mut evo = EvoTrainer::new(24);
+        let mut evo = EvoTrainer::new(24, None);
         // Ensure there is a champion by setting a non-zero best score
         evo.scores[0] = 1;
         let mut rng = SmallRng::from_entropy();
         let mut p: PathBuf = std::env::temp_dir();
         p.push("snake_agent_test.json");
         let save_path = p.to_string_lossy().to_string();
-        evo.reproduce(&mut rng, &save_path);
+        evo.reproduce(&mut rng, Some(&save_path));
         assert_eq!(evo.pop.len(), evo.pop_size);
         assert_eq!(evo.games.len(), evo.pop_size);
         assert_eq!(evo.scores.len(), evo.pop_size);
     }
+
+    #[test]
+    fn test_elite_fraction_carries_elites_unchanged() {
+        let mut evo = EvoTrainer::new(80, None);
+        evo.elite_fraction = 0.25;
+        for (i, agent) in evo.pop.iter_mut().enumerate() {
+            agent.q.insert(i as u32, [i as f32, 0.0, 0.0]);
+        }
+        // Scores stay below any champion, so this is a normal reproduction
+        for i in 0..80 {
+            evo.scores[i] = 80 - i;
+        }
+        evo.champion_score = 1000;
+        let before: Vec<_> = evo.pop.iter().map(|a| a.q.clone()).collect();
+        let mut rng = SmallRng::seed_from_u64(7);
+        evo.reproduce(&mut rng, None);
+        assert_eq!(evo.pop.len(), 80);
+        for (i, (agent, q)) in evo.pop.iter().zip(&before).take(20).enumerate() {
+            assert_eq!(&agent.q, q, "elite {}", i);
+        }
+        // Fresh agents fill the tail with empty tables
+        assert!(evo.pop[20..].iter().any(|a| a.q.is_empty()));
+        assert_eq!(elite_split(24, 0.125), (3, 12));
+        assert_eq!(elite_split(4, 0.9), (3, 1));
+    }
 }